@@ -16,7 +16,7 @@
 //! Contains all calls to `clap` so it doesn't clutter `main()`.
 
 
-use clap::{self, App, AppSettings, Arg};
+use clap::{self, App, AppSettings, Arg, ArgGroup};
 
 
 /// Returns an [`App`] instance.
@@ -46,6 +46,43 @@ pub fn get_app() -> clap::App<'static, 'static> {
              .long_help("Suppress information during execution of \
                          commands. Errors found in the given scenario \
                          files are still printed to stderr."))
+        .arg(Arg::with_name("verbose")
+             .short("v")
+             .long("verbose")
+             .requires("command")
+             .conflicts_with("quiet")
+             .help("Print each command line before executing it.")
+             .long_help("Print each command line to stderr before \
+                         executing it, together with the name of its \
+                         scenario. The arguments are quoted for a \
+                         POSIX shell, so the command line can be \
+                         copied to reproduce a single run."))
+        .arg(Arg::with_name("color")
+             .long("color")
+             .takes_value(true)
+             .value_name("WHEN")
+             .possible_values(&["auto", "always", "never"])
+             .default_value("auto")
+             .help("Whether to color error messages.")
+             .long_help("Whether to color error messages. The default, \
+                         \"auto\", uses colors only if stderr is a \
+                         terminal."))
+        .arg(Arg::with_name("log_format")
+             .long("log-format")
+             .takes_value(true)
+             .value_name("FORMAT")
+             .possible_values(&["human", "json"])
+             .default_value("human")
+             .help("The format of messages printed to stderr.")
+             .long_help("The format of messages printed to stderr. \
+                         With \"json\", each message is a JSON object \
+                         on a line of its own, with the fields \
+                         \"level\" (\"info\", \"warning\" or \"error\") \
+                         and \"message\". Errors also have \
+                         the field \"causes\", an array of strings, \
+                         and, if they concern a particular scenario, \
+                         \"scenario\". The output of COMMANDs is not \
+                         affected."))
 
         // Main options.
         .arg(Arg::with_name("print")
@@ -60,7 +97,9 @@ pub fn get_app() -> clap::App<'static, 'static> {
                          scenario combination. Names are separated by \
                          newlines. An optional format string may be \
                          passed, in which \"{}\" gets replaced with \
-                         SCENARIOS_NAME. [default]"))
+                         SCENARIOS_NAME, \"{count}\" with the number \
+                         of variables, and \"{names}\" with their \
+                         comma-separated names. [default]"))
         .arg(Arg::with_name("print0")
              .long("print0")
              .takes_value(true)
@@ -74,14 +113,46 @@ pub fn get_app() -> clap::App<'static, 'static> {
                          with a null byte instead of a newline. This \
                          is useful when piping the names to \
                          \"xargs -0\"."))
+        .arg(Arg::with_name("print_file")
+             .long("print-file")
+             .takes_value(true)
+             .conflicts_with_all(&["print", "print0"])
+             .value_name("PATH")
+             .help("Like --print, but read FORMAT from a file.")
+             .long_help("Like --print, but read FORMAT from a file. \
+                         A single line break at the end of the file is \
+                         ignored, since a newline is printed after each \
+                         scenario anyway. Add an empty line at the end \
+                         to print blank lines between scenarios."))
+        .arg(Arg::with_name("print_vars")
+             .long("print-vars")
+             .conflicts_with_all(&["print", "print0", "print_file", "command"])
+             .help("Print the variables of each scenario combination.")
+             .long_help("Print the variables of each scenario \
+                         combination as lines VARIABLE=value, sorted \
+                         by name. Each combination is followed by an \
+                         empty line, or by the string passed to \
+                         --terminator."))
+        .arg(Arg::with_name("terminator")
+             .long("terminator")
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .conflicts_with_all(&["print0", "exec", "exec_file", "exec_stdin"])
+             .value_name("STR")
+             .help("Like --print, but separate scenario names with \
+                    STR instead of a newline.")
+             .long_help("Like --print, but separate scenario names \
+                         with STR instead of a newline. The escape \
+                         sequences \"\\n\", \"\\t\", \"\\r\", \"\\0\" \
+                         and \"\\\\\" are recognized. This may be \
+                         combined with --print and --print-file."))
         .arg(Arg::with_name("exec")
              .long("exec")
              .takes_value(true)
              .allow_hyphen_values(true)
              .min_values(1)
              .value_terminator(";")
-             .conflicts_with("print")
-             .conflicts_with("print0")
+             .conflicts_with_all(&["print", "print0", "print_file", "terminator"])
              .value_name("COMMAND...")
              .help("A command line to execute for each scenario \
                     combination.")
@@ -89,6 +160,111 @@ pub fn get_app() -> clap::App<'static, 'static> {
                          combination. This must always preceded by \
                          \"--\" to distinguish it from the list of \
                          scenario files."))
+        .arg(Arg::with_name("exec_file")
+             .long("exec-file")
+             .takes_value(true)
+             .conflicts_with_all(&["print", "print0", "print_file", "terminator"])
+             .value_name("PATH")
+             .help("Like --exec, but read COMMAND from a file.")
+             .long_help("Like --exec, but read COMMAND from a file. \
+                         Each line of the file is one argument of \
+                         COMMAND, the first line being the program to \
+                         execute. Blank lines and lines starting with \
+                         \"#\" are skipped."))
+        .arg(Arg::with_name("exec_stdin")
+             .long("exec-stdin")
+             .conflicts_with_all(&["print", "print0", "print_file", "terminator"])
+             .help("Like --exec-file, but read COMMAND from stdin.")
+             .long_help("Like --exec-file, but read COMMAND from stdin, \
+                         one argument per line. Because stdin can only \
+                         be read once, no scenario file may be '-' in \
+                         this case. The jobs inherit the exhausted \
+                         stdin."))
+        .group(ArgGroup::with_name("command")
+               .args(&["exec", "exec_file", "exec_stdin"]))
+        .arg(Arg::with_name("list_scenarios")
+             .long("list-scenarios")
+             .conflicts_with_all(&["print", "print0", "print_file", "print_vars", "command"])
+             .help("List the scenarios in each file without combining \
+                    them.")
+             .long_help("For each scenario file, print the file name \
+                         followed by the names of the scenarios \
+                         defined in it. Scenarios from different files \
+                         are not combined. This is useful to check \
+                         which file contributes which scenarios."))
+        .arg(Arg::with_name("explain")
+             .long("explain")
+             .conflicts_with_all(&["print", "print0", "print_file", "print_vars", "command",
+                                   "list_scenarios", "emit_envfiles", "table", "check"])
+             .help("Print each scenario combination with its variables.")
+             .long_help("Print each scenario combination with its \
+                         variables. For each variable, the scenario \
+                         that defined it is printed as well. This is \
+                         useful to understand conflicts between \
+                         scenario files, especially with --lax."))
+        .arg(Arg::with_name("count")
+             .long("count")
+             .conflicts_with_all(&["print", "print0", "print_file", "print_vars", "command",
+                                   "list_scenarios", "explain", "emit_envfiles", "table",
+                                   "check"])
+             .help("Print the number of scenario combinations.")
+             .long_help("Print the number of scenario combinations \
+                         instead of the combinations themselves. \
+                         Scenarios skipped by --choose or --exclude \
+                         are not counted."))
+        .arg(Arg::with_name("check")
+             .long("check")
+             .conflicts_with_all(&["print", "print0", "print_file", "print_vars", "command",
+                                   "list_scenarios", "explain", "count", "emit_envfiles",
+                                   "table"])
+             .help("Validate all input files without printing or \
+                    executing anything.")
+             .long_help("Read all input files, build all scenario \
+                         combinations, and report every error that \
+                         occurs along the way, e.g. malformed files or \
+                         conflicting variables in strict mode. Then \
+                         print the number of combinations and exit. \
+                         The exit code is non-zero if any error was \
+                         found. This is a quick sanity check for CI."))
+        .arg(Arg::with_name("emit_envfiles")
+             .long("emit-envfiles")
+             .takes_value(true)
+             .value_name("DIR")
+             .conflicts_with_all(&["print", "print0", "print_file", "print_vars", "command",
+                                   "list_scenarios", "explain", "count", "table",
+                                   "check"])
+             .help("Write each scenario combination to DIR/NAME.env.")
+             .long_help("Write each scenario combination to a file \
+                         DIR/NAME.env that contains one line \
+                         VARIABLE=value per variable, as read by \
+                         --env-file. In NAME, all characters except \
+                         ASCII letters and digits are replaced with \
+                         underscores. DIR is created if it doesn't \
+                         exist. It is an error if two scenarios end up \
+                         with the same file name or if a file already \
+                         exists, unless --force is passed. Scenarios \
+                         that remove or append to variables or that \
+                         have multi-line values cannot be written. All \
+                         of this is checked before any file is \
+                         written."))
+        .arg(Arg::with_name("force")
+             .long("force")
+             .requires("emit_envfiles")
+             .help("Let --emit-envfiles overwrite existing files."))
+        .arg(Arg::with_name("table")
+             .long("table")
+             .conflicts_with_all(&["print", "print0", "print_file", "print_vars", "command",
+                                   "list_scenarios", "explain", "count", "emit_envfiles",
+                                   "check"])
+             .help("Print all scenario combinations as a table.")
+             .long_help("Print all scenario combinations as a table. \
+                         There is one row per combination and one \
+                         column per variable that any combination \
+                         defines. Cells of variables that a \
+                         combination does not define are left blank. \
+                         Because the columns must be known in advance, \
+                         all combinations are collected before anything \
+                         is printed."))
 
         // Input control.
         .arg(Arg::with_name("input")
@@ -99,7 +275,85 @@ pub fn get_app() -> clap::App<'static, 'static> {
              .long_help("The scenario files to process. If multiple \
                          files are passed, all possible combinations \
                          between them are iterated. Pass '-' to read \
-                         from stdin."))
+                         from stdin. This cannot be combined with \
+                         --exec-stdin."))
+        .arg(Arg::with_name("tsv")
+             .long("tsv")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("FILE")
+             .help("Also read scenarios from a file of tab-separated \
+                    values.")
+             .long_help("Also read scenarios from a file of \
+                         tab-separated values. The first line holds \
+                         the variable names, each following line one \
+                         scenario. A column named SCENARIOS_NAME \
+                         holds the scenario names; without it, the \
+                         scenarios are numbered. The file is combined \
+                         with the SCENARIO FILES as if it were passed \
+                         after them. This option may be passed \
+                         multiple times. Pass '-' to read from \
+                         stdin."))
+        .arg(Arg::with_name("scenario")
+             .long("scenario")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("NAME:VAR=VAL,...")
+             .help("Also use a scenario defined on the command line.")
+             .long_help("Also use a scenario defined on the command \
+                         line, e.g. \"fast:OPT=-O2,DEBUG=0\". Each \
+                         such scenario is combined with the others as \
+                         if it were the only scenario in a file that \
+                         is passed after all SCENARIO FILES. Write \
+                         \"\\,\", \"\\:\", and \"\\\\\" for a literal \
+                         comma, colon, and backslash. This option may \
+                         be passed multiple times."))
+        .arg(Arg::with_name("base")
+             .long("base")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Merge the scenarios in FILE into every \
+                    combination.")
+             .long_help("Merge the scenarios in FILE into every \
+                         combination of the SCENARIO FILES. Unlike \
+                         the SCENARIO FILES, FILE does not add to the \
+                         number of combinations, and its scenario \
+                         names are not part of the combined names. If \
+                         FILE contains several scenarios, all of them \
+                         are merged. Unless --lax is passed, the base \
+                         and the combinations must not define the \
+                         same variables."))
+        .arg(Arg::with_name("fail_on_empty")
+             .long("fail-on-empty")
+             .help("Fail if an input file defines no scenarios.")
+             .long_help("Fail if an input file defines no scenarios. \
+                         By default, such a file silently leaves no \
+                         scenario combinations, which may hide e.g. a \
+                         broken script that generates scenario \
+                         files."))
+        .arg(Arg::with_name("files_from")
+             .long("files-from")
+             .takes_value(true)
+             .value_name("MANIFEST")
+             .help("Also read the scenario files listed in MANIFEST.")
+             .long_help("Also read the scenario files listed in \
+                         MANIFEST, one path per line. Relative paths \
+                         are relative to the directory that contains \
+                         MANIFEST. Empty lines and lines starting with \
+                         \"#\" are ignored. The listed files are \
+                         combined as if they were passed after the \
+                         SCENARIO FILES."))
+        .arg(Arg::with_name("glob_inputs")
+             .long("glob-inputs")
+             .help("Expand glob patterns in SCENARIO FILES.")
+             .long_help("Treat each of the SCENARIO FILES as a glob \
+                         pattern, e.g. \"*.ini\", and replace it with \
+                         the sorted list of matching files. This is \
+                         useful on shells that don't expand patterns \
+                         themselves. Quote the patterns so that your \
+                         shell doesn't expand them first."))
         .arg(Arg::with_name("choose")
              .short("c")
              .long("choose")
@@ -109,7 +363,8 @@ pub fn get_app() -> clap::App<'static, 'static> {
              .long_help("Ignore all scenarios except the one with the \
                          given name. SCENARIO NAME may be a \
                          shell-like glob pattern to choose more than \
-                         one scenario at once."))
+                         one scenario at once. Brace groups like \
+                         {a,b} are expanded, but may not be nested."))
         .arg(Arg::with_name("exclude")
              .short("x")
              .long("exclude")
@@ -120,6 +375,65 @@ pub fn get_app() -> clap::App<'static, 'static> {
              .long_help("Ignore all scenarios with the given name. As \
                          for --choose, SCENARIO NAME may be a \
                          shell-like glob pattern."))
+        .arg(Arg::with_name("with_tag")
+             .long("with-tag")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("TAG")
+             .help("Only process scenarios with the given tag.")
+             .long_help("Only process scenarios that carry the given \
+                         tag. A scenario is tagged by a line \
+                         \"@tag = TAG\" in its definition. Tags are \
+                         not exported to COMMAND. A combination of \
+                         scenarios carries the tags of all its parts. \
+                         If this option is passed several times, all \
+                         tags are required."))
+        .arg(Arg::with_name("without_tag")
+             .long("without-tag")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("TAG")
+             .help("Ignore scenarios with the given tag.")
+             .long_help("Ignore all scenarios that carry the given \
+                         tag. This option may be passed several times \
+                         to ignore several tags."))
+        .arg(Arg::with_name("case_insensitive_names")
+             .long("case-insensitive-names")
+             .help("Compare scenario names case-insensitively.")
+             .long_help("Compare scenario names case-insensitively. \
+                         In strict mode, \"[Build]\" and \"[build]\" \
+                         in the same file then count as duplicate \
+                         scenario names. The patterns passed to \
+                         --choose and --exclude also match \
+                         case-insensitively. Note that the patterns \
+                         are matched against the combined names, so \
+                         all parts of a combined name are affected, \
+                         no matter which file they come from."))
+        .arg(Arg::with_name("name_pattern")
+             .long("name-pattern")
+             .takes_value(true)
+             .value_name("PATTERN")
+             .help("Require all scenario names to match a glob \
+                    pattern.")
+             .long_help("Require all scenario names to match the given \
+                         shell-like glob pattern, e.g. \"test_*\". A \
+                         scenario file that contains any other name is \
+                         rejected. Unlike --choose, this applies to \
+                         the names in each file, not to the combined \
+                         names. With --case-insensitive-names, the \
+                         pattern matches case-insensitively."))
+        .arg(Arg::with_name("dedup_vars")
+             .long("dedup-vars")
+             .help("Skip combinations whose variables equal those of \
+                    an earlier one.")
+             .long_help("Skip each scenario combination that defines, \
+                         removes and appends to exactly the same \
+                         variables with the same values as an earlier \
+                         combination. Only the names may differ. The \
+                         first of such combinations is kept. Filters \
+                         like --choose are applied first."))
 
         // Strict mode control.
         .arg(Arg::with_name("strict")
@@ -138,47 +452,329 @@ pub fn get_app() -> clap::App<'static, 'static> {
              .long("lax")
              .conflicts_with("strict")
              .help("Disable strict mode."))
+        .arg(Arg::with_name("override")
+             .long("override")
+             .takes_value(true)
+             .requires("lax")
+             .value_name("WHICH")
+             .possible_values(&["first", "last"])
+             .help("In lax mode, let the first or the last definition \
+                    of a variable win. [default: last]")
+             .long_help("Decide which definition of a variable wins if \
+                         several scenarios define it in lax mode. With \
+                         \"last\", the scenario from the file given \
+                         last on the command line wins; with \"first\", \
+                         the one from the file given first. The same \
+                         applies to removals. Variables from --base \
+                         are always overridden. [default: last]"))
+        .arg(Arg::with_name("allow_inline_comments")
+             .long("allow-inline-comments")
+             .help("Allow comments at the end of variable definitions.")
+             .long_help("Allow comments at the end of variable \
+                         definitions. If this flag is passed, a hash \
+                         sign \"#\" preceded by whitespace in a \
+                         variable's value starts a comment. Write \
+                         \"\\#\" to get a literal hash sign. Header \
+                         lines are not affected."))
+        .arg(Arg::with_name("allow_file_values")
+             .long("allow-file-values")
+             .help("Read values of the form @PATH from files.")
+             .long_help("Read values of the form @PATH from files. If \
+                         this flag is passed, a variable definition \
+                         like \"cert = @certs/ca.pem\" gets the \
+                         contents of the file \"certs/ca.pem\" as its \
+                         value. Relative paths are resolved relative \
+                         to the scenario file. Without this flag, such \
+                         values are taken literally."))
+        .arg(Arg::with_name("allow_non_utf8_values")
+             .long("allow-non-utf8-values")
+             .help("Pass values that are not valid UTF-8 on to COMMAND.")
+             .long_help("Pass values that are not valid UTF-8 on to \
+                         COMMAND. By default, scenario files must be \
+                         valid UTF-8. If this flag is passed, variable \
+                         values in INI files may contain arbitrary \
+                         bytes, which are exported unchanged on Unix. \
+                         Scenario names, variable names, and heredocs \
+                         must still be valid UTF-8."))
+        .arg(Arg::with_name("stdin_format")
+             .long("stdin-format")
+             .takes_value(true)
+             .value_name("FORMAT")
+             .possible_values(&["ini", "nul", "tsv"])
+             .default_value("ini")
+             .help("The format of scenarios read from stdin.")
+             .long_help("The format of scenarios read from stdin. \
+                         \"ini\" is the regular format of scenario \
+                         files. With \"nul\", stdin is read as a \
+                         stream of records, each of which consists of \
+                         NUL-terminated fields: the scenario name, \
+                         followed by variable definitions of the form \
+                         NAME=VALUE, followed by an empty field. This \
+                         is useful when piping scenarios from other \
+                         programs. \"tsv\" reads tab-separated values \
+                         as described for --tsv."))
 
         // Command line execution.
         .arg(Arg::with_name("ignore_env")
              .short("I")
              .long("ignore-env")
-             .requires("exec")
+             .requires("command")
+             .conflicts_with("warn_env_shadow")
              .help("Don't export the current environment to COMMAND.")
              .long_help("Don't export the current environment to \
                          COMMAND. If this flag is passed, COMMAND sees \
                          _only_ the environment variables defined in \
                          the scenario files."))
+        .arg(Arg::with_name("env_passthrough")
+             .long("env-passthrough")
+             .takes_value(true)
+             .requires("ignore_env")
+             .value_name("LIST")
+             .help("With --ignore-env, still inherit the variables in \
+                    this comma-separated LIST.")
+             .long_help("A comma-separated list of variables that \
+                         COMMAND inherits despite --ignore-env, e.g. \
+                         \"HOME,PATH\". Variables that are not set \
+                         are skipped. Scenarios may still redefine \
+                         or remove them."))
+        .arg(Arg::with_name("env_file")
+             .long("env-file")
+             .takes_value(true)
+             .requires("command")
+             .value_name("PATH")
+             .help("Export the variables in PATH to every COMMAND.")
+             .long_help("Export the variables defined in PATH to every \
+                         COMMAND. Each line of the file has the form \
+                         NAME=VALUE, optionally preceded by \"export\"; \
+                         quotes around VALUE are removed. Blank lines \
+                         and lines starting with \"#\" are skipped. \
+                         The variables of a scenario take precedence, \
+                         but unless --lax is passed, it is an error \
+                         for a scenario to redefine a variable of \
+                         PATH."))
+        .arg(Arg::with_name("placeholder")
+             .long("placeholder")
+             .takes_value(true)
+             .value_name("STR")
+             .help("Replace STR instead of '{}' with SCENARIOS_NAME.")
+             .long_help("Replace STR instead of '{}' with \
+                         SCENARIOS_NAME. This applies both to COMMAND \
+                         and to the format string of --print. It is \
+                         useful if COMMAND needs a literal '{}', e.g. \
+                         for \"find -exec\". If STR is empty, nothing \
+                         is replaced."))
         .arg(Arg::with_name("no_insert_name")
              .long("no-insert-name")
-             .requires("exec")
+             .requires("command")
              .help("Don't replace '{}' with SCENARIOS_NAME when \
-                    reading COMMAND."))
+                    reading COMMAND.")
+             .long_help("Don't replace '{}' with SCENARIOS_NAME when \
+                         reading COMMAND. This is independent of \
+                         --no-export-name: the name is still exported \
+                         unless that is passed as well."))
         .arg(Arg::with_name("no_export_name")
              .long("no-export-name")
-             .requires("exec")
+             .requires("command")
              .help("Don't export SCENARIOS_NAME to COMMAND.")
              .long_help("Don't export SCENARIOS_NAME to COMMAND. If \
-                         use this parameter, you are able to define \
+                         you use this parameter, you are able to define \
                          your own SCENARIOS_NAME without it being \
-                         overwritten. (Why would you, though?)"))
+                         overwritten. (Why would you, though?) This is \
+                         independent of --no-insert-name: '{}' is \
+                         still replaced unless that is passed as \
+                         well."))
+        .arg(Arg::with_name("export_index")
+             .long("export-index")
+             .requires("command")
+             .help("Export SCENARIOS_INDEX and SCENARIOS_TOTAL to \
+                    COMMAND.")
+             .long_help("Export the position of each scenario to \
+                         COMMAND. SCENARIOS_INDEX is the zero-based \
+                         index of the scenario among all scenarios \
+                         that are executed, SCENARIOS_TOTAL is their \
+                         number. This is useful to distribute work \
+                         across several machines."))
+        .arg(Arg::with_name("name_transform")
+             .long("name-transform")
+             .takes_value(true)
+             .requires("command")
+             .value_name("TRANSFORM")
+             .possible_values(&["none", "upper", "lower", "slug"])
+             .help("Transform SCENARIOS_NAME before passing it to \
+                    COMMAND.")
+             .long_help("Transform the scenario name before it \
+                         replaces '{}' in COMMAND and before it is \
+                         exported as SCENARIOS_NAME. \"upper\" and \
+                         \"lower\" change the case of the name, \
+                         \"slug\" replaces all characters except ASCII \
+                         letters, digits and underscores with \
+                         underscores. Messages printed by scenarios \
+                         still use the original name. [default: \
+                         none]"))
+        .arg(Arg::with_name("append_separator")
+             .long("append-separator")
+             .takes_value(true)
+             .requires("command")
+             .value_name("SEP")
+             .help("The separator for values appended with \"+=\".")
+             .long_help("The separator placed between a variable's \
+                         value and the values appended to it with \
+                         \"+=\". The default is the separator that \
+                         this platform uses in PATH, i.e. \":\" on \
+                         Unix and \";\" on Windows."))
 
         // Handling multiple scenarios.
         .arg(Arg::with_name("delimiter")
              .short("d")
              .long("delimiter")
              .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
              .value_name("STRING")
              .help("The delimiter to use when combining scenario \
-                    names. [default: ', ']"))
+                    names. [default: ', ']")
+             .conflicts_with("name_format")
+             .long_help("The delimiter to use when combining scenario \
+                         names. [default: ', '] This option may be \
+                         passed several times to use a different \
+                         delimiter after each file's scenario name. \
+                         If there are more files than delimiters, the \
+                         last delimiter is repeated."))
+        .arg(Arg::with_name("escape_names")
+             .long("escape-names")
+             .conflicts_with("name_format")
+             .help("Escape delimiters inside scenario names.")
+             .long_help("Put a backslash before every delimiter and \
+                         every backslash that occurs in a scenario \
+                         name before combining it with others. This \
+                         way, tools can split combined names back into \
+                         their parts without ambiguity."))
+        .arg(Arg::with_name("name_format")
+             .long("name-format")
+             .takes_value(true)
+             .value_name("FORMAT")
+             .conflicts_with("delimiter")
+             .help("A template for the names of combined scenarios.")
+             .long_help("A template for the names of combined \
+                         scenarios. Each \"{i}\" in FORMAT is replaced \
+                         with the name of the scenario taken from the \
+                         i-th input file, counting from zero. For \
+                         example, \"{1}-{0}\" names the combination of \
+                         \"a\" and \"b\" \"b-a\"."))
+        .arg(Arg::with_name("shuffle")
+             .long("shuffle")
+             .takes_value(true)
+             .conflicts_with("group_by")
+             .min_values(0)
+             .max_values(1)
+             .value_name("SEED")
+             .help("Go through the scenario combinations in random \
+                    order.")
+             .long_help("Go through the scenario combinations in \
+                         random order. This helps to uncover COMMANDs \
+                         that depend on the order of execution. \
+                         Passing the same SEED gives the same order. \
+                         If no SEED is passed, a random one is chosen \
+                         and printed to stderr, so that the order can \
+                         be reproduced."))
+        .arg(Arg::with_name("sort_names")
+             .long("sort-names")
+             .conflicts_with_all(&["shuffle", "group_by"])
+             .help("Go through the scenario combinations sorted by \
+                    name.")
+             .long_help("Go through the scenario combinations sorted \
+                         alphabetically by their combined names instead \
+                         of in the order of the files. This gives \
+                         stable output for diffs. Because sorting \
+                         requires all combinations to be known, no \
+                         COMMAND is started before all of them have \
+                         been built."))
+        .arg(Arg::with_name("axis_order")
+             .long("axis-order")
+             .takes_value(true)
+             .conflicts_with("group_by")
+             .value_name("INDICES")
+             .help("Change which scenario file varies fastest.")
+             .long_help("Change the order in which the scenario files \
+                         are iterated over. INDICES is a \
+                         comma-separated list of the zero-based \
+                         positions of all scenario files, from the one \
+                         that varies slowest to the one that varies \
+                         fastest. By default, the last file varies \
+                         fastest, i.e. the order is \"0,1,...\". \
+                         Scenario names still follow the order of the \
+                         files on the command line."))
+        .arg(Arg::with_name("group_by")
+             .long("group-by")
+             .takes_value(true)
+             .value_name("INDEX")
+             .conflicts_with_all(&["axis_order", "shuffle"])
+             .help("Run all combinations that share a scenario of \
+                    file INDEX in one go.")
+             .long_help("Iterate over the scenario file at the \
+                         zero-based position INDEX slowest, so that all \
+                         combinations that share a scenario of this \
+                         file come one after another. Together with \
+                         --on-group-change, this allows setting up an \
+                         expensive resource once per scenario of this \
+                         file."))
+        .arg(Arg::with_name("continue_from")
+             .long("continue-from")
+             .takes_value(true)
+             .value_name("NAME")
+             .help("Skip all scenarios before the one called NAME.")
+             .long_help("Skip all scenario combinations before the one \
+                         called NAME and continue normally from there. \
+                         This allows resuming an interrupted run. \
+                         Together with --shuffle, pass the same SEED to \
+                         resume in the same order. It is an error if \
+                         no scenario is called NAME. Combinations that \
+                         cannot be merged are reported even before \
+                         NAME."))
+        .arg(Arg::with_name("only_first")
+             .long("only-first")
+             .takes_value(true)
+             .value_name("N")
+             .help("Only process the first N scenario combinations.")
+             .long_help("Only process the first N scenario combinations \
+                         that pass all filters. This is useful for a \
+                         quick smoke test of a large matrix. Together \
+                         with --shuffle, this processes a random sample \
+                         of N combinations."))
+        .arg(Arg::with_name("if_changed")
+             .long("if-changed")
+             .takes_value(true)
+             .value_name("STATEFILE")
+             .requires("command")
+             .help("Skip scenarios that have succeeded before unchanged.")
+             .long_help("Skip scenario combinations that have already \
+                         run successfully with the same name, the same \
+                         variables and the same command. The \
+                         fingerprints of successful runs are kept in \
+                         STATEFILE, which is created if it does not \
+                         exist. Failed scenarios are always run again."))
         .arg(Arg::with_name("keep_going")
              .short("k")
              .long("keep-going")
-             .requires("exec")
+             .requires("command")
              .help("Don't abort if a COMMAND fails.")
              .long_help("Don't abort if a COMMAND fails. The default \
                          is to cancel everything as soon as one job \
-                         has been found out to have failed."))
+                         has been found out to have failed. Note that \
+                         this only applies to jobs that exit with an \
+                         error or time out. If a job cannot be started \
+                         at all, e.g. because COMMAND does not exist, \
+                         everything is cancelled regardless."))
+        .arg(Arg::with_name("fail_fast_after")
+             .long("fail-fast-after")
+             .takes_value(true)
+             .requires("command")
+             .conflicts_with("keep_going")
+             .value_name("N")
+             .help("Abort once N COMMANDs have failed.")
+             .long_help("Like --keep-going, but abort once N COMMANDs \
+                         have failed. Passing 1 is the same as the \
+                         default behavior."))
         .arg(Arg::with_name("jobs")
              .short("j")
              .long("jobs")
@@ -188,7 +784,213 @@ pub fn get_app() -> clap::App<'static, 'static> {
              .help("The number of COMMANDs to execute in parallel.")
              .long_help("The number of COMMANDs to execute in \
                         parallel. If no number is passed, the detected \
-                        number of CPUs on this machine is used."))
+                        number of CPUs on this machine is used. A \
+                        percentage like \"50%\" means that share of \
+                        the detected CPUs, rounded up. Percentages \
+                        above 100% are allowed. Zero means that all \
+                        COMMANDs are started at once; beware that \
+                        this may exhaust your system's resources if \
+                        there are many combinations."))
+        .arg(Arg::with_name("summary")
+             .long("summary")
+             .requires("command")
+             .help("Print how many COMMANDs succeeded and failed.")
+             .long_help("Print a summary line once all COMMANDs have \
+                         finished, stating how many have been run and \
+                         how many of them succeeded and failed. Like \
+                         other information, the summary is printed to \
+                         stderr and suppressed by --quiet."))
+        .arg(Arg::with_name("ordered_output")
+             .long("ordered-output")
+             .requires("command")
+             .conflicts_with("out_dir")
+             .help("Print the output of COMMANDs in order.")
+             .long_help("Capture the output of each COMMAND and print \
+                         it in the order in which the scenarios were \
+                         given, even if the COMMANDs run in parallel. \
+                         The output of a COMMAND is printed only once \
+                         it has finished and all COMMANDs before it \
+                         have been printed. COMMANDs that have \
+                         finished but wait to be printed count \
+                         towards the limit set by --jobs."))
+        .arg(Arg::with_name("reap_in_order")
+             .long("reap-in-order")
+             .requires("command")
+             .help("Handle finished COMMANDs oldest first.")
+             .long_help("If several COMMANDs have finished by the time \
+                         scenarios checks on them, handle them in the \
+                         order in which they were started. This makes \
+                         messages about finished COMMANDs more \
+                         predictable with --jobs. Unlike \
+                         --ordered-output, no output is held back."))
+        .arg(Arg::with_name("max_name_length")
+             .long("max-name-length")
+             .takes_value(true)
+             .requires("command")
+             .value_name("N")
+             .help("Truncate names passed to COMMAND to N characters.")
+             .long_help("Truncate scenario names longer than N \
+                         characters before they replace '{}' in \
+                         COMMAND, are exported as SCENARIOS_NAME, or \
+                         name files in --out-dir. The end of a \
+                         truncated name is replaced with '~' and eight \
+                         hex digits of a hash of the whole name, so \
+                         truncated names stay distinct. N must be \
+                         greater than 9. Messages printed by scenarios \
+                         still use the full name."))
+        .arg(Arg::with_name("out_dir")
+             .long("out-dir")
+             .takes_value(true)
+             .requires("command")
+             .conflicts_with("ordered_output")
+             .value_name("DIR")
+             .help("Write the output of each COMMAND to files in DIR.")
+             .long_help("Write the standard output and standard error \
+                         of each COMMAND to the files DIR/NAME.out and \
+                         DIR/NAME.err. In NAME, all characters except \
+                         ASCII letters and digits are replaced with \
+                         underscores, like for --emit-envfiles. DIR is \
+                         created if it doesn't exist. Existing files \
+                         are overwritten. A COMMAND whose files would \
+                         have the same NAME as those of an earlier one \
+                         is not started."))
+        .arg(Arg::with_name("timeout")
+             .long("timeout")
+             .takes_value(true)
+             .requires("command")
+             .value_name("SECONDS")
+             .help("Kill each COMMAND after this many seconds.")
+             .long_help("Kill each COMMAND after this many seconds. \
+                         A COMMAND that is killed this way counts as \
+                         failed. A scenario may override this limit \
+                         by defining the variable SCENARIOS_TIMEOUT. \
+                         This variable is not passed on to COMMAND."))
+        .arg(Arg::with_name("abort_timeout")
+             .long("abort-timeout")
+             .takes_value(true)
+             .requires("command")
+             .value_name("SECONDS")
+             .help("Give COMMANDs this many seconds to clean up \
+                    before killing them.")
+             .long_help("Give COMMANDs this many seconds to clean up \
+                         before killing them. If a COMMAND runs into \
+                         its --timeout, it is first sent SIGTERM and \
+                         only killed if it is still running after \
+                         this time. Likewise, if scenarios is \
+                         interrupted, COMMANDs that don't exit within \
+                         this time are killed. By default, timed-out \
+                         COMMANDs are killed right away and \
+                         interrupted ones are waited for."))
+        .arg(Arg::with_name("separator")
+             .long("separator")
+             .takes_value(true)
+             .requires("command")
+             .value_name("STR")
+             .help("Print STR before the output of each COMMAND.")
+             .long_help("Print STR on a line of its own before the \
+                         output of each COMMAND. Any occurrence of the \
+                         placeholder (see --placeholder) is replaced \
+                         with the scenario name. When running several \
+                         jobs at once, combine this with \
+                         --ordered-output to keep each separator next \
+                         to the output it belongs to."))
+        .arg(Arg::with_name("enable_functions")
+             .long("enable-functions")
+             .requires("command")
+             .help("Expand $(uuid) and $(now) in variable values.")
+             .long_help("Expand calls to built-in functions in variable \
+                         values before passing them to COMMAND. \
+                         $(uuid) becomes a random UUID and $(now) \
+                         becomes the current Unix time in seconds. \
+                         Both are evaluated anew for each COMMAND. \
+                         Calling any other function is an error; other \
+                         text like $(echo hi) is left untouched."))
+        .arg(Arg::with_name("expand_command")
+             .long("expand-command")
+             .requires("command")
+             .help("Expand ${VAR} in COMMAND to the scenario's variables.")
+             .long_help("Replace each ${VAR} in the arguments of \
+                         COMMAND with the value of the variable VAR \
+                         in the current scenario. Inherited variables \
+                         are not looked up, so no shell is needed to \
+                         pass scenario values to COMMAND. Referring \
+                         to a variable that the scenario does not \
+                         define is an error, unless --allow-unset is \
+                         passed. Other text like $VAR is left \
+                         untouched."))
+        .arg(Arg::with_name("allow_unset")
+             .long("allow-unset")
+             .requires("expand_command")
+             .help("Let --expand-command expand unset variables to \
+                    nothing."))
+        .arg(Arg::with_name("strict_unused_vars")
+             .long("strict-unused-vars")
+             .requires("command")
+             .help("Warn if COMMAND ignores the scenario variables.")
+             .long_help("Together with --ignore-env, warn if the \
+                         scenarios define variables, but COMMAND \
+                         certainly doesn't use them. This is a \
+                         heuristic that only recognizes placeholder \
+                         commands like \"true\" and \":\". The \
+                         warning is printed at most once."))
+        .arg(Arg::with_name("warn_env_shadow")
+             .long("warn-env-shadow")
+             .requires("command")
+             .conflicts_with("ignore_env")
+             .help("Warn about variables that differ only in case from \
+                    inherited ones.")
+             .long_help("Print a warning for each variable of a \
+                         scenario whose name differs only in case from \
+                         an inherited environment variable, e.g. \
+                         \"path\" and \"PATH\". On Windows, such a \
+                         variable replaces the inherited one. This is \
+                         purely a diagnostic and never fails the run."))
+        .arg(Arg::with_name("max_total_time")
+             .long("max-total-time")
+             .takes_value(true)
+             .requires("command")
+             .value_name("SECONDS")
+             .help("Stop starting new COMMANDs after this many seconds.")
+             .long_help("Stop starting new COMMANDs after this many \
+                         seconds. COMMANDs that are already running are \
+                         allowed to finish. At the end, the number of \
+                         skipped scenarios is reported."))
+        .arg(Arg::with_name("on_success")
+             .long("on-success")
+             .takes_value(true)
+             .requires("command")
+             .value_name("CMD")
+             .help("Run CMD once after all COMMANDs have succeeded.")
+             .long_help("Run CMD once after all COMMANDs have \
+                         succeeded. CMD is passed to the shell and \
+                         inherits the environment of scenarios. The \
+                         variables SCENARIOS_SUCCEEDED and \
+                         SCENARIOS_FAILED contain the number of \
+                         scenarios that have succeeded and failed. If \
+                         CMD fails, scenarios fails as well."))
+        .arg(Arg::with_name("on_failure")
+             .long("on-failure")
+             .takes_value(true)
+             .requires("command")
+             .value_name("CMD")
+             .help("Run CMD once after any COMMAND has failed.")
+             .long_help("Like --on-success, but run CMD only if at \
+                         least one COMMAND has failed or could not be \
+                         started. CMD runs after all other COMMANDs \
+                         have finished."))
+        .arg(Arg::with_name("on_group_change")
+             .long("on-group-change")
+             .takes_value(true)
+             .requires_all(&["command", "group_by"])
+             .value_name("CMD")
+             .help("Run CMD before each group of --group-by.")
+             .long_help("Run the shell command CMD before the first \
+                         COMMAND of each group formed by --group-by. \
+                         CMD only starts after all COMMANDs of the \
+                         previous group have finished. The name of the \
+                         group's scenario is passed in the variable \
+                         SCENARIOS_GROUP. If CMD fails, no further \
+                         COMMANDs are started."))
 }
 
 
@@ -204,8 +1006,8 @@ pub fn print_long_help(app: clap::App) {
 }
 
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-static LONG_EXPLANATION: &'static str = "\
+#[rustfmt::skip]
+static LONG_EXPLANATION: &str = "\
 This program takes one or more scenario files. A scenario is named \
 set of environment variables to apply at the same time. A scenario \
 file contains a list of scenarios in the following format:
@@ -217,6 +1019,11 @@ file contains a list of scenarios in the following format:
     [Second scenario name]
     FIRST_VARIABLE = value
     SECOND_VARIABLE = value
+    !THIRD_VARIABLE
+
+A line starting with an exclamation mark removes the named variable \
+from the environment that COMMAND inherits. This has no effect if \
+--ignore-env is passed.
 
 If you pass several scenario files, all possible combinations that \
 take one scenario from each file are executed. For instance, assume \
@@ -257,6 +1064,15 @@ each scenario (unless --no-export-name is passed). This variable \
 contains the name of the current combination of scenarios. Strict \
 mode will prevent you from defining SCENARIOS_NAME yourself. With the \
 --lax option, your own definition will silently be overwritten.
+
+The exit code tells what kind of error occurred, if any:
+
+    0   success;
+    1   any other error;
+    2   invalid arguments or invalid scenario files;
+    3   no scenario files passed;
+    4   COMMAND failed for at least one scenario;
+    5   COMMAND could not be started at all.
 ";
 
 
@@ -291,6 +1107,21 @@ mod tests {
         assert!(get_matches(&[]).is_ok());
     }
 
+    #[test]
+    fn glob_inputs() {
+        let matches = get_matches(&["--glob-inputs", "*.ini"]).unwrap();
+        assert!(matches.is_present("glob_inputs"));
+        assert_eq!(&matches.values_vec_of("input"), &["*.ini"]);
+    }
+
+    #[test]
+    fn files_from() {
+        let matches = get_matches(&["--files-from", "list.txt"]).unwrap();
+        assert_eq!(matches.value_of("files_from"), Some("list.txt"));
+        assert!(matches.values_of("input").is_none());
+        assert!(get_matches(&["a.ini", "--files-from"]).is_err());
+    }
+
     #[test]
     fn choose() {
         let matches = get_matches(&["--choose", "a.ini", "b.ini"]).unwrap();
@@ -305,6 +1136,60 @@ mod tests {
         assert_eq!(&matches.values_vec_of("input"), &["a.ini", "c.ini"]);
     }
 
+    #[test]
+    fn tags() {
+        let args = ["--with-tag", "a", "--with-tag", "b", "--without-tag", "c", "x.ini"];
+        let matches = get_matches(&args).unwrap();
+        assert_eq!(matches.values_vec_of("with_tag"), &["a", "b"]);
+        assert_eq!(matches.values_vec_of("without_tag"), &["c"]);
+        assert_eq!(matches.values_vec_of("input"), &["x.ini"]);
+    }
+
+    #[test]
+    fn case_insensitive_names() {
+        let matches = get_matches(&["--case-insensitive-names", "a.ini"]).unwrap();
+        assert!(matches.is_present("case_insensitive_names"));
+        assert_eq!(&matches.values_vec_of("input"), &["a.ini"]);
+        assert!(!get_matches(&[]).unwrap().is_present("case_insensitive_names"));
+    }
+
+    #[test]
+    fn name_pattern() {
+        let matches = get_matches(&["--name-pattern", "test_*", "a.ini"]).unwrap();
+        assert_eq!(matches.value_of("name_pattern"), Some("test_*"));
+        assert_eq!(&matches.values_vec_of("input"), &["a.ini"]);
+        assert!(get_matches(&[]).unwrap().value_of("name_pattern").is_none());
+    }
+
+    #[test]
+    fn dedup_vars() {
+        let matches = get_matches(&["--dedup-vars", "a.ini"]).unwrap();
+        assert!(matches.is_present("dedup_vars"));
+        assert_eq!(&matches.values_vec_of("input"), &["a.ini"]);
+        assert!(!get_matches(&[]).unwrap().is_present("dedup_vars"));
+    }
+
+    #[test]
+    fn allow_file_values() {
+        let matches = get_matches(&["--allow-file-values", "a.ini"]).unwrap();
+        assert!(matches.is_present("allow_file_values"));
+        assert_eq!(&matches.values_vec_of("input"), &["a.ini"]);
+    }
+
+    #[test]
+    fn allow_non_utf8_values() {
+        let matches = get_matches(&["--allow-non-utf8-values", "a.ini"]).unwrap();
+        assert!(matches.is_present("allow_non_utf8_values"));
+        assert_eq!(&matches.values_vec_of("input"), &["a.ini"]);
+    }
+
+    #[test]
+    fn allow_inline_comments() {
+        let matches = get_matches(&["--allow-inline-comments", "a.ini"]).unwrap();
+        assert!(matches.is_present("allow_inline_comments"));
+        assert_eq!(&matches.values_vec_of("input"), &["a.ini"]);
+    }
+
     #[test]
     fn default_action() {
         let matches = get_matches(&[]).unwrap();
@@ -341,6 +1226,32 @@ mod tests {
         assert_eq!(matches.value_of("print0"), Some("<>"));
     }
 
+    #[test]
+    fn placeholder() {
+        let matches = get_matches(&["a.ini", "--placeholder", "%%", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("placeholder"), Some("%%"));
+        let matches = get_matches(&["a.ini", "--placeholder", "%%", "--print", "%%"]).unwrap();
+        assert_eq!(matches.value_of("placeholder"), Some("%%"));
+    }
+
+    #[test]
+    fn print_file() {
+        let matches = get_matches(&["a.ini", "--print-file", "tpl.txt"]).unwrap();
+        assert_eq!(matches.value_of("print_file"), Some("tpl.txt"));
+        assert!(get_matches(&["a.ini", "--print-file", "tpl.txt", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--print-file", "tpl.txt", "--print0"]).is_err());
+    }
+
+    #[test]
+    fn terminator() {
+        let matches = get_matches(&["a.ini", "--terminator", "\\t"]).unwrap();
+        assert_eq!(matches.value_of("terminator"), Some("\\t"));
+        let matches = get_matches(&["a.ini", "--print", "{}", "--terminator", ";"]).unwrap();
+        assert_eq!(matches.value_of("terminator"), Some(";"));
+        assert!(get_matches(&["a.ini", "--terminator", ";", "--print0"]).is_err());
+        assert!(get_matches(&["a.ini", "--terminator", ";", "--exec", "echo"]).is_err());
+    }
+
     #[test]
     fn print_with_args_prefix_bad() {
         assert!(get_matches(&["--print", "a.ini", "b.ini"]).is_err());
@@ -390,6 +1301,27 @@ mod tests {
         assert_eq!(matches.values_vec_of("exec"), &["echo"]);
     }
 
+    #[test]
+    fn exec_file() {
+        let matches = get_matches(&["a.ini", "--exec-file", "cmd.txt", "--keep-going"]).unwrap();
+        assert_eq!(matches.value_of("exec_file"), Some("cmd.txt"));
+        assert!(matches.is_present("command"));
+        assert!(get_matches(&["a.ini", "--exec-file", "cmd.txt", "--exec", "echo"]).is_err());
+        assert!(get_matches(&["a.ini", "--exec-file", "cmd.txt", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--exec-file", "cmd.txt", "--print0"]).is_err());
+        assert!(get_matches(&["a.ini", "--exec-file", "cmd.txt", "--count"]).is_err());
+    }
+
+    #[test]
+    fn exec_stdin() {
+        let matches = get_matches(&["a.ini", "--exec-stdin", "--keep-going"]).unwrap();
+        assert!(matches.is_present("exec_stdin"));
+        assert!(matches.is_present("command"));
+        assert!(get_matches(&["a.ini", "--exec-stdin", "--exec-file", "cmd.txt"]).is_err());
+        assert!(get_matches(&["a.ini", "--exec-stdin", "--exec", "echo"]).is_err());
+        assert!(get_matches(&["a.ini", "--exec-stdin", "--print"]).is_err());
+    }
+
     #[test]
     fn print_print0_exec_conflicts() {
         assert!(get_matches(&["a.ini", "--print", "--print0"]).is_err());
@@ -398,12 +1330,312 @@ mod tests {
         assert!(get_matches(&["a.ini", "--strict", "--lax"]).is_err());
     }
 
+    #[test]
+    fn override_precedence() {
+        let matches = get_matches(&["a.ini", "--lax", "--override", "first"]).unwrap();
+        assert_eq!(matches.value_of("override"), Some("first"));
+        assert!(get_matches(&["a.ini", "--override", "first"]).is_err());
+        assert!(get_matches(&["a.ini", "--lax", "--override", "middle"]).is_err());
+    }
+
+    #[test]
+    fn list_scenarios_conflicts() {
+        assert!(get_matches(&["a.ini", "--list-scenarios"]).is_ok());
+        assert!(get_matches(&["a.ini", "--list-scenarios", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--list-scenarios", "--print0"]).is_err());
+        assert!(get_matches(&["a.ini", "--list-scenarios", "--exec", "echo"]).is_err());
+    }
+
+    #[test]
+    fn color() {
+        assert_eq!(get_matches(&[]).unwrap().value_of("color"), Some("auto"));
+        let matches = get_matches(&["--color", "never"]).unwrap();
+        assert_eq!(matches.value_of("color"), Some("never"));
+        assert!(get_matches(&["--color", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn log_format() {
+        assert_eq!(get_matches(&[]).unwrap().value_of("log_format"), Some("human"));
+        let matches = get_matches(&["--log-format", "json", "a.ini"]).unwrap();
+        assert_eq!(matches.value_of("log_format"), Some("json"));
+        assert_eq!(matches.values_vec_of("input"), &["a.ini"]);
+        assert!(get_matches(&["--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn base() {
+        let matches = get_matches(&["--base", "base.ini", "a.ini", "b.ini"]).unwrap();
+        assert_eq!(matches.value_of("base"), Some("base.ini"));
+        assert_eq!(matches.values_vec_of("input"), &["a.ini", "b.ini"]);
+        assert!(get_matches(&["--base"]).is_err());
+    }
+
+    #[test]
+    fn fail_on_empty() {
+        let matches = get_matches(&["--fail-on-empty", "a.ini"]).unwrap();
+        assert!(matches.is_present("fail_on_empty"));
+        assert_eq!(matches.values_vec_of("input"), &["a.ini"]);
+        assert!(!get_matches(&[]).unwrap().is_present("fail_on_empty"));
+    }
+
+    #[test]
+    fn scenario() {
+        let matches = get_matches(&["--scenario", "x:A=1", "a.ini", "--scenario", "y"]).unwrap();
+        assert_eq!(matches.values_vec_of("scenario"), &["x:A=1", "y"]);
+        assert_eq!(matches.values_vec_of("input"), &["a.ini"]);
+        let matches = get_matches(&["--scenario", "x:A=1"]).unwrap();
+        assert!(matches.values_of("input").is_none());
+    }
+
+    #[test]
+    fn tsv() {
+        let matches = get_matches(&["--tsv", "a.tsv", "b.ini", "--tsv", "c.tsv"]).unwrap();
+        assert_eq!(matches.values_vec_of("tsv"), &["a.tsv", "c.tsv"]);
+        assert_eq!(matches.values_vec_of("input"), &["b.ini"]);
+        let matches = get_matches(&["--tsv", "a.tsv"]).unwrap();
+        assert!(matches.values_of("input").is_none());
+    }
+
+    #[test]
+    fn stdin_format() {
+        assert_eq!(get_matches(&[]).unwrap().value_of("stdin_format"), Some("ini"));
+        let matches = get_matches(&["--stdin-format", "nul", "-"]).unwrap();
+        assert_eq!(matches.value_of("stdin_format"), Some("nul"));
+        let matches = get_matches(&["--stdin-format", "tsv", "-"]).unwrap();
+        assert_eq!(matches.value_of("stdin_format"), Some("tsv"));
+        assert!(get_matches(&["--stdin-format", "csv"]).is_err());
+    }
+
+    #[test]
+    fn timeout() {
+        assert!(get_matches(&["--timeout", "10", "a.ini"]).is_err());
+        let matches = get_matches(&["--timeout", "10", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("timeout"), Some("10"));
+    }
+
+    #[test]
+    fn abort_timeout() {
+        assert!(get_matches(&["--abort-timeout", "5", "a.ini"]).is_err());
+        let matches = get_matches(&["--abort-timeout", "5", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("abort_timeout"), Some("5"));
+    }
+
+    #[test]
+    fn out_dir() {
+        assert!(get_matches(&["--out-dir", "logs", "a.ini"]).is_err());
+        let matches = get_matches(&["--out-dir", "logs", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("out_dir"), Some("logs"));
+        let args = ["--out-dir", "logs", "--ordered-output", "a.ini", "--exec", "echo"];
+        assert!(get_matches(&args).is_err());
+    }
+
+    #[test]
+    fn separator() {
+        assert!(get_matches(&["--separator", "== {} ==", "a.ini"]).is_err());
+        let matches = get_matches(&["--separator", "== {} ==", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("separator"), Some("== {} =="));
+    }
+
+    #[test]
+    fn verbose() {
+        assert!(get_matches(&["-v", "a.ini"]).is_err());
+        assert!(get_matches(&["-v", "-q", "a.ini", "--exec", "echo"]).is_err());
+        let matches = get_matches(&["--verbose", "a.ini", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("verbose"));
+    }
+
+    #[test]
+    fn max_name_length() {
+        let matches = get_matches(&["--max-name-length=20", "a.ini", "--exec", "cmd"]).unwrap();
+        assert_eq!(matches.value_of("max_name_length"), Some("20"));
+        assert!(get_matches(&["--max-name-length=20", "a.ini"]).is_err());
+    }
+
+    #[test]
+    fn warn_env_shadow() {
+        let matches = get_matches(&["--warn-env-shadow", "a.ini", "--exec", "cmd"]).unwrap();
+        assert!(matches.is_present("warn_env_shadow"));
+        assert!(get_matches(&["--warn-env-shadow", "a.ini"]).is_err());
+    }
+
+    #[test]
+    fn expand_command() {
+        assert!(get_matches(&["--expand-command", "a.ini"]).is_err());
+        let matches = get_matches(&["--expand-command", "a.ini", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("expand_command"));
+        assert!(get_matches(&["--allow-unset", "a.ini", "--exec", "echo"]).is_err());
+        let args = ["--expand-command", "--allow-unset", "a.ini", "--exec", "echo"];
+        assert!(get_matches(&args).unwrap().is_present("allow_unset"));
+    }
+
+    #[test]
+    fn strict_unused_vars() {
+        assert!(get_matches(&["--strict-unused-vars", "a.ini"]).is_err());
+        let matches = get_matches(&["--strict-unused-vars", "a.ini", "--exec", "true"]).unwrap();
+        assert!(matches.is_present("strict_unused_vars"));
+    }
+
+    #[test]
+    fn enable_functions() {
+        assert!(get_matches(&["--enable-functions", "a.ini"]).is_err());
+        let matches = get_matches(&["--enable-functions", "a.ini", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("enable_functions"));
+    }
+
+    #[test]
+    fn max_total_time() {
+        assert!(get_matches(&["--max-total-time", "10", "a.ini"]).is_err());
+        let matches = get_matches(&["--max-total-time", "10", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("max_total_time"), Some("10"));
+    }
+
+    #[test]
+    fn explain_conflicts() {
+        assert!(get_matches(&["a.ini", "--explain"]).is_ok());
+        assert!(get_matches(&["a.ini", "--explain", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--explain", "--exec", "echo"]).is_err());
+        assert!(get_matches(&["a.ini", "--explain", "--count"]).is_err());
+    }
+
+    #[test]
+    fn count_conflicts() {
+        assert!(get_matches(&["a.ini", "--count"]).is_ok());
+        assert!(get_matches(&["a.ini", "--count", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--count", "--print0"]).is_err());
+        assert!(get_matches(&["a.ini", "--count", "--exec", "echo"]).is_err());
+        assert!(get_matches(&["a.ini", "--count", "--list-scenarios"]).is_err());
+    }
+
+    #[test]
+    fn print_vars() {
+        assert!(get_matches(&["a.ini", "--print-vars"]).unwrap().is_present("print_vars"));
+        assert!(get_matches(&["a.ini", "--print-vars", "--terminator", "--"]).is_ok());
+        assert!(get_matches(&["a.ini", "--print-vars", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--print-vars", "--exec", "echo"]).is_err());
+        assert!(get_matches(&["a.ini", "--print-vars", "--table"]).is_err());
+    }
+
+    #[test]
+    fn check_conflicts() {
+        assert!(get_matches(&["a.ini", "--check"]).is_ok());
+        assert!(get_matches(&["a.ini", "--check", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--check", "--exec", "echo"]).is_err());
+        assert!(get_matches(&["a.ini", "--check", "--count"]).is_err());
+        assert!(get_matches(&["a.ini", "--check", "--list-scenarios"]).is_err());
+    }
+
+    #[test]
+    fn table_conflicts() {
+        assert!(get_matches(&["a.ini", "--table"]).is_ok());
+        assert!(get_matches(&["a.ini", "--table", "--print"]).is_err());
+        assert!(get_matches(&["a.ini", "--table", "--exec", "echo"]).is_err());
+        assert!(get_matches(&["a.ini", "--table", "--count"]).is_err());
+        assert!(get_matches(&["a.ini", "--table", "--explain"]).is_err());
+    }
+
+    #[test]
+    fn emit_envfiles() {
+        let matches = get_matches(&["a.ini", "--emit-envfiles", "out"]).unwrap();
+        assert_eq!(matches.value_of("emit_envfiles"), Some("out"));
+        assert!(!matches.is_present("force"));
+        assert!(get_matches(&["a.ini", "--emit-envfiles", "out", "--force"]).is_ok());
+        assert!(get_matches(&["a.ini", "--force"]).is_err());
+        assert!(get_matches(&["a.ini", "--emit-envfiles", "out", "--count"]).is_err());
+        assert!(get_matches(&["a.ini", "--emit-envfiles", "out", "--exec", "echo"]).is_err());
+    }
+
+    #[test]
+    fn sort_names() {
+        assert!(get_matches(&["--sort-names", "a.ini"]).unwrap().is_present("sort_names"));
+        assert!(get_matches(&["--sort-names", "--shuffle=42", "a.ini"]).is_err());
+        assert!(get_matches(&["--sort-names", "--group-by", "1", "a.ini"]).is_err());
+    }
+
+    #[test]
+    fn shuffle() {
+        let matches = get_matches(&["--shuffle", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("shuffle"));
+        assert_eq!(matches.value_of("shuffle"), None);
+        let matches = get_matches(&["--shuffle=42", "a.ini"]).unwrap();
+        assert_eq!(matches.value_of("shuffle"), Some("42"));
+        assert_eq!(matches.values_vec_of("input"), &["a.ini"]);
+        assert!(!get_matches(&[]).unwrap().is_present("shuffle"));
+    }
+
+    #[test]
+    fn axis_order() {
+        let matches = get_matches(&["--axis-order", "1,0", "a.ini", "b.ini"]).unwrap();
+        assert_eq!(matches.value_of("axis_order"), Some("1,0"));
+        assert!(get_matches(&["a.ini", "--axis-order"]).is_err());
+    }
+
+    #[test]
+    fn group_by() {
+        let matches = get_matches(&["--group-by", "1", "a.ini", "b.ini"]).unwrap();
+        assert_eq!(matches.value_of("group_by"), Some("1"));
+        assert!(get_matches(&["--group-by", "1", "--axis-order", "1,0", "a.ini"]).is_err());
+        assert!(get_matches(&["a.ini", "--on-group-change", "cmd", "--exec", "cmd"]).is_err());
+        let args = ["--group-by=0", "--on-group-change", "cmd", "a.ini", "--exec", "cmd"];
+        assert!(get_matches(&args).is_ok());
+    }
+
+    #[test]
+    fn continue_from() {
+        let matches = get_matches(&["--continue-from", "A2, B1", "a.ini"]).unwrap();
+        assert_eq!(matches.value_of("continue_from"), Some("A2, B1"));
+        assert!(get_matches(&["a.ini", "--continue-from"]).is_err());
+    }
+
+    #[test]
+    fn only_first() {
+        let matches = get_matches(&["a.ini", "--only-first", "10"]).unwrap();
+        assert_eq!(matches.value_of("only_first"), Some("10"));
+        assert!(get_matches(&["a.ini", "--only-first"]).is_err());
+    }
+
+    #[test]
+    fn fail_fast_after() {
+        assert!(get_matches(&["--fail-fast-after", "2", "a.ini"]).is_err());
+        let matches = get_matches(&["--fail-fast-after", "2", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("fail_fast_after"), Some("2"));
+        let args = ["--fail-fast-after", "2", "--keep-going", "a.ini", "--exec", "echo"];
+        assert!(get_matches(&args).is_err());
+    }
+
     #[test]
     fn delimiter() {
         let matches = get_matches(&["--delimiter", "/", "a.ini"]).unwrap();
         assert_eq!(matches.value_of("delimiter"), Some("/"));
     }
 
+    #[test]
+    fn delimiter_multiple() {
+        let matches = get_matches(&["-d", ":", "--delimiter", "/", "a.ini", "b.ini"]).unwrap();
+        assert_eq!(matches.values_vec_of("delimiter"), &[":", "/"]);
+        assert_eq!(matches.values_vec_of("input"), &["a.ini", "b.ini"]);
+    }
+
+    #[test]
+    fn if_changed() {
+        let matches = get_matches(&["--if-changed", "state", "a.ini", "--exec", "cmd"]).unwrap();
+        assert_eq!(matches.value_of("if_changed"), Some("state"));
+        assert!(get_matches(&["--if-changed", "state", "a.ini"]).is_err());
+    }
+
+    #[test]
+    fn name_format() {
+        let matches = get_matches(&["--name-format", "{1}/{0}", "a.ini", "b.ini"]).unwrap();
+        assert_eq!(matches.value_of("name_format"), Some("{1}/{0}"));
+        assert!(get_matches(&["--name-format", "{0}", "-d", "/", "a.ini"]).is_err());
+    }
+
+    #[test]
+    fn escape_names() {
+        assert!(get_matches(&["--escape-names", "a.ini"]).unwrap().is_present("escape_names"));
+        assert!(get_matches(&["--escape-names", "--name-format", "{0}", "a.ini"]).is_err());
+    }
+
     #[test]
     fn delimiter_arg_required() {
         assert!(get_matches(&["--delimiter"]).is_err());
@@ -426,6 +1658,53 @@ mod tests {
         assert!(get_matches(&["--no-export-name", "--exec", "echo"]).is_ok());
     }
 
+    #[test]
+    fn name_transform() {
+        let matches = get_matches(&["--name-transform", "slug", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("name_transform"), Some("slug"));
+        assert!(get_matches(&["--name-transform", "slug"]).is_err());
+        assert!(get_matches(&["--name-transform", "camel", "--exec", "echo"]).is_err());
+    }
+
+    #[test]
+    fn export_index() {
+        let matches = get_matches(&["--export-index", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("export_index"));
+        assert!(get_matches(&["--export-index"]).is_err());
+    }
+
+    #[test]
+    fn env_passthrough() {
+        let args = ["--ignore-env", "--env-passthrough", "HOME,PATH", "--exec", "echo"];
+        let matches = get_matches(&args).unwrap();
+        assert_eq!(matches.value_of("env_passthrough"), Some("HOME,PATH"));
+        assert!(get_matches(&["--env-passthrough", "HOME", "--exec", "echo"]).is_err());
+    }
+
+    #[test]
+    fn env_file() {
+        let matches = get_matches(&["--env-file", "base.env", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("env_file"), Some("base.env"));
+        assert!(get_matches(&["--env-file", "base.env"]).is_err());
+    }
+
+    #[test]
+    fn append_separator() {
+        let matches = get_matches(&["--append-separator", ",", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("append_separator"), Some(","));
+        assert!(get_matches(&["--append-separator", ","]).is_err());
+    }
+
+    #[test]
+    fn hooks() {
+        let args = ["--on-success", "true", "--on-failure", "false", "--exec", "echo"];
+        let matches = get_matches(&args).unwrap();
+        assert_eq!(matches.value_of("on_success"), Some("true"));
+        assert_eq!(matches.value_of("on_failure"), Some("false"));
+        assert!(get_matches(&["--on-success", "true"]).is_err());
+        assert!(get_matches(&["--on-failure", "false"]).is_err());
+    }
+
     #[test]
     fn jobs() {
         let matches = get_matches(&["--jobs", "2", "a.ini", "b.ini", "--exec", "echo"]).unwrap();
@@ -435,6 +1714,20 @@ mod tests {
         assert_eq!(matches.values_vec_of("input"), &["a.ini", "b.ini"]);
     }
 
+    #[test]
+    fn jobs_unbounded() {
+        let matches = get_matches(&["--jobs", "0", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("jobs"), Some("0"));
+        assert_eq!(matches.values_vec_of("input"), &["a.ini"]);
+    }
+
+    #[test]
+    fn jobs_percentage() {
+        let matches = get_matches(&["--jobs", "50%", "a.ini", "--exec", "echo"]).unwrap();
+        assert_eq!(matches.value_of("jobs"), Some("50%"));
+        assert_eq!(matches.values_vec_of("input"), &["a.ini"]);
+    }
+
     #[test]
     fn jobs_default() {
         let matches = get_matches(&[]).unwrap();
@@ -443,6 +1736,27 @@ mod tests {
         assert_eq!(matches.value_of("jobs"), Some("auto"));
     }
 
+    #[test]
+    fn summary() {
+        let matches = get_matches(&["--summary", "a.ini", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("summary"));
+        assert!(get_matches(&["--summary", "a.ini"]).is_err());
+    }
+
+    #[test]
+    fn ordered_output() {
+        let matches = get_matches(&["--ordered-output", "a.ini", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("ordered_output"));
+        assert!(get_matches(&["--ordered-output", "a.ini"]).is_err());
+    }
+
+    #[test]
+    fn reap_in_order() {
+        let matches = get_matches(&["--reap-in-order", "a.ini", "--exec", "echo"]).unwrap();
+        assert!(matches.is_present("reap_in_order"));
+        assert!(get_matches(&["--reap-in-order", "a.ini"]).is_err());
+    }
+
     #[test]
     fn jobs_no_arg_required() {
         let matches = get_matches(&["--jobs", "--exec", "echo"]).unwrap();
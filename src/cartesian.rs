@@ -16,6 +16,9 @@
 //! Provides the function `cartesian::product()`.
 //!
 //! The name has been chosen entirely for this combination.
+//!
+//! If the collections are not all of the same type,
+//! `cartesian::product_dyn()` takes trait objects instead.
 
 
 /// Iterates over the Cartesian product of a list of containers.
@@ -81,11 +84,115 @@ where
 {
     // We start with fresh iterators and a `next_item` full of `None`s.
     let mut iterators = collections.iter().map(<&C>::into_iter).collect::<Vec<_>>();
-    let next_item = iterators.iter_mut().map(Iterator::next).collect();
+    let next_item: Option<Vec<_>> = iterators.iter_mut().map(Iterator::next).collect();
+    // The length is simply the product of all sizes -- unless one of
+    // the collections is empty.
+    let remaining = if next_item.is_some() {
+        collections
+            .iter()
+            .map(|c| SizeHint::from(&c.into_iter()))
+            .product()
+    } else {
+        SizeHint::ZERO
+    };
     Product {
         collections,
         iterators,
         next_item,
+        remaining,
+    }
+}
+
+
+/// Like [`product()`], but for collections of different types.
+///
+/// Each collection is passed as a [`Reiterable`] trait object, so
+/// e.g. vectors and arrays may be mixed freely, as long as their
+/// items have the same type. The combinations are built with the same
+/// algorithm as in [`product()`].
+///
+/// This flexibility has a price: every step of a sub-iterator goes
+/// through dynamic dispatch, and every restart of a sub-iterator
+/// allocates a new boxed iterator. The returned iterator also does
+/// not implement `ExactSizeIterator`, though `size_hint()` still
+/// gives exact bounds for the collections provided here. Prefer
+/// [`product()`] if all collections have the same type.
+///
+/// # Example
+///
+/// ```rust
+/// extern crate scenarios;
+///
+/// use scenarios::cartesian::{self, Reiterable};
+///
+/// let vector = vec![1, 2];
+/// let array = [11, 22];
+/// let collections: [&dyn Reiterable<Item = i32>; 2] = [&vector, &array];
+/// let mut combinations = cartesian::product_dyn(&collections);
+/// assert_eq!(combinations.next(), Some(vec![&1, &11]));
+/// assert_eq!(combinations.next(), Some(vec![&1, &22]));
+/// assert_eq!(combinations.next(), Some(vec![&2, &11]));
+/// assert_eq!(combinations.next(), Some(vec![&2, &22]));
+/// assert_eq!(combinations.next(), None);
+/// ```
+///
+/// [`product()`]: ./fn.product.html
+/// [`Reiterable`]: ./trait.Reiterable.html
+pub fn product_dyn<'a, T: 'a>(
+    collections: &'a [&'a dyn Reiterable<Item = T>],
+) -> Product<'a, &'a dyn Reiterable<Item = T>, T> {
+    product(collections)
+}
+
+
+/// A collection that can be iterated over several times.
+///
+/// This is the trait behind [`product_dyn()`]. It is implemented for
+/// slice references, arrays, and vectors.
+///
+/// [`product_dyn()`]: ./fn.product_dyn.html
+pub trait Reiterable {
+    /// The type of the items in the collection.
+    type Item;
+
+    /// Returns a fresh iterator over references to all items.
+    fn reiter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Self::Item> + 'a>;
+}
+
+impl<T> Reiterable for &[T] {
+    type Item = T;
+
+    fn reiter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+        Box::new(self.iter())
+    }
+}
+
+impl<T, const N: usize> Reiterable for [T; N] {
+    type Item = T;
+
+    fn reiter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+        Box::new(self.iter())
+    }
+}
+
+impl<T> Reiterable for Vec<T> {
+    type Item = T;
+
+    fn reiter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+        Box::new(self.iter())
+    }
+}
+
+/// Lets [`product()`] iterate over the collections of [`product_dyn()`].
+///
+/// [`product()`]: ./fn.product.html
+/// [`product_dyn()`]: ./fn.product_dyn.html
+impl<'a, 'b: 'a, T: 'a> IntoIterator for &'a &'b (dyn Reiterable<Item = T> + 'b) {
+    type Item = &'a T;
+    type IntoIter = Box<dyn Iterator<Item = &'a T> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.reiter()
     }
 }
 
@@ -103,6 +210,13 @@ where
     iterators: Vec<<&'a C as IntoIterator>::IntoIter>,
     /// The next item to yield.
     next_item: Option<Vec<&'a T>>,
+    /// Bounds on the number of remaining items.
+    ///
+    /// This is calculated once in [`product()`] and decremented with
+    /// each yielded item, so that `len()` runs in constant time.
+    ///
+    /// [`product()`]: ./fn.product.html
+    remaining: SizeHint,
 }
 
 impl<'a, C, T> Iterator for Product<'a, C, T>
@@ -113,35 +227,20 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         let result = self.next_item.clone();
+        if result.is_some() {
+            self.remaining.decrement();
+        }
         self.advance();
         result
     }
 
-    /// Calculate bounds on the number of remaining elements.
-    ///
-    /// This is calculated the same way as [`Product::len()`], but uses
-    /// a helper type to deal with the return type of `size_hint()`.
-    /// See there for information on why the used formula is corrected.
+    /// Returns bounds on the number of remaining elements.
     ///
-    /// [`Product::len()`]: #method.len
+    /// The bounds are the product of the size hints of all
+    /// collections, minus the number of items yielded so far. Once the
+    /// iterator is exhausted, they are zero.
     fn size_hint(&self) -> (usize, Option<usize>) {
-        if self.next_item.is_none() {
-            return (0, Some(0));
-        }
-        let SizeHint(lower, upper) = SizeHint(1, Some(1))
-            + self
-                .iterators
-                .iter()
-                .enumerate()
-                .map(|(i, iterator)| {
-                    SizeHint::from(iterator)
-                        * self.collections[i + 1..]
-                            .iter()
-                            .map(|c| SizeHint::from(&c.into_iter()))
-                            .product()
-                })
-                .sum();
-        (lower, upper)
+        self.remaining.into_inner()
     }
 }
 
@@ -150,50 +249,14 @@ where
     &'a C: IntoIterator<Item = &'a T>,
     <&'a C as IntoIterator>::IntoIter: ExactSizeIterator,
 {
-    /// Calculates the exact number of remaining elements.
-    ///
-    /// The length consists of the following contributions:
-    ///
-    /// - 1 for the `next_item` to be yielded;
-    /// - `X` for each currently active iterator, where X is the
-    ///   product of the iterators length and the sizes of all
-    ///   *collections* to the right of it in the product.
-    ///
-    /// Example
-    /// -------
-    ///
-    /// Assume the Cartesian product `[1, 2, 3]×[1, 2]×[1, 2, 3]`. Upon
-    /// construction, the `Product` type creates three iterators `A`,
-    /// `B`, and `C` ­– one iterator for each array. It also extracts
-    /// one item from each to form `next_item`. Hence, `next_item`
-    /// contributes `1` to the total length. The three iterators
-    /// contribute as follows:
+    /// Returns the exact number of remaining elements.
     ///
-    /// - A: 2 items left × collection of size 2 × collection of size
-    ///   3 = 12;
-    /// - B: 1 item left × collection of size 3 = 3;
-    /// - C: 2 items left = 2.
+    /// Because all sub-iterators have an exact size, the lower bound
+    /// of [`size_hint()`] is exact as well. This takes constant time.
     ///
-    /// Thus, we end up with a total length of `1+12+3+2=18`. This is
-    /// the same length we get when multiplying the size of all passed
-    /// collections. (`3*2*3=18`) However, our (complicated) formula
-    /// also works when the iterator has already yielded some elements.
+    /// [`size_hint()`]: #method.size_hint
     fn len(&self) -> usize {
-        if self.next_item.is_none() {
-            return 0;
-        }
-        1 + self
-            .iterators
-            .iter()
-            .enumerate()
-            .map(|(i, iterator)| {
-                iterator.len()
-                    * self.collections[i + 1..]
-                        .iter()
-                        .map(|c| c.into_iter().len())
-                        .product::<usize>()
-            })
-            .sum::<usize>()
+        self.remaining.0
     }
 }
 
@@ -222,6 +285,7 @@ where
     /// scheme can break in two ways.
     /// 1. The very first iterator (`i==0`) is exhausted.
     /// 2. A freshly restarted iterator is empty. (should never happen!)
+    ///
     /// In both cases, we want to exhaust `self` immediately. We do so
     /// by breaking out of the loop, falling through to the very last
     /// line, and manually set `self.next_item` to `None`.
@@ -267,17 +331,28 @@ where
         }
         // Exhaust this iterator if the above loop `break`s.
         self.next_item = None;
+        self.remaining = SizeHint::ZERO;
     }
 }
 
 
-#[derive(Debug)]
+/// Helper type to calculate with the return type of `size_hint()`.
+#[derive(Clone, Copy, Debug)]
 struct SizeHint(usize, Option<usize>);
 
 impl SizeHint {
+    /// The size hint of an empty iterator.
+    const ZERO: SizeHint = SizeHint(0, Some(0));
+
     fn into_inner(self) -> (usize, Option<usize>) {
         (self.0, self.1)
     }
+
+    /// Accounts for one item having been yielded.
+    fn decrement(&mut self) {
+        self.0 = self.0.saturating_sub(1);
+        self.1 = self.1.map(|upper| upper.saturating_sub(1));
+    }
 }
 
 impl<'a, I: Iterator> From<&'a I> for SizeHint {
@@ -287,19 +362,6 @@ impl<'a, I: Iterator> From<&'a I> for SizeHint {
     }
 }
 
-impl ::std::ops::Add for SizeHint {
-    type Output = Self;
-
-    fn add(self, other: Self) -> Self {
-        let lower = self.0 + other.0;
-        let upper = match (self.1, other.1) {
-            (Some(left), Some(right)) => Some(left + right),
-            _ => None,
-        };
-        SizeHint(lower, upper)
-    }
-}
-
 impl ::std::ops::Mul for SizeHint {
     type Output = Self;
 
@@ -313,12 +375,6 @@ impl ::std::ops::Mul for SizeHint {
     }
 }
 
-impl ::std::iter::Sum for SizeHint {
-    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
-        iter.fold(SizeHint(0, Some(0)), |acc, x| acc + x)
-    }
-}
-
 impl ::std::iter::Product for SizeHint {
     fn product<I: Iterator<Item = Self>>(iter: I) -> Self {
         iter.fold(SizeHint(1, Some(1)), |acc, x| acc * x)
@@ -333,7 +389,7 @@ mod tests {
 
         /// Asserts that the `len(V1×V2×...VN) ==
         /// len(V1)×len(V2)×...len(VN)`.
-        fn assert_length<T>(vectors: &Vec<Vec<T>>) {
+        fn assert_length<T>(vectors: &[Vec<T>]) {
             let expected_len = vectors.iter().map(Vec::len).product::<usize>();
             let p = cartesian::product(vectors);
             let (lower, upper) = p.size_hint();
@@ -357,9 +413,23 @@ mod tests {
             assert_length(&vectors);
         }
 
+        #[test]
+        fn test_length_while_iterating() {
+            let vectors = vec![vec![1, 2, 3], vec![1, 2], vec![1, 2, 3]];
+            let mut p = cartesian::product(&vectors);
+            for remaining in (0..18).rev() {
+                assert!(p.next().is_some());
+                assert_eq!(p.len(), remaining);
+                assert_eq!(p.size_hint(), (remaining, Some(remaining)));
+            }
+            assert!(p.next().is_none());
+            assert_eq!(p.len(), 0);
+        }
+
         #[test]
         fn test_empty_vector() {
             let one_is_empty = [vec![0; 3], vec![0; 3], vec![0; 0]];
+            assert_eq!(cartesian::product(&one_is_empty).len(), 0);
             let empty_product: Vec<_> = cartesian::product(&one_is_empty).collect();
             assert_eq!(empty_product.len(), 0);
         }
@@ -368,7 +438,9 @@ mod tests {
         fn test_nullary_product() {
             let empty: [[u32; 1]; 0] = [];
             let mut nullary_product = cartesian::product(&empty);
+            assert_eq!(nullary_product.len(), 1);
             assert_eq!(nullary_product.next(), Some(Vec::new()));
+            assert_eq!(nullary_product.len(), 0);
             assert_eq!(nullary_product.next(), None);
         }
     }
@@ -409,6 +481,24 @@ mod tests {
             assert_eq!(expected, actual);
         }
 
+        #[test]
+        fn test_mixed_collections() {
+            use cartesian::Reiterable;
+
+            let vector = vec![0, 4, 8, 12];
+            let array = [0, 1, 2, 3];
+            let slice: &[u32] = &[0, 16, 32, 48];
+            let collections: [&dyn Reiterable<Item = u32>; 3] = [&slice, &vector, &array];
+            let combinations = cartesian::product_dyn(&collections);
+            assert_eq!(combinations.size_hint(), (64, Some(64)));
+            let expected: Vec<u32> = (0..64).collect();
+            let actual: Vec<u32> = combinations
+                .map(Vec::into_iter)
+                .map(Iterator::sum)
+                .collect();
+            assert_eq!(expected, actual);
+        }
+
         #[test]
         fn test_slices() {
             let bits: [[u8; 2]; 4] = [[0, 8], [0, 4], [0, 2], [0, 1]];
@@ -14,16 +14,26 @@
 
 
 use std::{
+    env,
     ffi::OsStr,
-    io, mem,
-    process::{Command, ExitStatus},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom},
+    mem,
+    process::{self, Command, ExitStatus},
+    time::Duration,
 };
 
 use failure::{Error, ResultExt};
 use futures::{Async, Future, Poll};
-use tokio_core::reactor::Handle;
+#[cfg(unix)]
+use libc;
+use libc::c_int;
+use tokio_core::reactor::{Handle, Timeout};
 use tokio_process::{Child, CommandExt};
 
+use super::signals::SIGTERM;
+
 
 /// Wrapper type combining `std::process::Command` with a name.
 ///
@@ -39,6 +49,11 @@ pub struct PreparedChild<'a> {
     name: String,
     program: &'a OsStr,
     command: Command,
+    timeout: Option<Duration>,
+    abort_timeout: Option<Duration>,
+    index: usize,
+    capture_output: bool,
+    warnings: Vec<String>,
 }
 
 impl<'a> PreparedChild<'a> {
@@ -52,30 +67,134 @@ impl<'a> PreparedChild<'a> {
             name,
             program,
             command,
+            timeout: None,
+            abort_timeout: None,
+            index: 0,
+            capture_output: false,
+            warnings: Vec::new(),
         }
     }
 
+    /// Sets the time after which the child process is killed.
+    ///
+    /// If `timeout` is `None`, the child may run indefinitely. This is
+    /// the default.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the grace period before the child process is killed.
+    ///
+    /// If `abort_timeout` is not `None`, a child that has to be
+    /// stopped is first sent SIGTERM and only killed if it is still
+    /// running after this time. This gives it a chance to clean up.
+    /// If it is `None`, the child is killed right away. This is the
+    /// default.
+    pub fn set_abort_timeout(&mut self, abort_timeout: Option<Duration>) {
+        self.abort_timeout = abort_timeout;
+    }
+
+    /// Sets the position of this child in the order of submission.
+    ///
+    /// The index is passed on to the [`FinishedChild`] so that
+    /// children can be put back into order after they have finished.
+    /// The default is zero.
+    ///
+    /// [`FinishedChild`]: ./struct.FinishedChild.html
+    pub fn set_index(&mut self, index: usize) {
+        self.index = index;
+    }
+
+    /// Returns the name of the corresponding scenario.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the command that is executed by [`spawn()`].
+    ///
+    /// [`spawn()`]: #method.spawn
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Attaches a warning about this child for the user.
+    ///
+    /// Warnings don't keep the child from being spawned. It is up to
+    /// the caller to report them.
+    pub fn add_warning(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    /// Returns all warnings that have been attached to this child.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Sets whether the output of the child process is captured.
+    ///
+    /// If `capture` is `true`, the child's standard output and
+    /// standard error are redirected into temporary files instead of
+    /// being inherited. They can be printed later via
+    /// [`FinishedChild::flush_output()`]. The default is `false`.
+    ///
+    /// [`FinishedChild::flush_output()`]:
+    /// ./struct.FinishedChild.html#method.flush_output
+    pub fn set_capture_output(&mut self, capture: bool) {
+        self.capture_output = capture;
+    }
+
     /// Turns `self` into a [`RunningChild`].
     ///
     /// This starts a process from the wrapped `Command`.
     ///
     /// # Errors
     /// This function fails if the wrapped call to
-    /// `std::process:Command::spawn()` fails.
+    /// `std::process:Command::spawn()` fails, if the timer for the
+    /// timeout cannot be created, or if the files to capture the
+    /// output cannot be created.
     ///
     /// [`RunningChild`]: ./struct.RunningChild.html
-    pub fn spawn(mut self, handle: &Handle) -> Result<RunningChild, Error> {
+    pub fn spawn(self, handle: &Handle) -> Result<RunningChild, Error> {
         let name = self.name;
         let program = self.program;
-        let child = self
-            .command
+        let mut command = self.command;
+        // Create the timer first so that we never leave a child
+        // running if this fails.
+        let timeout = match self.timeout {
+            Some(duration) => {
+                let timer = Timeout::new(duration, handle)
+                    .with_context(|_| ScenarioNotStarted(name.clone()))?;
+                Some((duration, timer))
+            },
+            None => None,
+        };
+        let output = if self.capture_output {
+            let output = CapturedOutput::new(self.index)
+                .and_then(|output| output.redirect(&mut command).map(|_| output))
+                .context(CaptureFailed)
+                .with_context(|_| ScenarioNotStarted(name.clone()))?;
+            Some(output)
+        } else {
+            None
+        };
+        let child = command
             .spawn_async(handle)
             .map_err(|cause| {
                 let name = program.to_string_lossy().into_owned();
                 SpawnFailed { cause, name }
             })
             .with_context(|_| ScenarioNotStarted(name.clone()))?;
-        Ok(RunningChild { name, child })
+        Ok(RunningChild {
+            name,
+            child,
+            handle: handle.clone(),
+            timeout,
+            timed_out: false,
+            abort_timeout: self.abort_timeout,
+            abort_timer: None,
+            index: self.index,
+            output,
+        })
     }
 }
 
@@ -86,18 +205,95 @@ impl<'a> PreparedChild<'a> {
 /// a process that is currently running. Because it implements
 /// [`Future`], you can wait on it to finish.
 ///
+/// If the child has a timeout and runs for longer than that, it is
+/// terminated via [`terminate()`]. It is still waited for as usual
+/// afterwards.
+///
 /// [`Child`]: ../../tokio_process/struct.Child.html
 /// [`Future`]: ../../futures/future/trait.Future.html
 /// [`PreparedChild::spawn()`]: ./struct.PreparedChild.html#method.spawn
+/// [`terminate()`]: #method.terminate
 #[derive(Debug)]
 pub struct RunningChild {
     name: String,
     child: Child,
+    handle: Handle,
+    timeout: Option<(Duration, Timeout)>,
+    timed_out: bool,
+    abort_timeout: Option<Duration>,
+    abort_timer: Option<Timeout>,
+    index: usize,
+    output: Option<CapturedOutput>,
 }
 
 impl RunningChild {
     fn take_name(&mut self) -> String {
-        mem::replace(&mut self.name, String::new())
+        mem::take(&mut self.name)
+    }
+
+    /// Sends `signal` to the child process.
+    #[cfg(unix)]
+    pub fn send_signal(&self, signal: c_int) -> io::Result<()> {
+        let pid = self.child.id() as libc::pid_t;
+        if unsafe { libc::kill(pid, signal) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Sends `signal` to the child process.
+    ///
+    /// Outside of Unix, processes cannot receive signals, so this
+    /// always fails with [`io::ErrorKind::Unsupported`].
+    ///
+    /// [`io::ErrorKind::Unsupported`]:
+    /// https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    #[cfg(not(unix))]
+    pub fn send_signal(&self, _signal: c_int) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Forcibly kills the child process.
+    ///
+    /// The child must still be waited for afterwards so that it gets
+    /// reaped. It then counts as failed.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+
+    /// Stops the child process, giving it time to clean up.
+    ///
+    /// If the child has an abort timeout, this is like
+    /// [`terminate_with()`] with SIGTERM. Otherwise, the child is
+    /// killed right away.
+    ///
+    /// [`terminate_with()`]: #method.terminate_with
+    pub fn terminate(&mut self) -> io::Result<()> {
+        if self.abort_timeout.is_some() {
+            self.terminate_with(SIGTERM)
+        } else {
+            self.kill()
+        }
+    }
+
+    /// Sends `signal` to the child process and kills it later.
+    ///
+    /// If the child has an abort timeout, it is killed once that time
+    /// has passed. Calling this function again does not restart the
+    /// timer. If the child has no abort timeout, this only sends the
+    /// signal. Where signals are not supported, the child is killed
+    /// right away.
+    pub fn terminate_with(&mut self, signal: c_int) -> io::Result<()> {
+        if let Some(abort_timeout) = self.abort_timeout {
+            if self.abort_timer.is_none() {
+                self.abort_timer = Some(Timeout::new(abort_timeout, &self.handle)?);
+            }
+        }
+        match self.send_signal(signal) {
+            Err(ref err) if err.kind() == io::ErrorKind::Unsupported => self.kill(),
+            result => result,
+        }
     }
 }
 
@@ -106,6 +302,27 @@ impl Future for RunningChild {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // If the timer has fired, terminate the child. We then keep
+        // polling it as usual so that it gets reaped.
+        let timer_fired = match self.timeout {
+            Some((_, ref mut timer)) if !self.timed_out => {
+                timer.poll().unwrap_or(Async::NotReady).is_ready()
+            },
+            _ => false,
+        };
+        if timer_fired {
+            self.timed_out = true;
+            let _ = self.terminate();
+        }
+        // If the child has outlived its grace period, kill it for good.
+        let grace_over = match self.abort_timer {
+            Some(ref mut timer) => timer.poll().unwrap_or(Async::NotReady).is_ready(),
+            None => false,
+        };
+        if grace_over {
+            self.abort_timer = None;
+            let _ = self.kill();
+        }
         let status = self
             .child
             .poll()
@@ -113,7 +330,17 @@ impl Future for RunningChild {
             .with_context(|_| ScenarioFailed(self.take_name()));
         let status = try_ready!(status);
         let name = self.take_name();
-        Ok(Async::Ready(FinishedChild { name, status }))
+        let timed_out = match self.timeout {
+            Some((duration, _)) if self.timed_out => Some(duration),
+            _ => None,
+        };
+        Ok(Async::Ready(FinishedChild {
+            name,
+            status,
+            timed_out,
+            index: self.index,
+            output: self.output.take(),
+        }))
     }
 }
 
@@ -130,15 +357,68 @@ impl Future for RunningChild {
 pub struct FinishedChild {
     name: String,
     status: ExitStatus,
+    timed_out: Option<Duration>,
+    index: usize,
+    output: Option<CapturedOutput>,
 }
 
 impl FinishedChild {
+    /// Returns the name of the corresponding scenario.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the index that was set via [`PreparedChild::set_index()`].
+    ///
+    /// [`PreparedChild::set_index()`]: ./struct.PreparedChild.html#method.set_index
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the exit code of the child process, if any.
+    ///
+    /// This is `None` if the process has been terminated by a signal,
+    /// e.g. because it has timed out.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// Returns the signal that terminated the child process, if any.
+    ///
+    /// This is `None` if the process exited normally.
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+        self.status.signal()
+    }
+
+    /// Prints the captured output of the child process, if any.
+    ///
+    /// The captured standard output is written to our standard output
+    /// and the captured standard error to our standard error. The
+    /// output is printed at most once; further calls do nothing.
+    ///
+    /// # Errors
+    /// This fails if reading the captured output or writing it fails.
+    pub fn flush_output(&mut self) -> io::Result<()> {
+        match self.output.take() {
+            Some(output) => output.flush(),
+            None => Ok(()),
+        }
+    }
+
     /// Checks whether the child process had exited successfully.
     ///
     /// This inspects the wrapped `ExitStatus` and returns `Ok(())` if
     /// the child exited sucessfully. Otherwise, an error is returned.
+    /// A child that has been killed because of its timeout is always
+    /// considered to have failed.
     pub fn into_result(self) -> Result<(), Error> {
-        if self.status.success() {
+        if let Some(duration) = self.timed_out {
+            Err(TimedOut(duration))
+                .with_context(|_| ScenarioFailed(self.name.clone()))
+                .map_err(Error::from)
+        } else if self.status.success() {
             Ok(())
         } else {
             Err(ChildFailed(self.status))
@@ -149,6 +429,58 @@ impl FinishedChild {
 }
 
 
+/// Files holding the captured output of a child process.
+///
+/// The files are deleted right after creation. They stay accessible
+/// through their handles, and the operating system cleans them up as
+/// soon as they are closed.
+#[derive(Debug)]
+struct CapturedOutput {
+    stdout: File,
+    stderr: File,
+}
+
+impl CapturedOutput {
+    /// Creates the files for the child with the given index.
+    fn new(index: usize) -> io::Result<Self> {
+        Ok(CapturedOutput {
+            stdout: temp_file(index, "stdout")?,
+            stderr: temp_file(index, "stderr")?,
+        })
+    }
+
+    /// Redirects the output of `command` into the files.
+    fn redirect(&self, command: &mut Command) -> io::Result<()> {
+        command.stdout(self.stdout.try_clone()?);
+        command.stderr(self.stderr.try_clone()?);
+        Ok(())
+    }
+
+    /// Copies the contents of the files to our own output.
+    fn flush(mut self) -> io::Result<()> {
+        self.stdout.seek(SeekFrom::Start(0))?;
+        io::copy(&mut self.stdout, &mut io::stdout().lock())?;
+        self.stderr.seek(SeekFrom::Start(0))?;
+        io::copy(&mut self.stderr, &mut io::stderr().lock())?;
+        Ok(())
+    }
+}
+
+
+/// Creates and immediately deletes a temporary file.
+fn temp_file(index: usize, stream: &str) -> io::Result<File> {
+    let name = format!("scenarios-{}-{}.{}", process::id(), index, stream);
+    let path = env::temp_dir().join(name);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    Ok(file)
+}
+
+
 /// The error used to signify that a scenario couldn't even be started.
 #[derive(Debug, Fail)]
 #[fail(display = "could not start scenario \"{}\"", _0)]
@@ -171,6 +503,12 @@ pub struct SpawnFailed {
 }
 
 
+/// Creating the files to capture a child's output failed.
+#[derive(Debug, Fail)]
+#[fail(display = "could not create temporary files to capture the output")]
+pub struct CaptureFailed;
+
+
 /// Waiting for a child process's completion failed.
 ///
 /// `std::process::Child::wait()` can fail for any number of
@@ -181,9 +519,66 @@ pub struct SpawnFailed {
 pub struct WaitFailed;
 
 
+/// A child process has been killed because it ran for too long.
+#[derive(Debug, Fail)]
+pub struct TimedOut(Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "job timed out after {} seconds", self.0.as_secs())
+    }
+}
+
+
 /// A child process has exited in a non-successful manner.
 ///
 /// This can mean a non-zero exit status or exit by signal.
 #[derive(Debug, Fail)]
-#[fail(display = "job exited with non-zero {}", _0)]
 pub struct ChildFailed(ExitStatus);
+
+impl fmt::Display for ChildFailed {
+    /// Formats the exit code ourselves because the `Display`
+    /// implementation of `ExitStatus` differs between Rust versions.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.code() {
+            Some(code) => write!(f, "job exited with non-zero exit code: {}", code),
+            None => write!(f, "job exited with non-zero {}", self.0),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio_core::reactor::Core;
+
+    fn run_shell(name: &str, script: &str) -> FinishedChild {
+        let mut core = Core::new().unwrap();
+        let program = OsStr::new("sh");
+        let mut command = Command::new(program);
+        command.arg("-c").arg(script);
+        let child = PreparedChild::new(name.to_owned(), program, command);
+        let child = child.spawn(&core.handle()).unwrap();
+        core.run(child).unwrap()
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let child = run_shell("exit", "exit 2");
+        assert_eq!(child.exit_code(), Some(2));
+        assert!(child.into_result().is_err());
+        assert_eq!(run_shell("success", "true").exit_code(), Some(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_signal() {
+        let child = run_shell("exit", "exit 2");
+        assert_eq!(child.signal(), None);
+        let child = run_shell("killed", "kill -SEGV $$");
+        assert_eq!(child.exit_code(), None);
+        assert_eq!(child.signal(), Some(libc::SIGSEGV));
+    }
+}
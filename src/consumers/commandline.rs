@@ -13,21 +13,60 @@
 // permissions and limitations under the License.
 
 
-use std::{ffi::OsStr, process::Command};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
+    fs::File,
+    hash::Hasher,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
 
-use failure::{Error, ResultExt};
+use failure::{Error, Fail, ResultExt};
 
-use scenarios::Scenario;
-use trytostr::OsStrExt;
+use scenarios::{self, Scenario};
+use trytostr::{self, OsStrExt};
 
 use super::{
     children::{PreparedChild, ScenarioNotStarted},
-    Printer,
+    expansion, functions,
+    runcache::Fnv1a,
+    Printer, DEFAULT_PLACEHOLDER,
 };
 
 
 /// The name of the environment variable to hold the scenario name.
-const SCENARIOS_NAME_NAME: &str = "SCENARIOS_NAME";
+const SCENARIOS_NAME_NAME: &str = scenarios::NAME_VARIABLE;
+
+/// The name of the variable to hold the index of a scenario.
+const SCENARIOS_INDEX_NAME: &str = "SCENARIOS_INDEX";
+
+/// The name of the variable to hold the total number of scenarios.
+const SCENARIOS_TOTAL_NAME: &str = "SCENARIOS_TOTAL";
+
+/// The name of the variable that overrides the timeout of a scenario.
+const SCENARIOS_TIMEOUT_NAME: &str = "SCENARIOS_TIMEOUT";
+
+/// The number of characters that a truncated name ends in.
+///
+/// See `Options::max_name_length`.
+pub const TRUNCATION_SUFFIX_LEN: usize = 9;
+
+/// The default string placed between appended values.
+///
+/// This is the separator that the platform uses in `PATH`.
+#[cfg(windows)]
+pub const DEFAULT_APPEND_SEPARATOR: &str = ";";
+
+/// The default string placed between appended values.
+///
+/// This is the separator that the platform uses in `PATH`.
+#[cfg(not(windows))]
+pub const DEFAULT_APPEND_SEPARATOR: &str = ":";
 
 
 /// Customization flags for [`CommandLine`].
@@ -44,19 +83,42 @@ pub struct Options {
     ///
     /// The default is `false`.
     pub ignore_env: bool,
-    /// Replace all `"{}"` in the command line with the scenario name.
+    /// Variables that are inherited even if `ignore_env` is set.
+    ///
+    /// Each of these variables is read from the environment of this
+    /// process and passed on to child processes. Variables that are not
+    /// set in this process are silently skipped. This has no effect if
+    /// `ignore_env` is `false`.
+    ///
+    /// The default is an empty list.
+    pub env_passthrough: Vec<String>,
+    /// Replace all `placeholder`s in the command line with the
+    /// scenario name.
     ///
     /// If `true`, use a `Printer` to insert the scenario's name into
     /// the command line when executing it.
     /// If `false`, the command line is executed as-is.
     ///
+    /// This is independent of `add_scenarios_name`; all four
+    /// combinations are allowed.
+    ///
     /// The default is `true`.
     pub insert_name_in_args: bool,
+    /// The string that is replaced with the scenario name.
+    ///
+    /// This only has an effect if `insert_name_in_args` is `true`.
+    ///
+    /// The default is [`DEFAULT_PLACEHOLDER`], i.e. `"{}"`.
+    ///
+    /// [`DEFAULT_PLACEHOLDER`]: ./constant.DEFAULT_PLACEHOLDER.html
+    pub placeholder: String,
     /// Define a variable "SCENARIOS_NAME".
     ///
     /// If `true`, always define an additional environment variable
     /// whose name is "SCENARIOS_NAME". This variable contains the name
     /// of the scenario in which the child process is being executed.
+    /// If `false`, a variable of this name is passed on like any other
+    /// variable, be it inherited or defined by the scenario.
     ///
     /// The default is `true`.
     pub add_scenarios_name: bool,
@@ -70,6 +132,128 @@ pub struct Options {
     ///
     /// The default is `true`.
     pub is_strict: bool,
+    /// The time after which child processes are killed.
+    ///
+    /// If `None`, child processes may run indefinitely. A scenario may
+    /// override this value by defining the variable
+    /// "SCENARIOS_TIMEOUT" as a number of seconds. This variable is
+    /// never passed on to the child process.
+    ///
+    /// The default is `None`.
+    pub timeout: Option<Duration>,
+    /// The grace period before child processes are killed.
+    ///
+    /// If this is not `None`, child processes that run into their
+    /// timeout are sent SIGTERM first and only killed if they are
+    /// still running after this time. The same applies if a signal
+    /// is forwarded to them.
+    ///
+    /// The default is `None`.
+    pub abort_timeout: Option<Duration>,
+    /// Expand calls to built-in functions in variable values.
+    ///
+    /// If `true`, calls like `$(uuid)` or `$(now)` in the values of
+    /// variable definitions are replaced with their results, freshly
+    /// for each child process. Calling an unknown function is an
+    /// error.
+    ///
+    /// The default is `false`.
+    pub enable_functions: bool,
+    /// Pass raw bytes in variable values on to child processes.
+    ///
+    /// If `true`, values that carry raw bytes, as described in
+    /// [`trytostr::from_utf8_with_raw_bytes()`], are decoded before
+    /// they are exported. This only has an effect on Unix.
+    ///
+    /// The default is `false`.
+    ///
+    /// [`trytostr::from_utf8_with_raw_bytes()`]:
+    /// ../trytostr/fn.from_utf8_with_raw_bytes.html
+    pub allow_non_utf8_values: bool,
+    /// The string placed between values appended to a variable.
+    ///
+    /// If a scenario appends to a variable, e.g. with `PATH += /bin`,
+    /// this string separates the appended value from the previous
+    /// one. The previous value is the scenario's definition of the
+    /// variable or, if there is none, the inherited value. No
+    /// separator is added if the previous value is empty.
+    ///
+    /// The default is [`DEFAULT_APPEND_SEPARATOR`].
+    ///
+    /// [`DEFAULT_APPEND_SEPARATOR`]: ./constant.DEFAULT_APPEND_SEPARATOR.html
+    pub append_separator: String,
+    /// How to transform the scenario name before passing it on.
+    ///
+    /// The transformed name is what replaces the placeholder in the
+    /// command line and what is exported as "SCENARIOS_NAME". Error
+    /// messages still use the original name.
+    ///
+    /// The default is `NameTransform::None`.
+    pub name_transform: NameTransform,
+    /// Variables that are defined for every child process.
+    ///
+    /// These variables are added to the inherited environment (or to
+    /// the clean environment if `ignore_env` is set). The variables of
+    /// a scenario are added afterwards and thus take precedence. If
+    /// `is_strict` is `true`, it is an error for a scenario to define
+    /// one of these variables; appending to them is allowed.
+    ///
+    /// The default is an empty list.
+    pub base_env: Vec<(String, String)>,
+    /// A directory to write the output of child processes to.
+    ///
+    /// If this is not `None`, the standard output and standard error
+    /// of each child process are redirected to the files `NAME.out`
+    /// and `NAME.err` in this directory. In `NAME`, all characters of
+    /// the scenario name except ASCII letters and digits are replaced
+    /// with underscores. Existing files are overwritten. The directory
+    /// must already exist. If two scenarios end up with the same file
+    /// names, the second one is not started.
+    ///
+    /// The default is `None`.
+    pub out_dir: Option<PathBuf>,
+    /// Warn about variables that shadow inherited ones.
+    ///
+    /// If `true`, each child process gets a warning for every variable
+    /// whose name differs only in case from the name of an inherited
+    /// environment variable, e.g. `path` and `PATH`. On platforms with
+    /// case-insensitive variable names, such a variable silently
+    /// replaces the inherited one. This has no effect if `ignore_env`
+    /// is set.
+    ///
+    /// The default is `false`.
+    pub warn_env_shadow: bool,
+    /// The maximum number of characters in a name passed to a child.
+    ///
+    /// If this is not `None`, longer names are cut short before they
+    /// replace the placeholder, are exported as "SCENARIOS_NAME", or
+    /// name the files in `out_dir`. The cut-off part is replaced with
+    /// `~` and eight hex digits of a hash of the whole name, so
+    /// truncated names stay distinct. The limit should thus be greater
+    /// than [`TRUNCATION_SUFFIX_LEN`]. The name of the scenario itself
+    /// is not changed.
+    ///
+    /// The default is `None`.
+    ///
+    /// [`TRUNCATION_SUFFIX_LEN`]: ./constant.TRUNCATION_SUFFIX_LEN.html
+    pub max_name_length: Option<usize>,
+    /// Expand references to scenario variables in the command line.
+    ///
+    /// If `true`, each `${NAME}` in an argument is replaced with the
+    /// value that the child process receives for the variable `NAME`.
+    /// Only variables of the scenario are looked up, not inherited
+    /// ones. This happens after the scenario name has been inserted.
+    ///
+    /// The default is `false`.
+    pub expand_command: bool,
+    /// Expand references to unset variables to the empty string.
+    ///
+    /// If `false`, referring to a variable that the scenario does not
+    /// define is an error. This only has an effect if `expand_command`
+    /// is `true`.
+    ///
+    /// The default is `false`.
+    pub allow_unset: bool,
 }
 
 impl Default for Options {
@@ -77,9 +261,64 @@ impl Default for Options {
     fn default() -> Self {
         Self {
             ignore_env: false,
+            env_passthrough: Vec::new(),
             insert_name_in_args: true,
+            placeholder: DEFAULT_PLACEHOLDER.to_owned(),
             add_scenarios_name: true,
             is_strict: true,
+            timeout: None,
+            abort_timeout: None,
+            enable_functions: false,
+            allow_non_utf8_values: false,
+            append_separator: DEFAULT_APPEND_SEPARATOR.to_owned(),
+            name_transform: NameTransform::None,
+            base_env: Vec::new(),
+            out_dir: None,
+            warn_env_shadow: false,
+            max_name_length: None,
+            expand_command: false,
+            allow_unset: false,
+        }
+    }
+}
+
+
+/// The ways in which a scenario name can be transformed.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum NameTransform {
+    /// Leave the name as it is.
+    None,
+    /// Convert the name to uppercase.
+    Upper,
+    /// Convert the name to lowercase.
+    Lower,
+    /// Replace all characters that may not appear in an identifier
+    /// with underscores.
+    ///
+    /// Identifier characters are ASCII letters, digits, and the
+    /// underscore.
+    Slug,
+}
+
+impl NameTransform {
+    /// Applies this transformation to `name`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// assert_eq!(NameTransform::Upper.apply("a, b"), "A, B");
+    /// assert_eq!(NameTransform::Slug.apply("a, b"), "a__b");
+    /// ```
+    pub fn apply<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match *self {
+            NameTransform::None => Cow::Borrowed(name),
+            NameTransform::Upper => Cow::Owned(name.to_uppercase()),
+            NameTransform::Lower => Cow::Owned(name.to_lowercase()),
+            NameTransform::Slug => name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+                .into(),
         }
     }
 }
@@ -88,9 +327,14 @@ impl Default for Options {
 /// A consumer of `Scenario`s that executes a command line in them.
 ///
 /// This uses the variable definitions in a scenario to define
-/// environment variables. In this environment, the specified command
+/// environment variables. Variables that the scenario removes are
+/// taken out of the inherited environment. (Unless `ignore_env` is
+/// set, in which case there is nothing to remove them from.) Values
+/// that the scenario appends to a variable are joined with its
+/// previous value. In this environment, the specified command
 /// line is executed. The scenario's name can be inserted into the
-/// command line (by replacing all occurrences of `"{}"` with it) and
+/// command line (by replacing all occurrences of a placeholder,
+/// `"{}"` by default, with it) and
 /// defined as an additional environment variable called
 /// `SCENARIOS_NAME`.
 ///
@@ -105,6 +349,8 @@ pub struct CommandLine<S: AsRef<OsStr>> {
     command_line: Vec<S>,
     /// Flags to customize the creation of child processes.
     options: Options,
+    /// The scenario that writes to each file stem in `out_dir`.
+    out_files: RefCell<HashMap<String, String>>,
 }
 
 impl<S: AsRef<OsStr>> CommandLine<S> {
@@ -144,6 +390,7 @@ impl<S: AsRef<OsStr>> CommandLine<S> {
             CommandLine {
                 command_line,
                 options,
+                out_files: RefCell::default(),
             }
             .into()
         }
@@ -195,66 +442,374 @@ impl<S: AsRef<OsStr>> CommandLine<S> {
     /// a variable named `"SCENARIOS_NAME"` even though this command
     /// line is instructed to add such a variable itself. (See
     /// documentation of `Options` for more information.)
-    pub fn with_scenario(&self, scenario: Scenario) -> Result<PreparedChild, Error> {
+    ///
+    /// It also fails if the scenario defines `"SCENARIOS_TIMEOUT"`,
+    /// but its value is not a number, or if strict mode is enabled and
+    /// the scenario defines a variable of `Options::base_env`.
+    pub fn with_scenario(&self, scenario: Scenario) -> Result<PreparedChild<'_>, Error> {
+        self.prepare_child(scenario, None)
+    }
+
+    /// Like [`with_scenario()`], but also exports the scenario's index.
+    ///
+    /// The child process receives the variables `"SCENARIOS_INDEX"`,
+    /// which contains `index`, and `"SCENARIOS_TOTAL"`, which contains
+    /// `total`. The index is meant to be zero-based.
+    ///
+    /// # Errors
+    /// In addition to the errors of [`with_scenario()`], this fails if
+    /// strict mode is enabled and the scenario defines one of these
+    /// two variables itself.
+    ///
+    /// [`with_scenario()`]: #method.with_scenario
+    pub fn with_indexed_scenario(
+        &self,
+        scenario: Scenario,
+        index: usize,
+        total: usize,
+    ) -> Result<PreparedChild<'_>, Error> {
+        self.prepare_child(scenario, Some((index, total)))
+    }
+
+    /// Internal implementation of `with_scenario()` and
+    /// `with_indexed_scenario()`.
+    fn prepare_child(
+        &self,
+        scenario: Scenario,
+        position: Option<(usize, usize)>,
+    ) -> Result<PreparedChild<'_>, Error> {
+        if self.options.is_strict {
+            let redefined = self
+                .options
+                .base_env
+                .iter()
+                .find(|(var, _)| scenario.get_variable(var).is_some());
+            if let Some((var, _)) = redefined {
+                let err = BaseVarRedefined(var.clone())
+                    .context(ScenarioNotStarted(scenario.name().to_owned()));
+                return Err(err.into());
+            }
+        }
+        let timeout = match scenario.get_variable(SCENARIOS_TIMEOUT_NAME) {
+            Some(seconds) => seconds
+                .parse()
+                .map(|seconds| Some(Duration::from_secs(seconds)))
+                .map_err(|_| InvalidTimeout(seconds.to_owned()))
+                .with_context(|_| ScenarioNotStarted(scenario.name().to_owned()))?,
+            None => self.options.timeout,
+        };
+        let removals = scenario.removals().cloned().collect::<Vec<_>>();
+        let appended = self.resolve_appends(&scenario);
         let (name, variables) = scenario.into_parts();
-        let command = self.create_command(variables, &name)?;
+        // Appended variables replace any definitions of the same name.
+        let variables = variables
+            .filter(|&(var, _)| var != SCENARIOS_TIMEOUT_NAME)
+            .filter(|&(var, _)| appended.iter().all(|&(other, _)| var != other))
+            .map(|(var, value)| Ok((var, self.resolve_value(value)?)))
+            .collect::<Result<Vec<_>, functions::UnknownFunction>>()
+            .with_context(|_| ScenarioNotStarted(name.clone().into_owned()))?;
+        let variables = variables
+            .into_iter()
+            .chain(
+                appended
+                    .iter()
+                    .map(|&(var, ref value)| (var, Cow::Borrowed(value.as_os_str()))),
+            );
+        let command = self.create_command(variables, &removals, &name, position)?;
         let program = self.program().as_ref();
-        Ok(PreparedChild::new(name.into_owned(), program, command))
+        let shadowed = if self.options.warn_env_shadow && !self.options.ignore_env {
+            Self::find_shadowed_vars(&command)
+        } else {
+            Vec::new()
+        };
+        let mut child = PreparedChild::new(name.into_owned(), program, command);
+        for (var, inherited) in shadowed {
+            child.add_warning(format!(
+                "variable \"{}\" differs only in case from inherited variable \"{}\"",
+                var, inherited
+            ));
+        }
+        child.set_timeout(timeout);
+        child.set_abort_timeout(self.options.abort_timeout);
+        Ok(child)
+    }
+
+    /// Computes the value that is exported for a variable.
+    ///
+    /// This expands function calls and decodes raw bytes in `value`,
+    /// each only if it is enabled.
+    fn resolve_value<'s>(
+        &self,
+        value: &'s str,
+    ) -> Result<Cow<'s, OsStr>, functions::UnknownFunction> {
+        let value = if self.options.enable_functions {
+            functions::expand_functions(value)?
+        } else {
+            Cow::Borrowed(value)
+        };
+        Ok(if self.options.allow_non_utf8_values {
+            trytostr::decode_raw_bytes(value)
+        } else {
+            match value {
+                Cow::Borrowed(value) => Cow::Borrowed(OsStr::new(value)),
+                Cow::Owned(value) => Cow::Owned(OsString::from(value)),
+            }
+        })
     }
 
-    /// Internal implementation of `with_scenario`.
-    fn create_command<I, K, V>(&self, env_vars: I, name: &str) -> Result<Command, Error>
+    /// Computes the final values of all variables that `scenario`
+    /// appends to.
+    ///
+    /// The appended values are joined with the previous value of each
+    /// variable. This is the scenario's own definition, if there is
+    /// one. Otherwise, unless the scenario removes the variable, it is
+    /// the value from `base_env` or the inherited value. The latter is
+    /// not used if `ignore_env` is set.
+    fn resolve_appends<'s>(&self, scenario: &Scenario<'s>) -> Vec<(&'s str, OsString)> {
+        scenario
+            .appends()
+            .map(|(&var, values)| {
+                let previous = match scenario.get_variable(var) {
+                    Some(value) => Some(OsString::from(value)),
+                    None if scenario.has_removal(var) => None,
+                    None => match self.base_var(var) {
+                        Some(value) => Some(OsString::from(value)),
+                        None if self.options.ignore_env && !self.passes_through(var) => None,
+                        None => env::var_os(var),
+                    },
+                };
+                let mut result = previous.unwrap_or_default();
+                for value in values {
+                    if !result.is_empty() {
+                        result.push(&self.options.append_separator);
+                    }
+                    if self.options.allow_non_utf8_values {
+                        result.push(trytostr::decode_raw_bytes(Cow::Borrowed(value)));
+                    } else {
+                        result.push(value);
+                    }
+                }
+                (var, result)
+            })
+            .collect()
+    }
+
+    /// Returns `true` if `var` is in `Options::env_passthrough`.
+    fn passes_through(&self, var: &str) -> bool {
+        self.options.env_passthrough.iter().any(|name| name == var)
+    }
+
+    /// Returns the value of `var` in `Options::base_env`, if any.
+    fn base_var(&self, var: &str) -> Option<&str> {
+        self.options
+            .base_env
+            .iter()
+            .find(|(name, _)| name == var)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Builds the `Command` for `prepare_child()`.
+    fn create_command<I, K, V>(
+        &self,
+        env_vars: I,
+        removals: &[&str],
+        name: &str,
+        position: Option<(usize, usize)>,
+    ) -> Result<Command, Error>
     where
         I: IntoIterator<Item = (K, V)>,
         K: AsRef<OsStr>,
         V: AsRef<OsStr>,
     {
         let mut cmd = Command::new(self.program().as_ref());
+        let transformed_name = self.options.name_transform.apply(name);
+        let transformed_name = self.truncate_name(&transformed_name);
+        let env_vars = env_vars.into_iter().collect::<Vec<_>>();
         // Go through each of the options and prepare `cmd` accordingly.
-        if self.options.insert_name_in_args {
-            self.add_args_formatted(&mut cmd, name)
-                .context("could not replace \"{}\" with scenario name in an argument")?;
+        let args = if self.options.insert_name_in_args {
+            self.args_formatted(&transformed_name)
+                .context("could not insert scenario name into an argument")?
         } else {
-            cmd.args(self.args().iter().map(AsRef::as_ref));
-        }
+            self.args().iter().map(|arg| arg.as_ref().to_owned()).collect()
+        };
+        let args = if self.options.expand_command {
+            self.args_expanded(&args, &env_vars)
+                .with_context(|_| ScenarioNotStarted(name.to_owned()))?
+        } else {
+            args
+        };
+        cmd.args(args);
         if self.options.ignore_env {
             cmd.env_clear();
+            for var in &self.options.env_passthrough {
+                if removals.contains(&var.as_str()) {
+                    continue;
+                }
+                if let Some(value) = env::var_os(var) {
+                    cmd.env(var, value);
+                }
+            }
+        } else {
+            for var in removals {
+                cmd.env_remove(var);
+            }
         }
-        if self.options.add_scenarios_name && self.options.is_strict {
-            Self::add_vars_checked(&mut cmd, env_vars)
+        for (var, value) in &self.options.base_env {
+            if !removals.contains(&var.as_str()) {
+                cmd.env(var, value);
+            }
+        }
+        let mut reserved = Vec::new();
+        if self.options.add_scenarios_name {
+            reserved.push(SCENARIOS_NAME_NAME);
+        }
+        if position.is_some() {
+            reserved.extend(&[SCENARIOS_INDEX_NAME, SCENARIOS_TOTAL_NAME]);
+        }
+        if self.options.is_strict && !reserved.is_empty() {
+            Self::add_vars_checked(&mut cmd, env_vars, &reserved)
                 .map_err(ReservedVarName)
                 .with_context(|_| ScenarioNotStarted(name.to_owned()))?;
         } else {
             cmd.envs(env_vars);
         }
         if self.options.add_scenarios_name {
-            cmd.env(SCENARIOS_NAME_NAME, OsStr::new(name));
+            cmd.env(SCENARIOS_NAME_NAME, OsStr::new(&*transformed_name));
+        }
+        if let Some((index, total)) = position {
+            cmd.env(SCENARIOS_INDEX_NAME, index.to_string());
+            cmd.env(SCENARIOS_TOTAL_NAME, total.to_string());
+        }
+        if let Some(ref dir) = self.options.out_dir {
+            let stem = NameTransform::Slug.apply(name);
+            let stem = self.truncate_name(&stem);
+            self.claim_out_files(dir, &stem, name)
+                .with_context(|_| ScenarioNotStarted(name.to_owned()))?;
+            let stdout = Self::create_output_file(dir, &stem, "out")
+                .with_context(|_| ScenarioNotStarted(name.to_owned()))?;
+            let stderr = Self::create_output_file(dir, &stem, "err")
+                .with_context(|_| ScenarioNotStarted(name.to_owned()))?;
+            cmd.stdout(Stdio::from(stdout));
+            cmd.stderr(Stdio::from(stderr));
         }
         Ok(cmd)
     }
 
-    /// Inserts `name` into `self.args()` before adding them to `cmd`.
-    fn add_args_formatted(&self, cmd: &mut Command, name: &str) -> Result<(), Error> {
+    /// Lists the variables set on `cmd` that differ only in case from
+    /// an inherited variable.
+    ///
+    /// Each item is a pair of the variable name and the name of the
+    /// inherited variable.
+    fn find_shadowed_vars(cmd: &Command) -> Vec<(String, String)> {
+        let inherited: Vec<String> = env::vars_os()
+            .filter_map(|(var, _)| var.into_string().ok())
+            .collect();
+        cmd.get_envs()
+            .filter(|&(_, value)| value.is_some())
+            .filter_map(|(var, _)| {
+                let var = var.to_str()?;
+                let other = inherited
+                    .iter()
+                    .find(|other| *other != var && other.eq_ignore_ascii_case(var))?;
+                Some((var.to_owned(), other.clone()))
+            })
+            .collect()
+    }
+
+    /// Shortens `name` to `Options::max_name_length`, if necessary.
+    fn truncate_name<'s>(&self, name: &'s str) -> Cow<'s, str> {
+        let max_len = match self.options.max_name_length {
+            Some(max_len) if name.chars().count() > max_len => max_len,
+            _ => return Cow::Borrowed(name),
+        };
+        let mut hasher = Fnv1a::new();
+        hasher.write(name.as_bytes());
+        let suffix = format!("~{:08x}", hasher.finish() as u32);
+        let prefix_len = max_len.saturating_sub(TRUNCATION_SUFFIX_LEN);
+        let prefix: String = name.chars().take(prefix_len).collect();
+        Cow::Owned(prefix + &suffix)
+    }
+
+    /// Reserves the output files with `stem` for the scenario `name`.
+    ///
+    /// # Errors
+    /// This fails if another scenario has already used `stem`.
+    fn claim_out_files(&self, dir: &Path, stem: &str, name: &str) -> Result<(), OutFileCollision> {
+        let mut out_files = self.out_files.borrow_mut();
+        match out_files.get(stem) {
+            Some(other) if other != name => {
+                let path = dir.join(format!("{}.out", stem));
+                Err(OutFileCollision(other.clone(), name.to_owned(), path))
+            },
+            Some(_) => Ok(()),
+            None => {
+                out_files.insert(stem.to_owned(), name.to_owned());
+                Ok(())
+            },
+        }
+    }
+
+    /// Creates the file `dir/stem.extension` for `Options::out_dir`.
+    fn create_output_file(dir: &Path, stem: &str, extension: &str) -> Result<File, Error> {
+        let path = dir.join(format!("{}.{}", stem, extension));
+        let file = File::create(&path).with_context(|_| format!("could not create {:?}", path))?;
+        Ok(file)
+    }
+
+    /// Returns `self.args()` with `name` inserted into them.
+    fn args_formatted(&self, name: &str) -> Result<Vec<OsString>, Error> {
         // We treat each argument as a template in which `name` is
-        // inserted before being added to `cmd`.
+        // inserted.
         let mut printer = Printer::new_null();
+        printer.set_placeholder(&self.options.placeholder);
+        let mut args = Vec::with_capacity(self.args().len());
         for arg in self.args().iter() {
             printer.set_template(arg.as_ref().try_to_str()?);
-            cmd.arg(printer.format(name));
+            args.push(OsString::from(printer.format(name)));
         }
-        Ok(())
+        Ok(args)
+    }
+
+    /// Replaces references to `env_vars` in `args` with their values.
+    fn args_expanded<K, V>(
+        &self,
+        args: &[OsString],
+        env_vars: &[(K, V)],
+    ) -> Result<Vec<OsString>, Error>
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let lookup = |name: &str| {
+            env_vars
+                .iter()
+                .find(|(var, _)| var.as_ref() == name)
+                .map(|(_, value)| value.as_ref())
+        };
+        let mut expanded = Vec::with_capacity(args.len());
+        for arg in args {
+            let arg = arg.try_to_str().context("could not expand variables in an argument")?;
+            expanded.push(expansion::expand_variables(arg, lookup, self.options.allow_unset)?);
+        }
+        Ok(expanded)
     }
 
     /// Checks the name of each variable before adding it to `cmd`.
-    fn add_vars_checked<I, K, V>(cmd: &mut Command, vars: I) -> Result<(), String>
+    ///
+    /// This returns the first variable name that is in `reserved`.
+    fn add_vars_checked<I, K, V>(
+        cmd: &mut Command,
+        vars: I,
+        reserved: &[&str],
+    ) -> Result<(), String>
     where
         I: IntoIterator<Item = (K, V)>,
         K: AsRef<OsStr>,
         V: AsRef<OsStr>,
     {
         for (k, v) in vars {
-            if k.as_ref() == SCENARIOS_NAME_NAME {
-                return Err(SCENARIOS_NAME_NAME.to_owned());
+            if let Some(name) = reserved.iter().find(|&&name| k.as_ref() == name) {
+                return Err((*name).to_owned());
             }
             cmd.env(k, v);
         }
@@ -272,6 +827,32 @@ impl<S: AsRef<OsStr>> CommandLine<S> {
 pub struct ReservedVarName(String);
 
 
+/// The error type used if a scenario overrides a base variable.
+#[derive(Debug, Fail)]
+#[fail(
+    display = "variable also defined in the environment file: \"{}\" (strict mode is enabled)",
+    _0
+)]
+pub struct BaseVarRedefined(String);
+
+
+/// The error type used if two scenarios would write to the same file.
+#[derive(Debug, Fail)]
+#[fail(
+    display = "scenarios \"{}\" and \"{}\" would both write to {:?}",
+    _0,
+    _1,
+    _2
+)]
+pub struct OutFileCollision(String, String, PathBuf);
+
+
+/// The error type used if "SCENARIOS_TIMEOUT" is not a number.
+#[derive(Debug, Fail)]
+#[fail(display = "invalid value for SCENARIOS_TIMEOUT: {:?}", _0)]
+pub struct InvalidTimeout(String);
+
+
 #[cfg(test)]
 mod tests {
     use std::iter;
@@ -282,22 +863,197 @@ mod tests {
     #[test]
     fn test_echo() {
         let cl = CommandLine::new(["echo", "-n"].iter()).unwrap();
-        cl.create_command(iter::empty::<(&str, &str)>(), "name")
+        cl.create_command(iter::empty::<(&str, &str)>(), &[], "name", None)
             .expect("CommandLine::create_command failed")
             .status()
             .expect("Child::status failed");
     }
 
+    #[test]
+    fn test_name_transform() {
+        let name = "Äpfel, b-2_c";
+        assert_eq!(NameTransform::None.apply(name), name);
+        assert_eq!(NameTransform::Upper.apply(name), "ÄPFEL, B-2_C");
+        assert_eq!(NameTransform::Lower.apply(name), "äpfel, b-2_c");
+        assert_eq!(NameTransform::Slug.apply(name), "_pfel__b_2_c");
+    }
+
+    #[test]
+    fn test_resolve_appends() {
+        let mut scenario = Scenario::new("name").unwrap();
+        scenario.add_append("UNSET_VARIABLE", "a").unwrap();
+        scenario.add_append("UNSET_VARIABLE", "b").unwrap();
+        let mut cl = CommandLine::new(["true"].iter()).unwrap();
+        cl.options_mut().append_separator = "/".to_owned();
+        assert_eq!(
+            cl.resolve_appends(&scenario),
+            [("UNSET_VARIABLE", OsString::from("a/b"))]
+        );
+        // A definition in the same (merged) scenario comes first.
+        let mut defined = Scenario::new("defined").unwrap();
+        defined.add_variable("UNSET_VARIABLE", "x").unwrap();
+        defined.merge(&scenario, Default::default()).unwrap();
+        assert_eq!(
+            cl.resolve_appends(&defined),
+            [("UNSET_VARIABLE", OsString::from("x/a/b"))]
+        );
+        // Otherwise, the base environment comes first.
+        cl.options_mut().base_env = vec![("UNSET_VARIABLE".to_owned(), "y".to_owned())];
+        assert_eq!(
+            cl.resolve_appends(&scenario),
+            [("UNSET_VARIABLE", OsString::from("y/a/b"))]
+        );
+    }
+
+    #[test]
+    fn test_base_env_redefined() {
+        let mut scenario = Scenario::new("name").unwrap();
+        scenario.add_variable("BASE", "scenario").unwrap();
+        let mut cl = CommandLine::new(["true"].iter()).unwrap();
+        cl.options_mut().base_env = vec![("BASE".to_owned(), "base".to_owned())];
+        let err = cl.with_scenario(scenario.clone()).err().unwrap();
+        assert_eq!(
+            err.cause().cause().unwrap().to_string(),
+            "variable also defined in the environment file: \"BASE\" (strict mode is enabled)"
+        );
+        cl.options_mut().is_strict = false;
+        assert!(cl.with_scenario(scenario).is_ok());
+    }
+
+    #[test]
+    fn test_warn_env_shadow() {
+        env::set_var("SCENARIOS_TEST_SHADOWED", "inherited");
+        let mut scenario = Scenario::new("name").unwrap();
+        scenario.add_variable("scenarios_test_shadowed", "mine").unwrap();
+        let mut cl = CommandLine::new(["true"].iter()).unwrap();
+        assert!(cl.with_scenario(scenario.clone()).unwrap().warnings().is_empty());
+        cl.options_mut().warn_env_shadow = true;
+        assert_eq!(
+            cl.with_scenario(scenario.clone()).unwrap().warnings(),
+            ["variable \"scenarios_test_shadowed\" differs only in case from inherited \
+              variable \"SCENARIOS_TEST_SHADOWED\""]
+        );
+        cl.options_mut().ignore_env = true;
+        assert!(cl.with_scenario(scenario).unwrap().warnings().is_empty());
+    }
+
+    #[test]
+    fn test_env_passthrough() {
+        env::set_var("SCENARIOS_TEST_PASSED", "inherited");
+        env::remove_var("SCENARIOS_TEST_MISSING");
+        let mut cl = CommandLine::new(["env"].iter()).unwrap();
+        cl.options_mut().ignore_env = true;
+        cl.options_mut().add_scenarios_name = false;
+        cl.options_mut().env_passthrough =
+            vec!["SCENARIOS_TEST_PASSED".to_owned(), "SCENARIOS_TEST_MISSING".to_owned()];
+        let vars: [(&str, &str); 0] = [];
+        let output = cl
+            .create_command(vars.iter().cloned(), &[], "name", None)
+            .expect("CommandLine::create_command failed")
+            .output()
+            .expect("Child::output failed");
+        assert_eq!(output.stdout, b"SCENARIOS_TEST_PASSED=inherited\n");
+        let output = cl
+            .create_command(vars.iter().cloned(), &["SCENARIOS_TEST_PASSED"], "name", None)
+            .expect("CommandLine::create_command failed")
+            .output()
+            .expect("Child::output failed");
+        assert_eq!(output.stdout, b"");
+    }
+
+    #[test]
+    fn test_truncate_name() {
+        let mut cl = CommandLine::new(["true"].iter()).unwrap();
+        assert_eq!(cl.truncate_name("a long name"), "a long name");
+        cl.options_mut().max_name_length = Some(12);
+        assert_eq!(cl.truncate_name("short"), Cow::Borrowed("short"));
+        let first = cl.truncate_name("Äpfel, a long name");
+        let second = cl.truncate_name("Äpfel, a long game");
+        assert_eq!(first.chars().count(), 12);
+        assert!(first.starts_with("Äpf~"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_expand_command() {
+        let mut cl = CommandLine::new(["echo", "${greeting}, ${target}!"].iter()).unwrap();
+        cl.options_mut().expand_command = true;
+        let vars = [("greeting", "hello"), ("target", "{}")];
+        let output = cl
+            .create_command(vars.iter().cloned(), &[], "name", None)
+            .expect("CommandLine::create_command failed")
+            .output()
+            .expect("Child::output failed");
+        // The name is inserted first, so values are not templates.
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello, {}!\n");
+        let err = cl
+            .create_command(vars[..1].iter().cloned(), &[], "name", None)
+            .unwrap_err();
+        assert_eq!(
+            err.cause().cause().unwrap().to_string(),
+            "variable is not set: \"target\""
+        );
+        cl.options_mut().allow_unset = true;
+        let output = cl
+            .create_command(vars[..1].iter().cloned(), &[], "name", None)
+            .expect("CommandLine::create_command failed")
+            .output()
+            .expect("Child::output failed");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello, !\n");
+    }
+
+    #[test]
+    fn test_indexed_scenario() {
+        let cl = CommandLine::new(["sh", "-c", "echo $SCENARIOS_INDEX/$SCENARIOS_TOTAL"].iter())
+            .unwrap();
+        let mut scenario = Scenario::new("name").unwrap();
+        let output = cl
+            .create_command(iter::empty::<(&str, &str)>(), &[], "name", Some((2, 5)))
+            .expect("CommandLine::create_command failed")
+            .output()
+            .expect("Child::output failed");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "2/5\n");
+        scenario.add_variable("SCENARIOS_TOTAL", "1").unwrap();
+        let err = cl.with_indexed_scenario(scenario.clone(), 0, 1).err().unwrap();
+        assert_eq!(
+            err.cause().cause().unwrap().to_string(),
+            "use of reserved variable name: \"SCENARIOS_TOTAL\" (strict mode is enabled)"
+        );
+        assert!(cl.with_scenario(scenario).is_ok());
+    }
+
     #[test]
     fn test_insert_name() {
         let mut cl = CommandLine::new(["echo", "a cool {}!"].iter()).unwrap();
         cl.options_mut().insert_name_in_args = true;
         let output = cl
-            .create_command(iter::empty::<(&str, &str)>(), "name")
+            .create_command(iter::empty::<(&str, &str)>(), &[], "name", None)
             .expect("CommandLine::create_command failed")
             .output()
             .expect("Child::output failed");
         let output = String::from_utf8(output.stdout).unwrap();
         assert_eq!(output, "a cool name!\n");
     }
+
+    #[test]
+    fn test_insert_and_export_independently() {
+        let mut cl = CommandLine::new(["sh", "-c", "echo ${SCENARIOS_NAME-unset} {}"].iter())
+            .unwrap();
+        let cases = [
+            (true, true, "name name\n"),
+            (true, false, "unset name\n"),
+            (false, true, "name {}\n"),
+            (false, false, "unset {}\n"),
+        ];
+        for &(insert, export, expected) in &cases {
+            cl.options_mut().insert_name_in_args = insert;
+            cl.options_mut().add_scenarios_name = export;
+            let output = cl
+                .create_command(iter::empty::<(&str, &str)>(), &[], "name", None)
+                .expect("CommandLine::create_command failed")
+                .output()
+                .expect("Child::output failed");
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+        }
+    }
 }
@@ -0,0 +1,120 @@
+// Copyright 2017 Nico Madysa.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you
+// may not use this file except in compliance with the License. You may
+// obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Expansion of variable references in command-line arguments.
+//!
+//! A reference looks like `${NAME}`, where `NAME` consists of ASCII
+//! letters, digits and underscores. It is replaced with the value of
+//! the variable of that name. Everything else, including `$NAME` and
+//! `${}`, is left alone, so that shell syntax mostly passes through
+//! unchanged.
+
+
+use std::ffi::{OsStr, OsString};
+
+
+/// The string that starts a variable reference.
+const REFERENCE_START: &str = "${";
+
+/// The string that ends a variable reference.
+const REFERENCE_END: char = '}';
+
+
+/// Replaces all variable references in `arg` with their values.
+///
+/// `lookup` returns the value of a variable or `None` if it is not
+/// set. Unset variables expand to the empty string if `allow_unset` is
+/// `true`.
+///
+/// # Errors
+/// This fails if `arg` refers to an unset variable and `allow_unset`
+/// is `false`.
+pub fn expand_variables<'v, F>(
+    arg: &str,
+    lookup: F,
+    allow_unset: bool,
+) -> Result<OsString, UnsetVariable>
+where
+    F: Fn(&str) -> Option<&'v OsStr>,
+{
+    let mut result = OsString::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find(REFERENCE_START) {
+        let (before, reference) = rest.split_at(start);
+        result.push(before);
+        let name = &reference[REFERENCE_START.len()..];
+        let name_len = name
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(name.len());
+        let (name, after) = name.split_at(name_len);
+        if name.is_empty() || !after.starts_with(REFERENCE_END) {
+            // Not a reference after all; keep it literally.
+            result.push(REFERENCE_START);
+            rest = &reference[REFERENCE_START.len()..];
+            continue;
+        }
+        match lookup(name) {
+            Some(value) => result.push(value),
+            None if allow_unset => {},
+            None => return Err(UnsetVariable(name.to_owned())),
+        }
+        rest = &after[REFERENCE_END.len_utf8()..];
+    }
+    result.push(rest);
+    Ok(result)
+}
+
+
+/// The error returned for references to unset variables.
+#[derive(Debug, Fail)]
+#[fail(display = "variable is not set: \"{}\"", _0)]
+pub struct UnsetVariable(pub String);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<&'static OsStr> {
+        match name {
+            "greeting" => Some(OsStr::new("hello")),
+            "empty" => Some(OsStr::new("")),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_no_references() {
+        assert_eq!(expand_variables("plain", lookup, false).unwrap(), "plain");
+        assert_eq!(expand_variables("$greeting ${", lookup, false).unwrap(), "$greeting ${");
+        assert_eq!(expand_variables("${} ${a b}", lookup, false).unwrap(), "${} ${a b}");
+    }
+
+    #[test]
+    fn test_references() {
+        let expanded = expand_variables("${greeting}, ${empty}world", lookup, false).unwrap();
+        assert_eq!(expanded, "hello, world");
+        let expanded = expand_variables("${${greeting}}", lookup, false).unwrap();
+        assert_eq!(expanded, "${hello}");
+    }
+
+    #[test]
+    fn test_unset() {
+        let err = expand_variables("a ${missing} b", lookup, false).unwrap_err();
+        assert_eq!(err.to_string(), "variable is not set: \"missing\"");
+        let expanded = expand_variables("a ${missing} b", lookup, true).unwrap();
+        assert_eq!(expanded, "a  b");
+    }
+}
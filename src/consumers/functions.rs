@@ -0,0 +1,156 @@
+// Copyright 2017 Nico Madysa.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you
+// may not use this file except in compliance with the License. You may
+// obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Built-in functions that may be called in variable values.
+//!
+//! A call looks like `$(name)`, where `name` consists of ASCII
+//! letters, digits and underscores. The following functions exist:
+//!
+//! - `$(uuid)` expands to a random version 4 UUID;
+//! - `$(now)` expands to the current Unix time in seconds.
+//!
+//! Everything else that starts with `$(`, e.g. `$(echo hi)`, is left
+//! alone, so that shell syntax passes through unchanged.
+
+
+use std::{
+    borrow::Cow,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use shuffle;
+
+
+/// The string that starts a function call.
+const CALL_START: &str = "$(";
+
+/// The string that ends a function call.
+const CALL_END: char = ')';
+
+
+/// Replaces all function calls in `value` with their results.
+///
+/// If `value` contains no function calls, it is returned as-is.
+///
+/// # Errors
+/// This fails if `value` calls a function that does not exist.
+pub fn expand_functions(value: &str) -> Result<Cow<'_, str>, UnknownFunction> {
+    if !value.contains(CALL_START) {
+        return Ok(Cow::Borrowed(value));
+    }
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find(CALL_START) {
+        let (before, call) = rest.split_at(start);
+        result.push_str(before);
+        let args = &call[CALL_START.len()..];
+        let name_len = args
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(args.len());
+        let (name, after) = args.split_at(name_len);
+        if name.is_empty() || !after.starts_with(CALL_END) {
+            // Not a call after all; keep it literally.
+            result.push_str(CALL_START);
+            rest = args;
+            continue;
+        }
+        result.push_str(&call_function(name)?);
+        rest = &after[CALL_END.len_utf8()..];
+    }
+    result.push_str(rest);
+    Ok(Cow::Owned(result))
+}
+
+
+/// Calls the function of the given name.
+fn call_function(name: &str) -> Result<String, UnknownFunction> {
+    match name {
+        "uuid" => Ok(random_uuid()),
+        "now" => Ok(unix_time().to_string()),
+        _ => Err(UnknownFunction(name.to_owned())),
+    }
+}
+
+
+/// Returns a random version 4 UUID in its usual hyphenated form.
+///
+/// The randomness is good enough to make collisions unlikely, but it
+/// is not cryptographically secure.
+fn random_uuid() -> String {
+    let high = (shuffle::random_seed() & !0xf000) | 0x4000;
+    let low = (shuffle::random_seed() & !(0xc << 60)) | (0x8 << 60);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff,
+    )
+}
+
+
+/// Returns the number of seconds since the Unix epoch.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+
+/// The error returned for calls to functions that don't exist.
+#[derive(Debug, Fail)]
+#[fail(display = "unknown function: \"$({})\"", _0)]
+pub struct UnknownFunction(pub String);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_calls() {
+        assert_eq!(expand_functions("plain").unwrap(), Cow::Borrowed("plain"));
+        assert_eq!(expand_functions("$HOME $(").unwrap(), "$HOME $(");
+        assert_eq!(expand_functions("$(echo hi) $()").unwrap(), "$(echo hi) $()");
+    }
+
+    #[test]
+    fn test_uuid() {
+        let uuid = expand_functions("$(uuid)").unwrap();
+        let groups: Vec<usize> = uuid.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert!(uuid.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+        assert_eq!(&uuid[14..15], "4");
+        assert!("89ab".contains(&uuid[19..20]));
+        assert_ne!(uuid, expand_functions("$(uuid)").unwrap());
+    }
+
+    #[test]
+    fn test_now() {
+        let stamp = expand_functions("t=$(now);").unwrap();
+        assert!(stamp.starts_with("t="));
+        assert!(stamp.ends_with(';'));
+        let seconds: u64 = stamp[2..stamp.len() - 1].parse().unwrap();
+        assert!(seconds > 1_500_000_000);
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        let err = expand_functions("a $(pwd) b").unwrap_err();
+        assert_eq!(err.to_string(), "unknown function: \"$(pwd)\"");
+    }
+}
@@ -13,6 +13,12 @@
 // permissions and limitations under the License.
 
 
+use std::{
+    cmp,
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
 use failure::{Error, ResultExt};
 use futures::Stream;
 use tokio_core::reactor::Core;
@@ -20,6 +26,7 @@ use tokio_core::reactor::Core;
 use super::{
     children::{FinishedChild, PreparedChild},
     pool::ProcessPool,
+    signals::{Interrupted, Signals},
 };
 
 /// The interface used by [`loop_in_process_pool()`] for callbacks.
@@ -35,8 +42,75 @@ use super::{
 /// [`loop_in_process_pool()`]: ./fn.loop_in_process_pool.html
 pub trait LoopDriver<Item> {
     /// Returns the number of children allowed to run in parallel.
+    ///
+    /// This may be as large as the number of items, in which case all
+    /// of them run at once.
     fn max_num_of_children(&self) -> usize;
 
+    /// Returns the time after which no new children are started.
+    ///
+    /// If this returns `None`, there is no time limit.
+    fn max_total_time(&self) -> Option<Duration>;
+
+    /// Returns `true` if children should be reaped in order.
+    ///
+    /// If this returns `true`, finished children are passed to
+    /// [`on_reap()`] in the order in which they were started, even if
+    /// they finish in a different order. Children that finish early
+    /// are held back until all their predecessors have been reaped.
+    /// Held-back children count against [`max_num_of_children()`], so
+    /// a slow child may keep new ones from starting.
+    ///
+    /// [`on_reap()`]: #tymethod.on_reap
+    /// [`max_num_of_children()`]: #tymethod.max_num_of_children
+    fn preserve_order(&self) -> bool;
+
+    /// Returns `true` if finished children are reaped oldest first.
+    ///
+    /// If several children finish at about the same time, they are
+    /// passed to [`on_reap()`] in the order in which they were started.
+    /// Unlike [`preserve_order()`], nothing is held back. This makes
+    /// reaping a little slower. By default, the order is unspecified.
+    ///
+    /// [`on_reap()`]: #tymethod.on_reap
+    /// [`preserve_order()`]: #tymethod.preserve_order
+    fn reap_in_order(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if no child needs to be started for `item`.
+    ///
+    /// Skipped items are dropped without being passed to
+    /// [`prepare_child()`], and they don't take up an index. By
+    /// default, nothing is skipped.
+    ///
+    /// [`prepare_child()`]: #tymethod.prepare_child
+    fn should_skip(&self, _item: &Item) -> bool {
+        false
+    }
+
+    /// Returns `true` if `item` is the first of a new group.
+    ///
+    /// Before a child is prepared for such an item, the loop waits for
+    /// all running children and then calls [`on_group_start()`]. By
+    /// default, there are no groups.
+    ///
+    /// [`on_group_start()`]: #method.on_group_start
+    fn starts_group(&self, _item: &Item) -> bool {
+        false
+    }
+
+    /// Prepares for the group that starts with `item`.
+    ///
+    /// This is called whenever [`starts_group()`] returns `true`, at a
+    /// point where no children are running. If this returns an error,
+    /// the loop is aborted.
+    ///
+    /// [`starts_group()`]: #method.starts_group
+    fn on_group_start(&mut self, _item: &Item) -> Result<(), Error> {
+        Ok(())
+    }
+
     /// Takes some item and creates a [`PreparedChild`] from it.
     ///
     /// Beside the loop driver, an iterator is passed to the function
@@ -47,7 +121,7 @@ pub trait LoopDriver<Item> {
     ///
     /// [`PreparedChild`]: ./struct.PreparedChild.html
     /// [`loop_in_process_pool()`]: ./fn.loop_in_process_pool.html
-    fn prepare_child(&self, item: Item) -> Result<PreparedChild, Error>;
+    fn prepare_child(&self, item: Item) -> Result<PreparedChild<'_>, Error>;
 
     /// Handles any child processes that have terminated.
     ///
@@ -57,6 +131,17 @@ pub trait LoopDriver<Item> {
     /// function should return an error.
     fn on_reap(&mut self, child: FinishedChild) -> Result<(), Error>;
 
+    /// Observes that the loop ran out of time.
+    ///
+    /// This function is called if [`max_total_time()`] has passed
+    /// before all items could be turned into child processes. In this
+    /// case, the loop stops spawning new children, but still waits for
+    /// the running ones as usual. `num_skipped` is the number of items
+    /// that have not been processed.
+    ///
+    /// [`max_total_time()`]: #tymethod.max_total_time
+    fn on_truncated(&mut self, num_skipped: usize);
+
     /// Observes whether the loop terminated successfully.
     ///
     /// This function is called if the loop was exited not through
@@ -103,7 +188,8 @@ pub trait LoopDriver<Item> {
 /// This goes through the `items` and starts one child process for each
 /// of them. The  number of processes that can run at any time is
 /// limited. A [`LoopDriver`] type is used to drive the loop and answer
-/// callbacks.
+/// callbacks. If the driver sets a time limit, no new child processes
+/// are started once it is exceeded.
 ///
 /// # Errors
 ///
@@ -115,32 +201,124 @@ pub trait LoopDriver<Item> {
 ///
 /// - spawning a child process fails;
 /// - waiting on a child process fails;
+/// - SIGINT or SIGTERM is received;
 /// - any one of the calls to the [`LoopDriver`] fails.
 ///
+/// If the loop is interrupted by a signal, the signal is forwarded to
+/// all running child processes before waiting for them. The same
+/// happens for any further signal received while waiting.
+///
+/// If the driver asks to preserve the order of children, the clean-up
+/// loop tries to keep it as well. Children that are still held back
+/// when the clean-up is done are passed on in order of submission.
+///
 /// [`LoopDriver`]: ./trait.LoopDriver.html
 pub fn loop_in_process_pool<I, D>(items: I, mut driver: D) -> Result<(), Error>
 where
     I: IntoIterator,
     D: LoopDriver<I::Item>,
 {
-    // Initialize the control structures.
-    let mut pool = ProcessPool::new(driver.max_num_of_children());
+    // Initialize the control structures. The pool never holds more
+    // children than there are items, so don't allocate more room.
+    let start = Instant::now();
+    let items = items.into_iter();
+    let capacity = match items.size_hint() {
+        (_, Some(num_items)) => cmp::min(num_items, driver.max_num_of_children()),
+        (_, None) => driver.max_num_of_children(),
+    };
+    let mut pool = ProcessPool::new(capacity);
+    pool.set_keep_order(driver.reap_in_order());
     let mut core = Core::new().context(TokioInitFailed)?;
+    let mut signals = Signals::new(&mut core)?;
+    let mut reorder = if driver.preserve_order() {
+        Some(ReorderBuffer::new())
+    } else {
+        None
+    };
     // Perform the actual loop.
-    let loop_result = loop_inner(&mut core, &mut pool, items, &mut driver);
+    let loop_result = loop_inner(
+        &mut core,
+        &mut signals,
+        &mut pool,
+        &mut reorder,
+        start,
+        items,
+        &mut driver,
+    );
     if let Err(err) = loop_result {
+        forward_signal(&mut pool, &err);
         driver.on_loop_failed(err);
     }
-    // Wait for all remaining children and catch all errors.
-    enum Never {}
-    let _: Result<(), Never> = core.run(pool.reap_all().then(Ok).for_each(|result| {
-        driver.on_cleanup_reap(result);
-        Ok(())
-    }));
+    // Wait for all remaining children and catch all errors. The only
+    // error that can get through is `Interrupted`, which we forward.
+    loop {
+        let cleanup = pool
+            .reap_all()
+            .then(Ok::<_, Error>)
+            .for_each(|result| match result {
+                Ok(child) => deliver(&mut reorder, child, |child| {
+                    driver.on_cleanup_reap(Ok(child));
+                    Ok(())
+                }),
+                Err(err) => {
+                    driver.on_cleanup_reap(Err(err));
+                    Ok(())
+                },
+            });
+        match core.run(signals.guard(cleanup)) {
+            Ok(()) => break,
+            Err(err) => forward_signal(&mut pool, &err),
+        }
+    }
+    // Children whose predecessors never finished are still held back.
+    if let Some(buffer) = reorder {
+        for child in buffer.into_values() {
+            driver.on_cleanup_reap(Ok(child));
+        }
+    }
     driver.on_finish()
 }
 
 
+/// Sends the signal to all children if `error` is [`Interrupted`].
+///
+/// Children with an abort timeout are killed if they don't terminate
+/// in time.
+///
+/// [`Interrupted`]: ./struct.Interrupted.html
+fn forward_signal(pool: &mut ProcessPool, error: &Error) {
+    if let Some(&Interrupted(signal)) = error.downcast_ref() {
+        pool.terminate_all(signal);
+    }
+}
+
+
+/// Passes a finished child on to `on_reap`, in order if requested.
+///
+/// If `reorder` is `None`, `child` is passed on immediately.
+/// Otherwise, it is added to the buffer, and all children that are
+/// next in line are passed on. If `on_reap` fails, the remaining
+/// children stay in the buffer.
+fn deliver<F>(
+    reorder: &mut Option<ReorderBuffer<FinishedChild>>,
+    child: FinishedChild,
+    mut on_reap: F,
+) -> Result<(), Error>
+where
+    F: FnMut(FinishedChild) -> Result<(), Error>,
+{
+    let buffer = match *reorder {
+        Some(ref mut buffer) => buffer,
+        None => return on_reap(child),
+    };
+    buffer.insert(child.index(), child);
+    while let Some(child) = buffer.pop() {
+        on_reap(child)?;
+    }
+    Ok(())
+}
+
+
 /// The actual main loop of [`loop_in_process_pool()`].
 ///
 /// If no error occurs, this function waits for all child processes to
@@ -154,7 +332,10 @@ where
 /// [`loop_in_process_pool()`]: ./fn.loop_in_process_pool.html
 fn loop_inner<I, D>(
     core: &mut Core,
+    signals: &mut Signals,
     pool: &mut ProcessPool,
+    reorder: &mut Option<ReorderBuffer<FinishedChild>>,
+    start: Instant,
     items: I,
     driver: &mut D,
 ) -> Result<(), Error>
@@ -164,23 +345,136 @@ where
 {
     // For each item, wait for a free slot in the proces pool and push
     // it. If spawning or waiting fails, we always bail. All other
-    // failures are the loop driver's business.
-    for item in items {
-        let (slot, finished_child) = core.run(pool.get_slot())?;
+    // failures are the loop driver's business. If we run out of time,
+    // we count the remaining items and stop spawning.
+    let max_total_time = driver.max_total_time();
+    let max_num_of_children = driver.max_num_of_children();
+    let mut items = items.into_iter();
+    let mut index = 0;
+    while let Some(item) = items.next() {
+        if max_total_time.is_some_and(|max| start.elapsed() >= max) {
+            driver.on_truncated(1 + items.count());
+            break;
+        }
+        if driver.should_skip(&item) {
+            continue;
+        }
+        if driver.starts_group(&item) {
+            let reap_all = pool
+                .reap_all()
+                .for_each(|child| deliver(reorder, child, |child| driver.on_reap(child)));
+            core.run(signals.guard(reap_all))?;
+            driver.on_group_start(&item)?;
+        }
+        // Held-back children take up a slot as well. Otherwise, one
+        // slow child could make the buffer grow without bounds.
+        while !pool.is_empty() && pool.len() + num_held_back(reorder) >= max_num_of_children {
+            let finished_child = core.run(signals.guard(pool.reap_one()))?;
+            deliver(reorder, finished_child, |child| driver.on_reap(child))?;
+        }
+        let (slot, finished_child) = core.run(signals.guard(pool.get_slot()))?;
         if let Some(finished_child) = finished_child {
-            driver.on_reap(finished_child)?;
+            deliver(reorder, finished_child, |child| driver.on_reap(child))?;
         }
-        let child = driver.prepare_child(item)?;
+        let mut child = driver.prepare_child(item)?;
+        child.set_index(index);
+        index += 1;
         let child = child.spawn(&core.handle())?;
         slot.fill(child);
     }
     // If nothing has gone wrong until now, we wait for all child
     // processes to terminate, bailing on the first error.
-    core.run(pool.reap_all().for_each(|child| driver.on_reap(child)))?;
+    let reap_all = pool
+        .reap_all()
+        .for_each(|child| deliver(reorder, child, |child| driver.on_reap(child)));
+    core.run(signals.guard(reap_all))?;
     Ok(())
 }
 
+
+/// Returns the number of children held back by `reorder`.
+fn num_held_back<T>(reorder: &Option<ReorderBuffer<T>>) -> usize {
+    reorder.as_ref().map_or(0, ReorderBuffer::len)
+}
+
+
+/// A buffer that puts items back into their original order.
+///
+/// Each item is inserted along with its index in the original
+/// sequence. Items are only popped from the buffer once all items with
+/// a lower index have been popped.
+#[derive(Debug)]
+struct ReorderBuffer<T> {
+    /// The index of the next item to pop.
+    next_index: usize,
+    /// Items that have been inserted, but not popped yet.
+    pending: BTreeMap<usize, T>,
+}
+
+impl<T> ReorderBuffer<T> {
+    /// Creates an empty buffer that expects the item with index zero.
+    fn new() -> Self {
+        ReorderBuffer {
+            next_index: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of items that are held back.
+    fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Adds an item to the buffer.
+    fn insert(&mut self, index: usize, item: T) {
+        debug_assert!(index >= self.next_index, "index inserted twice");
+        self.pending.insert(index, item);
+    }
+
+    /// Removes the next item in line, if it is available.
+    fn pop(&mut self) -> Option<T> {
+        let item = self.pending.remove(&self.next_index)?;
+        self.next_index += 1;
+        Some(item)
+    }
+
+    /// Returns all remaining items in order, regardless of any gaps.
+    fn into_values(self) -> impl Iterator<Item = T> {
+        self.pending.into_values()
+    }
+}
+
 /// The Tokio event loop could not be started
 #[derive(Debug, Fail)]
 #[fail(display = "could not start event loop")]
 pub struct TokioInitFailed;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorder_buffer() {
+        let mut buffer = ReorderBuffer::new();
+        buffer.insert(1, "b");
+        buffer.insert(2, "c");
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.pop(), None);
+        buffer.insert(0, "a");
+        assert_eq!(buffer.pop(), Some("a"));
+        assert_eq!(buffer.pop(), Some("b"));
+        assert_eq!(buffer.pop(), Some("c"));
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.len(), 0);
+    }
+
+    #[test]
+    fn test_reorder_buffer_gaps() {
+        let mut buffer = ReorderBuffer::new();
+        buffer.insert(3, "d");
+        buffer.insert(1, "b");
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.into_values().collect::<Vec<_>>(), vec!["b", "d"]);
+    }
+}
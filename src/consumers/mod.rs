@@ -18,17 +18,31 @@
 
 mod children;
 mod commandline;
+mod expansion;
+mod functions;
 mod lifecycle;
 mod pool;
 mod printer;
+mod runcache;
+mod signals;
 mod tokens;
 
 
 pub use self::{
-    children::{FinishedChild, PreparedChild, RunningChild},
-    commandline::{CommandLine, Options as CommandLineOptions},
+    children::{
+        FinishedChild, PreparedChild, RunningChild, ScenarioFailed, ScenarioNotStarted,
+        SpawnFailed,
+    },
+    commandline::{
+        CommandLine, NameTransform, Options as CommandLineOptions, DEFAULT_APPEND_SEPARATOR,
+        TRUNCATION_SUFFIX_LEN,
+    },
+    expansion::UnsetVariable,
+    functions::UnknownFunction,
     lifecycle::{loop_in_process_pool, LoopDriver},
     pool::{ProcessPool, Select, Slot, WaitForSlot},
-    printer::Printer,
-    tokens::{PoolToken, TokenStock},
+    printer::{Printer, COUNT_PLACEHOLDER, DEFAULT_PLACEHOLDER, NAMES_DELIMITER, NAMES_PLACEHOLDER},
+    runcache::{BadFingerprint, RunCache},
+    signals::{Interrupted, Interruptible, SignalInitFailed, Signals},
+    tokens::{PoolToken, TokenStock, TooManyTokens},
 };
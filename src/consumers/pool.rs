@@ -17,6 +17,7 @@ use std::mem;
 
 use failure;
 use futures::{Async, Future, Poll, Stream};
+use libc::c_int;
 
 use super::children::RunningChild;
 
@@ -28,10 +29,9 @@ use super::children::RunningChild;
 /// remove them from the pool.
 ///
 /// # Panics
-/// In debug mode, this type panics if it is dropped while still
-/// containing child processes. In release mode, any remaining child
-/// processes are killed. It is highly advisable to empty the pool
-/// before dropping it.
+/// Any child processes that remain in the pool when it is dropped are
+/// killed. In debug mode, this type then panics. It is highly
+/// advisable to empty the pool before dropping it.
 ///
 /// [`RunningChild`]: ./struct.RunningChild.html
 /// [`wait_reap()`]: #method.wait_reap
@@ -39,13 +39,48 @@ use super::children::RunningChild;
 pub struct ProcessPool {
     /// The list of currently running child processes.
     children: Vec<RunningChild>,
+    /// Flag that keeps `children` in the order they were added.
+    keep_order: bool,
 }
 
 impl ProcessPool {
     /// Creates a new, empty process pool of the given maximum size.
     pub fn new(capacity: usize) -> Self {
         let children = Vec::with_capacity(capacity);
-        Self { children }
+        Self {
+            children,
+            keep_order: false,
+        }
+    }
+
+    /// Returns `true` if finished children are reaped oldest first.
+    pub fn keeps_order(&self) -> bool {
+        self.keep_order
+    }
+
+    /// Makes the pool reap finished children oldest first.
+    ///
+    /// If several children have finished by the time the pool is
+    /// polled, the one that was added first is returned first. To
+    /// achieve this, removing a child from the pool takes time linear
+    /// in the number of children instead of constant time. By default,
+    /// the order is unspecified.
+    pub fn set_keep_order(&mut self, keep_order: bool) {
+        self.keep_order = keep_order;
+    }
+
+    /// Returns the number of child processes currently in the pool.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Returns the maximum number of child processes in the pool.
+    ///
+    /// This is at least the capacity passed to [`new()`].
+    ///
+    /// [`new()`]: #method.new
+    pub fn capacity(&self) -> usize {
+        self.children.capacity()
     }
 
     /// Returns `true` if no child processes are currently in the pool.
@@ -53,6 +88,44 @@ impl ProcessPool {
         self.children.is_empty()
     }
 
+    /// Sends `signal` to all child processes in the pool.
+    ///
+    /// Failures to send the signal are ignored. They usually mean that
+    /// the child has already terminated, in which case it is reaped as
+    /// usual.
+    pub fn send_signal(&self, signal: c_int) {
+        for child in &self.children {
+            let _ = child.send_signal(signal);
+        }
+    }
+
+    /// Sends `signal` to all child processes and kills them later.
+    ///
+    /// This calls [`RunningChild::terminate_with()`] on each child.
+    /// For children without an abort timeout, this is the same as
+    /// [`send_signal()`]. As there, failures are ignored.
+    ///
+    /// [`RunningChild::terminate_with()`]:
+    /// ./struct.RunningChild.html#method.terminate_with
+    /// [`send_signal()`]: #method.send_signal
+    pub fn terminate_all(&mut self, signal: c_int) {
+        for child in &mut self.children {
+            let _ = child.terminate_with(signal);
+        }
+    }
+
+    /// Forcibly kills all child processes in the pool.
+    ///
+    /// As with [`send_signal()`], failures are ignored. The children
+    /// remain in the pool and still have to be reaped.
+    ///
+    /// [`send_signal()`]: #method.send_signal
+    pub fn kill_all(&mut self) {
+        for child in &mut self.children {
+            let _ = child.kill();
+        }
+    }
+
     /// Returns a stream of finished children.
     ///
     /// The returned stream is not-ready as long as all children are
@@ -68,8 +141,8 @@ impl ProcessPool {
     /// poll the stream.
     ///
     /// [`FinishedChild`]: ./struct.FinishedChild.html
-    pub fn reap_all(&mut self) -> Join<RunningChild> {
-        Join(&mut self.children)
+    pub fn reap_all(&mut self) -> Join<'_, RunningChild> {
+        Join(Select::new(&mut self.children, self.keep_order))
     }
 
     /// Adds a new child process to the pool, if possible.
@@ -90,8 +163,8 @@ impl ProcessPool {
     ///
     /// [`Slot`]: ./struct.Slot.html
     /// [`FinishedChild`]: ./struct.FinishedChild.html
-    pub fn get_slot(&mut self) -> WaitForSlot<RunningChild> {
-        WaitForSlot::Unpolled(&mut self.children)
+    pub fn get_slot(&mut self) -> WaitForSlot<'_, RunningChild> {
+        WaitForSlot::Unpolled(Select::new(&mut self.children, self.keep_order))
     }
 
     /// Returns one finished child.
@@ -107,13 +180,14 @@ impl ProcessPool {
     /// child is still removed from the pool.
     ///
     /// [`FinishedChild`]: ./struct.FinishedChild.html
-    pub fn reap_one(&mut self) -> Select<RunningChild> {
-        Select(&mut self.children)
+    pub fn reap_one(&mut self) -> Select<'_, RunningChild> {
+        Select::new(&mut self.children, self.keep_order)
     }
 }
 
 impl Drop for ProcessPool {
     fn drop(&mut self) {
+        self.kill_all();
         debug_assert!(self.is_empty(), "dropping a non-empty process pool");
     }
 }
@@ -124,7 +198,7 @@ impl Drop for ProcessPool {
 /// [`ProcessPool::get_slot()`]: ./struct.ProcessPool.html#method.get_slot
 pub enum WaitForSlot<'a, T: 'a> {
     /// Initial state.
-    Unpolled(&'a mut Vec<T>),
+    Unpolled(Select<'a, T>),
     /// The pool is full and we are waiting on a spot to become free.
     Waiting(Select<'a, T>),
     /// The future has finished and will never give a slot again.
@@ -143,18 +217,18 @@ where
         // Set the future to a dummy state while we're processing it.
         let future = mem::replace(self, WaitForSlot::SlotTaken);
         let mut select = match future {
-            WaitForSlot::Unpolled(vec) => {
-                if vec.len() < vec.capacity() {
-                    return Ok(Async::Ready((Slot(vec), None)));
+            WaitForSlot::Unpolled(select) => {
+                if select.items.len() < select.items.capacity() {
+                    return Ok(Async::Ready((Slot(select.items), None)));
                 }
-                Select(vec)
+                select
             },
             WaitForSlot::Waiting(select) => select,
             WaitForSlot::SlotTaken => panic!("slot already taken"),
         };
         // The pool is full, check if a spot has become free.
         match select.poll()? {
-            Async::Ready(result) => Ok(Async::Ready((Slot(select.0), Some(result)))),
+            Async::Ready(result) => Ok(Async::Ready((Slot(select.items), Some(result)))),
             Async::NotReady => {
                 *self = WaitForSlot::Waiting(select);
                 Ok(Async::NotReady)
@@ -184,17 +258,17 @@ impl<'a, T: 'a> Slot<'a, T> {
 /// Future returned by [`ProcessPool::reap_all()`].
 ///
 /// [`ProcessPool::reap_all()`]: ./struct.ProcessPool.html#method.reap_all
-pub struct Join<'a, T: 'a>(&'a mut Vec<T>);
+pub struct Join<'a, T: 'a>(Select<'a, T>);
 
 impl<'a, T: 'a + Future> Stream for Join<'a, T> {
     type Item = T::Item;
     type Error = T::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        if self.0.is_empty() {
+        if self.0.items.is_empty() {
             Ok(Async::Ready(None))
         } else {
-            Select(self.0)
+            self.0
                 .poll()
                 .map(|result: Async<T::Item>| result.map(Some))
         }
@@ -205,23 +279,40 @@ impl<'a, T: 'a + Future> Stream for Join<'a, T> {
 /// Future returned by [`ProcessPool::reap_one()`].
 ///
 /// [`ProcessPool::reap_one()`]: ./struct.ProcessPool.html#method.reap_one
-pub struct Select<'a, T: 'a>(&'a mut Vec<T>);
+pub struct Select<'a, T: 'a> {
+    /// The futures to select from.
+    items: &'a mut Vec<T>,
+    /// Flag that keeps the order of `items` when removing one.
+    keep_order: bool,
+}
+
+impl<'a, T: 'a> Select<'a, T> {
+    /// Creates a future that selects from `items`.
+    fn new(items: &'a mut Vec<T>, keep_order: bool) -> Self {
+        Select { items, keep_order }
+    }
+}
 
 impl<'a, T: 'a + Future> Future for Select<'a, T> {
     type Item = T::Item;
     type Error = T::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // Find the first future that has become ready.
+        // Find the first future that has become ready. If we keep the
+        // order, this is the oldest one.
         let item = self
-            .0
+            .items
             .iter_mut()
             .map(Future::poll)
             .enumerate()
-            .find(|&(_, ref poll)| is_ready_or_err(poll));
+            .find(|(_, poll)| is_ready_or_err(poll));
         // If there is one, discard it and return its result.
         if let Some((index, result)) = item {
-            self.0.swap_remove(index);
+            if self.keep_order {
+                self.items.remove(index);
+            } else {
+                self.items.swap_remove(index);
+            }
             result
         } else {
             Ok(Async::NotReady)
@@ -237,3 +328,61 @@ fn is_ready_or_err<T, E>(poll: &Poll<T, E>) -> bool {
         Ok(Async::NotReady) => false,
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{ffi::OsStr, process::Command};
+
+    use futures::future;
+
+    use tokio_core::reactor::Core;
+
+    use super::super::children::PreparedChild;
+
+    #[test]
+    fn test_len_and_capacity() {
+        let mut core = Core::new().unwrap();
+        let mut pool = ProcessPool::new(2);
+        assert_eq!(pool.capacity(), 2);
+        assert_eq!(pool.len(), 0);
+        for name in &["a", "b"] {
+            let program = OsStr::new("true");
+            let child = PreparedChild::new(name.to_string(), program, Command::new(program));
+            let child = child.spawn(&core.handle()).unwrap();
+            let (slot, reaped) = pool.get_slot().wait().unwrap();
+            assert!(reaped.is_none());
+            slot.fill(child);
+        }
+        assert_eq!(pool.len(), 2);
+        core.run(pool.reap_one()).unwrap();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(core.run(pool.reap_all().collect()).unwrap().len(), 1);
+        assert!(pool.is_empty());
+        assert_eq!(pool.capacity(), 2);
+    }
+
+    #[test]
+    fn test_keep_order() {
+        let mut pool = ProcessPool::new(4);
+        pool.set_keep_order(true);
+        assert!(pool.keeps_order());
+        // All futures are ready from the start, so the order in which
+        // they are selected only depends on how they are removed.
+        let select_all = |keep_order| {
+            let mut items: Vec<_> = ["a", "b", "c", "d"]
+                .iter()
+                .map(|&name| future::ok::<_, ()>(name))
+                .collect();
+            let mut names = Vec::new();
+            while !items.is_empty() {
+                names.push(Select::new(&mut items, keep_order).wait().unwrap());
+            }
+            names
+        };
+        assert_eq!(select_all(true), ["a", "b", "c", "d"]);
+        assert_eq!(select_all(false), ["a", "d", "c", "b"]);
+    }
+}
@@ -17,21 +17,41 @@ use std::io::{self, Write};
 
 use scenarios::Scenario;
 
-/// The string pattern that gets replaced in `Printer::template`.
-const PATTERN: &str = "{}";
+/// The string that gets replaced in `Printer::template` by default.
+pub const DEFAULT_PLACEHOLDER: &str = "{}";
+
+/// The string that gets replaced by the number of variables.
+pub const COUNT_PLACEHOLDER: &str = "{count}";
+
+/// The string that gets replaced by the names of all variables.
+pub const NAMES_PLACEHOLDER: &str = "{names}";
+
+/// The string placed between variable names for `NAMES_PLACEHOLDER`.
+pub const NAMES_DELIMITER: &str = ",";
 
 /// A consumer of [`Scenario`]s that prints their names to stdout.
 ///
 /// This is a very simple run-time formatter. It takes a template
-/// string, replaces all occurrences of `"{}"` in it with a given
-/// string, then appends a terminator string to the result. No
-/// validation nor sanitation takes place.
+/// string, replaces all occurrences of a placeholder (by default
+/// `"{}"`) in it with a given string, then appends a terminator string
+/// to the result. No validation nor sanitation takes place.
+///
+/// When printing a [`Scenario`], two more strings are replaced:
+/// [`COUNT_PLACEHOLDER`] by the number of variables the scenario
+/// defines, and [`NAMES_PLACEHOLDER`] by their sorted names, separated
+/// by [`NAMES_DELIMITER`].
+///
+/// [`COUNT_PLACEHOLDER`]: ./constant.COUNT_PLACEHOLDER.html
+/// [`NAMES_PLACEHOLDER`]: ./constant.NAMES_PLACEHOLDER.html
+/// [`NAMES_DELIMITER`]: ./constant.NAMES_DELIMITER.html
 ///
 /// [`Scenario`]: ../scenarios/struct.Scenario.html
 #[derive(Debug)]
 pub struct Printer<'tpl, 'trm> {
-    /// A string in which `PATTERN` is replaced by the scenario name.
+    /// A string in which `placeholder` is replaced by the scenario name.
     template: &'tpl str,
+    /// The string that is replaced in `template`.
+    placeholder: &'tpl str,
     /// A string printed after each template.
     terminator: &'trm str,
 }
@@ -45,6 +65,7 @@ impl<'tpl, 'trm> Printer<'tpl, 'trm> {
     pub fn new(template: &'tpl str, terminator: &'trm str) -> Self {
         Printer {
             template,
+            placeholder: DEFAULT_PLACEHOLDER,
             terminator,
         }
     }
@@ -68,6 +89,19 @@ impl<'tpl, 'trm> Printer<'tpl, 'trm> {
         self.template = template;
     }
 
+    /// Returns the string that is replaced in the template.
+    pub fn placeholder(&self) -> &str {
+        self.placeholder
+    }
+
+    /// Changes the string that is replaced in the template.
+    ///
+    /// If `placeholder` is empty, nothing is replaced and the template
+    /// is used as-is.
+    pub fn set_placeholder(&mut self, placeholder: &'tpl str) {
+        self.placeholder = placeholder;
+    }
+
     /// Returns the printer's terminator string.
     pub fn terminator(&self) -> &str {
         self.terminator
@@ -92,15 +126,79 @@ impl<'tpl, 'trm> Printer<'tpl, 'trm> {
     /// assert_eq!(p.format("hello world"), "hello world\n");
     /// ```
     pub fn format(&self, s: &str) -> String {
-        let mut result = self.template.replace(PATTERN, s);
-        result.push_str(self.terminator);
+        Self::format_template(self.template, self.placeholder, s, self.terminator)
+    }
+
+    /// Applies the printer to a scenario.
+    ///
+    /// This is like [`format()`] with the scenario's name, but also
+    /// replaces [`COUNT_PLACEHOLDER`] and [`NAMES_PLACEHOLDER`] in the
+    /// template.
+    ///
+    /// [`format()`]: #method.format
+    /// [`COUNT_PLACEHOLDER`]: ./constant.COUNT_PLACEHOLDER.html
+    /// [`NAMES_PLACEHOLDER`]: ./constant.NAMES_PLACEHOLDER.html
+    pub fn format_scenario(&self, scenario: &Scenario) -> String {
+        // Skip the work for the common case of a plain template.
+        if !self.template.contains(COUNT_PLACEHOLDER) && !self.template.contains(NAMES_PLACEHOLDER)
+        {
+            return self.format(scenario.name());
+        }
+        let mut names: Vec<&str> = scenario.variable_names().cloned().collect();
+        names.sort();
+        let template = self
+            .template
+            .replace(COUNT_PLACEHOLDER, &names.len().to_string())
+            .replace(NAMES_PLACEHOLDER, &names.join(NAMES_DELIMITER));
+        Self::format_template(&template, self.placeholder, scenario.name(), self.terminator)
+    }
+
+    /// Implementation of [`format()`] with an explicit template.
+    ///
+    /// [`format()`]: #method.format
+    fn format_template(template: &str, placeholder: &str, s: &str, terminator: &str) -> String {
+        let mut result = if placeholder.is_empty() {
+            template.to_owned()
+        } else {
+            template.replace(placeholder, s)
+        };
+        result.push_str(terminator);
         result
     }
 
-    /// Formats the scenario's name and prints it to `stdout`.
+    /// Formats the scenario and writes it to `writer`.
+    ///
+    /// # Errors
+    /// This fails if writing to `writer` fails.
+    pub fn write_scenario<W: Write>(&self, writer: &mut W, scenario: &Scenario) -> io::Result<()> {
+        let s = self.format_scenario(scenario);
+        writer.write_all(s.as_bytes())
+    }
+
+    /// Writes the variables of the scenario to `writer`.
+    ///
+    /// Each variable is written as a line `NAME=value`, sorted by
+    /// name. The terminator is written after the last line. The
+    /// template is not used.
+    ///
+    /// # Errors
+    /// This fails if writing to `writer` fails.
+    pub fn write_variables<W: Write>(&self, writer: &mut W, scenario: &Scenario) -> io::Result<()> {
+        for (name, value) in scenario.variables_sorted() {
+            writeln!(writer, "{}={}", name, value)?;
+        }
+        writer.write_all(self.terminator.as_bytes())
+    }
+
+    /// Formats the scenario and prints it to `stdout`.
+    ///
+    /// # Panics
+    /// This panics if writing to `stdout` fails. Use
+    /// [`write_scenario()`] to handle such errors.
+    ///
+    /// [`write_scenario()`]: #method.write_scenario
     pub fn print_scenario(&self, scenario: &Scenario) {
-        let s = self.format(scenario.name());
-        io::stdout().write_all(s.as_bytes()).unwrap();
+        self.write_scenario(&mut io::stdout(), scenario).unwrap();
     }
 }
 
@@ -111,7 +209,8 @@ impl<'a, 'b> Default for Printer<'a, 'b> {
     /// and a newline `"\n"` for the `terminator`.
     fn default() -> Self {
         Printer {
-            template: PATTERN,
+            template: DEFAULT_PLACEHOLDER,
+            placeholder: DEFAULT_PLACEHOLDER,
             terminator: "\n",
         }
     }
@@ -140,6 +239,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_placeholder() {
+        let mut printer = Printer::new("find {} -name %%", "");
+        printer.set_placeholder("%%");
+        assert_eq!(printer.format("a.txt"), "find {} -name a.txt");
+        printer.set_placeholder("");
+        assert_eq!(printer.format("a.txt"), "find {} -name %%");
+    }
+
+    #[test]
+    fn test_write_scenario() {
+        let mut buffer = Vec::new();
+        let printer = Printer::new("<{}>", "\0");
+        for name in &["first", "second"] {
+            let scenario = Scenario::new(*name).unwrap();
+            printer.write_scenario(&mut buffer, &scenario).unwrap();
+        }
+        assert_eq!(buffer, b"<first>\0<second>\0");
+    }
+
+    #[test]
+    fn test_write_variables() {
+        let mut buffer = Vec::new();
+        let mut scenario = Scenario::new("name").unwrap();
+        scenario.add_variable("b", "1").unwrap();
+        scenario.add_variable("a", "x=y").unwrap();
+        Printer::default().write_variables(&mut buffer, &scenario).unwrap();
+        Printer::new("ignored", "--\n").write_variables(&mut buffer, &scenario).unwrap();
+        assert_eq!(buffer, &b"a=x=y\nb=1\n\na=x=y\nb=1\n--\n"[..]);
+    }
+
+    #[test]
+    fn test_format_scenario() {
+        let mut scenario = Scenario::new("name").unwrap();
+        scenario.add_variable("b", "1").unwrap();
+        scenario.add_variable("a", "2").unwrap();
+        let printer = Printer::new("{}: {count} ({names})", "");
+        assert_eq!(printer.format_scenario(&scenario), "name: 2 (a,b)");
+        // Scenario names are inserted last and never expanded.
+        let scenario = Scenario::new("{count}").unwrap();
+        assert_eq!(printer.format_scenario(&scenario), "{count}: 0 ()");
+    }
+
     #[test]
     fn test_broken_pattern() {
         assert_eq!(
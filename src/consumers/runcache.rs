@@ -0,0 +1,251 @@
+// Copyright 2017 Nico Madysa.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you
+// may not use this file except in compliance with the License. You may
+// obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+
+//! Remembers which scenarios have already run successfully.
+//!
+//! Each scenario is identified by a fingerprint, a hash of its name,
+//! its variables and the command line that runs it. The fingerprints
+//! of successful runs are kept in a state file, one per line, so that
+//! the next run can skip scenarios that haven't changed.
+
+
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs,
+    hash::Hasher,
+    io,
+    path::{Path, PathBuf},
+};
+
+use failure::Error;
+
+use scenarios::Scenario;
+
+
+/// The fingerprints of all successful runs, read from a state file.
+#[derive(Debug)]
+pub struct RunCache {
+    path: PathBuf,
+    /// Fingerprints read from the state file.
+    previous: HashSet<u64>,
+    /// Fingerprints of scenarios that succeeded in this run.
+    succeeded: HashSet<u64>,
+}
+
+impl RunCache {
+    /// Reads the state file at `path`.
+    ///
+    /// If the file does not exist, the cache starts out empty.
+    ///
+    /// # Errors
+    /// This fails if the file cannot be read or contains anything but
+    /// fingerprints.
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let previous = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                u64::from_str_radix(line.trim(), 16).map_err(|_| BadFingerprint(line.to_owned()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RunCache {
+            path,
+            previous,
+            succeeded: HashSet::new(),
+        })
+    }
+
+    /// Returns the path of the state file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Computes the fingerprint of `scenario` run by `command_line`.
+    ///
+    /// The order in which variables were defined does not matter. The
+    /// fingerprint is the same on all platforms.
+    pub fn fingerprint<S: AsRef<OsStr>>(scenario: &Scenario, command_line: &[S]) -> u64 {
+        let mut removals: Vec<&str> = scenario.removals().cloned().collect();
+        removals.sort();
+        let mut appends: Vec<(&str, &Vec<&str>)> = scenario
+            .appends()
+            .map(|(&name, values)| (name, values))
+            .collect();
+        appends.sort();
+        let mut hasher = Fnv1a::new();
+        hasher.write_field(scenario.name().as_bytes());
+        let variables = scenario.variables_sorted();
+        hasher.write_len(variables.len());
+        for (name, value) in variables {
+            hasher.write_field(name.as_bytes());
+            hasher.write_field(value.as_bytes());
+        }
+        hasher.write_len(removals.len());
+        for name in removals {
+            hasher.write_field(name.as_bytes());
+        }
+        hasher.write_len(appends.len());
+        for (name, values) in appends {
+            hasher.write_field(name.as_bytes());
+            hasher.write_len(values.len());
+            for value in values {
+                hasher.write_field(value.as_bytes());
+            }
+        }
+        hasher.write_len(command_line.len());
+        for arg in command_line {
+            hasher.write_field(arg.as_ref().as_encoded_bytes());
+        }
+        hasher.finish()
+    }
+
+    /// Returns `true` if `fingerprint` belongs to a successful run.
+    pub fn is_unchanged(&self, fingerprint: u64) -> bool {
+        self.previous.contains(&fingerprint) || self.succeeded.contains(&fingerprint)
+    }
+
+    /// Remembers that the scenario with `fingerprint` has succeeded.
+    pub fn record_success(&mut self, fingerprint: u64) {
+        self.succeeded.insert(fingerprint);
+    }
+
+    /// Writes all known fingerprints back to the state file.
+    ///
+    /// # Errors
+    /// This fails if the file cannot be written.
+    pub fn save(&self) -> io::Result<()> {
+        let mut fingerprints: Vec<u64> = self.previous.union(&self.succeeded).cloned().collect();
+        fingerprints.sort();
+        let contents: String = fingerprints
+            .iter()
+            .map(|fingerprint| format!("{:016x}\n", fingerprint))
+            .collect();
+        fs::write(&self.path, contents)
+    }
+}
+
+
+/// The 64-bit FNV-1a hash function.
+///
+/// Unlike the hasher of the standard library, its output is fixed, so
+/// fingerprints can be stored across runs.
+pub(super) struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    pub(super) fn new() -> Self {
+        Fnv1a(Self::OFFSET_BASIS)
+    }
+
+    /// Feeds a length into the hash as a little-endian `u64`.
+    ///
+    /// Unlike [`Hasher::write_usize()`], this gives the same result
+    /// regardless of the platform's pointer width and byte order.
+    ///
+    /// [`Hasher::write_usize()`]:
+    /// https://doc.rust-lang.org/std/hash/trait.Hasher.html#method.write_usize
+    fn write_len(&mut self, len: usize) {
+        self.write(&(len as u64).to_le_bytes());
+    }
+
+    /// Feeds `bytes` into the hash, preceded by their length.
+    ///
+    /// The length prefix keeps neighboring fields apart, so that e.g.
+    /// `"ab", "c"` and `"a", "bc"` hash differently.
+    fn write_field(&mut self, bytes: &[u8]) {
+        self.write_len(bytes.len());
+        self.write(bytes);
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+
+/// A line of the state file is not a fingerprint.
+#[derive(Debug, Fail)]
+#[fail(display = "not a fingerprint: {:?}", _0)]
+pub struct BadFingerprint(String);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_scenario<'a>(name: &'a str, variables: &[(&'a str, &'a str)]) -> Scenario<'a> {
+        let mut scenario = Scenario::new(name).unwrap();
+        for &(name, value) in variables {
+            scenario.add_variable(name, value).unwrap();
+        }
+        scenario
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let a = make_scenario("s", &[("a", "1"), ("b", "2")]);
+        let b = make_scenario("s", &[("b", "2"), ("a", "1")]);
+        let command = ["echo", "hi"];
+        assert_eq!(RunCache::fingerprint(&a, &command), RunCache::fingerprint(&b, &command));
+        let c = make_scenario("s", &[("a", "1"), ("b", "3")]);
+        assert_ne!(RunCache::fingerprint(&a, &command), RunCache::fingerprint(&c, &command));
+        let other_command = ["echo", "ho"];
+        assert_ne!(RunCache::fingerprint(&a, &command), RunCache::fingerprint(&a, &other_command));
+        let split = ["ec", "hohi"];
+        assert_ne!(RunCache::fingerprint(&a, &command), RunCache::fingerprint(&a, &split));
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable() {
+        // Fingerprints are stored across runs and must never change.
+        let mut scenario = make_scenario("s", &[("a", "1")]);
+        scenario.add_removal("b").unwrap();
+        assert_eq!(RunCache::fingerprint(&scenario, &["true"]), 0x23ca_d9cd_71e2_a5cb);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("scenarios-runcache-{}", std::process::id()));
+        let mut cache = RunCache::load(&path).unwrap();
+        assert!(!cache.is_unchanged(42));
+        cache.record_success(42);
+        assert!(cache.is_unchanged(42));
+        cache.save().unwrap();
+        let cache = RunCache::load(&path).unwrap();
+        assert!(cache.is_unchanged(42));
+        assert!(!cache.is_unchanged(43));
+        fs::write(&path, "garbage\n").unwrap();
+        assert!(RunCache::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2017 Nico Madysa.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you
+// may not use this file except in compliance with the License. You may
+// obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Provides a way to abort the main loop on SIGINT and SIGTERM.
+//!
+//! Only Unix systems have proper signals. Elsewhere, only Ctrl-C is
+//! caught; it is reported as SIGINT.
+
+
+use std::{fmt, io};
+
+use failure::{Error, ResultExt};
+use futures::{Async, Future, Poll, Stream};
+use libc::c_int;
+use tokio_core::reactor::Core;
+
+
+#[cfg(unix)]
+pub use libc::{SIGINT, SIGTERM};
+
+/// The number of SIGINT in the C runtime.
+#[cfg(not(unix))]
+pub const SIGINT: c_int = 2;
+
+/// The number of SIGTERM in the C runtime.
+#[cfg(not(unix))]
+pub const SIGTERM: c_int = 15;
+
+
+/// A stream of the termination signals received by this process.
+///
+/// Once this type has been created, SIGINT and SIGTERM no longer
+/// terminate the process. Instead, they are caught and yielded by this
+/// stream. This stays so even after the stream has been dropped.
+///
+/// Should the underlying stream ever fail, it is discarded and no
+/// further signals are reported.
+pub struct Signals(Option<Box<dyn Stream<Item = c_int, Error = io::Error>>>);
+
+impl Signals {
+    /// Installs handlers for SIGINT and SIGTERM.
+    ///
+    /// # Errors
+    /// This fails if the signal handlers cannot be installed.
+    #[cfg(unix)]
+    pub fn new(core: &mut Core) -> Result<Self, Error> {
+        use tokio_signal::unix::Signal;
+
+        let handle = core.handle();
+        let signals = Signal::new(SIGINT, &handle).join(Signal::new(SIGTERM, &handle));
+        let (sigint, sigterm) = core.run(signals).context(SignalInitFailed)?;
+        Ok(Signals(Some(Box::new(sigint.select(sigterm)))))
+    }
+
+    /// Installs a handler for Ctrl-C.
+    ///
+    /// # Errors
+    /// This fails if the handler cannot be installed.
+    #[cfg(not(unix))]
+    pub fn new(core: &mut Core) -> Result<Self, Error> {
+        let ctrl_c = ::tokio_signal::ctrl_c(&core.handle());
+        let ctrl_c = core.run(ctrl_c).context(SignalInitFailed)?;
+        Ok(Signals(Some(Box::new(ctrl_c.map(|()| SIGINT)))))
+    }
+
+    /// Returns the next signal, if one has arrived.
+    fn poll_signal(&mut self) -> Option<c_int> {
+        let result = match self.0 {
+            Some(ref mut stream) => stream.poll(),
+            None => return None,
+        };
+        match result {
+            Ok(Async::Ready(Some(signal))) => Some(signal),
+            Ok(Async::NotReady) => None,
+            Ok(Async::Ready(None)) | Err(_) => {
+                self.0 = None;
+                None
+            },
+        }
+    }
+
+    /// Wraps a future so that it fails when a signal arrives.
+    ///
+    /// See [`Interruptible`] for more information.
+    ///
+    /// [`Interruptible`]: ./struct.Interruptible.html
+    pub fn guard<F: Future>(&mut self, future: F) -> Interruptible<'_, F> {
+        Interruptible {
+            future,
+            signals: self,
+        }
+    }
+}
+
+
+/// Future returned by [`Signals::guard()`].
+///
+/// This future behaves like the wrapped one. However, if a signal
+/// arrives before the wrapped future has finished, this future fails
+/// with an [`Interrupted`] error.
+///
+/// [`Signals::guard()`]: ./struct.Signals.html#method.guard
+/// [`Interrupted`]: ./struct.Interrupted.html
+pub struct Interruptible<'a, F> {
+    future: F,
+    signals: &'a mut Signals,
+}
+
+impl<'a, F> Future for Interruptible<'a, F>
+where
+    F: Future,
+    Error: From<F::Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(signal) = self.signals.poll_signal() {
+            return Err(Interrupted(signal).into());
+        }
+        self.future.poll().map_err(Error::from)
+    }
+}
+
+
+/// The main loop has been interrupted by a signal.
+#[derive(Debug, Fail)]
+pub struct Interrupted(pub c_int);
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            SIGINT => write!(f, "interrupted by SIGINT"),
+            SIGTERM => write!(f, "interrupted by SIGTERM"),
+            other => write!(f, "interrupted by signal {}", other),
+        }
+    }
+}
+
+
+/// The signal handlers could not be installed.
+#[derive(Debug, Fail)]
+#[fail(display = "could not handle signals")]
+pub struct SignalInitFailed;
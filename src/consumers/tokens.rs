@@ -12,6 +12,8 @@
 // implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
+use std::fmt;
+
 
 /// A stock of [`PoolToken`]s.
 ///
@@ -30,12 +32,22 @@
 pub struct TokenStock {
     /// The number of tokens remaining in this stock.
     num_tokens: usize,
+    /// The number of tokens this stock was created with.
+    capacity: usize,
 }
 
 impl TokenStock {
     /// Creates a new stock with an initial size of `num_tokens`.
     pub fn new(num_tokens: usize) -> Self {
-        Self { num_tokens }
+        Self {
+            num_tokens,
+            capacity: num_tokens,
+        }
+    }
+
+    /// Returns the number of tokens this stock was created with.
+    pub fn capacity(&self) -> usize {
+        self.capacity
     }
 
     /// Returns the number of currently available tokens.
@@ -54,9 +66,37 @@ impl TokenStock {
     }
 
     /// Accepts a previously handed-out token back into the stock.
+    ///
+    /// # Panics
+    /// In debug builds, this panics if the stock is already full. Use
+    /// [`try_return()`] to handle this case gracefully.
+    ///
+    /// [`try_return()`]: #method.try_return
     pub fn return_token(&mut self, _: PoolToken) {
+        debug_assert!(
+            self.num_tokens < self.capacity,
+            "returned more tokens than were handed out"
+        );
         self.num_tokens += 1;
     }
+
+    /// Like [`return_token()`], but checks that the stock isn't full.
+    ///
+    /// # Errors
+    /// If the stock already contains as many tokens as it was created
+    /// with, the token is dropped and [`TooManyTokens`] is returned.
+    /// The number of remaining tokens is unchanged in that case.
+    ///
+    /// [`return_token()`]: #method.return_token
+    /// [`TooManyTokens`]: ./struct.TooManyTokens.html
+    pub fn try_return(&mut self, _: PoolToken) -> Result<(), TooManyTokens> {
+        if self.num_tokens < self.capacity {
+            self.num_tokens += 1;
+            Ok(())
+        } else {
+            Err(TooManyTokens(self.capacity))
+        }
+    }
 }
 
 impl Default for TokenStock {
@@ -76,3 +116,62 @@ impl Default for TokenStock {
 #[derive(Debug)]
 #[must_use]
 pub struct PoolToken(());
+
+
+/// Returned by [`TokenStock::try_return()`] if the stock is full.
+///
+/// This indicates a bug: more tokens were returned to the stock than
+/// were handed out by it.
+///
+/// [`TokenStock::try_return()`]: ./struct.TokenStock.html#method.try_return
+#[derive(Debug, Fail)]
+pub struct TooManyTokens(usize);
+
+impl fmt::Display for TooManyTokens {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "token returned to a full stock, its capacity is {}",
+            self.0
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_return() {
+        let mut stock = TokenStock::new(2);
+        let first = stock.get_token().unwrap();
+        let second = stock.get_token().unwrap();
+        assert!(stock.get_token().is_none());
+        assert_eq!(stock.num_remaining(), 0);
+        stock.try_return(first).unwrap();
+        stock.return_token(second);
+        assert_eq!(stock.num_remaining(), stock.capacity());
+    }
+
+    #[test]
+    fn test_try_return_overflow() {
+        let mut stock = TokenStock::new(1);
+        let token = stock.get_token().unwrap();
+        stock.try_return(token).unwrap();
+        let err = stock.try_return(PoolToken(())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "token returned to a full stock, its capacity is 1"
+        );
+        assert_eq!(stock.num_remaining(), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "returned more tokens than were handed out")]
+    fn test_return_token_overflow() {
+        let mut stock = TokenStock::default();
+        stock.return_token(PoolToken(()));
+    }
+}
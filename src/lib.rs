@@ -0,0 +1,45 @@
+// Copyright 2017 Nico Madysa.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you
+// may not use this file except in compliance with the License. You may
+// obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! The library behind the command-line tool [`scenarios`].
+//!
+//! This crate contains everything that is not specific to the command
+//! line: reading and merging [`Scenario`]s, building their cartesian
+//! product, and running commands in them. The tool itself is a thin
+//! layer on top of this.
+//!
+//! [`scenarios`]: https://github.com/troiganto/scenarios
+//! [`Scenario`]: ./scenarios/struct.Scenario.html
+
+// Not every helper is used by the command-line tool.
+#![allow(dead_code)]
+#![allow(clippy::new_ret_no_self)]
+
+#[macro_use]
+extern crate failure;
+#[macro_use]
+extern crate futures;
+extern crate glob;
+extern crate libc;
+extern crate tokio_core;
+extern crate tokio_process;
+extern crate tokio_signal;
+
+
+pub mod cartesian;
+pub mod consumers;
+pub mod scenarios;
+pub mod shuffle;
+pub mod trytostr;
@@ -27,14 +27,72 @@
 //!
 //! All we are interested in is printing to standard error unless a
 //! `quiet` flag is set. Should be simple enough to roll out on our
-//! own!
+//! own! The same goes for colors: a handful of ANSI escape codes do
+//! not warrant a terminal library. Neither does writing a flat JSON
+//! object per message warrant a serialization library.
 
 use std::{
-    fmt::Display,
-    io::{self, Write},
+    fmt::{Display, Write as FmtWrite},
+    io::{self, IsTerminal, Write},
 };
 
-use failure::Error;
+use failure::{Context, Error, Fail};
+
+use consumers::{ScenarioFailed, ScenarioNotStarted};
+
+
+/// ANSI escape code that starts red, bold text.
+const RED: &str = "\x1b[1;31m";
+/// ANSI escape code that starts yellow text.
+const YELLOW: &str = "\x1b[33m";
+/// ANSI escape code that resets all text attributes.
+const RESET: &str = "\x1b[0m";
+
+
+/// Decides whether a [`Logger`] colors its output.
+///
+/// [`Logger`]: ./struct.Logger.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Use colors if standard error is a terminal.
+    #[default]
+    Auto,
+    /// Always use colors.
+    Always,
+    /// Never use colors.
+    Never,
+}
+
+impl ColorChoice {
+    /// Returns `true` if output to stderr should be colored.
+    ///
+    /// For `Auto`, this checks whether stderr refers to a terminal.
+    pub fn use_color(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stderr().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+
+/// The format in which a [`Logger`] writes messages.
+///
+/// [`Logger`]: ./struct.Logger.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable lines prefixed with the application name.
+    #[default]
+    Human,
+    /// One JSON object per line.
+    ///
+    /// Each object has the fields `"level"` (`"info"`, `"warning"` or
+    /// `"error"`) and `"message"`. Errors additionally have an array of
+    /// `"causes"` and, if they concern a scenario, its name in
+    /// `"scenario"`.
+    Json,
+}
 
 
 pub struct Logger<'a> {
@@ -42,35 +100,77 @@ pub struct Logger<'a> {
     name: &'a str,
     /// If set to `true`, suppresses all output.
     quiet: bool,
+    /// If set to `true`, error prefixes are highlighted.
+    color: bool,
+    /// The format in which messages are written.
+    format: LogFormat,
 }
 
 impl Logger<'static> {
     /// Creates a logger with the default name [`crate_name!`].
     ///
     /// [`crate_name!`]: ../../clap/macro.crate_name.html
-    pub fn new(quiet: bool) -> Self {
-        Logger::with_name(crate_name!(), quiet)
+    pub fn new(quiet: bool, color: ColorChoice) -> Self {
+        Logger::with_name(crate_name!(), quiet, color)
     }
 }
 
 impl<'a> Logger<'a> {
     /// Creates a logger with a custom name.
-    pub fn with_name(name: &'a str, quiet: bool) -> Self {
-        Logger { name, quiet }
+    pub fn with_name(name: &'a str, quiet: bool, color: ColorChoice) -> Self {
+        let color = color.use_color();
+        Logger {
+            name,
+            quiet,
+            color,
+            format: LogFormat::Human,
+        }
+    }
+
+    /// Changes the format in which messages are written.
+    pub fn set_format(&mut self, format: LogFormat) {
+        self.format = format;
     }
 
     /// Prints the given message to stderr.
     pub fn log<D: Display>(&self, message: D) {
-        if !self.quiet {
-            eprintln!("{}: {}", self.name, message);
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            LogFormat::Human => eprintln!("{}: {}", self.name, message),
+            LogFormat::Json => eprintln!(
+                "{{\"level\":\"info\",\"message\":{}}}",
+                json_string(&message.to_string())
+            ),
+        }
+    }
+
+    /// Prints the given message to stderr as a warning.
+    ///
+    /// This is like [`log()`], but puts `warning:` before the message.
+    /// Warnings are suppressed by `quiet` as well.
+    ///
+    /// [`log()`]: #method.log
+    pub fn warn<D: Display>(&self, message: D) {
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            LogFormat::Human if self.color => {
+                eprintln!("{}: {}warning:{} {}", self.name, YELLOW, RESET, message)
+            },
+            LogFormat::Human => eprintln!("{}: warning: {}", self.name, message),
+            LogFormat::Json => eprintln!(
+                "{{\"level\":\"warning\",\"message\":{}}}",
+                json_string(&message.to_string())
+            ),
         }
     }
 
     /// Prints the given message to stderr, prefixed by `"<prefix>: "`.
     pub fn log_with_prefix<D: Display>(&self, prefix: &str, message: D) {
-        if !self.quiet {
-            eprintln!("{}: {}, {}", self.name, prefix, message);
-        }
+        self.log(format_args!("{}, {}", prefix, message));
     }
 
     /// Acquire exclusive access to the output stream and write to it.
@@ -91,13 +191,84 @@ impl<'a> Logger<'a> {
 
     /// First logs an error, then all its causes.
     pub fn log_error_chain(&self, error: &Error) {
+        if self.format == LogFormat::Json {
+            return self.log_error_chain_as_json(error);
+        }
+        let (error_prefix, reason_prefix) = if self.color {
+            (
+                format!("{}error:{}", RED, RESET),
+                format!("{}reason:{}", YELLOW, RESET),
+            )
+        } else {
+            ("error:".to_owned(), "reason:".to_owned())
+        };
         self.with_lock(|lock| {
             let mut error = error.cause();
-            writeln!(lock, "{}: error: {}", self.name, error).unwrap();
+            writeln!(lock, "{}: {} {}", self.name, error_prefix, error).unwrap();
             while let Some(cause) = error.cause() {
-                writeln!(lock, "{}:   -> reason: {}", self.name, cause).unwrap();
+                writeln!(lock, "{}:   -> {} {}", self.name, reason_prefix, cause).unwrap();
                 error = cause;
             }
         })
     }
+
+    /// Logs an error and its causes as a single JSON object.
+    fn log_error_chain_as_json(&self, error: &Error) {
+        let mut causes = error.causes();
+        let message = causes.next().expect("error without cause");
+        let mut line = format!("{{\"level\":\"error\",\"message\":{}", json_string(&message));
+        let mut scenario = scenario_of(message);
+        line.push_str(",\"causes\":[");
+        for (i, cause) in causes.enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            line.push_str(&json_string(cause));
+            scenario = scenario.or_else(|| scenario_of(cause));
+        }
+        line.push(']');
+        if let Some(scenario) = scenario {
+            line.push_str(",\"scenario\":");
+            line.push_str(&json_string(scenario));
+        }
+        line.push('}');
+        self.with_lock(|lock| writeln!(lock, "{}", line).unwrap());
+    }
+}
+
+
+/// Returns the scenario name if `fail` is about a specific scenario.
+fn scenario_of(fail: &dyn Fail) -> Option<&str> {
+    if let Some(ScenarioFailed(name)) = fail.downcast_ref() {
+        Some(name)
+    } else if let Some(ScenarioNotStarted(name)) = fail.downcast_ref() {
+        Some(name)
+    } else if let Some(context) = fail.downcast_ref::<Context<ScenarioFailed>>() {
+        Some(&context.get_context().0)
+    } else if let Some(context) = fail.downcast_ref::<Context<ScenarioNotStarted>>() {
+        Some(&context.get_context().0)
+    } else {
+        None
+    }
+}
+
+
+/// Formats `value` as a quoted and escaped JSON string.
+fn json_string<D: Display + ?Sized>(value: &D) -> String {
+    let value = value.to_string();
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => write!(result, "\\u{:04x}", c as u32).unwrap(),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
 }
@@ -29,31 +29,55 @@
 
 #[macro_use]
 extern crate clap;
-#[macro_use]
 extern crate failure;
-#[macro_use]
-extern crate futures;
 extern crate glob;
 extern crate num_cpus;
-extern crate tokio_core;
-extern crate tokio_process;
+extern crate scenarios as lib;
 
 
 pub mod app;
-pub mod cartesian;
-pub mod consumers;
 pub mod logger;
-pub mod scenarios;
-pub mod trytostr;
 
+// Make the library's modules available under their usual names.
+use lib::{cartesian, consumers, scenarios, shuffle, trytostr};
+
+
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    cmp,
+    collections::{BTreeSet, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Read, Write},
+    iter,
+    mem,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
 
-use std::ffi::OsStr;
+use failure::{Context, Error, Fail, ResultExt};
 
-use failure::{Error, ResultExt};
+use consumers::{FinishedChild, PreparedChild, ScenarioFailed, ScenarioNotStarted, SpawnFailed};
+use scenarios::{
+    MergeError, MisplacedDefaults, Scenario, ScenarioError, ScenarioFile, UnexpectedVarDef,
+};
+use trytostr::{NotUtf8, OsStrExt};
 
-use consumers::{FinishedChild, PreparedChild};
-use scenarios::{MergeError, Scenario, ScenarioFile};
-use trytostr::OsStrExt;
+
+/// Exit code if everything went well.
+const EXIT_SUCCESS: i32 = 0;
+/// Exit code for errors that fit no other category.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code if the arguments or the scenario files are invalid.
+const EXIT_INVALID_INPUT: i32 = 2;
+/// Exit code if no scenario files have been passed.
+const EXIT_NO_SCENARIOS: i32 = 3;
+/// Exit code if COMMAND failed for at least one scenario.
+const EXIT_SCENARIOS_FAILED: i32 = 4;
+/// Exit code if COMMAND could not be executed at all.
+const EXIT_SPAWN_FAILED: i32 = 5;
 
 
 /// The entry point and wrapper around [`try_main()`].
@@ -65,29 +89,39 @@ pub fn main() {
         let app = app::get_app();
         // We clone `app` here because `get_matches` consumes it -- but we
         // might still need it when handling -h and --help.
-        let args = app.clone().get_matches();
+        let args = match app.clone().get_matches_safe() {
+            Ok(args) => args,
+            // Let `clap` handle --version. For actual errors, we use
+            // our own exit code instead of `clap`'s.
+            Err(err) if !err.use_stderr() => err.exit(),
+            Err(err) => {
+                eprintln!("{}", err.message);
+                ::std::process::exit(EXIT_INVALID_INPUT);
+            },
+        };
         // Handle -h (short help) and --help (long help).
         if args.is_present("short_help") {
             app::print_short_help(app);
-            0
+            EXIT_SUCCESS
         } else if args.is_present("long_help") {
             app::print_long_help(app);
-            0
+            EXIT_SUCCESS
         }
         // Delegate to `try_main`. Catch any error, print it to stderr, and
-        // exit with code 1.
+        // exit with a code that depends on the error.
         else if let Err(err) = try_main(&args) {
+            let exit_code = exit_code_of(&err);
             // We want `SomeScenariosFailed` to be printed as a regular info,
             // but all other errors with the full chain.
-            let logger = logger::Logger::new(args.is_present("quiet"));
+            let logger = logger_from_args(&args);
             match err.downcast::<SomeScenariosFailed>() {
                 Ok(err) => logger.log(err),
                 Err(err) => logger.log_error_chain(&err),
             }
-            1
+            exit_code
         } else {
             // `try_main()` returned Ok(()).
-            0
+            EXIT_SUCCESS
         }
     };
     // All destructors have run at this point.
@@ -95,82 +129,738 @@ pub fn main() {
 }
 
 
+/// Decides with which code to exit, based on the error that occurred.
+///
+/// The whole chain of causes is searched for known error types. If
+/// several are found, the outermost one wins.
+fn exit_code_of(err: &Error) -> i32 {
+    for cause in err.causes() {
+        if let Some(&SomeScenariosFailed(exit_code)) = cause.downcast_ref() {
+            return exit_code;
+        } else if is_a::<NoScenarios>(cause) {
+            return EXIT_NO_SCENARIOS;
+        } else if is_a::<SpawnFailed>(cause) {
+            return EXIT_SPAWN_FAILED;
+        } else if is_a::<ScenarioFailed>(cause) {
+            return EXIT_SCENARIOS_FAILED;
+        } else if is_invalid_input(cause) {
+            return EXIT_INVALID_INPUT;
+        }
+    }
+    EXIT_FAILURE
+}
+
+
+/// Returns `true` if `err` means that the user gave us bad input.
+fn is_invalid_input(err: &dyn Fail) -> bool {
+    is_a::<InvalidValue>(err)
+        || is_a::<ExpandFailed>(err)
+        || is_a::<ReadFailed>(err)
+        || is_a::<SharedScenarioName>(err)
+        || is_a::<CheckFailed>(err)
+        || is_a::<NoCommand>(err)
+        || is_a::<StdinConflict>(err)
+        || is_a::<StdinUsedTwice>(err)
+        || is_a::<EmptyFile>(err)
+        || is_a::<NotUtf8>(err)
+        || is_a::<MergeError>(err)
+        || is_a::<ScenarioError>(err)
+        || is_a::<UnexpectedVarDef>(err)
+        || is_a::<MisplacedDefaults>(err)
+}
+
+
+/// Returns `true` if `err` is a `T` or the context of one.
+fn is_a<T: Fail>(err: &dyn Fail) -> bool {
+    err.downcast_ref::<T>().is_some() || err.downcast_ref::<Context<T>>().is_some()
+}
+
+
+/// Creates a [`Logger`] from the --quiet and --color arguments.
+///
+/// [`Logger`]: ./logger/struct.Logger.html
+fn logger_from_args(args: &clap::ArgMatches) -> logger::Logger<'static> {
+    // The possible values of --color are checked by `clap`.
+    let color = match args.value_of("color") {
+        Some("always") => logger::ColorChoice::Always,
+        Some("never") => logger::ColorChoice::Never,
+        _ => logger::ColorChoice::Auto,
+    };
+    let mut logger = logger::Logger::new(args.is_present("quiet"), color);
+    // The possible values of --log-format are checked by `clap`.
+    if args.value_of("log_format") == Some("json") {
+        logger.set_format(logger::LogFormat::Json);
+    }
+    logger
+}
+
+
 /// The actual main function.
 ///
 /// It receives the fully parsed arguments and may return an error.
 /// After building the list of scenarios and depending on the
-/// arguments, this function hands control over either to
-/// [`handle_printing()`] or to [`CommandLineHandler`].
+/// arguments, this function hands control over to
+/// [`handle_printing()`], [`handle_counting()`], or
+/// [`CommandLineHandler`].
 ///
 /// [`handle_printing()`]: ./fn.handle_printing.html
+/// [`handle_counting()`]: ./fn.handle_counting.html
 /// [`CommandLineHandler`]: ./struct.CommandLineHandler.html
 pub fn try_main(args: &clap::ArgMatches) -> Result<(), Error> {
     // Collect scenario file names into a vector of vectors of scenarios.
     // Each inner vector represents one input file.
     let is_strict = !args.is_present("lax");
-    let delimiter = args
-        .value_of_os("delimiter")
-        .unwrap_or_else(|| ", ".as_ref())
-        .try_to_str()
-        .context("invalid value for --delimiter")?;
-    let scenario_files: Vec<ScenarioFile> = args
-        .values_of_os("input")
-        .ok_or(NoScenarios)?
-        .map(|path| ScenarioFile::from_cl_arg(path, is_strict))
-        .collect::<Result<_, _>>()
-        .context("could not read file")?;
-    let all_scenarios: Vec<Vec<Scenario>> = scenario_files
+    // The possible values of --stdin-format are checked by `clap`.
+    let stdin_format = match args.value_of("stdin_format") {
+        Some("nul") => scenarios::ScenarioFileFormat::Nul,
+        Some("tsv") => scenarios::ScenarioFileFormat::Tsv,
+        _ => scenarios::ScenarioFileFormat::Ini,
+    };
+    // Stdin can be read only once, so a second reader would silently
+    // get nothing.
+    let num_stdin_files = count_stdin_scenario_files(args);
+    if num_stdin_files > 1 {
+        return Err(StdinUsedTwice(num_stdin_files).into());
+    }
+    if args.is_present("exec_stdin") && num_stdin_files > 0 {
+        return Err(StdinConflict.into());
+    }
+    let name_pattern = name_pattern_from_args(args).context(InvalidValue("--name-pattern"))?;
+    let file_opts = scenarios::ScenarioFileOptions {
+        is_strict,
+        case_insensitive_names: args.is_present("case_insensitive_names"),
+        allow_inline_comments: args.is_present("allow_inline_comments"),
+        allow_file_values: args.is_present("allow_file_values"),
+        allow_non_utf8_values: args.is_present("allow_non_utf8_values"),
+        stdin_format,
+        name_pattern: name_pattern.as_ref(),
+    };
+    let delimiters: Vec<&str> = match args.values_of_os("delimiter") {
+        Some(values) => values
+            .map(OsStrExt::try_to_str)
+            .collect::<Result<_, _>>()
+            .context(InvalidValue("--delimiter"))?,
+        None => vec![", "],
+    };
+    let tsv_inputs: Vec<&OsStr> = args
+        .values_of_os("tsv")
+        .map_or_else(Vec::new, Iterator::collect);
+    let manifest_inputs = match args.value_of_os("files_from") {
+        Some(path) => read_manifest(Path::new(path)).context(InvalidValue("--files-from"))?,
+        None => Vec::new(),
+    };
+    let mut inputs: Vec<Cow<OsStr>> = match args.values_of_os("input") {
+        Some(inputs) if args.is_present("glob_inputs") => {
+            expand_globs(inputs).context(ExpandFailed)?
+        },
+        Some(inputs) => inputs.map(Cow::Borrowed).collect(),
+        None if tsv_inputs.is_empty()
+            && manifest_inputs.is_empty()
+            && !args.is_present("scenario") =>
+        {
+            return Err(NoScenarios.into())
+        },
+        None => Vec::new(),
+    };
+    inputs.extend(manifest_inputs.into_iter().map(|path| Cow::Owned(path.into_os_string())));
+    let tsv_format = scenarios::ScenarioFileFormat::Tsv;
+    let read_files = inputs
         .iter()
-        .map(|f| f.iter().collect::<Result<_, _>>())
-        .collect::<Result<_, _>>()
-        .context("could not build scenarios")?;
+        .map(|path| ScenarioFile::from_cl_arg(path, file_opts))
+        .chain(
+            tsv_inputs
+                .iter()
+                .map(|path| ScenarioFile::from_cl_arg_as(path, tsv_format, file_opts)),
+        );
+    let mut scenario_files: Vec<ScenarioFile> = if args.is_present("check") {
+        read_all_files(args, read_files)?
+    } else {
+        read_files.collect::<Result<_, _>>().context(ReadFailed)?
+    };
+    // Each scenario defined with --scenario acts like a file of its own.
+    for spec in args.values_of_os("scenario").into_iter().flatten() {
+        let spec = spec.try_to_str().context(InvalidValue("--scenario"))?;
+        let file = ScenarioFile::from_inline(spec, file_opts).context(InvalidValue("--scenario"))?;
+        scenario_files.push(file);
+    }
+    if is_strict {
+        scenarios::check_for_shared_names(&scenario_files, file_opts.case_insensitive_names)
+            .context(SharedScenarioName)?;
+    }
+    if args.is_present("fail_on_empty") {
+        if let Some(file) = scenario_files.iter().find(|f| f.iter().len() == 0) {
+            return Err(EmptyFile(file.filename().to_owned()).into());
+        }
+    }
+    let base_file = match args.value_of_os("base") {
+        Some(path) => Some(ScenarioFile::from_cl_arg(path, file_opts).context(ReadFailed)?),
+        None => None,
+    };
+    if args.is_present("list_scenarios") {
+        return handle_listing(&scenario_files);
+    }
+    let mut all_scenarios = scenarios::scenarios_per_file(&scenario_files)?;
+    // Iterate over the files in the requested order, but merge each
+    // combination in the order of the files on the command line.
+    let group_by =
+        group_by_from_args(args, all_scenarios.len()).context(InvalidValue("--group-by"))?;
+    let axis_order = match group_by {
+        Some(index) => Some(group_axis_order(index, all_scenarios.len())),
+        None => axis_order_from_args(args, all_scenarios.len())
+            .context(InvalidValue("--axis-order"))?,
+    };
+    let name_format = name_format_from_args(args, all_scenarios.len())
+        .context(InvalidValue("--name-format"))?;
+    if let Some(ref order) = axis_order {
+        all_scenarios = permute_axes(all_scenarios, order);
+    }
 
     // For each possible combination of scenarios, merge the combination
     // into a single scenario and check if it's allowed by the
     // `NameFilter`. We let errors automatically pass the filter so that we
     // can display them to the user.
     let filter = name_filter_from_args(args)?;
-    let merge_opts = scenarios::MergeOptions {
-        delimiter,
-        is_strict,
+    let tag_filter = tag_filter_from_args(args)?;
+    let merge_opts = scenarios::MergeOptions::builder()
+        .delimiters(&delimiters)
+        .strict(is_strict)
+        .track_origins(args.is_present("explain"))
+        .escape_names(args.is_present("escape_names"))
+        .precedence(match args.value_of("override") {
+            Some("first") => scenarios::Precedence::First,
+            _ => scenarios::Precedence::Last,
+        })
+        .build();
+    let base = match base_file {
+        Some(ref file) => merge_base_file(file, merge_opts)?,
+        None => None,
     };
+    let dedup_vars = args.is_present("dedup_vars");
+    let mut seen_vars = HashSet::new();
+    let mut num_combos = 0;
     let combos = cartesian::product(&all_scenarios)
-        .map(|set| Scenario::merge_all(set, merge_opts))
-        .filter(|result| match *result {
-            Ok(ref scenario) => filter.allows(scenario),
+        .map(|set| {
+            let set = match axis_order {
+                Some(ref order) => restore_axes(set, order),
+                None => set,
+            };
+            let group = group_by.map(|index| set[index].name());
+            let name = name_format.map(|format| {
+                let names: Vec<&str> = set.iter().map(|s| s.name()).collect();
+                format_name(format, &names).expect("name format not validated")
+            });
+            let merged = Scenario::merge_all(set, merge_opts).and_then(|mut merged| {
+                if let Some(name) = name {
+                    merged.set_name(name).expect("formatted name is empty");
+                }
+                match base {
+                    Some(ref base) => merged.with_base(base, merge_opts),
+                    None => Ok(merged),
+                }
+            });
+            (group, merged)
+        })
+        .filter(|(_, result)| match *result {
+            Ok(ref scenario) => filter.allows(scenario) && tag_filter.allows(scenario),
             Err(_) => true,
-        });
-    if args.is_present("exec") {
-        let handler = CommandLineHandler::new(args)?;
+        })
+        .filter(|(_, result)| match *result {
+            Ok(ref scenario) if dedup_vars => seen_vars.insert(ResolvedVars::of(scenario)),
+            _ => true,
+        })
+        .inspect(|_| num_combos += 1);
+    // With --continue-from, skip everything before the named scenario.
+    // This happens after shuffling so that a run can be resumed with
+    // the same seed. Merge errors are never skipped: they have no name
+    // to compare, and one of them may well be the named scenario.
+    let continue_from = match args.value_of_os("continue_from") {
+        Some(name) => Some(name.try_to_str().context(InvalidValue("--continue-from"))?),
+        None => None,
+    };
+    let found_start = Cell::new(continue_from.is_none());
+    let is_not_skipped = |(_, result): &Combination| {
+        if found_start.get() {
+            return true;
+        }
+        match *result {
+            Ok(ref scenario) => {
+                let is_start = continue_from == Some(scenario.name());
+                found_start.set(is_start);
+                is_start
+            },
+            Err(_) => true,
+        }
+    };
+    let only_first = only_first_from_args(args).context(InvalidValue("--only-first"))?;
+    let limit = only_first.unwrap_or(usize::MAX);
+    // Shuffling, sorting, exporting the total number of combinations,
+    // and running all of them at once all require us to collect all
+    // combinations up front.
+    let seed = shuffle_seed_from_args(args).context(InvalidValue("--shuffle"))?;
+    let sort_names = args.is_present("sort_names");
+    let collect_all = seed.is_some()
+        || sort_names
+        || args.is_present("export_index")
+        || has_unbounded_jobs(args);
+    if collect_all {
+        let mut combos = combos.collect::<Vec<_>>();
+        if let Some(seed) = seed {
+            shuffle::shuffle(&mut combos, seed);
+        }
+        if sort_names {
+            // Errors come first so that they are reported right away.
+            combos.sort_by(|(_, left), (_, right)| {
+                let left = left.as_ref().ok().map(Scenario::name);
+                let right = right.as_ref().ok().map(Scenario::name);
+                left.cmp(&right)
+            });
+        }
+        let combos = combos
+            .into_iter()
+            .filter(&is_not_skipped)
+            .take(limit)
+            .collect::<Vec<_>>();
+        let total = combos.len();
+        handle_combinations(args, combos.into_iter(), Some(total))?;
+    } else {
+        let combos = combos.filter(&is_not_skipped).take(limit);
+        handle_combinations(args, combos, None)?;
+    }
+    if let Some(name) = continue_from.filter(|_| !found_start.get()) {
+        return Err(NoSuchScenario(name.to_owned())
+            .context(InvalidValue("--continue-from"))
+            .into());
+    }
+    // If --only-first stopped early, the filters have not seen all
+    // scenarios and cannot tell whether their patterns matched.
+    if collect_all || num_combos < limit {
+        report_filter_results(args, &filter, &tag_filter, num_combos);
+    }
+    Ok(())
+}
+
+
+/// Collects the scenario files for --check.
+///
+/// Unlike the usual way of reading files, this does not stop at the
+/// first file that cannot be read. Instead, the errors of all files
+/// are logged.
+///
+/// # Errors
+/// This fails if any of the files could not be read.
+fn read_all_files<'a, I>(args: &clap::ArgMatches, files: I) -> Result<Vec<ScenarioFile<'a>>, Error>
+where
+    I: Iterator<Item = Result<ScenarioFile<'a>, Error>>,
+{
+    let logger = logger_from_args(args);
+    let mut num_errors = 0;
+    let mut good_files = Vec::new();
+    for file in files {
+        match file {
+            Ok(file) => good_files.push(file),
+            Err(err) => {
+                logger.log_error_chain(&err.context(ReadFailed).into());
+                num_errors += 1;
+            },
+        }
+    }
+    if num_errors > 0 {
+        return Err(CheckFailed(num_errors).into());
+    }
+    Ok(good_files)
+}
+
+
+/// Returns the number of scenario files that are to be read from stdin.
+fn count_stdin_scenario_files(args: &clap::ArgMatches) -> usize {
+    ["input", "tsv", "base"]
+        .iter()
+        .filter_map(|&name| args.values_of_os(name))
+        .flatten()
+        .filter(|&path| path == "-")
+        .count()
+}
+
+
+/// Parses the argument of `--group-by`, if any.
+///
+/// The result is the index of one of the `num_files` input files.
+fn group_by_from_args(args: &clap::ArgMatches, num_files: usize) -> Result<Option<usize>, Error> {
+    let arg = match args.value_of_os("group_by") {
+        Some(arg) => arg.try_to_str()?,
+        None => return Ok(None),
+    };
+    let index = arg.parse().map_err(|_| NotANumber(arg.to_owned()))?;
+    if index >= num_files {
+        return Err(NoSuchFile(index, num_files).into());
+    }
+    Ok(Some(index))
+}
+
+
+/// Returns an axis order in which the file `index` varies slowest.
+///
+/// All other files keep their relative order.
+fn group_axis_order(index: usize, num_files: usize) -> Vec<usize> {
+    iter::once(index)
+        .chain((0..num_files).filter(|&other| other != index))
+        .collect()
+}
+
+
+/// Parses the argument of `--axis-order`, if any.
+///
+/// The result is a permutation of the indices of all `num_files`
+/// input files, from the one that varies slowest to the one that
+/// varies fastest.
+fn axis_order_from_args(
+    args: &clap::ArgMatches,
+    num_files: usize,
+) -> Result<Option<Vec<usize>>, Error> {
+    let arg = match args.value_of_os("axis_order") {
+        Some(arg) => arg.try_to_str()?,
+        None => return Ok(None),
+    };
+    let order = arg
+        .split(',')
+        .map(|index| index.trim().parse().map_err(|_| NotANumber(index.to_owned())))
+        .collect::<Result<Vec<usize>, _>>()?;
+    let mut seen = vec![false; num_files];
+    let is_permutation = order.len() == num_files
+        && order
+            .iter()
+            .all(|&index| index < num_files && !mem::replace(&mut seen[index], true));
+    if !is_permutation {
+        return Err(NotAPermutation(arg.to_owned(), num_files).into());
+    }
+    Ok(Some(order))
+}
+
+
+/// Parses the argument of `--name-format`, if any.
+///
+/// The format is checked against `num_files` so that it can be
+/// applied to every combination without further errors.
+fn name_format_from_args<'a>(
+    args: &'a clap::ArgMatches,
+    num_files: usize,
+) -> Result<Option<&'a str>, Error> {
+    let format = match args.value_of_os("name_format") {
+        Some(format) => format.try_to_str()?,
+        None => return Ok(None),
+    };
+    if format.is_empty() {
+        return Err(EmptyNameFormat.into());
+    }
+    let dummies = vec!["x"; num_files];
+    if let Err(index) = format_name(format, &dummies) {
+        return Err(FileIndexOutOfRange(index, num_files).into());
+    }
+    Ok(Some(format))
+}
+
+
+/// Builds a scenario name by replacing each `{i}` in `format`.
+///
+/// `{i}` is replaced with `names[i]`, the name of the scenario taken
+/// from the `i`-th input file. Any other text, including braces that
+/// don't enclose a number, is copied verbatim.
+///
+/// # Errors
+/// If `i` is out of range for any `{i}`, `i` is returned.
+fn format_name(format: &str, names: &[&str]) -> Result<String, usize> {
+    let mut result = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let index = rest[1..]
+            .find('}')
+            .and_then(|end| rest[1..end + 1].parse::<usize>().ok().map(|i| (i, end + 2)));
+        match index {
+            Some((index, len)) => {
+                result.push_str(names.get(index).ok_or(index)?);
+                rest = &rest[len..];
+            },
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+
+/// Reorders the scenario files so that `order[i]` becomes the `i`-th.
+fn permute_axes<T>(axes: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut axes: Vec<Option<T>> = axes.into_iter().map(Some).collect();
+    order
+        .iter()
+        .map(|&index| axes[index].take().expect("index used twice"))
+        .collect()
+}
+
+
+/// Undoes [`permute_axes()`] on a single combination of scenarios.
+///
+/// [`permute_axes()`]: ./fn.permute_axes.html
+fn restore_axes<T>(set: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut pairs: Vec<(usize, T)> = order.iter().cloned().zip(set).collect();
+    pairs.sort_by_key(|&(index, _)| index);
+    pairs.into_iter().map(|(_, item)| item).collect()
+}
+
+
+/// Merges all scenarios of the `--base` file into one.
+///
+/// Returns `None` if the file contains no scenarios.
+fn merge_base_file<'s>(
+    file: &'s ScenarioFile,
+    opts: scenarios::MergeOptions,
+) -> Result<Option<Scenario<'s>>, Error> {
+    let scenarios: Vec<Scenario> = file
+        .iter()
+        .collect::<Result<_, _>>()
+        .context("could not build scenarios")?;
+    if scenarios.is_empty() {
+        return Ok(None);
+    }
+    let base = Scenario::merge_all(&scenarios, opts).context("could not merge base scenarios")?;
+    Ok(Some(base))
+}
+
+
+/// Everything a scenario does to the environment, in a comparable form.
+///
+/// Two scenarios with equal `ResolvedVars` run their commands with the
+/// same environment. This is used to implement `--dedup-vars`.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct ResolvedVars<'s> {
+    variables: Vec<(&'s str, &'s str)>,
+    removals: Vec<&'s str>,
+    appends: Vec<(&'s str, Vec<&'s str>)>,
+}
+
+impl<'s> ResolvedVars<'s> {
+    fn of(scenario: &Scenario<'s>) -> Self {
+        let mut removals: Vec<&str> = scenario.removals().cloned().collect();
+        removals.sort();
+        let mut appends: Vec<(&str, Vec<&str>)> = scenario
+            .appends()
+            .map(|(&name, values)| (name, values.clone()))
+            .collect();
+        appends.sort();
+        ResolvedVars {
+            variables: scenario.variables_sorted(),
+            removals,
+            appends,
+        }
+    }
+}
+
+
+/// A merged combination of scenarios, as handed to the consumers.
+///
+/// The first field is the name of the combination's scenario from the
+/// file passed to --group-by, if any.
+type Combination<'s> = (Option<&'s str>, Result<Scenario<'s>, MergeError>);
+
+
+/// Strips the group from a [`Combination`].
+///
+/// [`Combination`]: ./type.Combination.html
+fn without_group(combo: Combination) -> Result<Scenario, MergeError> {
+    combo.1
+}
+
+
+/// Tells the user if the filters removed all scenarios.
+///
+/// If the pattern of --choose or --exclude has not matched any
+/// scenario, it most likely contains a typo, so we print a warning.
+/// Otherwise, if no scenario combination passed the filters, we say
+/// so, so that an empty output does not come as a surprise.
+fn report_filter_results(
+    args: &clap::ArgMatches,
+    filter: &scenarios::NameFilter,
+    tag_filter: &scenarios::TagFilter,
+    num_combos: usize,
+) {
+    let logger = logger_from_args(args);
+    let pattern = filter.pattern().as_ref();
+    if let Some(pattern) = pattern.filter(|_| filter.num_matches() == 0) {
+        let option = match filter.mode() {
+            scenarios::FilterMode::ChooseMatching => "--choose",
+            scenarios::FilterMode::IgnoreMatching => "--exclude",
+        };
+        let negation = if filter.is_negated() { "!" } else { "" };
+        logger.warn(format_args!(
+            "the pattern {:?} of {} did not match any scenarios",
+            format!("{}{}", negation, pattern.as_str()),
+            option
+        ));
+    } else if num_combos == 0 && (pattern.is_some() || !tag_filter.is_empty()) {
+        logger.log("no scenarios matched the filter");
+    }
+}
+
+
+/// Hands the scenario combinations to the requested action.
+///
+/// This is one of [`handle_printing()`], [`handle_counting()`],
+/// [`handle_checking()`], [`handle_explaining()`], [`handle_table()`],
+/// or [`CommandLineHandler`].
+///
+/// [`handle_printing()`]: ./fn.handle_printing.html
+/// [`handle_counting()`]: ./fn.handle_counting.html
+/// [`handle_checking()`]: ./fn.handle_checking.html
+/// [`handle_explaining()`]: ./fn.handle_explaining.html
+/// [`handle_table()`]: ./fn.handle_table.html
+/// [`CommandLineHandler`]: ./struct.CommandLineHandler.html
+///
+/// `total` is the number of combinations if it is known in advance.
+fn handle_combinations<'s, I>(
+    args: &clap::ArgMatches,
+    combos: I,
+    total: Option<usize>,
+) -> Result<(), Error>
+where
+    I: Iterator<Item = Combination<'s>>,
+{
+    if args.is_present("count") {
+        handle_counting(combos.map(without_group))?;
+    } else if args.is_present("check") {
+        handle_checking(args, combos.map(without_group))?;
+    } else if args.is_present("explain") {
+        handle_explaining(combos.map(without_group))?;
+    } else if args.is_present("table") {
+        handle_table(combos.map(without_group))?;
+    } else if let Some(dir) = args.value_of_os("emit_envfiles") {
+        let combos = combos.map(without_group);
+        handle_emitting(combos, Path::new(dir), args.is_present("force"))?;
+    } else if args.is_present("command") {
+        let handler = CommandLineHandler::new(args, total)?;
         consumers::loop_in_process_pool(combos, handler)?;
     } else {
-        handle_printing(args, combos)?;
+        handle_printing(args, combos.map(without_group))?;
     }
     Ok(())
 }
 
 
+/// Reads the string that --placeholder passes, or `"{}"`.
+fn placeholder_from_args<'a>(args: &'a clap::ArgMatches) -> Result<&'a str, Error> {
+    match args.value_of_os("placeholder") {
+        Some(placeholder) => Ok(placeholder
+            .try_to_str()
+            .context(InvalidValue("--placeholder"))?),
+        None => Ok(consumers::DEFAULT_PLACEHOLDER),
+    }
+}
+
+
+/// Parses the --only-first option, if it is passed.
+fn only_first_from_args(args: &clap::ArgMatches) -> Result<Option<usize>, Error> {
+    let num = match args.value_of_os("only_first") {
+        Some(num) => num.try_to_str()?,
+        None => return Ok(None),
+    };
+    let count = num.parse().map_err(|_| NotANumber(num.to_owned()))?;
+    if count == 0 {
+        return Err(NotPositive(num.to_owned()).into());
+    }
+    Ok(Some(count))
+}
+
+
+/// Reads the seed for --shuffle from `args`.
+///
+/// This returns `None` if --shuffle has not been passed. If it has
+/// been passed without a seed, a random seed is chosen and logged.
+fn shuffle_seed_from_args(args: &clap::ArgMatches) -> Result<Option<u64>, Error> {
+    if !args.is_present("shuffle") {
+        return Ok(None);
+    }
+    let seed = match args.value_of_os("shuffle") {
+        Some(seed) => {
+            let seed = seed.try_to_str()?;
+            seed.parse().map_err(|_| NotANumber(seed.to_owned()))?
+        },
+        None => {
+            let seed = shuffle::random_seed();
+            logger_from_args(args).log(format!("shuffling with seed {}", seed));
+            seed
+        },
+    };
+    Ok(Some(seed))
+}
+
+
+/// Returns `true` if `--jobs 0` has been passed.
+///
+/// In this case, all combinations are run in parallel, so we need to
+/// know their number in advance.
+fn has_unbounded_jobs(args: &clap::ArgMatches) -> bool {
+    args.is_present("command")
+        && args.occurrences_of("jobs") != 0
+        && args
+            .value_of_os("jobs")
+            .and_then(OsStr::to_str)
+            .and_then(|jobs| jobs.parse().ok())
+            == Some(0)
+}
+
+
+/// Creates a [`TagFilter`] from `args`.
+///
+/// [`TagFilter`]: ./scenarios/struct.TagFilter.html
+pub fn tag_filter_from_args<'a>(
+    args: &'a clap::ArgMatches,
+) -> Result<scenarios::TagFilter<'a>, Error> {
+    let mut filter = scenarios::TagFilter::new();
+    for tag in args.values_of_os("with_tag").into_iter().flatten() {
+        filter.require(tag.try_to_str().context(InvalidValue("--with-tag"))?);
+    }
+    for tag in args.values_of_os("without_tag").into_iter().flatten() {
+        filter.forbid(tag.try_to_str().context(InvalidValue("--without-tag"))?);
+    }
+    Ok(filter)
+}
+
+
+/// Parses the argument of `--name-pattern`, if any.
+fn name_pattern_from_args(args: &clap::ArgMatches) -> Result<Option<glob::Pattern>, Error> {
+    match args.value_of_os("name_pattern") {
+        Some(pattern) => Ok(Some(glob::Pattern::new(pattern.try_to_str()?)?)),
+        None => Ok(None),
+    }
+}
+
+
 /// Creates a [`NameFilter`] from `args`.
 ///
 /// [`NameFilter`]: ./scenarios/struct.NameFilter.html
 pub fn name_filter_from_args(args: &clap::ArgMatches) -> Result<scenarios::NameFilter, Error> {
-    let filter = if let Some(pattern) = args.value_of_os("choose") {
+    let mut filter = if let Some(pattern) = args.value_of_os("choose") {
         let filter = scenarios::NameFilter::new_whitelist();
         pattern
             .try_to_str()
             .map_err(Error::from)
             .and_then(|p| filter.add_pattern(p))
-            .context("invalid value for --choose")?
+            .context(InvalidValue("--choose"))?
     } else if let Some(pattern) = args.value_of_os("exclude") {
         let filter = scenarios::NameFilter::new_blacklist();
         pattern
             .try_to_str()
             .map_err(Error::from)
             .and_then(|p| filter.add_pattern(p))
-            .context("invalid value for --exclude")?
+            .context(InvalidValue("--exclude"))?
     } else {
         scenarios::NameFilter::default()
     };
+    filter.set_case_sensitive(!args.is_present("case_insensitive_names"));
     Ok(filter)
 }
 
@@ -184,130 +874,983 @@ pub fn handle_printing<'s, I>(args: &clap::ArgMatches, scenarios: I) -> Result<(
 where
     I: Iterator<Item = Result<Scenario<'s>, MergeError>>,
 {
+    let template_from_file;
+    let terminator;
     let mut printer = consumers::Printer::default();
-    if let Some(template) = args.value_of_os("print0") {
+    if let Some(path) = args.value_of_os("print_file") {
+        template_from_file = read_print_file(path).context(InvalidValue("--print-file"))?;
+        printer.set_template(&template_from_file);
+    } else if let Some(template) = args.value_of_os("print0") {
         let template = template
             .try_to_str()
-            .context("invalid value for --print0")?;
+            .context(InvalidValue("--print0"))?;
         printer.set_template(template);
     } else if let Some(template) = args.value_of_os("print") {
-        let template = template.try_to_str().context("invalid value for --print")?;
+        let template = template.try_to_str().context(InvalidValue("--print"))?;
         printer.set_template(template);
     };
-    if args.is_present("print0") {
+    if let Some(value) = args.value_of_os("terminator") {
+        terminator = value
+            .try_to_str()
+            .map_err(Error::from)
+            .and_then(|s| unescape(s).map_err(Error::from))
+            .context(InvalidValue("--terminator"))?;
+        printer.set_terminator(&terminator);
+    } else if args.is_present("print0") {
         printer.set_terminator("\0");
     }
+    printer.set_placeholder(placeholder_from_args(args)?);
+    let stdout = io::stdout();
+    let mut stdout = io::BufWriter::new(stdout.lock());
+    let print_vars = args.is_present("print_vars");
     for scenario in scenarios {
-        printer.print_scenario(&scenario?);
+        let scenario = scenario?;
+        if print_vars {
+            printer
+                .write_variables(&mut stdout, &scenario)
+                .context("could not print variables")?;
+        } else {
+            printer
+                .write_scenario(&mut stdout, &scenario)
+                .context("could not print scenario name")?;
+        }
     }
+    stdout.flush().context("could not print scenario name")?;
     Ok(())
 }
 
 
-/// Helper struct that breaks up the task of executing a command line.
-///
-/// It is used as a loop driver for [`loop_in_process_pool()`].
+/// Replaces the escape sequences in an argument like `--terminator`.
 ///
-/// [`loop_in_process_pool()`]: ./consumers/fn.loop_in_process_pool.html
-pub struct CommandLineHandler<'a> {
-    /// Flag read from --keep-going.
-    keep_going: bool,
-    /// Argument read from --jobs.
-    max_num_of_children: usize,
-    /// The command line that is executed for each scenario.
-    command_line: consumers::CommandLine<&'a OsStr>,
-    /// A logger that helps us print information to the user.
-    logger: logger::Logger<'static>,
-    /// A flag that is set if any error occurs during processing.
-    ///
-    /// This is used so we can tell the user something went wrong even
-    /// if `keep_going` has been set.
-    any_errors: bool,
+/// Recognized are `\n`, `\t`, `\r`, `\0` and `\\`. Any other
+/// backslash is an error.
+fn unescape(s: &str) -> Result<String, BadEscape> {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('\\') => result.push('\\'),
+            Some(other) => return Err(BadEscape(format!("\\{}", other))),
+            None => return Err(BadEscape("\\".to_owned())),
+        }
+    }
+    Ok(result)
 }
 
-impl<'a> CommandLineHandler<'a> {
-    /// Creates a new handler.
-    ///
-    /// This reads the parsed command-line arguments and initializes
-    /// the fields of this struct from them.
-    pub fn new(args: &'a clap::ArgMatches) -> Result<Self, Error> {
-        let max_num_of_children =
-            Self::max_num_tokens_from_args(args).context("invalid value for --jobs")?;
-        let handler = CommandLineHandler {
-            any_errors: false,
-            max_num_of_children,
-            keep_going: args.is_present("keep_going"),
-            command_line: Self::command_line_from_args(args),
-            logger: logger::Logger::new(args.is_present("quiet")),
-        };
-        Ok(handler)
-    }
 
-    /// Creates a [`CommandLine`] from `args`.
-    ///
-    /// [`CommandLine`]: ./consumers/struct.CommandLine.html
-    fn command_line_from_args(args: &'a clap::ArgMatches) -> consumers::CommandLine<&'a OsStr> {
-        let options = consumers::CommandLineOptions {
-            is_strict: !args.is_present("lax"),
-            ignore_env: args.is_present("ignore_env"),
-            add_scenarios_name: !args.is_present("no_export_name"),
-            insert_name_in_args: !args.is_present("no_insert_name"),
-        };
-        // This is only called if the argument `exec` is
-        // present. And since it's a positional argument, i.e. not an
-        // --option, being present also means not being empty. Hence,
-        // it is safe to unwrap here.
-        args.values_of_os("exec")
-            .and_then(|argv| consumers::CommandLine::with_options(argv, options))
-            .unwrap()
+/// Quotes `arg` so that a POSIX shell reads it as a single word.
+///
+/// Arguments that consist only of safe characters are returned as-is.
+/// All others are put in single quotes. Invalid UTF-8 is replaced.
+fn shell_quote(arg: &OsStr) -> Cow<'_, str> {
+    let arg = arg.to_string_lossy();
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
     }
+}
 
-    /// Parses and interprets the `--jobs` option.
-    fn max_num_tokens_from_args(args: &clap::ArgMatches) -> Result<usize, Error> {
-        if args.occurrences_of("jobs") == 0 {
-            return Ok(1);
-        }
-        let jobs_arg = args
-            .value_of_os("jobs")
-            .expect("default value")
-            .try_to_str()?;
-        if jobs_arg == "auto" {
-            return Ok(num_cpus::get());
+
+/// Reads the template for `--print-file`.
+///
+/// A single trailing line break is removed from the file's contents,
+/// because the printer adds one after each scenario anyway.
+fn read_print_file(path: &OsStr) -> Result<String, Error> {
+    let mut template = trytostr::try_from_utf8(fs::read(path)?)?;
+    if template.ends_with('\n') {
+        template.pop();
+        if template.ends_with('\r') {
+            template.pop();
         }
-        let num_jobs = jobs_arg
-            .parse()
-            .map_err(|_| NotANumber(jobs_arg.to_owned()))?;
-        Ok(num_jobs)
     }
+    Ok(template)
 }
 
-impl<'a, 's> consumers::LoopDriver<Result<Scenario<'s>, MergeError>> for CommandLineHandler<'a> {
-    fn max_num_of_children(&self) -> usize {
-        self.max_num_of_children
-    }
-
-    fn prepare_child(&self, s: Result<Scenario<'s>, MergeError>) -> Result<PreparedChild, Error> {
-        let child = self.command_line.with_scenario(s?)?;
-        Ok(child)
-    }
 
-    fn on_reap(&mut self, child: FinishedChild) -> Result<(), Error> {
-        let result = child.into_result();
-        if self.keep_going {
-            if let Err(err) = result {
-                // TODO: Avoid logging the word "error" here, because
-                // this event does not stop us from running.
-                self.any_errors = true;
-                self.logger.log_error_chain(&err)
-            }
-            Ok(())
+/// Prints the number of given scenarios to stdout.
+///
+/// # Errors
+/// This fails if two variable names conflict and strict mode is
+/// enabled.
+pub fn handle_counting<'s, I>(scenarios: I) -> Result<(), Error>
+where
+    I: Iterator<Item = Result<Scenario<'s>, MergeError>>,
+{
+    let mut count = 0;
+    for scenario in scenarios {
+        scenario?;
+        count += 1;
+    }
+    println!("{}", count);
+    Ok(())
+}
+
+
+/// Goes through all scenarios and reports how many there are.
+///
+/// This is the second half of --check. Unlike [`handle_counting()`],
+/// this does not stop at the first conflict; every conflict is logged.
+/// Afterwards, the total number of combinations is printed to stdout.
+///
+/// # Errors
+/// This fails if any two variable names conflict and strict mode is
+/// enabled.
+///
+/// [`handle_counting()`]: ./fn.handle_counting.html
+pub fn handle_checking<'s, I>(args: &clap::ArgMatches, scenarios: I) -> Result<(), Error>
+where
+    I: Iterator<Item = Result<Scenario<'s>, MergeError>>,
+{
+    let logger = logger_from_args(args);
+    let mut count = 0;
+    let mut num_errors = 0;
+    for scenario in scenarios {
+        if let Err(err) = scenario {
+            logger.log_error_chain(&err.into());
+            num_errors += 1;
+        }
+        count += 1;
+    }
+    println!("{}", count);
+    if num_errors > 0 {
+        return Err(CheckFailed(num_errors).into());
+    }
+    Ok(())
+}
+
+
+/// Writes each of the given scenarios to a file `dir/NAME.env`.
+///
+/// Each file contains one line `NAME=value` per variable, sorted by
+/// name, in the format read by --env-file. In the file name, all
+/// characters of the scenario name except ASCII letters and digits
+/// are replaced with underscores, so the files can't escape `dir`.
+///
+/// All scenarios are checked before the first file is written, so an
+/// error never leaves an incomplete set of files behind.
+///
+/// # Errors
+/// This fails if two variable names conflict and strict mode is
+/// enabled, if a scenario cannot be represented as an env file (see
+/// [`env_file_contents()`]), if two scenarios map to the same file
+/// name, or if a file already exists and `force` is `false`.
+///
+/// [`env_file_contents()`]: ./fn.env_file_contents.html
+pub fn handle_emitting<'s, I>(scenarios: I, dir: &Path, force: bool) -> Result<(), Error>
+where
+    I: Iterator<Item = Result<Scenario<'s>, MergeError>>,
+{
+    let mut files = Vec::new();
+    let mut file_names = HashMap::new();
+    for scenario in scenarios {
+        let scenario = scenario?;
+        let stem = consumers::NameTransform::Slug.apply(scenario.name());
+        let path = dir.join(format!("{}.env", stem));
+        if let Some(other) = file_names.insert(stem.into_owned(), scenario.name().to_owned()) {
+            return Err(NameCollision(other, scenario.name().to_owned(), path).into());
+        }
+        if !force && fs::symlink_metadata(&path).is_ok() {
+            return Err(FileExists(path).into());
+        }
+        let contents = env_file_contents(&scenario).context("cannot write an env file")?;
+        files.push((path, contents));
+    }
+    fs::create_dir_all(dir).with_context(|_| format!("could not create {:?}", dir))?;
+    for (path, contents) in files {
+        let mut options = fs::OpenOptions::new();
+        options.write(true);
+        if force {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
+        let result = options
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()));
+        match result {
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(FileExists(path).into());
+            },
+            result => result.with_context(|_| format!("could not write {:?}", path))?,
+        }
+    }
+    Ok(())
+}
+
+
+/// Formats the variables of `scenario` as the contents of an env file.
+///
+/// Each variable becomes a line `NAME=value`. Values are wrapped in
+/// double quotes if --env-file would otherwise not read them back
+/// unchanged, i.e. if they have surrounding whitespace or quotes.
+///
+/// # Errors
+/// Env files have no way to remove or append to variables, nor to
+/// write values that span several lines. Thus, this fails if the
+/// scenario has removals or appends, or if a value contains a line
+/// feed or raw bytes.
+fn env_file_contents(scenario: &Scenario) -> Result<String, NotRepresentable> {
+    let scenario_name = scenario.name().to_owned();
+    if let Some(name) = scenario.removals().min() {
+        return Err(NotRepresentable::Removal(scenario_name, name.to_string()));
+    }
+    if let Some(name) = scenario.appends().map(|(name, _)| name).min() {
+        return Err(NotRepresentable::Append(scenario_name, name.to_string()));
+    }
+    let mut contents = String::new();
+    for (name, value) in scenario.variables_sorted() {
+        if value.contains('\n') || trytostr::has_raw_bytes(value) {
+            return Err(NotRepresentable::Value(scenario_name, name.to_owned()));
+        }
+        if value.trim() != value || unquote(value) != value {
+            contents.push_str(&format!("{}=\"{}\"\n", name, value));
         } else {
-            result.map_err(Error::from)
+            contents.push_str(&format!("{}={}\n", name, value));
+        }
+    }
+    Ok(contents)
+}
+
+
+/// Prints the given scenarios and where their variables come from.
+///
+/// For each scenario, its name is printed, followed by one line
+/// `NAME=value (from "scenario")` per variable, sorted by name.
+///
+/// # Errors
+/// This fails if two variable names conflict and strict mode is
+/// enabled.
+pub fn handle_explaining<'s, I>(scenarios: I) -> Result<(), Error>
+where
+    I: Iterator<Item = Result<Scenario<'s>, MergeError>>,
+{
+    for scenario in scenarios {
+        let scenario = scenario?;
+        println!("{}", scenario.name());
+        for (name, value) in scenario.variables_sorted() {
+            let origin = scenario.origin_of(name).unwrap_or_default();
+            println!("    {}={} (from {:?})", name, value, origin);
+        }
+    }
+    Ok(())
+}
+
+
+/// Prints the given scenarios as a table with aligned columns.
+///
+/// The first column contains the scenario names, every further column
+/// one variable. The columns are the union of all variables defined by
+/// any scenario, sorted by name. If a scenario does not define a
+/// variable, its cell is left blank.
+///
+/// Because the columns must be known before the first row can be
+/// printed, all scenarios are collected first.
+///
+/// # Errors
+/// This fails if two variable names conflict and strict mode is
+/// enabled.
+pub fn handle_table<'s, I>(scenarios: I) -> Result<(), Error>
+where
+    I: Iterator<Item = Result<Scenario<'s>, MergeError>>,
+{
+    let scenarios = scenarios.collect::<Result<Vec<_>, _>>()?;
+    let columns = scenarios
+        .iter()
+        .flat_map(|s| s.variable_names().cloned())
+        .collect::<BTreeSet<_>>();
+    let header = iter::once("scenario").chain(columns.iter().cloned());
+    let rows = scenarios.iter().map(|scenario| {
+        let cells = columns
+            .iter()
+            .map(|&name| scenario.get_variable(name).unwrap_or_default());
+        iter::once(scenario.name()).chain(cells).collect::<Vec<_>>()
+    });
+    let table = iter::once(header.collect::<Vec<_>>()).chain(rows).collect::<Vec<_>>();
+    let mut widths = vec![0; columns.len() + 1];
+    for row in &table {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = cmp::max(*width, cell.chars().count());
+        }
+    }
+    for row in &table {
+        let mut line = String::new();
+        for (width, cell) in widths.iter().zip(row) {
+            line.push_str(&format!("{:1$}  ", cell, width));
+        }
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+
+/// Prints the names of the scenarios in each file to stdout.
+///
+/// In contrast to [`handle_printing()`], this does not combine the
+/// scenarios of different files. Instead, each file's name is printed,
+/// followed by the names of the scenarios defined in it.
+///
+/// # Errors
+/// This fails if any scenario in any of the files cannot be built.
+///
+/// [`handle_printing()`]: ./fn.handle_printing.html
+pub fn handle_listing(files: &[ScenarioFile]) -> Result<(), Error> {
+    for file in files {
+        println!("{}:", file.filename().display());
+        for scenario in file {
+            let scenario = scenario.context("could not build scenarios")?;
+            println!("    {}", scenario.name());
+        }
+    }
+    Ok(())
+}
+
+
+/// Expands each of the given glob patterns into a list of paths.
+///
+/// Matches of each pattern are sorted. `"-"` is passed through
+/// unchanged so that it still means standard input.
+///
+/// # Errors
+/// This fails if a pattern is invalid, not valid UTF-8, or does not
+/// match any file. It also fails if a directory cannot be read while
+/// matching.
+fn expand_globs<'a, I>(patterns: I) -> Result<Vec<Cow<'a, OsStr>>, Error>
+where
+    I: Iterator<Item = &'a OsStr>,
+{
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        if pattern == "-" {
+            paths.push(Cow::Borrowed(pattern));
+            continue;
+        }
+        let pattern = pattern.try_to_str()?;
+        let mut matches = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(NoMatches(pattern.to_owned()).into());
+        }
+        matches.sort();
+        paths.extend(matches.into_iter().map(|path| Cow::Owned(path.into_os_string())));
+    }
+    Ok(paths)
+}
+
+
+/// Reads the paths listed in the manifest file of --files-from.
+///
+/// Each line names one file. Relative paths are resolved against the
+/// directory of `manifest`. Empty lines and lines starting with `#`
+/// are skipped.
+///
+/// # Errors
+/// This fails if `manifest` cannot be read or if a listed file does
+/// not exist.
+fn read_manifest(manifest: &Path) -> Result<Vec<PathBuf>, Error> {
+    let contents = fs::read_to_string(manifest)
+        .with_context(|_| format!("could not read {:?}", manifest))?;
+    let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let mut paths = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let path = dir.join(line);
+        if !path.is_file() {
+            return Err(MissingManifestEntry(manifest.to_owned(), i + 1, path).into());
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+
+/// Parses an option that takes a number of seconds.
+///
+/// If the option `name` is not present, this returns `Ok(None)`.
+fn duration_from_args(args: &clap::ArgMatches, name: &str) -> Result<Option<Duration>, Error> {
+    let seconds = match args.value_of_os(name) {
+        Some(seconds) => seconds.try_to_str()?,
+        None => return Ok(None),
+    };
+    let seconds = seconds
+        .parse()
+        .map_err(|_| NotANumber(seconds.to_owned()))?;
+    Ok(Some(Duration::from_secs(seconds)))
+}
+
+
+/// Helper struct that breaks up the task of executing a command line.
+///
+/// It is used as a loop driver for [`loop_in_process_pool()`].
+///
+/// [`loop_in_process_pool()`]: ./consumers/fn.loop_in_process_pool.html
+pub struct CommandLineHandler<'a> {
+    /// The number of failed scenarios after which to abort.
+    ///
+    /// This is `Some(1)` by default, `None` if --keep-going is passed,
+    /// and the argument of --fail-fast-after if that is passed.
+    max_failures: Option<usize>,
+    /// The number of scenarios that have failed so far.
+    num_failures: usize,
+    /// The number of scenarios that have succeeded so far.
+    num_successes: usize,
+    /// Argument read from --on-success.
+    on_success: Option<&'a OsStr>,
+    /// Argument read from --on-failure.
+    on_failure: Option<&'a OsStr>,
+    /// Argument read from --on-group-change.
+    on_group_change: Option<&'a OsStr>,
+    /// The group of the scenarios that are currently being run.
+    current_group: Option<String>,
+    /// Argument read from --jobs.
+    max_num_of_children: usize,
+    /// Flag read from --ordered-output.
+    ordered_output: bool,
+    /// Flag read from --reap-in-order.
+    reap_in_order: bool,
+    /// Argument read from --separator.
+    separator: Option<&'a str>,
+    /// Flag read from --verbose.
+    verbose: bool,
+    /// The placeholder for the scenario name in `separator`.
+    placeholder: &'a str,
+    /// Flag read from --summary.
+    print_summary: bool,
+    /// The number of combinations if --export-index is passed.
+    total: Option<usize>,
+    /// The index of the next combination that is started.
+    ///
+    /// This is a `Cell` because `prepare_child()` only receives a
+    /// shared reference.
+    next_index: Cell<usize>,
+    /// Argument read from --max-total-time.
+    max_total_time: Option<Duration>,
+    /// The number of scenarios skipped because of --max-total-time.
+    num_skipped: usize,
+    /// State file read from --if-changed.
+    run_cache: Option<consumers::RunCache>,
+    /// The fingerprints of all started scenarios, by index.
+    ///
+    /// This is only filled if --if-changed is passed. It is a
+    /// `RefCell` for the same reason as `next_index`.
+    fingerprints: RefCell<Vec<u64>>,
+    /// The number of scenarios skipped because of --if-changed.
+    num_unchanged: Cell<usize>,
+    /// Flag that is set until --strict-unused-vars has warned once.
+    ///
+    /// This is only ever set if COMMAND is known to ignore its
+    /// environment.
+    warn_unused_vars: Cell<bool>,
+    /// The command line that is executed for each scenario.
+    command_line: consumers::CommandLine<Cow<'a, OsStr>>,
+    /// A logger that helps us print information to the user.
+    logger: logger::Logger<'static>,
+    /// A flag that is set if any error occurs during processing.
+    ///
+    /// This is used so we can tell the user something went wrong even
+    /// if --keep-going has been passed.
+    any_errors: bool,
+    /// The exit code that belongs to the error that aborted the loop.
+    ///
+    /// This is `None` if the loop has not been aborted.
+    abort_exit_code: Option<i32>,
+}
+
+impl<'a> CommandLineHandler<'a> {
+    /// Creates a new handler.
+    ///
+    /// This reads the parsed command-line arguments and initializes
+    /// the fields of this struct from them. `total` is the number of
+    /// combinations that will be run, if known. It is used if
+    /// --export-index or `--jobs 0` is passed.
+    pub fn new(args: &'a clap::ArgMatches, total: Option<usize>) -> Result<Self, Error> {
+        let max_num_of_children = match Self::max_num_tokens_from_args(args)
+            .context(InvalidValue("--jobs"))?
+        {
+            0 => total.expect("--jobs 0 collects all combinations").max(1),
+            num_jobs => num_jobs,
+        };
+        let max_total_time = duration_from_args(args, "max_total_time")
+            .context(InvalidValue("--max-total-time"))?;
+        let max_failures =
+            Self::max_failures_from_args(args).context(InvalidValue("--fail-fast-after"))?;
+        let separator = match args.value_of_os("separator") {
+            Some(separator) => Some(separator.try_to_str().context(InvalidValue("--separator"))?),
+            None => None,
+        };
+        let run_cache = match args.value_of_os("if_changed") {
+            Some(path) => Some(
+                consumers::RunCache::load(path).context(InvalidValue("--if-changed"))?,
+            ),
+            None => None,
+        };
+        let command_line = Self::command_line_from_args(args)?;
+        let logger = logger_from_args(args);
+        if Self::inserts_unexported_name(&command_line) {
+            logger.warn(
+                "the scenario name is inserted into COMMAND, but not exported \
+                 because of --no-export-name (pass --no-insert-name to turn off both)",
+            );
+        }
+        let options = command_line.options();
+        let passes_name = options.add_scenarios_name || options.insert_name_in_args;
+        if args.is_present("name_transform") && !passes_name {
+            logger.warn(
+                "--name-transform has no effect because of --no-export-name \
+                 and --no-insert-name",
+            );
+        }
+        let warn_unused_vars = args.is_present("strict_unused_vars")
+            && args.is_present("ignore_env")
+            && Self::ignores_environment(&command_line);
+        let handler = CommandLineHandler {
+            any_errors: false,
+            abort_exit_code: None,
+            max_num_of_children,
+            ordered_output: args.is_present("ordered_output"),
+            reap_in_order: args.is_present("reap_in_order"),
+            separator,
+            verbose: args.is_present("verbose"),
+            placeholder: placeholder_from_args(args)?,
+            print_summary: args.is_present("summary"),
+            total: total.filter(|_| args.is_present("export_index")),
+            next_index: Cell::new(0),
+            max_total_time,
+            num_skipped: 0,
+            run_cache,
+            fingerprints: RefCell::new(Vec::new()),
+            num_unchanged: Cell::new(0),
+            warn_unused_vars: Cell::new(warn_unused_vars),
+            max_failures,
+            num_failures: 0,
+            num_successes: 0,
+            on_success: args.value_of_os("on_success"),
+            on_failure: args.value_of_os("on_failure"),
+            on_group_change: args.value_of_os("on_group_change"),
+            current_group: None,
+            command_line,
+            logger,
+        };
+        Ok(handler)
+    }
+
+    /// Checks whether COMMAND is a no-op that ignores its environment.
+    ///
+    /// This is a heuristic for --strict-unused-vars. It only recognizes
+    /// programs like `true` that are commonly used as placeholders.
+    fn ignores_environment(command_line: &consumers::CommandLine<Cow<'a, OsStr>>) -> bool {
+        const NO_OP_PROGRAMS: &[&str] = &["true", "false", ":"];
+        Path::new(command_line.program())
+            .file_name()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| NO_OP_PROGRAMS.contains(&name))
+    }
+
+    /// Checks whether --no-export-name only has half of its effect.
+    ///
+    /// This is the case if the placeholder still appears in COMMAND,
+    /// so the scenario name is passed on anyway. This might be
+    /// intended, but more likely --no-insert-name was forgotten. An
+    /// empty placeholder never inserts anything.
+    fn inserts_unexported_name(command_line: &consumers::CommandLine<Cow<'a, OsStr>>) -> bool {
+        let options = command_line.options();
+        !options.add_scenarios_name
+            && options.insert_name_in_args
+            && !options.placeholder.is_empty()
+            && command_line
+                .args()
+                .iter()
+                .filter_map(|arg| arg.to_str())
+                .any(|arg| arg.contains(&options.placeholder))
+    }
+
+    /// Creates a [`CommandLine`] from `args`.
+    ///
+    /// [`CommandLine`]: ./consumers/struct.CommandLine.html
+    fn command_line_from_args(
+        args: &'a clap::ArgMatches,
+    ) -> Result<consumers::CommandLine<Cow<'a, OsStr>>, Error> {
+        let timeout =
+            duration_from_args(args, "timeout").context(InvalidValue("--timeout"))?;
+        let abort_timeout = duration_from_args(args, "abort_timeout")
+            .context(InvalidValue("--abort-timeout"))?;
+        let append_separator = match args.value_of_os("append_separator") {
+            Some(separator) => separator
+                .try_to_str()
+                .context(InvalidValue("--append-separator"))?,
+            None => consumers::DEFAULT_APPEND_SEPARATOR,
+        };
+        let placeholder = placeholder_from_args(args)?;
+        let name_transform = match args.value_of("name_transform") {
+            Some("upper") => consumers::NameTransform::Upper,
+            Some("lower") => consumers::NameTransform::Lower,
+            Some("slug") => consumers::NameTransform::Slug,
+            _ => consumers::NameTransform::None,
+        };
+        let out_dir = match args.value_of_os("out_dir") {
+            Some(dir) => {
+                fs::create_dir_all(dir)
+                    .with_context(|_| format!("could not create {:?}", dir))
+                    .context(InvalidValue("--out-dir"))?;
+                Some(PathBuf::from(dir))
+            },
+            None => None,
+        };
+        let max_name_length =
+            Self::max_name_length_from_args(args).context(InvalidValue("--max-name-length"))?;
+        let base_env = match args.value_of_os("env_file") {
+            Some(path) => Self::read_env_file(path)
+                .with_context(|_| format!("could not read {:?}", path))
+                .context(InvalidValue("--env-file"))?,
+            None => Vec::new(),
+        };
+        let env_passthrough =
+            Self::env_passthrough_from_args(args).context(InvalidValue("--env-passthrough"))?;
+        let options = consumers::CommandLineOptions {
+            is_strict: !args.is_present("lax"),
+            ignore_env: args.is_present("ignore_env"),
+            env_passthrough,
+            add_scenarios_name: !args.is_present("no_export_name"),
+            insert_name_in_args: !args.is_present("no_insert_name"),
+            placeholder: placeholder.to_owned(),
+            timeout,
+            abort_timeout,
+            enable_functions: args.is_present("enable_functions"),
+            allow_non_utf8_values: args.is_present("allow_non_utf8_values"),
+            append_separator: append_separator.to_owned(),
+            name_transform,
+            base_env,
+            out_dir,
+            warn_env_shadow: args.is_present("warn_env_shadow"),
+            max_name_length,
+            expand_command: args.is_present("expand_command"),
+            allow_unset: args.is_present("allow_unset"),
+        };
+        if let Some(path) = args.value_of_os("exec_file") {
+            let argv = fs::read_to_string(path)
+                .map(|contents| Self::parse_exec_lines(&contents))
+                .with_context(|_| format!("could not read {:?}", path))
+                .context(InvalidValue("--exec-file"))?;
+            let argv = argv.into_iter().map(Cow::Owned);
+            return consumers::CommandLine::with_options(argv, options)
+                .ok_or_else(|| Error::from(NoCommand("--exec-file")));
+        }
+        if args.is_present("exec_stdin") {
+            let mut contents = String::new();
+            io::stdin()
+                .read_to_string(&mut contents)
+                .context("could not read stdin")
+                .context(InvalidValue("--exec-stdin"))?;
+            let argv = Self::parse_exec_lines(&contents).into_iter().map(Cow::Owned);
+            return consumers::CommandLine::with_options(argv, options)
+                .ok_or_else(|| Error::from(NoCommand("--exec-stdin")));
+        }
+        // This is only called if the argument `exec` is
+        // present. And since it's a positional argument, i.e. not an
+        // --option, being present also means not being empty. Hence,
+        // it is safe to unwrap here.
+        let command_line = args
+            .values_of_os("exec")
+            .map(|argv| argv.map(Cow::Borrowed))
+            .and_then(|argv| consumers::CommandLine::with_options(argv, options))
+            .unwrap();
+        Ok(command_line)
+    }
+
+    /// Parses a command line given as one argument per line.
+    ///
+    /// This is the format of --exec-file and --exec-stdin. Blank lines
+    /// and lines starting with `#` are skipped. All other lines are
+    /// taken verbatim, including surrounding whitespace.
+    fn parse_exec_lines(contents: &str) -> Vec<OsString> {
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(OsString::from)
+            .collect()
+    }
+
+    /// Reads the variables passed via --env-file.
+    ///
+    /// Each line of the file has the form `NAME=VALUE`, optionally
+    /// preceded by `export`. Surrounding whitespace is stripped, and
+    /// so are matching single or double quotes around the value.
+    /// Blank lines and lines starting with "#" are skipped.
+    fn read_env_file(path: &OsStr) -> Result<Vec<(String, String)>, Error> {
+        let contents = fs::read_to_string(path)?;
+        let mut vars = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = match line.strip_prefix("export ") {
+                Some(rest) => rest.trim_start(),
+                None => line,
+            };
+            let (name, value) = match line.find('=') {
+                Some(pos) if pos > 0 => (line[..pos].trim_end(), line[pos + 1..].trim_start()),
+                _ => return Err(BadEnvFileLine(i + 1, line.to_owned()).into()),
+            };
+            vars.push((name.to_owned(), unquote(value).to_owned()));
+        }
+        Ok(vars)
+    }
+
+    /// Parses and interprets the `--keep-going` and `--fail-fast-after`
+    /// options.
+    fn max_failures_from_args(args: &clap::ArgMatches) -> Result<Option<usize>, Error> {
+        if args.is_present("keep_going") {
+            return Ok(None);
         }
+        let max_failures = match args.value_of_os("fail_fast_after") {
+            Some(max_failures) => max_failures.try_to_str()?,
+            None => return Ok(Some(1)),
+        };
+        let num_failures = max_failures
+            .parse()
+            .map_err(|_| NotANumber(max_failures.to_owned()))?;
+        if num_failures == 0 {
+            return Err(NotPositive(max_failures.to_owned()).into());
+        }
+        Ok(Some(num_failures))
+    }
+
+    /// Parses the `--max-name-length` option, if it is passed.
+    fn max_name_length_from_args(args: &clap::ArgMatches) -> Result<Option<usize>, Error> {
+        let max_len = match args.value_of_os("max_name_length") {
+            Some(max_len) => max_len.try_to_str()?,
+            None => return Ok(None),
+        };
+        let max_len = max_len
+            .parse()
+            .map_err(|_| NotANumber(max_len.to_owned()))?;
+        if max_len <= consumers::TRUNCATION_SUFFIX_LEN {
+            return Err(NameLengthTooShort(consumers::TRUNCATION_SUFFIX_LEN).into());
+        }
+        Ok(Some(max_len))
+    }
+
+    /// Parses the comma-separated list of the `--env-passthrough`
+    /// option.
+    ///
+    /// Names must not be empty and must not contain `=` or NUL
+    /// characters, since no environment variable could have them.
+    fn env_passthrough_from_args(args: &clap::ArgMatches) -> Result<Vec<String>, Error> {
+        let list = match args.value_of_os("env_passthrough") {
+            Some(list) => list.try_to_str()?,
+            None => return Ok(Vec::new()),
+        };
+        list.split(',')
+            .map(|name| {
+                if name.is_empty() || name.contains(['=', '\0']) {
+                    Err(BadVariableName(name.to_owned()).into())
+                } else {
+                    Ok(name.to_owned())
+                }
+            })
+            .collect()
+    }
+
+    /// Parses and interprets the `--jobs` option.
+    ///
+    /// This returns zero if the number of jobs should be unbounded.
+    fn max_num_tokens_from_args(args: &clap::ArgMatches) -> Result<usize, Error> {
+        if args.occurrences_of("jobs") == 0 {
+            return Ok(1);
+        }
+        let jobs_arg = args
+            .value_of_os("jobs")
+            .expect("default value")
+            .try_to_str()?;
+        if jobs_arg == "auto" {
+            return Ok(num_cpus::get());
+        }
+        if let Some(percentage) = jobs_arg.strip_suffix('%') {
+            let percentage: usize = percentage
+                .parse()
+                .map_err(|_| NotANumber(percentage.to_owned()))?;
+            if percentage == 0 {
+                return Err(NotPositive(jobs_arg.to_owned()).into());
+            }
+            // Round up so that we never end up with zero jobs.
+            return Ok((num_cpus::get() * percentage).div_ceil(100));
+        }
+        let num_jobs = jobs_arg
+            .parse()
+            .map_err(|_| NotANumber(jobs_arg.to_owned()))?;
+        Ok(num_jobs)
+    }
+
+    /// Prints the --separator line for the scenario `name`, if any.
+    fn print_separator(&self, name: &str) -> Result<(), Error> {
+        if let Some(separator) = self.separator {
+            let line = separator.replace(self.placeholder, name);
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            writeln!(stdout, "{}", line)
+                .and_then(|_| stdout.flush())
+                .context("could not print separator")?;
+        }
+        Ok(())
+    }
+
+    /// Remembers that the child with `index` has succeeded.
+    ///
+    /// This does nothing unless --if-changed is passed.
+    fn record_success(&mut self, index: usize) {
+        if let Some(ref mut cache) = self.run_cache {
+            cache.record_success(self.fingerprints.borrow()[index]);
+        }
+    }
+
+    /// Prints the captured output of `child`, preceded by a separator.
+    fn flush_output(&self, child: &mut FinishedChild) -> Result<(), Error> {
+        if self.ordered_output {
+            self.print_separator(child.name())?;
+        }
+        child
+            .flush_output()
+            .context("could not print output of job")?;
+        Ok(())
+    }
+}
+
+impl<'a, 's> consumers::LoopDriver<Combination<'s>> for CommandLineHandler<'a> {
+    fn max_num_of_children(&self) -> usize {
+        self.max_num_of_children
+    }
+
+    fn max_total_time(&self) -> Option<Duration> {
+        self.max_total_time
+    }
+
+    fn preserve_order(&self) -> bool {
+        self.ordered_output
+    }
+
+    fn reap_in_order(&self) -> bool {
+        self.reap_in_order
+    }
+
+    fn should_skip(&self, (_, s): &Combination<'s>) -> bool {
+        let (cache, scenario) = match (&self.run_cache, s) {
+            (Some(cache), Ok(scenario)) => (cache, scenario),
+            _ => return false,
+        };
+        let fingerprint =
+            consumers::RunCache::fingerprint(scenario, self.command_line.command_line());
+        if cache.is_unchanged(fingerprint) {
+            self.num_unchanged.set(self.num_unchanged.get() + 1);
+            return true;
+        }
+        self.fingerprints.borrow_mut().push(fingerprint);
+        false
+    }
+
+    fn starts_group(&self, &(group, _): &Combination<'s>) -> bool {
+        group.is_some() && group != self.current_group.as_deref()
+    }
+
+    fn on_group_start(&mut self, &(group, _): &Combination<'s>) -> Result<(), Error> {
+        let group = group.expect("item without group");
+        self.current_group = Some(group.to_owned());
+        let hook = match self.on_group_change {
+            Some(hook) => hook,
+            None => return Ok(()),
+        };
+        let mut command = shell_command(hook);
+        command.env("SCENARIOS_GROUP", group);
+        self.run_command_as_hook("--on-group-change", command)
+    }
+
+    fn prepare_child(&self, (_, s): Combination<'s>) -> Result<PreparedChild<'_>, Error> {
+        let s = s?;
+        if self.warn_unused_vars.get() && !s.has_no_variables() {
+            self.warn_unused_vars.set(false);
+            self.logger.warn(format_args!(
+                "scenario \"{}\" defines variables, but COMMAND {:?} ignores them",
+                s.name(),
+                self.command_line.program(),
+            ));
+        }
+        let mut child = match self.total {
+            Some(total) => {
+                let index = self.next_index.get();
+                self.next_index.set(index + 1);
+                self.command_line.with_indexed_scenario(s, index, total)?
+            },
+            None => self.command_line.with_scenario(s)?,
+        };
+        child.set_capture_output(self.ordered_output);
+        for warning in child.warnings() {
+            self.logger
+                .warn(format_args!("scenario \"{}\": {}", child.name(), warning));
+        }
+        if self.verbose {
+            let command = child.command();
+            let argv = iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(shell_quote)
+                .collect::<Vec<_>>();
+            self.logger
+                .log(format!("running scenario \"{}\": {}", child.name(), argv.join(" ")));
+        }
+        // Captured output gets its separator when it is printed.
+        if !self.ordered_output {
+            self.print_separator(child.name())?;
+        }
+        Ok(child)
+    }
+
+    fn on_reap(&mut self, mut child: FinishedChild) -> Result<(), Error> {
+        self.flush_output(&mut child)?;
+        // Jobs that could not be started never reach this point. They
+        // always abort the loop, regardless of --keep-going, because
+        // they usually point to a mistake in the command line.
+        let index = child.index();
+        let err = match child.into_result() {
+            Ok(()) => {
+                self.num_successes += 1;
+                self.record_success(index);
+                return Ok(());
+            },
+            Err(err) => err,
+        };
+        self.num_failures += 1;
+        if self.max_failures.is_some_and(|max| self.num_failures >= max) {
+            return Err(err);
+        }
+        // TODO: Avoid logging the word "error" here, because this
+        // event does not stop us from running.
+        self.any_errors = true;
+        self.logger.log_error_chain(&err);
+        Ok(())
+    }
+
+    fn on_truncated(&mut self, num_skipped: usize) {
+        self.num_skipped = num_skipped;
+        self.logger.log("time limit exceeded, not starting any more jobs");
     }
 
     fn on_loop_failed(&mut self, error: Error) {
         self.any_errors = true;
+        // Failed jobs have already been counted in `on_reap()`.
+        if is_a::<ScenarioNotStarted>(error.cause()) {
+            self.num_failures += 1;
+        }
+        self.abort_exit_code = Some(exit_code_of(&error));
         self.logger.log_error_chain(&error);
         if self.max_num_of_children > 1 {
             self.logger.log("waiting for unfinished jobs ...");
@@ -315,32 +1858,257 @@ impl<'a, 's> consumers::LoopDriver<Result<Scenario<'s>, MergeError>> for Command
     }
 
     fn on_cleanup_reap(&mut self, child: Result<FinishedChild, Error>) {
-        if let Err(err) = child.and_then(FinishedChild::into_result) {
-            // TODO: Avoid logging the word "error" here, because this
-            // event does not stop us from running.
-            self.logger.log_error_chain(&err);
+        let child = child.and_then(|mut child| {
+            self.flush_output(&mut child)?;
+            Ok(child)
+        });
+        let index = child.as_ref().map(FinishedChild::index).ok();
+        match child.and_then(FinishedChild::into_result) {
+            Ok(()) => {
+                self.num_successes += 1;
+                self.record_success(index.expect("successful child"));
+            },
+            Err(err) => {
+                self.num_failures += 1;
+                // TODO: Avoid logging the word "error" here, because
+                // this event does not stop us from running.
+                self.logger.log_error_chain(&err);
+            },
         }
     }
 
-    fn on_finish(self) -> Result<(), Error> {
+    fn on_finish(mut self) -> Result<(), Error> {
+        if self.num_skipped > 0 {
+            self.logger
+                .log(format!("skipped {} scenarios because time ran out", self.num_skipped));
+        }
+        if self.num_unchanged.get() > 0 {
+            self.logger
+                .log(format!("skipped {} unchanged scenarios", self.num_unchanged.get()));
+        }
+        // Failed runs must not keep successful ones from being saved.
+        if let Some(ref cache) = self.run_cache {
+            if let Err(err) = cache.save() {
+                let err = Error::from(err.context(StateFileFailed(cache.path().to_owned())));
+                self.logger.log_error_chain(&err);
+                self.any_errors = true;
+            }
+        }
+        if self.print_summary {
+            self.logger.log(format!(
+                "ran {} scenarios: {} ok, {} failed",
+                self.num_successes + self.num_failures,
+                self.num_successes,
+                self.num_failures
+            ));
+        }
         if !self.any_errors {
-            Ok(())
+            self.run_hook("--on-success", self.on_success)
         } else {
-            Err(Error::from(SomeScenariosFailed))
+            if let Err(err) = self.run_hook("--on-failure", self.on_failure) {
+                self.logger.log_error_chain(&err);
+            }
+            let exit_code = self.abort_exit_code.unwrap_or(EXIT_SCENARIOS_FAILED);
+            Err(Error::from(SomeScenariosFailed(exit_code)))
+        }
+    }
+}
+
+impl<'a> CommandLineHandler<'a> {
+    /// Runs the command passed to `option`, if any, and waits for it.
+    ///
+    /// The command is passed to the shell. It inherits our environment
+    /// plus variables that contain the number of succeeded and failed
+    /// scenarios.
+    fn run_hook(&self, option: &'static str, hook: Option<&OsStr>) -> Result<(), Error> {
+        match hook {
+            Some(hook) => self.run_command_as_hook(option, shell_command(hook)),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `command` for the hook `option` and waits for it.
+    ///
+    /// The counts of succeeded and failed scenarios are added to the
+    /// environment of `command`.
+    fn run_command_as_hook(&self, option: &'static str, mut command: Command) -> Result<(), Error> {
+        let status = command
+            .env("SCENARIOS_SUCCEEDED", self.num_successes.to_string())
+            .env("SCENARIOS_FAILED", self.num_failures.to_string())
+            .status()
+            .context(HookFailed(option))?;
+        if !status.success() {
+            let err = HookExitStatus(status.code());
+            return Err(Error::from(err.context(HookFailed(option))));
+        }
+        Ok(())
+    }
+}
+
+
+/// Removes matching single or double quotes around `value`.
+fn unquote(value: &str) -> &str {
+    for quote in &["\"", "'"] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
         }
     }
+    value
+}
+
+
+/// Creates a command that passes `command` to the system shell.
+#[cfg(unix)]
+fn shell_command(command: &OsStr) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Creates a command that passes `command` to the system shell.
+#[cfg(windows)]
+fn shell_command(command: &OsStr) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+
+/// Error that signals that an argument has an invalid value.
+#[derive(Debug, Fail)]
+#[fail(display = "invalid value for {}", _0)]
+pub struct InvalidValue(&'static str);
+
+
+/// Error that signals that --glob-inputs could not be applied.
+#[derive(Debug, Fail)]
+#[fail(display = "could not expand file name patterns")]
+pub struct ExpandFailed;
+
+
+/// Error that signals that --on-success or --on-failure failed.
+#[derive(Debug, Fail)]
+#[fail(display = "{} command failed", _0)]
+pub struct HookFailed(&'static str);
+
+
+/// Error that signals that a hook exited with a non-zero exit status.
+#[derive(Debug, Fail)]
+pub struct HookExitStatus(Option<i32>);
+
+impl std::fmt::Display for HookExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            Some(code) => write!(f, "exited with non-zero exit code: {}", code),
+            None => write!(f, "exited by signal"),
+        }
+    }
+}
+
+
+/// Error that signals that a line of --env-file is malformed.
+#[derive(Debug, Fail)]
+#[fail(display = "line {}: expected NAME=VALUE: {:?}", _0, _1)]
+pub struct BadEnvFileLine(usize, String);
+
+
+/// Error that signals an impossible name in --env-passthrough.
+#[derive(Debug, Fail)]
+#[fail(display = "not a valid variable name: {:?}", _0)]
+pub struct BadVariableName(String);
+
+
+/// Error that signals an unknown escape sequence in an argument.
+#[derive(Debug, Fail)]
+#[fail(display = "invalid escape sequence: {:?}", _0)]
+pub struct BadEscape(String);
+
+
+/// Error that signals that a scenario file could not be read.
+#[derive(Debug, Fail)]
+#[fail(display = "could not read file")]
+pub struct ReadFailed;
+
+
+/// Error that signals that two scenario files define the same scenario
+/// name in strict mode.
+#[derive(Debug, Fail)]
+#[fail(display = "two scenario files define the same scenario")]
+pub struct SharedScenarioName;
+
+
+/// Error that signals that --check found problems.
+#[derive(Debug, Fail)]
+#[fail(display = "--check found {} error(s)", _0)]
+pub struct CheckFailed(usize);
+
+
+/// Error that signals that --fail-on-empty found a file without
+/// scenarios.
+#[derive(Debug, Fail)]
+pub struct EmptyFile(PathBuf);
+
+impl std::fmt::Display for EmptyFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "file \"{}\" defines no scenarios", self.0.display())
+    }
+}
+
+
+/// Error that signals that --emit-envfiles would overwrite a file.
+#[derive(Debug, Fail)]
+pub struct FileExists(PathBuf);
+
+impl std::fmt::Display for FileExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "file \"{}\" already exists, pass --force to overwrite it",
+            self.0.display()
+        )
+    }
+}
+
+
+/// Error that signals that --emit-envfiles cannot write a scenario.
+#[derive(Debug, Fail)]
+pub enum NotRepresentable {
+    #[fail(display = "scenario \"{}\" removes variable \"{}\"", _0, _1)]
+    Removal(String, String),
+    #[fail(display = "scenario \"{}\" appends to variable \"{}\"", _0, _1)]
+    Append(String, String),
+    #[fail(display = "scenario \"{}\": value of \"{}\" has line breaks or invalid UTF-8", _0, _1)]
+    Value(String, String),
+}
+
+
+/// Error that signals that two scenarios map to the same env file.
+#[derive(Debug, Fail)]
+pub struct NameCollision(String, String, PathBuf);
+
+impl std::fmt::Display for NameCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "scenarios \"{}\" and \"{}\" would both be written to \"{}\"",
+            self.0,
+            self.1,
+            self.2.display()
+        )
+    }
 }
 
 
 /// Dummy error that signals that *some* thing went wrong.
 ///
 /// Because [`CommandLineHandler`] already reports errors, we use this
-/// dummy error to avoid reporting the same error twice.
+/// dummy error to avoid reporting the same error twice. It carries the
+/// exit code that the reported errors call for.
 ///
 /// [`CommandLineHandler`]: ./struct.CommandLineHandler.html
 #[derive(Debug, Fail)]
 #[fail(display = "not all scenarios terminated successfully")]
-pub struct SomeScenariosFailed;
+pub struct SomeScenariosFailed(i32);
 
 
 /// Error that signals that no scenario files were given.
@@ -349,6 +2117,98 @@ pub struct SomeScenariosFailed;
 pub struct NoScenarios;
 
 
+/// Error that signals that a glob pattern did not match any file.
+#[derive(Debug, Fail)]
+#[fail(display = "no files match the pattern {:?}", _0)]
+pub struct NoMatches(String);
+
+
+/// Error that signals that a file listed by --files-from does not
+/// exist.
+#[derive(Debug, Fail)]
+pub struct MissingManifestEntry(PathBuf, usize, PathBuf);
+
+impl std::fmt::Display for MissingManifestEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "in {}:{}: no such file: {:?}",
+            self.0.display(),
+            self.1,
+            self.2
+        )
+    }
+}
+
+
+/// Error that signals that --continue-from named an unknown scenario.
+#[derive(Debug, Fail)]
+#[fail(display = "no scenario is named {:?}", _0)]
+pub struct NoSuchScenario(String);
+
+
+/// Error that signals that --exec-file or --exec-stdin did not
+/// contain a command.
+#[derive(Debug, Fail)]
+#[fail(display = "no command given in {}", _0)]
+pub struct NoCommand(&'static str);
+
+
+/// Error that signals that stdin was requested for two things.
+#[derive(Debug, Fail)]
+#[fail(display = "cannot read both scenarios and --exec-stdin from stdin")]
+pub struct StdinConflict;
+
+
+/// Error that signals that stdin was given as input more than once.
+#[derive(Debug, Fail)]
+#[fail(display = "stdin can only be read once, but \"-\" was given {} times", _0)]
+pub struct StdinUsedTwice(usize);
+
+
+/// Error that signals that a number is zero where it must not be.
+#[derive(Debug, Fail)]
+#[fail(display = "must be greater than zero: {:?}", _0)]
+pub struct NotPositive(String);
+
+
+/// Error that signals a bad argument to `--axis-order`.
+#[derive(Debug, Fail)]
+#[fail(display = "not an ordering of the {} input files: {:?}", _1, _0)]
+pub struct NotAPermutation(String, usize);
+
+
+/// Error that signals an empty argument to `--name-format`.
+#[derive(Debug, Fail)]
+#[fail(display = "name format must not be empty")]
+pub struct EmptyNameFormat;
+
+
+/// Error that signals a reference to a file that wasn't given.
+#[derive(Debug, Fail)]
+#[fail(display = "{{{}}} is out of range, there are only {} input files", _0, _1)]
+pub struct FileIndexOutOfRange(usize, usize);
+
+
+/// Error that signals that the --if-changed state could not be saved.
+#[derive(Debug, Fail)]
+#[fail(display = "could not write state file: {:?}", _0)]
+pub struct StateFileFailed(PathBuf);
+
+
+/// Error that signals an argument to `--max-name-length` that leaves
+/// no room for the name.
+#[derive(Debug, Fail)]
+#[fail(display = "must be greater than {}, the length of the hash suffix", _0)]
+pub struct NameLengthTooShort(usize);
+
+
+/// Error that signals an index that doesn't belong to an input file.
+#[derive(Debug, Fail)]
+#[fail(display = "no file at index {}, there are only {} input files", _0, _1)]
+pub struct NoSuchFile(usize, usize);
+
+
 /// Error that signals that a number could not be parsed.
 #[derive(Debug, Fail)]
 #[fail(display = "not a number: {:?}", _0)]
@@ -12,7 +12,7 @@
 // implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
-use std::fmt;
+use std::{cell::Cell, fmt};
 
 use failure::{Error, ResultExt};
 use glob::{self, MatchOptions, Pattern};
@@ -33,7 +33,23 @@ use super::Scenario;
 ///
 /// The pattern may be any shell-like glob pattern, in which the
 /// patterns `"*"`, `"?"`, `"[...]"` and `"[^...]"` are interpreted
-/// specially. (See the [`glob`] crate for more information.)
+/// specially. (See the [`glob`] crate for more information.) By
+/// default, matching is case-sensitive.
+///
+/// A pattern that starts with an exclamation mark `"!"` is negated: a
+/// name matches it if it does *not* match the rest of the pattern.
+/// Thus, `"!*debug*"` in [`ChooseMatching`] mode allows all names that
+/// don't contain `"debug"`. A literal leading exclamation mark can be
+/// written as `"\!"`.
+///
+/// Brace groups like `"{a,b}"` are expanded before matching, so
+/// `"{foo,bar}*"` matches all names that start with `"foo"` or
+/// `"bar"`. Braces may not be nested. A literal brace can be written as
+/// `"[{]"` or `"[}]"`.
+///
+/// The filter counts how many names have matched its pattern. This
+/// can be used to detect patterns that don't match anything, e.g.
+/// because of a typo.
 ///
 /// [`Mode`]: ./enum.FilterMode.html
 /// [`ChooseMatching`]: ./enum.FilterMode.html
@@ -43,6 +59,10 @@ use super::Scenario;
 pub struct NameFilter {
     mode: Mode,
     pattern: Option<Pattern>,
+    expansions: Vec<Pattern>,
+    negated: bool,
+    ignore_case: bool,
+    num_matches: Cell<usize>,
 }
 
 impl NameFilter {
@@ -51,6 +71,10 @@ impl NameFilter {
         NameFilter {
             mode,
             pattern: None,
+            expansions: Vec::new(),
+            negated: false,
+            ignore_case: false,
+            num_matches: Cell::new(0),
         }
     }
 
@@ -68,19 +92,35 @@ impl NameFilter {
     ///
     /// Depending on the filter's [`Mode`], the scenario's name must
     /// either match or *not* match the filter's pattern to be allowed.
+    /// If the pattern is negated, this is reversed.
     ///
     /// [`Mode`]: ./enum.FilterMode.html
     pub fn allows(&self, scenario: &Scenario) -> bool {
+        self.allows_name(scenario.name())
+    }
+
+    /// Returns `true` if the filter allows a scenario of this name.
+    ///
+    /// This is like [`allows()`], but does not require a [`Scenario`].
+    /// It can be used to filter names before building scenarios.
+    ///
+    /// [`allows()`]: #method.allows
+    /// [`Scenario`]: ./struct.Scenario.html
+    pub fn allows_name(&self, name: &str) -> bool {
         let options = MatchOptions {
-            case_sensitive: true,
+            case_sensitive: !self.ignore_case,
             require_literal_separator: false,
             require_literal_leading_dot: false,
         };
-        let matches = self
-            .pattern
-            .as_ref()
-            .map(|p| p.matches_with(scenario.name(), &options))
-            .unwrap_or(false);
+        let matches = self.pattern.is_some()
+            && self
+                .expansions
+                .iter()
+                .any(|p| p.matches_with(name, &options))
+                != self.negated;
+        if matches {
+            self.num_matches.set(self.num_matches.get() + 1);
+        }
         match self.mode {
             Mode::ChooseMatching => matches,
             Mode::IgnoreMatching => !matches,
@@ -101,6 +141,21 @@ impl NameFilter {
         self.mode = mode;
     }
 
+    /// Returns `true` if the filter's pattern is matched
+    /// case-sensitively.
+    pub fn is_case_sensitive(&self) -> bool {
+        !self.ignore_case
+    }
+
+    /// Sets whether the filter's pattern is matched case-sensitively.
+    ///
+    /// Note that the filter is applied to merged scenario names. With
+    /// case-insensitive matching, every part of a merged name is
+    /// compared case-insensitively, no matter which file it came from.
+    pub fn set_case_sensitive(&mut self, case_sensitive: bool) {
+        self.ignore_case = !case_sensitive;
+    }
+
     /// Adds a pattern to this filter.
     ///
     /// In contrast to [`set_pattern()`], this takes and returns
@@ -113,18 +168,110 @@ impl NameFilter {
     }
 
     /// Sets the filter's pattern.
+    ///
+    /// A leading `"!"` negates the pattern; a leading `"\!"` stands
+    /// for a literal exclamation mark. Brace groups are expanded into
+    /// several patterns; a name matches if it matches any of them.
+    ///
+    /// # Errors
+    /// This fails if the pattern is not a valid glob pattern, or if it
+    /// contains nested or unclosed braces.
     pub fn set_pattern(&mut self, pattern: &str) -> Result<(), Error> {
-        let pattern = Pattern::new(pattern)
+        let (glob, negated) = if let Some(rest) = pattern.strip_prefix('!') {
+            (rest, true)
+        } else if pattern.starts_with("\\!") {
+            (&pattern[1..], false)
+        } else {
+            (pattern, false)
+        };
+        let expansions = expand_braces(glob)
+            .map_err(Error::from)
+            .and_then(|globs| {
+                globs
+                    .iter()
+                    .map(|glob| Pattern::new(glob).map_err(PatternError))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(Error::from)
+            })
+            .with_context(|_| BadPattern(pattern.to_owned()))?;
+        let glob = Pattern::new(glob)
             .map_err(PatternError)
             .with_context(|_| BadPattern(pattern.to_owned()))?;
-        self.pattern = Some(pattern);
+        self.pattern = Some(glob);
+        self.expansions = expansions;
+        self.negated = negated;
         Ok(())
     }
 
     /// Returns the filter's pattern, if it has one.
+    ///
+    /// If the pattern is negated, this does not include the leading
+    /// `"!"`. Use [`is_negated()`] to check for it.
+    ///
+    /// [`is_negated()`]: #method.is_negated
     pub fn pattern(&self) -> &Option<Pattern> {
         &self.pattern
     }
+
+    /// Returns `true` if the filter's pattern is negated.
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Returns how many names have matched the pattern so far.
+    ///
+    /// Every call to [`allows()`] or [`allows_name()`] with a matching
+    /// name increases this number by one, regardless of the filter's
+    /// [`Mode`]. For a negated pattern, a name matches if it does not
+    /// match the rest of the pattern.
+    ///
+    /// [`allows()`]: #method.allows
+    /// [`allows_name()`]: #method.allows_name
+    /// [`Mode`]: ./enum.FilterMode.html
+    pub fn num_matches(&self) -> usize {
+        self.num_matches.get()
+    }
+}
+
+
+/// Type that allows filtering scenarios based on their tags.
+///
+/// A scenario is allowed to pass if it carries all of the filter's
+/// *required* tags and none of its *forbidden* tags. A filter without
+/// any tags allows all scenarios.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TagFilter<'a> {
+    required: Vec<&'a str>,
+    forbidden: Vec<&'a str>,
+}
+
+impl<'a> TagFilter<'a> {
+    /// Creates a new filter that allows all scenarios.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow scenarios that carry `tag`.
+    pub fn require(&mut self, tag: &'a str) {
+        self.required.push(tag);
+    }
+
+    /// Only allow scenarios that do not carry `tag`.
+    pub fn forbid(&mut self, tag: &'a str) {
+        self.forbidden.push(tag);
+    }
+
+    /// Returns `true` if the filter neither requires nor forbids any
+    /// tags.
+    pub fn is_empty(&self) -> bool {
+        self.required.is_empty() && self.forbidden.is_empty()
+    }
+
+    /// Returns `true` if the filter allows this scenario.
+    pub fn allows(&self, scenario: &Scenario) -> bool {
+        self.required.iter().all(|tag| scenario.has_tag(tag))
+            && !self.forbidden.iter().any(|tag| scenario.has_tag(tag))
+    }
 }
 
 
@@ -133,18 +280,64 @@ impl NameFilter {
 /// The default value is `IgnoreMatching`.
 ///
 /// [`NameFilter`]: ./struct.NameFilter.html
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Mode {
     /// Only scenarios with matching name are allowed.
     ChooseMatching,
     /// Only scenarios with non-matching name are allowed.
+    #[default]
     IgnoreMatching,
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Mode::IgnoreMatching
+
+/// Expands all brace groups in `pattern`.
+///
+/// Each group `{a,b,...}` is replaced with each of its alternatives in
+/// turn. With several groups, all combinations are produced. Braces
+/// inside character classes like `[{]` are taken literally, as is a
+/// closing brace without an opening one.
+fn expand_braces(pattern: &str) -> Result<Vec<String>, BraceError> {
+    let start = match find_unbracketed(pattern, |c| c == '{') {
+        Some(start) => start,
+        None => return Ok(vec![pattern.to_owned()]),
+    };
+    let (prefix, group) = pattern.split_at(start);
+    let group = &group[1..];
+    let end = find_unbracketed(group, |c| c == '{' || c == '}').ok_or(BraceError::Unclosed)?;
+    if group[end..].starts_with('{') {
+        return Err(BraceError::Nested);
     }
+    let (alternatives, suffix) = (&group[..end], &group[end + 1..]);
+    let suffixes = expand_braces(suffix)?;
+    let mut result = Vec::new();
+    for alternative in alternatives.split(',') {
+        for suffix in &suffixes {
+            result.push(format!("{}{}{}", prefix, alternative, suffix));
+        }
+    }
+    Ok(result)
+}
+
+
+/// Returns the position of the first char that matches `pred`.
+///
+/// Characters inside of glob character classes `[...]` are skipped.
+fn find_unbracketed<F: Fn(char) -> bool>(s: &str, pred: F) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(c) = s[pos..].chars().next() {
+        if c == '[' {
+            // A closing bracket right at the start of the class is
+            // part of it, e.g. in `[]]` or `[!]]`.
+            let body = pos + 1 + usize::from(s[pos + 1..].starts_with('!'));
+            let end = s[body..].char_indices().skip(1).find(|&(_, c)| c == ']')?.0;
+            pos = body + end + 1;
+        } else if pred(c) {
+            return Some(pos);
+        } else {
+            pos += c.len_utf8();
+        }
+    }
+    None
 }
 
 
@@ -153,6 +346,16 @@ impl Default for Mode {
 pub struct BadPattern(String);
 
 
+/// Errors in the brace groups of a pattern.
+#[derive(Debug, Fail)]
+pub enum BraceError {
+    #[fail(display = "nested braces are not supported")]
+    Nested,
+    #[fail(display = "no closing brace \"}}\"")]
+    Unclosed,
+}
+
+
 #[derive(Debug, Fail)]
 pub struct PatternError(glob::PatternError);
 
@@ -193,10 +396,56 @@ mod tests {
         let filtered = names
             .iter()
             .map(|n| Scenario::new(*n).expect(n))
-            .filter(|s| blacklist.allows(&s))
+            .filter(|s| blacklist.allows(s))
             .map(|s| s.name().to_owned())
             .collect::<Vec<_>>();
         assert_eq!(filtered, &["bark", "berk", "bork", "burk"]);
+        assert_eq!(blacklist.num_matches(), 1);
+    }
+
+    #[test]
+    fn test_num_matches() {
+        let filter = NameFilter::new_blacklist().add_pattern("typo").unwrap();
+        assert_eq!(filter.num_matches(), 0);
+        assert!(filter.allows_name("bark"));
+        assert!(filter.allows_name("berk"));
+        assert_eq!(filter.num_matches(), 0);
+        assert!(!filter.allows_name("typo"));
+        assert_eq!(filter.num_matches(), 1);
+        let filter = NameFilter::default();
+        assert!(filter.allows_name("anything"));
+        assert_eq!(filter.num_matches(), 0);
+    }
+
+    #[test]
+    fn test_negated_pattern() {
+        let filter = NameFilter::new_whitelist().add_pattern("!*debug*").unwrap();
+        assert!(filter.is_negated());
+        assert_eq!(filter.pattern().as_ref().unwrap().as_str(), "*debug*");
+        assert!(filter.allows_name("release"));
+        assert!(!filter.allows_name("x86, debug"));
+        assert_eq!(filter.num_matches(), 1);
+        let filter = NameFilter::new_blacklist().add_pattern("!*debug*").unwrap();
+        assert!(!filter.allows_name("release"));
+        assert!(filter.allows_name("x86, debug"));
+    }
+
+    #[test]
+    fn test_escaped_exclamation_mark() {
+        let filter = NameFilter::new_whitelist().add_pattern("\\!bang").unwrap();
+        assert!(!filter.is_negated());
+        assert!(filter.allows_name("!bang"));
+        assert!(!filter.allows_name("bang"));
+        let filter = NameFilter::new_whitelist().add_pattern("\\bang").unwrap();
+        assert_eq!(filter.pattern().as_ref().unwrap().as_str(), "\\bang");
+    }
+
+    #[test]
+    fn test_allows_name() {
+        let filter = NameFilter::new_whitelist().add_pattern("b?rk").unwrap();
+        assert!(filter.allows_name("bark"));
+        assert!(!filter.allows_name("bar"));
+        assert!(NameFilter::default().allows_name(""));
     }
 
     #[test]
@@ -206,9 +455,73 @@ mod tests {
         let filtered = names
             .iter()
             .map(|n| Scenario::new(*n).expect(n))
-            .filter(|s| blacklist.allows(&s))
+            .filter(|s| blacklist.allows(s))
             .map(|s| s.name().to_owned())
             .collect::<Vec<_>>();
         assert_eq!(filtered, &["bark", "bork", "burk"]);
     }
+
+    #[test]
+    fn test_tag_filter() {
+        let mut fast = Scenario::new("fast").unwrap();
+        fast.add_tag("unit").unwrap();
+        let mut slow = Scenario::new("slow").unwrap();
+        slow.add_tag("unit").unwrap();
+        slow.add_tag("slow").unwrap();
+        let untagged = Scenario::new("untagged").unwrap();
+        let mut filter = TagFilter::new();
+        assert!(filter.is_empty());
+        assert!(filter.allows(&untagged));
+        filter.require("unit");
+        assert!(filter.allows(&fast));
+        assert!(filter.allows(&slow));
+        assert!(!filter.allows(&untagged));
+        filter.forbid("slow");
+        assert!(!filter.is_empty());
+        assert!(filter.allows(&fast));
+        assert!(!filter.allows(&slow));
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(expand_braces("plain*").unwrap(), ["plain*"]);
+        assert_eq!(expand_braces("{foo,bar}*").unwrap(), ["foo*", "bar*"]);
+        assert_eq!(
+            expand_braces("{a,b}, {1,2}").unwrap(),
+            ["a, 1", "a, 2", "b, 1", "b, 2"]
+        );
+        assert_eq!(expand_braces("x{,y}").unwrap(), ["x", "xy"]);
+        assert_eq!(expand_braces("[{]a,b[}]").unwrap(), ["[{]a,b[}]"]);
+        assert_eq!(expand_braces("{[}],b}").unwrap(), ["[}]", "b"]);
+        assert_eq!(expand_braces("a}").unwrap(), ["a}"]);
+        assert_eq!(expand_braces("[]{]{a,b}").unwrap(), ["[]{]a", "[]{]b"]);
+        let err = expand_braces("{a,{b,c}}").unwrap_err();
+        assert_eq!(err.to_string(), "nested braces are not supported");
+        let err = expand_braces("{a,b").unwrap_err();
+        assert_eq!(err.to_string(), "no closing brace \"}\"");
+    }
+
+    #[test]
+    fn test_brace_pattern() {
+        let filter = NameFilter::new_whitelist().add_pattern("{foo,bar}*").unwrap();
+        assert_eq!(filter.pattern().as_ref().unwrap().as_str(), "{foo,bar}*");
+        assert!(filter.allows_name("foo, 1"));
+        assert!(filter.allows_name("bar, 2"));
+        assert!(!filter.allows_name("baz, 3"));
+        assert_eq!(filter.num_matches(), 2);
+        let filter = NameFilter::new_whitelist().add_pattern("!{foo,bar}*").unwrap();
+        assert!(!filter.allows_name("foo"));
+        assert!(filter.allows_name("baz"));
+        assert!(NameFilter::new_whitelist().add_pattern("{a,{b}}").is_err());
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let s = Scenario::new("Build, Debug").unwrap();
+        let mut filter = NameFilter::new_whitelist().add_pattern("build, *").unwrap();
+        assert!(filter.is_case_sensitive());
+        assert!(!filter.allows(&s));
+        filter.set_case_sensitive(false);
+        assert!(filter.allows(&s));
+    }
 }
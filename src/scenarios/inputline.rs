@@ -13,7 +13,7 @@
 // permissions and limitations under the License.
 
 
-use std::str::FromStr;
+use std::{borrow::Cow, str::FromStr};
 
 
 /// Type that defines how each line of an input file is interpreted.
@@ -25,20 +25,38 @@ use std::str::FromStr;
 /// 1. if it is blank or it starts with a hash sign `#`, it is a
 ///    comment;
 /// 2. if it is surrounded by square brackets `[` and `]`, it is a
-///    header line;
-/// 3. if it contains at least one equals sign, it is a definition
-///    line.
+///    header line; `\[` and `\]` inside the header stand for plain
+///    brackets;
+/// 3. if it starts with an exclamation mark `!`, it is a removal
+///    line, which names a variable to remove from the environment;
+/// 4. if it starts with an at sign `@`, it is a tag line of the form
+///    `@tag = NAME`, which attaches a tag to the scenario;
+/// 5. if it contains at least one equals sign, it is a definition
+///    line -- unless the first equals sign directly follows a plus
+///    sign `+`, in which case it is an append line.
 ///
 /// Anything else is considered a syntax error. Use the [`kind()`]
 /// method to query which of these kinds an input line is classified
 /// as.
 ///
+/// Values that span multiple lines may be given as a heredoc, which
+/// starts with a line like `NAME <<MARKER`. Because heredocs consist
+/// of several lines, they are recognized by [`parse_heredoc_start()`]
+/// before input lines are parsed; the whole heredoc then becomes a
+/// single definition line.
+///
+/// Optionally, definition lines may end in an inline comment. See
+/// [`parse()`] for details.
+///
 /// # Example
 ///
 /// ```
 /// [This is a header line]
 /// definition = value
 /// other definition = more values
+/// appended variable += more
+/// !removed variable
+/// @tag = slow
 ///
 /// # Comment line, ignored completely
 /// [A new header line]
@@ -52,87 +70,177 @@ use std::str::FromStr;
 /// `usize`.
 ///
 /// [`kind()`]: #method.kind
+/// [`parse()`]: #method.parse
+/// [`parse_heredoc_start()`]: ./fn.parse_heredoc_start.html
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct InputLine {
-    /// The string content of the line.
-    ///
-    /// For header lines, this is the name of the header, with
-    /// surrounding whitespace and the brackets removed. For definition
-    /// lines, this is the full line, with surrounding whitespace
-    /// removed. For comments, this is `None`.
-    content: Option<Box<str>>,
+    /// What kind of line this is.
+    kind: InputLineKind,
     /// The position of the equal sign inside the line.
     ///
-    /// This value is zero for comments and header lines. Only for
-    /// definition lines, it is non-zero. It is the index of the equals
-    /// sign inside `content` that separates variable name and value.
+    /// Only for definition and append lines, this is non-zero. It is
+    /// the index of the equals sign inside `content` that separates
+    /// variable name and value. For append lines, the plus sign
+    /// directly precedes it. For all other lines, it is zero.
     ///
-    /// Note that header lines may very well contain equals signs.
-    /// This field will be zero for them regardless.
-    eq_pos: usize,
+    /// This is a `u32` so that it fits next to `kind` in a single
+    /// `usize`; no variable name is that long.
+    eq_pos: u32,
+    /// The string content of the line.
+    ///
+    /// For header lines, this is the name of the header, with
+    /// surrounding whitespace, the brackets and escapes removed. For
+    /// removal lines, this is the variable name. For tag lines, this
+    /// is the tag. For definition and append lines, this is the full
+    /// line, with surrounding whitespace and whitespace after the
+    /// equals sign removed. For comments, this is empty.
+    content: Box<str>,
 }
 
 impl FromStr for InputLine {
     type Err = SyntaxError;
 
     /// Parses a line and decide how to interpret it.
+    ///
+    /// This does not allow inline comments.
     fn from_str(line: &str) -> Result<Self, Self::Err> {
+        InputLine::parse(line, false)
+    }
+}
+
+impl InputLine {
+    /// Parses a line and decide how to interpret it.
+    ///
+    /// If `allow_inline_comments` is `true`, any hash sign `#` in the
+    /// value of a definition line that follows whitespace starts a
+    /// comment. The comment and the whitespace before it are removed.
+    /// To get a literal hash sign in this mode, escape it as `\#`.
+    /// Header lines, removal lines and variable names are never
+    /// affected.
+    pub fn parse(line: &str, allow_inline_comments: bool) -> Result<Self, SyntaxError> {
         let line = line.trim();
         if is_comment(line) {
-            let line = InputLine {
-                content: None,
-                eq_pos: 0,
-            };
-            Ok(line)
+            Ok(InputLine::new_comment())
         } else if let Some(name) = try_parse_header(line) {
-            let line = InputLine {
-                content: Some(Box::from(name?)),
-                eq_pos: 0,
-            };
-            Ok(line)
+            Ok(InputLine::new_header(&name?))
+        } else if let Some(name) = try_parse_removal(line) {
+            Ok(InputLine::with_kind(InputLineKind::Removal, name?))
+        } else if let Some(tag) = try_parse_tag(line) {
+            Ok(InputLine::with_kind(InputLineKind::Tag, tag?))
         } else if let Some(equals_sign_pos) = try_parse_definition(line) {
-            let line = InputLine {
-                content: Some(Box::from(line)),
-                eq_pos: equals_sign_pos?,
+            let eq_pos = equals_sign_pos?;
+            let line = if allow_inline_comments {
+                Cow::Owned(strip_inline_comment(line, eq_pos))
+            } else {
+                Cow::Borrowed(line)
             };
-            Ok(line)
+            let (name, value) = (&line[..eq_pos], &line[eq_pos + 1..]);
+            Ok(InputLine::new_definition(name, value.trim_start()))
         } else {
             Err(SyntaxError::NotAVarDef(line.to_owned()))
         }
     }
-}
 
-impl InputLine {
+    /// Creates a comment line.
+    pub fn new_comment() -> Self {
+        InputLine::with_kind(InputLineKind::Comment, "")
+    }
+
+    /// Creates a header line without parsing.
+    ///
+    /// Unlike with [`parse()`], `name` may contain any character.
+    ///
+    /// [`parse()`]: #method.parse
+    pub fn new_header(name: &str) -> Self {
+        InputLine::with_kind(InputLineKind::Header, name)
+    }
+
+    /// Creates a definition line without parsing.
+    ///
+    /// `value` may contain any character, including line breaks, and is
+    /// kept verbatim. If `name` ends with a plus sign `+`, this is an
+    /// append line.
+    ///
+    /// # Panics
+    /// This panics if `name` is empty or longer than `u32::MAX` bytes.
+    pub fn new_definition(name: &str, value: &str) -> Self {
+        assert!(!name.is_empty(), "empty variable name");
+        assert!(name.len() <= u32::MAX as usize, "variable name too long");
+        let kind = if name.ends_with('+') {
+            InputLineKind::Append
+        } else {
+            InputLineKind::Definition
+        };
+        InputLine {
+            kind,
+            eq_pos: name.len() as u32,
+            content: format!("{}={}", name, value).into_boxed_str(),
+        }
+    }
+
+    /// Creates a line of any kind except definitions and appends.
+    fn with_kind(kind: InputLineKind, content: &str) -> Self {
+        InputLine {
+            kind,
+            eq_pos: 0,
+            content: Box::from(content),
+        }
+    }
+
     /// Returns `true` if this is a comment line.
     pub fn is_comment(&self) -> bool {
-        self.content.is_none()
+        self.kind == InputLineKind::Comment
     }
 
     /// Returns `true` if this is a header line.
     pub fn is_header(&self) -> bool {
-        self.content.is_some() && self.eq_pos == 0
+        self.kind == InputLineKind::Header
+    }
+
+    /// Returns `true` if this is a removal line.
+    pub fn is_removal(&self) -> bool {
+        self.kind == InputLineKind::Removal
+    }
+
+    /// Returns `true` if this is a tag line.
+    pub fn is_tag(&self) -> bool {
+        self.kind == InputLineKind::Tag
     }
 
     /// Returns `true` if this is a definition line.
     pub fn is_definition(&self) -> bool {
-        self.content.is_some() && self.eq_pos > 0
+        self.kind == InputLineKind::Definition
+    }
+
+    /// Returns `true` if this is an append line.
+    pub fn is_append(&self) -> bool {
+        self.kind == InputLineKind::Append
     }
 
     /// Returns what kind of input line that this string got parsed as.
     pub fn kind(&self) -> InputLineKind {
-        if self.eq_pos > 0 {
-            InputLineKind::Definition
-        } else if self.content.is_some() {
-            InputLineKind::Header
-        } else {
-            InputLineKind::Comment
-        }
+        self.kind
     }
 
     /// If this is a header line, return its contents.
     pub fn as_header(&self) -> Option<&str> {
-        if self.eq_pos == 0 {
-            self.content.as_ref().map(Box::as_ref)
+        self.content_if(InputLineKind::Header)
+    }
+
+    /// If this is a removal line, return the name of the variable.
+    pub fn as_removal(&self) -> Option<&str> {
+        self.content_if(InputLineKind::Removal)
+    }
+
+    /// If this is a tag line, return the tag.
+    pub fn as_tag(&self) -> Option<&str> {
+        self.content_if(InputLineKind::Tag)
+    }
+
+    /// Returns `content` if this line is of the given `kind`.
+    fn content_if(&self, kind: InputLineKind) -> Option<&str> {
+        if self.kind == kind {
+            Some(&self.content)
         } else {
             None
         }
@@ -140,19 +248,34 @@ impl InputLine {
 
     /// If this is a definition line, return its split contents.
     pub fn as_definition(&self) -> Option<(&str, &str)> {
-        if self.eq_pos > 0 {
-            self.content.as_ref().map(|s| {
-                (
-                    s[..self.eq_pos].trim_right(),
-                    s[self.eq_pos + 1..].trim_left(),
-                )
-            })
+        if self.is_definition() {
+            Some(self.split_at_eq(self.eq_pos as usize))
         } else {
             None
         }
     }
-}
 
+    /// If this is an append line, return its split contents.
+    ///
+    /// The plus sign is not part of the returned variable name.
+    pub fn as_append(&self) -> Option<(&str, &str)> {
+        if self.is_append() {
+            // Should be safe because '+' is one byte long in UTF-8.
+            Some(self.split_at_eq(self.eq_pos as usize - 1))
+        } else {
+            None
+        }
+    }
+
+    /// Splits `content` into variable name and value.
+    ///
+    /// `name_end` is the index at which the variable name ends. The
+    /// value always begins after the equals sign.
+    fn split_at_eq(&self, name_end: usize) -> (&str, &str) {
+        let value_start = self.eq_pos as usize + 1;
+        (self.content[..name_end].trim_end(), &self.content[value_start..])
+    }
+}
 
 /// The kinds of [`InputLine`]s that exist.
 ///
@@ -163,6 +286,12 @@ pub enum InputLineKind {
     Header,
     /// A variable definition.
     Definition,
+    /// A value appended to a variable.
+    Append,
+    /// The removal of a variable.
+    Removal,
+    /// A tag attached to the scenario.
+    Tag,
     /// A comment or empty line.
     Comment,
 }
@@ -176,11 +305,16 @@ fn is_comment(s: &str) -> bool {
 
 /// Returns the inside of the brackets if `s` is a header line.
 ///
+/// Everything between the opening bracket at the start of the line
+/// and the closing bracket at its end is the name. In the returned
+/// name, the escapes `\[` and `\]` are replaced with plain brackets.
+/// Other backslashes are kept as-is.
+///
 /// # Errors
 /// If `s` is not a header line, this returns `None`.
 /// If `s` begins with an opening bracket, but doesn't end with a
 /// closing bracket, this returns `Some(Err(err))`.
-fn try_parse_header(s: &str) -> Option<Result<&str, SyntaxError>> {
+fn try_parse_header(s: &str) -> Option<Result<Cow<'_, str>, SyntaxError>> {
     if !s.starts_with('[') {
         return None;
     }
@@ -194,26 +328,125 @@ fn try_parse_header(s: &str) -> Option<Result<&str, SyntaxError>> {
     }
     // Should be safe because '[' and ']' are one byte long
     // in UTF-8.
-    let inner = s[1..s.len() - 1].trim();
-    Some(Ok(inner))
+    let name = s[1..s.len() - 1].trim();
+    let name = if name.contains("\\[") || name.contains("\\]") {
+        Cow::Owned(name.replace("\\[", "[").replace("\\]", "]"))
+    } else {
+        Cow::Borrowed(name)
+    };
+    Some(Ok(name))
+}
+
+
+/// Returns the variable name if `s` is a removal line.
+///
+/// # Errors
+/// If `s` is not a removal line, this returns `None`.
+/// If `s` consists of nothing but an exclamation mark, this returns
+/// `Some(Err(err))`.
+fn try_parse_removal(s: &str) -> Option<Result<&str, SyntaxError>> {
+    if !s.starts_with('!') {
+        return None;
+    }
+    // Should be safe because '!' is one byte long in UTF-8.
+    let name = s[1..].trim();
+    if name.is_empty() {
+        Some(Err(SyntaxError::MissingRemovalName(s.to_owned())))
+    } else {
+        Some(Ok(name))
+    }
+}
+
+
+/// Returns the tag if `s` is a tag line.
+///
+/// # Errors
+/// If `s` is not a tag line, this returns `None`.
+/// If `s` begins with an at sign, but is not of the form
+/// `@tag = NAME`, this returns `Some(Err(err))`.
+fn try_parse_tag(s: &str) -> Option<Result<&str, SyntaxError>> {
+    if !s.starts_with('@') {
+        return None;
+    }
+    let tag = s
+        .find('=')
+        .filter(|&pos| s[..pos].trim_end() == "@tag")
+        .map(|pos| s[pos + 1..].trim_start());
+    Some(tag.ok_or_else(|| SyntaxError::UnknownDirective(s.to_owned())))
 }
 
 
 /// Returns the position of the equals sign if `s` is a definition.
 ///
+/// This also applies to append lines.
+///
 /// # Errors
 /// This function returns `Some(Err(SyntaxError))` if the line contains
-/// an equals sign, but it is at index `0`, i.e. there is no variable
-/// name in front of it.
+/// an equals sign, but there is no variable name in front of it. This
+/// is the case if the equals sign is at index `0` or, for append
+/// lines, at index `1`.
 fn try_parse_definition(s: &str) -> Option<Result<usize, SyntaxError>> {
     match s.find('=') {
-        Some(pos) if pos > 0 => Some(Ok(pos)),
+        Some(pos) if pos > 0 && !s.starts_with("+=") => Some(Ok(pos)),
         Some(_) => Some(Err(SyntaxError::MissingVariableName(s.to_owned()))),
         None => None,
     }
 }
 
 
+/// Returns the variable name and the marker if `s` starts a heredoc.
+///
+/// A heredoc starts with a line of the form `NAME <<MARKER`. Both
+/// the name and the marker must not be empty, and the marker must not
+/// contain whitespace. Lines that contain an equals sign or that are
+/// comments, headers, or removals never start a heredoc.
+pub fn parse_heredoc_start(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim();
+    if is_comment(s) || s.starts_with('[') || s.starts_with('!') || s.contains('=') {
+        return None;
+    }
+    let pos = s.find("<<")?;
+    let name = s[..pos].trim_end();
+    // Should be safe because '<' is one byte long in UTF-8.
+    let marker = s[pos + 2..].trim_start();
+    if name.is_empty() || marker.is_empty() || marker.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((name, marker))
+}
+
+
+/// Removes an inline comment from a definition line.
+///
+/// `eq_pos` is the position of the equals sign in `s`. Only the part
+/// after it is searched for comments. A comment starts at a hash sign
+/// that follows whitespace; an escaped hash sign `\#` is replaced by a
+/// plain `#`.
+fn strip_inline_comment(s: &str, eq_pos: usize) -> String {
+    let (name, value) = s.split_at(eq_pos + 1);
+    let mut result = String::with_capacity(s.len());
+    result.push_str(name);
+    let mut prev = '=';
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'#') => {
+                result.push('#');
+                // Skip the escaped hash sign, it cannot start a comment.
+                prev = chars.next().unwrap();
+                continue;
+            },
+            '#' if prev.is_whitespace() => break,
+            _ => result.push(c),
+        }
+        prev = c;
+    }
+    let len = result.trim_end().len();
+    result.truncate(len);
+    result
+}
+
+
 /// Error caused by a line not adhering to the syntax described in
 /// the documentation for [`InputLine`].
 ///
@@ -229,8 +462,12 @@ pub enum SyntaxError {
         _0
     )]
     MissingVariableName(String),
+    #[fail(display = "no variable name after \"!\" of a removal: \"{}\"", _0)]
+    MissingRemovalName(String),
     #[fail(display = "no equals sign \"=\" in variable definition: \"{}\"", _0)]
     NotAVarDef(String),
+    #[fail(display = "unknown directive, expected \"@tag = NAME\": \"{}\"", _0)]
+    UnknownDirective(String),
 }
 
 
@@ -265,10 +502,19 @@ mod tests {
         assert_eq_header("[Header = with = equals]", "Header = with = equals");
         assert_eq_header("[#Pound sign header]", "#Pound sign header");
         assert_eq_header("[]", "");
+        assert_eq_header("[foo\\[bar\\]]", "foo[bar]");
+        assert_eq_header("[foo[bar\\]]", "foo[bar]");
+        assert_eq_header("[foo[bar]]", "foo[bar]");
+        assert_eq_header("[a\\]", "a\\");
+        assert_eq_header("[C:\\Windows]", "C:\\Windows");
         assert_eq!(
             err_string("[Bad header"),
             "no closing bracket \"]\" in header line: \"[Bad header\""
         );
+        assert_eq!(
+            err_string("[foo] bar"),
+            "closing bracket \"]\" does not end the line: \"[foo] bar\""
+        );
     }
 
 
@@ -307,6 +553,99 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_append() {
+        fn assert_eq_append(line: &str, expected_var: &str, expected_value: &str) {
+            let input_line = line.parse::<InputLine>().unwrap();
+            assert_eq!(input_line.as_append(), Some((expected_var, expected_value)));
+            assert!(input_line.as_definition().is_none());
+            assert_eq!(input_line.kind(), InputLineKind::Append);
+        }
+        assert_eq_append("var+=value", "var", "value");
+        assert_eq_append("  var += /opt/bin ", "var", "/opt/bin");
+        assert_eq_append("var += a += b", "var", "a += b");
+        assert_eq_append("var +=", "var", "");
+        // The plus sign must directly precede the first equals sign.
+        let input_line = "var + = value".parse::<InputLine>().unwrap();
+        assert_eq!(input_line.as_definition(), Some(("var +", "value")));
+        let input_line = "var = +=value".parse::<InputLine>().unwrap();
+        assert_eq!(input_line.as_definition(), Some(("var", "+=value")));
+        assert_eq!(
+            err_string("+= value"),
+            "no variable name before \"=\" of a variable definition: \"+= value\""
+        );
+        // Inline comments work as for definitions.
+        let input_line = InputLine::parse("var += a # comment", true).unwrap();
+        assert_eq!(input_line.as_append(), Some(("var", "a")));
+    }
+
+
+    #[test]
+    fn test_inline_comment() {
+        fn assert_eq_vardef(line: &str, expected_var: &str, expected_def: &str) {
+            let input_line = InputLine::parse(line, true).unwrap();
+            assert_eq!(input_line.as_definition(), Some((expected_var, expected_def)));
+        }
+        assert_eq_vardef("var = def # comment", "var", "def");
+        assert_eq_vardef("var = def\t#comment", "var", "def");
+        assert_eq_vardef("var = # comment", "var", "");
+        assert_eq_vardef("var = def#no comment", "var", "def#no comment");
+        assert_eq_vardef("var = def \\# no comment", "var", "def # no comment");
+        assert_eq_vardef("var = \\#def", "var", "#def");
+        assert_eq_vardef("var = a\\b", "var", "a\\b");
+        assert_eq_vardef("v #ar = def", "v #ar", "def");
+        // Without the option, nothing changes.
+        let input_line = InputLine::parse("var = def \\# no comment", false).unwrap();
+        assert_eq!(input_line.as_definition(), Some(("var", "def \\# no comment")));
+        // Headers are not affected.
+        let input_line = InputLine::parse("[header # no comment]", true).unwrap();
+        assert_eq!(input_line.as_header(), Some("header # no comment"));
+    }
+
+
+    #[test]
+    fn test_removal() {
+        fn assert_eq_removal(line: &str, expected_var: &str) {
+            let input_line = line.parse::<InputLine>().unwrap();
+            assert_eq!(input_line.as_removal(), Some(expected_var));
+            assert!(input_line.as_header().is_none());
+            assert_eq!(input_line.kind(), InputLineKind::Removal);
+        }
+        assert_eq_removal("!var", "var");
+        assert_eq_removal("  ! var\t", "var");
+        assert_eq_removal("!var = def", "var = def");
+        assert_eq_removal("![var]", "[var]");
+        assert!("[!var]".parse::<InputLine>().unwrap().as_removal().is_none());
+        assert_eq!(
+            err_string("!"),
+            "no variable name after \"!\" of a removal: \"!\""
+        );
+    }
+
+
+    #[test]
+    fn test_tag() {
+        fn assert_eq_tag(line: &str, expected_tag: &str) {
+            let input_line = line.parse::<InputLine>().unwrap();
+            assert_eq!(input_line.as_tag(), Some(expected_tag));
+            assert!(input_line.as_definition().is_none());
+            assert_eq!(input_line.kind(), InputLineKind::Tag);
+        }
+        assert_eq_tag("@tag=slow", "slow");
+        assert_eq_tag("  @tag =\tslow  ", "slow");
+        assert_eq_tag("@tag = a = b", "a = b");
+        assert_eq_tag("@tag =", "");
+        assert_eq!(
+            err_string("@tags = slow"),
+            "unknown directive, expected \"@tag = NAME\": \"@tags = slow\""
+        );
+        assert_eq!(
+            err_string("@tag"),
+            "unknown directive, expected \"@tag = NAME\": \"@tag\""
+        );
+    }
+
+
     #[test]
     fn test_comment() {
         fn assert_eq_comment(line: &str) {
@@ -324,4 +663,33 @@ mod tests {
         assert_eq_comment("");
         assert_eq_comment("\t\t\t");
     }
+
+
+    #[test]
+    fn test_constructed_lines() {
+        let header = InputLine::new_header("!not a removal");
+        assert_eq!(header.kind(), InputLineKind::Header);
+        assert_eq!(header.as_header(), Some("!not a removal"));
+        assert_eq!(header.as_removal(), None);
+        let header = InputLine::new_header("");
+        assert_eq!(header.as_header(), Some(""));
+        let append = InputLine::new_definition("a+", "[x]\n@y");
+        assert_eq!(append.kind(), InputLineKind::Append);
+        assert_eq!(append.as_append(), Some(("a", "[x]\n@y")));
+        assert!(InputLine::new_comment().is_comment());
+    }
+
+
+    #[test]
+    fn test_heredoc_start() {
+        assert_eq!(parse_heredoc_start("var <<END\n"), Some(("var", "END")));
+        assert_eq!(parse_heredoc_start("  my var<<  EOF "), Some(("my var", "EOF")));
+        assert_eq!(parse_heredoc_start("var << END OF TEXT"), None);
+        assert_eq!(parse_heredoc_start("<<END"), None);
+        assert_eq!(parse_heredoc_start("var <<"), None);
+        assert_eq!(parse_heredoc_start("var = <<END"), None);
+        assert_eq!(parse_heredoc_start("# var <<END"), None);
+        assert_eq!(parse_heredoc_start("[var <<END]"), None);
+        assert_eq!(parse_heredoc_start("!var <<END"), None);
+    }
 }
@@ -30,9 +30,148 @@ mod scenario;
 mod scenario_file;
 
 pub use self::{
-    filter::{Mode as FilterMode, NameFilter},
-    scenario::{MergeOptions, Scenario},
-    scenario_file::{ScenarioFile, ScenariosIter},
+    filter::{Mode as FilterMode, NameFilter, TagFilter},
+    scenario::{MergeOptions, MergeOptionsBuilder, Precedence, Scenario, NAME_VARIABLE},
+    scenario_file::{
+        check_for_shared_names, Format as ScenarioFileFormat, Options as ScenarioFileOptions,
+        ScenarioFile, ScenariosIter,
+    },
 };
 
 pub use self::scenario::{MergeError, ScenarioError};
+pub use self::scenario_file::{MisplacedDefaults, UnexpectedVarDef};
+
+
+use std::ffi::OsStr;
+
+use failure::{Error, ResultExt};
+
+use cartesian;
+
+
+/// Builds the scenarios of each file, one `Vec` per file.
+///
+/// # Errors
+/// This fails if any scenario in any of the files cannot be built.
+pub fn scenarios_per_file<'a>(files: &'a [ScenarioFile]) -> Result<Vec<Vec<Scenario<'a>>>, Error> {
+    let scenarios = files
+        .iter()
+        .map(|file| file.iter().collect::<Result<_, _>>())
+        .collect::<Result<_, _>>()
+        .context("could not build scenarios")?;
+    Ok(scenarios)
+}
+
+
+/// Merges every combination of scenarios from `files`.
+///
+/// Each combination takes one scenario from each file. The
+/// combinations are returned in the order of the cartesian product,
+/// i.e. the last file varies fastest. If `files` is empty, there are
+/// no combinations.
+///
+/// # Errors
+/// This fails if any scenario cannot be built or if any combination
+/// cannot be merged, e.g. because of a conflict in strict mode.
+pub fn combinations<'a>(
+    files: &'a [ScenarioFile],
+    opts: MergeOptions,
+) -> Result<Vec<Scenario<'a>>, Error> {
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+    let all_scenarios = scenarios_per_file(files)?;
+    let combinations = cartesian::product(&all_scenarios)
+        .map(|set| Scenario::merge_all(set, opts))
+        .collect::<Result<_, _>>()?;
+    Ok(combinations)
+}
+
+
+/// Reads the files at `paths` and hands all their combinations to `f`.
+///
+/// This is the whole pipeline of the command-line tool in one call:
+/// each path is read as with [`ScenarioFile::from_cl_arg()`], then
+/// [`combinations()`] are built and merged. Because the scenarios
+/// borrow from the files, they cannot outlive this function and are
+/// passed to `f` instead. Its return value is passed through.
+///
+/// # Errors
+/// This fails if any file cannot be read or if [`combinations()`]
+/// fails.
+///
+/// [`ScenarioFile::from_cl_arg()`]: ./struct.ScenarioFile.html#method.from_cl_arg
+/// [`combinations()`]: ./fn.combinations.html
+pub fn combinations_from_paths<P, F, T>(
+    paths: &[P],
+    file_opts: ScenarioFileOptions,
+    merge_opts: MergeOptions,
+    f: F,
+) -> Result<T, Error>
+where
+    P: AsRef<OsStr>,
+    F: FnOnce(Vec<Scenario>) -> T,
+{
+    let files = paths
+        .iter()
+        .map(|path| ScenarioFile::from_cl_arg(path.as_ref(), file_opts))
+        .collect::<Result<Vec<_>, _>>()
+        .context("could not read file")?;
+    let combinations = combinations(&files, merge_opts)?;
+    Ok(f(combinations))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combinations() {
+        let opts = ScenarioFileOptions::default();
+        let files = vec![
+            ScenarioFile::from_str("[a]\nx = 1\n[b]\nx = 2\n", "first.ini", opts).unwrap(),
+            ScenarioFile::from_str("[c]\ny = 3\n", "second.ini", opts).unwrap(),
+        ];
+        let combinations = combinations(&files, MergeOptions::default()).unwrap();
+        let names = combinations.iter().map(Scenario::name).collect::<Vec<_>>();
+        assert_eq!(names, ["a, c", "b, c"]);
+        assert_eq!(combinations[1].variables_sorted(), [("x", "2"), ("y", "3")]);
+        assert!(super::combinations(&[], MergeOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_combinations_conflict() {
+        let opts = ScenarioFileOptions::default();
+        let files = vec![
+            ScenarioFile::from_str("[a]\nx = 1\n", "first.ini", opts).unwrap(),
+            ScenarioFile::from_str("[b]\nx = 2\n", "second.ini", opts).unwrap(),
+        ];
+        assert!(combinations(&files, MergeOptions::default()).is_err());
+        let lax = MergeOptions::builder().strict(false).build();
+        let merged = combinations(&files, lax).unwrap();
+        assert_eq!(merged[0].get_variable("x"), Some("2"));
+    }
+
+    #[test]
+    fn test_combinations_from_paths() {
+        let paths = [
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/good_a.ini"),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/good_b.ini"),
+        ];
+        let file_opts = ScenarioFileOptions::default();
+        let names = combinations_from_paths(&paths, file_opts, MergeOptions::default(), |c| {
+            c.iter().map(|s| s.name().to_owned()).collect::<Vec<_>>()
+        })
+        .unwrap();
+        assert_eq!(names, ["A1, B1", "A1, B2", "A2, B1", "A2, B2"]);
+        let err = combinations_from_paths(
+            &["does not exist.ini"],
+            ScenarioFileOptions::default(),
+            MergeOptions::default(),
+            |combinations| combinations.len(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "could not read file");
+    }
+}
@@ -15,11 +15,19 @@
 
 use std::{
     borrow::{Borrow, Cow},
-    collections::hash_map::{self, HashMap},
+    collections::{
+        hash_map::{self, HashMap},
+        hash_set::{self, HashSet},
+    },
     fmt::{self, Display},
 };
 
 
+/// The name of the variable that conventionally holds the name of a
+/// scenario.
+pub const NAME_VARIABLE: &str = "SCENARIOS_NAME";
+
+
 /// Named set of environment variable definitions.
 ///
 /// A scenario has a name and a set of environment variable
@@ -28,14 +36,45 @@ use std::{
 /// follow the rules for regular C identifiers. A scenario name must be
 /// non-empty and not contain any null byte.
 ///
+/// Beside variable definitions, a scenario may also contain
+/// *removals*. These are the names of variables that should be removed
+/// from an inherited environment. A scenario cannot both define and
+/// remove the same variable.
+///
+/// Finally, a scenario may contain *appends*. These are values that
+/// should be appended to a variable instead of replacing it. The same
+/// variable may be appended to several times, but a scenario cannot
+/// both append to a variable and define or remove it.
+///
+/// Besides that, a scenario may carry *tags*. These are names that
+/// can be used to filter scenarios, but that are never exported as
+/// environment variables. A tag must be non-empty and must not
+/// contain whitespace.
+///
 /// Note: The rules for regular C identifiers are as follows: The name
 /// must contain only the 26 Latin characters (upper- or lowercase),
 /// the underscore, and the ten digits of the ASCII character set. The
 /// first character must not be a digit.
+///
+/// When merging scenarios, the name of the scenario that each variable
+/// came from can optionally be tracked. See [`origin_of()`].
+///
+/// [`origin_of()`]: #method.origin_of
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Scenario<'a> {
     name: Cow<'a, str>,
     variables: HashMap<&'a str, &'a str>,
+    removals: HashSet<&'a str>,
+    /// Maps variable names to the values appended to them, in order.
+    appends: HashMap<&'a str, Vec<&'a str>>,
+    tags: HashSet<&'a str>,
+    /// Maps variable names to the name of their original scenario.
+    ///
+    /// This is `None` unless this scenario has been merged with
+    /// origin tracking enabled.
+    origins: Option<HashMap<&'a str, Cow<'a, str>>>,
+    /// Whether the name is made of parts escaped by `escape_name()`.
+    has_escaped_name: bool,
 }
 
 impl<'a> Scenario<'a> {
@@ -52,7 +91,18 @@ impl<'a> Scenario<'a> {
             Err(ScenarioError::InvalidName(name.into_owned()))
         } else {
             let variables = HashMap::new();
-            Ok(Scenario { name, variables })
+            let removals = HashSet::new();
+            let appends = HashMap::new();
+            let tags = HashSet::new();
+            Ok(Scenario {
+                name,
+                variables,
+                removals,
+                appends,
+                tags,
+                origins: None,
+                has_escaped_name: false,
+            })
         }
     }
 
@@ -68,8 +118,9 @@ impl<'a> Scenario<'a> {
     /// [`DuplicateVariable`]:
     /// ./enum.ScenarioError.html#variant.DuplicateVariable
     pub fn add_variable(&mut self, name: &'a str, value: &'a str) -> Result<(), ScenarioError> {
-        if self.has_variable(name) {
-            Err(ScenarioError::DuplicateVariable(name.to_owned()))
+        if let Some(existing) = self.describe_variable(name) {
+            let attempted = format!("{} = {}", name, value);
+            Err(ScenarioError::DuplicateVariable(name.to_owned(), existing, attempted))
         } else if !is_c_identifier(name) {
             Err(ScenarioError::InvalidVariable(name.to_owned()))
         } else {
@@ -78,11 +129,183 @@ impl<'a> Scenario<'a> {
         }
     }
 
+    /// Marks a variable to be removed from the environment.
+    ///
+    /// # Errors
+    /// This call fails with [`InvalidVariable`] if `name` is not a
+    /// valid variable name. It fails with [`DuplicateVariable`] if a
+    /// variable of this name already has been added to or removed from
+    /// the scenario.
+    ///
+    /// [`InvalidVariable`]:
+    /// ./enum.ScenarioError.html#variant.InvalidVariable
+    /// [`DuplicateVariable`]:
+    /// ./enum.ScenarioError.html#variant.DuplicateVariable
+    pub fn add_removal(&mut self, name: &'a str) -> Result<(), ScenarioError> {
+        if let Some(existing) = self.describe_variable(name) {
+            let attempted = format!("!{}", name);
+            Err(ScenarioError::DuplicateVariable(name.to_owned(), existing, attempted))
+        } else if !is_c_identifier(name) {
+            Err(ScenarioError::InvalidVariable(name.to_owned()))
+        } else {
+            self.removals.insert(name);
+            Ok(())
+        }
+    }
+
+    /// Appends a value to a variable.
+    ///
+    /// Unlike definitions, appends may be repeated; the values are
+    /// appended in the order in which they have been added.
+    ///
+    /// # Errors
+    /// This call fails with [`InvalidVariable`] if `name` is not a
+    /// valid variable name. It fails with [`DuplicateVariable`] if a
+    /// variable of this name already has been defined in or removed
+    /// from the scenario.
+    ///
+    /// [`InvalidVariable`]:
+    /// ./enum.ScenarioError.html#variant.InvalidVariable
+    /// [`DuplicateVariable`]:
+    /// ./enum.ScenarioError.html#variant.DuplicateVariable
+    pub fn add_append(&mut self, name: &'a str, value: &'a str) -> Result<(), ScenarioError> {
+        if self.has_variable(name) || self.has_removal(name) {
+            let existing = self.describe_variable(name).unwrap_or_default();
+            let attempted = format!("{} += {}", name, value);
+            Err(ScenarioError::DuplicateVariable(name.to_owned(), existing, attempted))
+        } else if !is_c_identifier(name) {
+            Err(ScenarioError::InvalidVariable(name.to_owned()))
+        } else {
+            self.appends.entry(name).or_default().push(value);
+            Ok(())
+        }
+    }
+
+    /// Describes what this scenario does with the variable `name`.
+    ///
+    /// The result looks like the input line that caused it, e.g.
+    /// `name = value`, `!name` or `name += value`. For appends, only
+    /// the last appended value is shown. If the scenario doesn't touch
+    /// the variable, this returns `None`.
+    fn describe_variable(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.get_variable(name) {
+            Some(format!("{} = {}", name, value))
+        } else if self.has_removal(name) {
+            Some(format!("!{}", name))
+        } else {
+            self.get_appends(name)
+                .and_then(|values| values.last())
+                .map(|value| format!("{} += {}", name, value))
+        }
+    }
+
+    /// Attaches a tag to the scenario.
+    ///
+    /// Adding the same tag twice has no effect.
+    ///
+    /// # Errors
+    /// This call fails with [`InvalidTag`] if `tag` is empty or
+    /// contains whitespace.
+    ///
+    /// [`InvalidTag`]: ./enum.ScenarioError.html#variant.InvalidTag
+    pub fn add_tag(&mut self, tag: &'a str) -> Result<(), ScenarioError> {
+        if tag.is_empty() || tag.contains(char::is_whitespace) {
+            Err(ScenarioError::InvalidTag(tag.to_owned()))
+        } else {
+            self.tags.insert(tag);
+            Ok(())
+        }
+    }
+
+    /// Fills in everything from `defaults` this scenario doesn't cover.
+    ///
+    /// A default definition, removal or append is only added if this
+    /// scenario neither defines, removes nor appends to the same
+    /// variable. Thus, the scenario's own lines always take precedence
+    /// and never conflict with the defaults. Tags are simply added.
+    pub fn add_defaults(&mut self, defaults: &Scenario<'a>) {
+        for (&name, &value) in &defaults.variables {
+            if !self.mentions(name) {
+                self.variables.insert(name, value);
+            }
+        }
+        for &name in &defaults.removals {
+            if !self.mentions(name) {
+                self.removals.insert(name);
+            }
+        }
+        for (&name, values) in &defaults.appends {
+            if !self.mentions(name) {
+                self.appends.insert(name, values.clone());
+            }
+        }
+        self.tags.extend(&defaults.tags);
+    }
+
+    /// Returns `true` if the variable is defined, removed or appended to.
+    fn mentions(&self, name: &str) -> bool {
+        self.has_variable(name) || self.has_removal(name) || self.has_append(name)
+    }
+
+    /// Keeps only the variables for which `f` returns `true`.
+    ///
+    /// `f` is called with the name and value of each variable
+    /// definition. Removals and appends are not affected.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// // Strip bookkeeping variables before running a command.
+    /// scenario.retain_variables(|name, _| !name.starts_with('_'));
+    /// ```
+    pub fn retain_variables<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&str, &str) -> bool,
+    {
+        self.variables.retain(|name, value| f(name, value));
+        if let Some(ref mut origins) = self.origins {
+            let variables = &self.variables;
+            origins.retain(|name, _| variables.contains_key(name));
+        }
+    }
+
     /// Returns the name of the scenario.
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// Replaces the name of the scenario with `name`.
+    ///
+    /// # Errors
+    /// This call fails with [`InvalidName`] under the same conditions
+    /// as [`new()`]. In this case, the scenario keeps its old name.
+    ///
+    /// [`InvalidName`]: ./enum.ScenarioError.html#variant.InvalidName
+    /// [`new()`]: #method.new
+    pub fn set_name<S: Into<Cow<'a, str>>>(&mut self, name: S) -> Result<(), ScenarioError> {
+        let name = name.into();
+        if name.is_empty() || name.contains('\0') {
+            return Err(ScenarioError::InvalidName(name.into_owned()));
+        }
+        self.name = name;
+        self.has_escaped_name = false;
+        Ok(())
+    }
+
+    /// Splits the name of a merged scenario into its parts.
+    ///
+    /// The name is split at each of the given `delimiters`. If the
+    /// scenario has been merged with [`MergeOptions::escape_names`]
+    /// enabled, escaped delimiters are not split at and the escapes
+    /// are removed. Otherwise, names that contain a delimiter
+    /// themselves are split as well.
+    ///
+    /// [`MergeOptions::escape_names`]:
+    /// ./struct.MergeOptions.html#structfield.escape_names
+    pub fn name_parts(&self, delimiters: &[&str]) -> Vec<String> {
+        split_name(&self.name, delimiters, self.has_escaped_name)
+    }
+
     /// Returns `true` if the variable already exists in this scenario.
     pub fn has_variable(&self, name: &str) -> bool {
         self.variables.contains_key(name)
@@ -93,29 +316,155 @@ impl<'a> Scenario<'a> {
         self.variables.get(name).cloned()
     }
 
+    /// Returns the number of variables that this scenario defines.
+    ///
+    /// Removals and appends are not counted.
+    pub fn variables_len(&self) -> usize {
+        self.variables.len()
+    }
+
+    /// Returns `true` if this scenario defines no variables.
+    ///
+    /// A scenario may still remove or append to variables even if this
+    /// returns `true`.
+    pub fn has_no_variables(&self) -> bool {
+        self.variables.is_empty()
+    }
+
+    /// Returns `true` if the scenario removes the variable `name`.
+    pub fn has_removal(&self, name: &str) -> bool {
+        self.removals.contains(name)
+    }
+
+    /// Returns an iterator over the names of all removed variables.
+    pub fn removals(&self) -> hash_set::Iter<'_, &'a str> {
+        self.removals.iter()
+    }
+
+    /// Returns `true` if the scenario appends to the variable `name`.
+    pub fn has_append(&self, name: &str) -> bool {
+        self.appends.contains_key(name)
+    }
+
+    /// Returns the values appended to variable `name`, if any.
+    pub fn get_appends(&self, name: &str) -> Option<&[&'a str]> {
+        self.appends.get(name).map(Vec::as_slice)
+    }
+
+    /// Returns an iterator over all appends.
+    ///
+    /// Each item is a variable name and the values appended to it.
+    pub fn appends(&self) -> hash_map::Iter<'_, &'a str, Vec<&'a str>> {
+        self.appends.iter()
+    }
+
+    /// Returns `true` if the scenario carries the tag `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    /// Returns an iterator over all tags.
+    pub fn tags(&self) -> hash_set::Iter<'_, &'a str> {
+        self.tags.iter()
+    }
+
     /// Returns an iterator over all variable names.
-    pub fn variable_names(&self) -> hash_map::Keys<&'a str, &'a str> {
+    pub fn variable_names(&self) -> hash_map::Keys<'_, &'a str, &'a str> {
         self.variables.keys()
     }
 
     /// Returns an iterator over all variables.
-    pub fn variables(&self) -> hash_map::Iter<&'a str, &'a str> {
+    ///
+    /// The order of iteration is arbitrary and may differ between
+    /// runs. Use [`variables_sorted()`] if you need a stable order.
+    ///
+    /// [`variables_sorted()`]: #method.variables_sorted
+    pub fn variables(&self) -> hash_map::Iter<'_, &'a str, &'a str> {
         self.variables.iter()
     }
 
+    /// Returns all variables, sorted by name.
+    pub fn variables_sorted(&self) -> Vec<(&'a str, &'a str)> {
+        let mut variables = self
+            .variables
+            .iter()
+            .map(|(&name, &value)| (name, value))
+            .collect::<Vec<_>>();
+        variables.sort_unstable();
+        variables
+    }
+
+    /// Returns all variables as owned pairs, sorted by name.
+    ///
+    /// If `include_name` is `true`, an entry for [`NAME_VARIABLE`]
+    /// that contains the scenario name is added at the end. It
+    /// replaces any definition of this variable by the scenario.
+    ///
+    /// Removals and appends are not included, because their effect
+    /// depends on the environment that the variables are applied to.
+    ///
+    /// [`NAME_VARIABLE`]: ./constant.NAME_VARIABLE.html
+    pub fn to_env_vec(&self, include_name: bool) -> Vec<(String, String)> {
+        let mut env: Vec<(String, String)> = self
+            .variables_sorted()
+            .into_iter()
+            .filter(|&(name, _)| !include_name || name != NAME_VARIABLE)
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        if include_name {
+            env.push((NAME_VARIABLE.to_owned(), self.name.clone().into_owned()));
+        }
+        env
+    }
+
+    /// Returns the name of the scenario that defined `variable`.
+    ///
+    /// For scenarios that have been merged with
+    /// [`MergeOptions::track_origins`] enabled, this is the name of the
+    /// original scenario. For scenarios that have not been merged,
+    /// this is the name of the scenario itself. If origins have not
+    /// been tracked during a merge, this is the merged name.
+    ///
+    /// This returns `None` if the variable is not defined.
+    ///
+    /// [`MergeOptions::track_origins`]:
+    /// ./struct.MergeOptions.html#structfield.track_origins
+    pub fn origin_of(&self, variable: &str) -> Option<&str> {
+        if !self.has_variable(variable) {
+            return None;
+        }
+        let origin = self
+            .origins
+            .as_ref()
+            .and_then(|origins| origins.get(variable))
+            .unwrap_or(&self.name);
+        Some(origin)
+    }
+
     /// Consumes the scenario to return an iterator over all variables.
     pub fn into_variables(self) -> hash_map::IntoIter<&'a str, &'a str> {
         self.variables.into_iter()
     }
 
     /// Splits the scenario into the name and the variables.
+    ///
+    /// Removals and appends are dropped. Use [`removals()`] and
+    /// [`appends()`] beforehand if you need them.
+    ///
+    /// [`removals()`]: #method.removals
+    /// [`appends()`]: #method.appends
     pub fn into_parts(self) -> (Cow<'a, str>, hash_map::IntoIter<&'a str, &'a str>) {
         (self.name, self.variables.into_iter())
     }
 
     /// Merges several scenarios into one.
     ///
-    /// See [`merge()`] for more information.
+    /// See [`merge()`] for more information. The names of the
+    /// scenarios are joined with the delimiters in
+    /// [`opts.delimiters`], in order. The first delimiter is placed
+    /// between the first and the second name, the second delimiter
+    /// between the second and the third name, and so on. If there are
+    /// fewer delimiters than gaps, the last one is repeated.
     ///
     /// # Errors
     /// The merge can fail if strict mode was enabled and two scenarios
@@ -126,6 +475,8 @@ impl<'a> Scenario<'a> {
     /// iterator.
     ///
     /// [`merge()`]: #method.merge
+    /// [`opts.delimiters`]:
+    /// ./struct.MergeOptions.html#structfield.delimiters
     pub fn merge_all<I>(scenarios: I, opts: MergeOptions) -> Result<Self, MergeError>
     where
         I: IntoIterator,
@@ -133,59 +484,208 @@ impl<'a> Scenario<'a> {
         I::Item: Borrow<Self>,
     {
         let mut scenarios = scenarios.into_iter();
-        let backup_iter = scenarios.clone();
         let mut accumulator = scenarios
             .next()
             .expect("no scenarios to merge")
             .borrow()
             .clone();
-        // Go over each scenario `s` and merge it into `accumulator`. Abort on
+        accumulator.merge_all_into(scenarios, opts)?;
+        Ok(accumulator)
+    }
+
+    /// Merges several scenarios into this one.
+    ///
+    /// This is like [`merge_all()`] with `self` prepended to
+    /// `scenarios`, but it modifies `self` instead of cloning it. This
+    /// saves an allocation per variable if you already own the first
+    /// scenario. If `scenarios` is empty, nothing happens.
+    ///
+    /// # Errors
+    /// Same as for [`merge_all()`]. If an error occurs, `self` is left
+    /// with the scenarios merged up to the failing one.
+    ///
+    /// [`merge_all()`]: #method.merge_all
+    pub fn merge_all_into<I>(&mut self, scenarios: I, opts: MergeOptions) -> Result<(), MergeError>
+    where
+        I: IntoIterator,
+        I::IntoIter: Clone,
+        I::Item: Borrow<Self>,
+    {
+        let scenarios = scenarios.into_iter();
+        let backup_iter = scenarios.clone();
+        // Our own name is always a prefix of the merged name, unless it
+        // gets escaped.
+        let own_name = self.name.clone();
+        // Go over each scenario `s` and merge it into `self`. Abort on
         // the first error.
-        let result: Result<(), MergeError> = scenarios
-            .map(|s| accumulator.merge(s.borrow(), opts))
-            .collect();
-        match result {
-            Ok(()) => Ok(accumulator),
-            Err(mut err) => {
-                // If a `StrictMergeFailed` error occurs, the `left` scenario is a
-                // merged intermediary. This is useless! Change it to the correct
-                // scenario name by searching through `scenarios` once more.
-                err.left = name_of_first_scenario_with_variable(backup_iter, &err.varname).unwrap();
-                Err(err)
-            },
-        }
+        let mut num_merged = 0;
+        let result = scenarios.enumerate().try_for_each(|(i, s)| {
+            num_merged = i;
+            self.merge_with_delimiter(s.borrow(), opts.delimiter(i), opts)
+        });
+        result.map_err(|mut err| {
+            // If a `StrictMergeFailed` error occurs, the `left` scenario is a
+            // merged intermediary. This is useless! Change it to the correct
+            // scenario name by searching through the merged scenarios once
+            // more. If none of them has the variable, it must be our own.
+            let merged = backup_iter.take(num_merged);
+            err.left = name_of_first_scenario_with_variable(merged, &err.varname)
+                .unwrap_or_else(|| own_name.into_owned());
+            err
+        })
     }
 
     /// Merges another scenario into this one.
     ///
     /// This combines the names and variables of both scenarios. The
-    /// names get combined with [`opts.delimiter`] between them.
+    /// names get combined with the first of [`opts.delimiters`]
+    /// between them.
     /// Variables are combined by adding definitions from `other` to
     /// `self`. If both scenarios define the same variable and
     /// [`opts.is_strict`] is `false`, the value of `other`'s
-    /// variable takes precedence.
+    /// variable takes precedence. Removals are combined the same way;
+    /// a removal in `other` overrides a definition in `self` and vice
+    /// versa. If [`opts.precedence`] is [`Precedence::First`], it is
+    /// the other way around and `self` keeps its definitions and
+    /// removals.
+    ///
+    /// Appends never conflict with anything. The appends of `other`
+    /// are added after those of `self`, and they apply to whatever
+    /// value `self` gives to the variable. A definition or removal in
+    /// `other`, however, discards the appends of `self`.
+    ///
+    /// The merged scenario carries the tags of both scenarios.
+    ///
+    /// If [`opts.escape_names`] is `true`, a backslash is put before
+    /// each backslash and delimiter in the names before they are
+    /// combined. This way, [`name_parts()`] can split the name up
+    /// again unambiguously.
     ///
     /// # Errors
-    /// If [`opts.is_strict`] is `true` and both scenarios define the
-    /// same variable, [`MergeError`] is returned.
+    /// If [`opts.is_strict`] is `true` and both scenarios define or
+    /// remove the same variable, [`MergeError`] is returned. The same
+    /// happens if `other` defines or removes a variable that `self`
+    /// appends to.
     ///
-    /// [`opts.delimiter`]:
-    /// ./struct.MergeOptions.html#structfield.delimiter
+    /// [`opts.delimiters`]:
+    /// ./struct.MergeOptions.html#structfield.delimiters
     /// [`opts.is_strict`]:
     /// ./struct.MergeOptions.html#structfield.is_strict
+    /// [`opts.precedence`]:
+    /// ./struct.MergeOptions.html#structfield.precedence
+    /// [`opts.escape_names`]:
+    /// ./struct.MergeOptions.html#structfield.escape_names
+    /// [`Precedence::First`]: ./enum.Precedence.html#variant.First
+    /// [`name_parts()`]: #method.name_parts
     /// [`MergeError`]: ./struct.MergeError.html
     pub fn merge(&mut self, other: &Scenario<'a>, opts: MergeOptions) -> Result<(), MergeError> {
+        self.merge_with_delimiter(other, opts.delimiter(0), opts)
+    }
+
+    /// Returns this scenario merged on top of `base`.
+    ///
+    /// The result contains the variables of both scenarios, but keeps
+    /// the name of `self`. Apart from that, this is like merging
+    /// `self` into a copy of `base`: in lax mode, the variables of
+    /// `self` take precedence, regardless of `opts.precedence`.
+    ///
+    /// # Errors
+    /// Same as for [`merge()`].
+    ///
+    /// [`merge()`]: #method.merge
+    pub fn with_base(&self, base: &Scenario<'a>, opts: MergeOptions) -> Result<Self, MergeError> {
+        let opts = MergeOptions {
+            precedence: Precedence::Last,
+            escape_names: false,
+            ..opts
+        };
+        let mut result = base.clone();
+        result.merge_with_delimiter(self, "", opts)?;
+        result.name = self.name.clone();
+        result.has_escaped_name = self.has_escaped_name;
+        Ok(result)
+    }
+
+    /// Implementation of [`merge()`] with an explicit delimiter.
+    ///
+    /// [`merge()`]: #method.merge
+    fn merge_with_delimiter(
+        &mut self,
+        other: &Scenario<'a>,
+        delimiter: &str,
+        opts: MergeOptions,
+    ) -> Result<(), MergeError> {
+        let is_strict = opts.is_strict;
+        // If the first definition wins, find out which variables we keep
+        // before the merge overwrites them. (In strict mode, they would
+        // conflict anyway.)
+        let kept: Vec<&str> = if !is_strict && opts.precedence == Precedence::First {
+            other
+                .variables
+                .keys()
+                .chain(other.removals.iter())
+                .filter(|&&var| self.has_variable(var) || self.has_removal(var))
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
         // Turn (&&str, &&str) iterator into (&str, &str) iterator.
-        let other_vars = other.variables().map(|(&k, &v)| (k, v));
+        let other_vars = other
+            .variables()
+            .map(|(&k, &v)| (k, v))
+            .filter(|(k, _)| !kept.contains(k));
+        let other_removals = other.removals().cloned().filter(|k| !kept.contains(k));
         // Merge variable definitions first, then the scenario names. If we
         // merged names before the variables, the error message would contain
         // the already-merged name.
-        self.merge_vars(other_vars, opts.is_strict)
+        self.merge_vars(other_vars, is_strict)
+            .and_then(|()| self.merge_removals(other_removals, is_strict))
             .map_err(|var| MergeError::new(var, self.name(), other.name()))?;
-        self.merge_name(opts.delimiter, &other.name);
+        self.merge_appends(other);
+        self.tags.extend(other.tags.iter().cloned());
+        if opts.track_origins {
+            self.merge_origins(other, &kept);
+        }
+        if opts.escape_names {
+            self.merge_escaped_name(delimiter, other, opts.delimiters);
+        } else {
+            self.merge_name(delimiter, &other.name);
+        }
         Ok(())
     }
 
+    /// Records where the variables of `other` came from.
+    ///
+    /// This must be called after `other`'s variables have been added,
+    /// but before the names have been merged. The variables in `kept`
+    /// were not taken from `other` and keep their origin.
+    fn merge_origins(&mut self, other: &Scenario<'a>, kept: &[&str]) {
+        // If we haven't tracked origins so far, all variables that
+        // aren't `other`'s come from this scenario.
+        if self.origins.is_none() {
+            let name = &self.name;
+            let origins = self
+                .variables
+                .keys()
+                .map(|&var| (var, name.clone()))
+                .collect();
+            self.origins = Some(origins);
+        }
+        let origins = self.origins.as_mut().unwrap();
+        for &var in other.variables.keys().filter(|var| !kept.contains(var)) {
+            // Variables added after `other`'s last merge have no
+            // recorded origin; they come from `other` itself.
+            let origin = other
+                .origins
+                .as_ref()
+                .and_then(|other_origins| other_origins.get(var))
+                .unwrap_or(&other.name)
+                .clone();
+            origins.insert(var, origin);
+        }
+    }
+
     /// Appends `delimiter` and `other_name` to `self.name`.
     fn merge_name(&mut self, delimiter: &str, other_name: &str) {
         let name = self.name.to_mut();
@@ -194,6 +694,22 @@ impl<'a> Scenario<'a> {
         name.push_str(other_name);
     }
 
+    /// Like `merge_name()`, but escapes both names first.
+    ///
+    /// Names that already consist of escaped parts are not escaped
+    /// again.
+    fn merge_escaped_name(&mut self, delimiter: &str, other: &Scenario<'a>, delimiters: &[&str]) {
+        if !self.has_escaped_name {
+            self.name = Cow::Owned(escape_name(&self.name, delimiters));
+            self.has_escaped_name = true;
+        }
+        if other.has_escaped_name {
+            self.merge_name(delimiter, &other.name);
+        } else {
+            self.merge_name(delimiter, &escape_name(&other.name, delimiters));
+        }
+    }
+
     /// Adds all variable definitions in `to_add` to `self.variables`.
     ///
     /// If `strict` is `true`, this refuses to overwrite existing
@@ -203,18 +719,50 @@ impl<'a> Scenario<'a> {
     where
         I: Iterator<Item = (&'a str, &'a str)>,
     {
-        if strict {
-            for (key, value) in to_add {
-                if self.variables.contains_key(key) {
-                    return Err(key.to_owned());
-                }
-                self.variables.insert(key, value);
+        for (key, value) in to_add {
+            if strict && (self.has_variable(key) || self.has_removal(key) || self.has_append(key)) {
+                return Err(key.to_owned());
             }
-        } else {
-            self.variables.extend(to_add);
+            self.removals.remove(key);
+            self.appends.remove(key);
+            self.variables.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Adds all removals in `to_add` to `self.removals`.
+    ///
+    /// This works like [`merge_vars()`]. In lax mode, a removal
+    /// overrides any previous definition of the same variable.
+    ///
+    /// [`merge_vars()`]: #method.merge_vars
+    fn merge_removals<I>(&mut self, to_add: I, strict: bool) -> ::std::result::Result<(), String>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        for key in to_add {
+            if strict && (self.has_variable(key) || self.has_removal(key) || self.has_append(key)) {
+                return Err(key.to_owned());
+            }
+            self.variables.remove(key);
+            self.appends.remove(key);
+            if let Some(ref mut origins) = self.origins {
+                origins.remove(key);
+            }
+            self.removals.insert(key);
         }
         Ok(())
     }
+
+    /// Adds the appends of `other` after those of `self`.
+    fn merge_appends(&mut self, other: &Scenario<'a>) {
+        for (&key, values) in other.appends() {
+            self.appends
+                .entry(key)
+                .or_default()
+                .extend(values.iter().cloned());
+        }
+    }
 }
 
 impl<'a> Display for Scenario<'a> {
@@ -229,36 +777,164 @@ impl<'a> Display for Scenario<'a> {
 /// [`Scenario::merge()`]: ./struct.Scenario.html#method.merge
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MergeOptions<'a> {
-    /// A string used to join the scenario names together.
+    /// Strings used to join the scenario names together.
+    ///
+    /// When merging several scenarios, the `i`-th delimiter is placed
+    /// after the `i`-th name. If there are fewer delimiters than
+    /// names, the last delimiter is repeated. If there are no
+    /// delimiters at all, names are joined without anything between
+    /// them.
     ///
-    /// The default is `", "`, a comma followed by a space.
-    pub delimiter: &'a str,
+    /// The default is a single `", "`, a comma followed by a space.
+    pub delimiters: &'a [&'a str],
     /// Flag that enables strict mode.
     ///
     /// In strict mode, merging fails if two scenarios define the same
     /// variable. By default, strict mode is enabled.
     pub is_strict: bool,
+    /// Flag that enables tracking where variables come from.
+    ///
+    /// If enabled, the merged scenario remembers for each variable the
+    /// name of the scenario that defined it. Use
+    /// [`Scenario::origin_of()`] to query it. This is disabled by
+    /// default to save memory.
+    ///
+    /// [`Scenario::origin_of()`]: ./struct.Scenario.html#method.origin_of
+    pub track_origins: bool,
+    /// Which definition wins if two scenarios define the same variable.
+    ///
+    /// This only matters if strict mode is disabled. By default, the
+    /// last definition wins.
+    pub precedence: Precedence,
+    /// Flag that enables escaping delimiters in scenario names.
+    ///
+    /// If enabled, backslashes and delimiters that occur in the names
+    /// of the merged scenarios are prefixed with a backslash. Use
+    /// [`Scenario::name_parts()`] to split the merged name. This is
+    /// disabled by default.
+    ///
+    /// [`Scenario::name_parts()`]: ./struct.Scenario.html#method.name_parts
+    pub escape_names: bool,
 }
 
 impl<'a> MergeOptions<'a> {
-    fn new(delimiter: &'a str, is_strict: bool) -> Self {
+    /// Returns a builder that starts out with the default options.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let opts = MergeOptions::builder()
+    ///     .delimiters(&["/"])
+    ///     .strict(false)
+    ///     .build();
+    /// assert_eq!(opts.delimiter(0), "/");
+    /// ```
+    pub fn builder() -> MergeOptionsBuilder<'a> {
+        MergeOptionsBuilder::default()
+    }
+
+    fn new(delimiters: &'a [&'a str], is_strict: bool) -> Self {
         MergeOptions {
-            delimiter,
+            delimiters,
             is_strict,
+            track_origins: false,
+            precedence: Precedence::Last,
+            escape_names: false,
         }
     }
+
+    /// Returns the delimiter to put after the name at `position`.
+    ///
+    /// See [`delimiters`] for details.
+    ///
+    /// [`delimiters`]: #structfield.delimiters
+    pub fn delimiter(&self, position: usize) -> &'a str {
+        self.delimiters
+            .get(position)
+            .or_else(|| self.delimiters.last())
+            .cloned()
+            .unwrap_or("")
+    }
 }
 
 impl<'a> Default for MergeOptions<'a> {
     fn default() -> Self {
         MergeOptions {
-            delimiter: ", ",
+            delimiters: &[", "],
             is_strict: true,
+            track_origins: false,
+            precedence: Precedence::Last,
+            escape_names: false,
         }
     }
 }
 
 
+/// The order of precedence between conflicting variable definitions.
+///
+/// See [`MergeOptions::precedence`].
+///
+/// [`MergeOptions::precedence`]: ./struct.MergeOptions.html#structfield.precedence
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    /// The definition of the earliest merged scenario wins.
+    First,
+    /// The definition of the latest merged scenario wins.
+    Last,
+}
+
+
+/// Builder for [`MergeOptions`].
+///
+/// This is returned by [`MergeOptions::builder()`]. Each method sets
+/// the field of the same name; see [`MergeOptions`] for their meaning.
+/// Fields that are not set keep their default value.
+///
+/// [`MergeOptions`]: ./struct.MergeOptions.html
+/// [`MergeOptions::builder()`]: ./struct.MergeOptions.html#method.builder
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct MergeOptionsBuilder<'a> {
+    options: MergeOptions<'a>,
+}
+
+impl<'a> MergeOptionsBuilder<'a> {
+    /// Sets the strings used to join the scenario names together.
+    pub fn delimiters(mut self, delimiters: &'a [&'a str]) -> Self {
+        self.options.delimiters = delimiters;
+        self
+    }
+
+    /// Enables or disables strict mode.
+    pub fn strict(mut self, is_strict: bool) -> Self {
+        self.options.is_strict = is_strict;
+        self
+    }
+
+    /// Enables or disables tracking where variables come from.
+    pub fn track_origins(mut self, track_origins: bool) -> Self {
+        self.options.track_origins = track_origins;
+        self
+    }
+
+    /// Sets which definition wins in lax mode.
+    pub fn precedence(mut self, precedence: Precedence) -> Self {
+        self.options.precedence = precedence;
+        self
+    }
+
+    /// Enables or disables escaping delimiters in scenario names.
+    pub fn escape_names(mut self, escape_names: bool) -> Self {
+        self.options.escape_names = escape_names;
+        self
+    }
+
+    /// Returns the finished options.
+    pub fn build(self) -> MergeOptions<'a> {
+        self.options
+    }
+}
+
+
 /// Tests if a character is a valid C identifier.
 ///
 /// C identifiers contain only the following characters:
@@ -269,19 +945,19 @@ impl<'a> Default for MergeOptions<'a> {
 ///
 /// Additionally, they must not begin with a digit, and contain at
 /// least one character.
-fn is_c_identifier(s: &str) -> bool {
+pub fn is_c_identifier(s: &str) -> bool {
     let mut iter = s.as_bytes().iter();
     let first_byte = match iter.next() {
         Some(byte) => byte,
         None => return false,
     };
     match *first_byte {
-        b'A'...b'Z' | b'a'...b'z' | b'_' => {},
+        b'A'..=b'Z' | b'a'..=b'z' | b'_' => {},
         _ => return false,
     }
     for byte in s.as_bytes().iter() {
         match *byte {
-            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'_' => {},
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' => {},
             _ => return false,
         }
     }
@@ -289,19 +965,75 @@ fn is_c_identifier(s: &str) -> bool {
 }
 
 
+/// Puts a backslash before each backslash and delimiter in `name`.
+///
+/// Empty delimiters are ignored. See [`split_name()`] for the
+/// reverse.
+///
+/// [`split_name()`]: ./fn.split_name.html
+fn escape_name(name: &str, delimiters: &[&str]) -> String {
+    let mut result = String::with_capacity(name.len());
+    for (i, c) in name.char_indices() {
+        let rest = &name[i..];
+        if c == '\\' || delimiters.iter().any(|d| !d.is_empty() && rest.starts_with(d)) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+
+/// Splits `name` at each of the `delimiters`.
+///
+/// If `is_escaped` is `true`, a backslash makes the following
+/// character a literal one and is removed. Where several delimiters
+/// match, the longest one is taken.
+fn split_name(name: &str, delimiters: &[&str], is_escaped: bool) -> Vec<String> {
+    let mut delimiters: Vec<&str> = delimiters.iter().cloned().filter(|d| !d.is_empty()).collect();
+    delimiters.sort_by_key(|d| ::std::cmp::Reverse(d.len()));
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut chars = name.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if is_escaped && c == '\\' {
+            part.extend(chars.next().map(|(_, c)| c));
+            continue;
+        }
+        match delimiters.iter().find(|&&d| name[i..].starts_with(d)) {
+            Some(delimiter) => {
+                parts.push(::std::mem::take(&mut part));
+                // Skip the rest of the delimiter.
+                for _ in delimiter.chars().skip(1) {
+                    chars.next();
+                }
+            },
+            None => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+
 /// Finds a scenario that defines a variable and returns its name.
 ///
-/// This is a helper function to `Scenario::merge_all()`.
+/// This is a helper function to [`Scenario::merge_all_into()`].
 ///
-/// [`Scenario::merge_all()`]: ./struct.Scenario.html#method.merge_all
+/// [`Scenario::merge_all_into()`]: ./struct.Scenario.html#method.merge_all_into
 fn name_of_first_scenario_with_variable<'a, I>(mut scenarios: I, varname: &str) -> Option<String>
 where
     I: Iterator,
     I::Item: Borrow<Scenario<'a>>,
 {
-    scenarios
-        .find(|s| s.borrow().has_variable(varname))
-        .map(|s| s.borrow().name().to_owned())
+    scenarios.find_map(|s| {
+        let s = s.borrow();
+        if s.has_variable(varname) || s.has_removal(varname) || s.has_append(varname) {
+            Some(s.name().to_owned())
+        } else {
+            None
+        }
+    })
 }
 
 
@@ -316,9 +1048,16 @@ pub enum ScenarioError {
     /// The variable name is illegal.
     #[fail(display = "invalid variable name: \"{}\"", _0)]
     InvalidVariable(String),
-    /// The variable name has already been used..
-    #[fail(display = "variable already defined: \"{}\"", _0)]
-    DuplicateVariable(String),
+    /// The variable name has already been used.
+    ///
+    /// This holds the variable name, the existing definition and the
+    /// attempted one. Both definitions are written like the input
+    /// lines that cause them, e.g. `name = value` or `!name`.
+    #[fail(display = "variable already defined: \"{}\": \"{}\" conflicts with \"{}\"", _0, _1, _2)]
+    DuplicateVariable(String, String, String),
+    /// The tag is illegal.
+    #[fail(display = "invalid tag: \"{}\"", _0)]
+    InvalidTag(String),
 }
 
 
@@ -362,7 +1101,7 @@ mod tests {
 
     fn make_dummy_scenario<'a>(name: &'a str, vars: &[&'a str]) -> Scenario<'a> {
         let mut result = Scenario::new(name).expect(name);
-        for var in vars.into_iter().cloned() {
+        for var in vars.iter().cloned() {
             result.add_variable(var, "").expect(var);
         }
         result
@@ -396,6 +1135,16 @@ mod tests {
         assert!(Scenario::new("").is_err());
     }
 
+    #[test]
+    fn test_scenario_set_name() {
+        let mut s = Scenario::new("old").unwrap();
+        assert!(s.set_name("").is_err());
+        assert!(s.set_name("with\0null").is_err());
+        assert_eq!(s.name(), "old");
+        s.set_name(format!("{}-{}", "a", "b")).unwrap();
+        assert_eq!(s.name(), "a-b");
+    }
+
     #[test]
     fn test_scenario_add_variable() {
         let mut s = Scenario::new("name").unwrap();
@@ -404,7 +1153,10 @@ mod tests {
         // Values may contain spaces.
         assert!(s.add_variable("key2", "a value").is_ok());
         // The same variable must not be added twice.
-        assert!(s.add_variable("key", "value").is_err());
+        assert_eq!(
+            s.add_variable("key", "other").unwrap_err().to_string(),
+            "variable already defined: \"key\": \"key = value\" conflicts with \"key = other\""
+        );
         // Variable names must be C identifiers.
         assert!(s.add_variable("a key", "value").is_err());
         assert!(s.add_variable("[key]", "value").is_err());
@@ -413,6 +1165,175 @@ mod tests {
         assert!(!s.has_variable("a key"));
     }
 
+    #[test]
+    fn test_scenario_variables_len() {
+        let mut s = Scenario::new("name").unwrap();
+        assert!(s.has_no_variables());
+        s.add_removal("removed").unwrap();
+        s.add_append("appended", "value").unwrap();
+        assert_eq!(s.variables_len(), 0);
+        assert!(s.has_no_variables());
+        let s = make_dummy_scenario("name", &["a", "b"]);
+        assert_eq!(s.variables_len(), 2);
+        assert!(!s.has_no_variables());
+    }
+
+    #[test]
+    fn test_scenario_to_env_vec() {
+        let mut s = Scenario::new("name").unwrap();
+        s.add_variable("b", "2").unwrap();
+        s.add_variable("a", "1").unwrap();
+        s.add_removal("removed").unwrap();
+        let pair = |name: &str, value: &str| (name.to_owned(), value.to_owned());
+        assert_eq!(s.to_env_vec(false), [pair("a", "1"), pair("b", "2")]);
+        s.add_variable(NAME_VARIABLE, "own").unwrap();
+        assert_eq!(
+            s.to_env_vec(true),
+            [pair("a", "1"), pair("b", "2"), pair(NAME_VARIABLE, "name")]
+        );
+    }
+
+    #[test]
+    fn test_scenario_add_removal() {
+        let mut s = Scenario::new("name").unwrap();
+        assert!(s.add_removal("key").is_ok());
+        // A variable cannot be removed twice or removed and defined.
+        assert!(s.add_removal("key").is_err());
+        assert!(s.add_variable("key", "value").is_err());
+        // Removed variables must be C identifiers, too.
+        assert!(s.add_removal("a key").is_err());
+        assert!(s.has_removal("key"));
+        assert!(!s.has_variable("key"));
+        assert_eq!(s.removals().collect::<Vec<_>>(), [&"key"]);
+    }
+
+    #[test]
+    fn test_scenario_add_append() {
+        let mut s = Scenario::new("name").unwrap();
+        // Appending to the same variable twice is fine.
+        assert!(s.add_append("key", "a").is_ok());
+        assert!(s.add_append("key", "b").is_ok());
+        assert_eq!(s.get_appends("key"), Some(&["a", "b"][..]));
+        // But it cannot be mixed with definitions or removals.
+        assert!(s.add_variable("key", "value").is_err());
+        assert!(s.add_removal("key").is_err());
+        s.add_variable("other", "value").unwrap();
+        assert!(s.add_append("other", "a").is_err());
+        assert!(s.add_append("a key", "a").is_err());
+        assert!(!s.has_variable("key"));
+        assert!(s.has_append("key"));
+    }
+
+    #[test]
+    fn test_scenario_add_tag() {
+        let mut s = Scenario::new("name").unwrap();
+        s.add_tag("slow").unwrap();
+        s.add_tag("slow").unwrap();
+        assert!(s.add_tag("").is_err());
+        assert!(s.add_tag("two words").is_err());
+        assert!(s.has_tag("slow"));
+        assert_eq!(s.tags().count(), 1);
+        // Tags are not variables.
+        assert_eq!(s.variables().count(), 0);
+        assert!(s.add_variable("slow", "1").is_ok());
+    }
+
+    #[test]
+    fn test_merge_tags() {
+        let mut left = Scenario::new("left").unwrap();
+        left.add_tag("a").unwrap();
+        left.add_tag("b").unwrap();
+        let mut right = Scenario::new("right").unwrap();
+        right.add_tag("b").unwrap();
+        right.add_tag("c").unwrap();
+        left.merge(&right, MergeOptions::default()).unwrap();
+        let mut tags = left.tags().cloned().collect::<Vec<_>>();
+        tags.sort();
+        assert_eq!(tags, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_merge_appends() {
+        let mut left = Scenario::new("left").unwrap();
+        left.add_variable("defined", "x").unwrap();
+        left.add_append("appended", "a").unwrap();
+        let mut right = Scenario::new("right").unwrap();
+        right.add_append("defined", "y").unwrap();
+        right.add_append("appended", "b").unwrap();
+        // Appends don't conflict, even in strict mode.
+        left.merge(&right, MergeOptions::default()).unwrap();
+        assert_eq!(left.get_variable("defined"), Some("x"));
+        assert_eq!(left.get_appends("defined"), Some(&["y"][..]));
+        assert_eq!(left.get_appends("appended"), Some(&["a", "b"][..]));
+    }
+
+    #[test]
+    fn test_merge_definition_after_append() {
+        let mut left = Scenario::new("left").unwrap();
+        left.add_append("key", "a").unwrap();
+        let mut right = Scenario::new("right").unwrap();
+        right.add_variable("key", "value").unwrap();
+        // In strict mode, a later definition conflicts with appends.
+        let expected_message = "variable \"key\" defined both in scenario \"left\" and in \
+                                scenario \"right\"";
+        let error = Scenario::merge_all(&[left.clone(), right.clone()], MergeOptions::default())
+            .unwrap_err();
+        assert_eq!(expected_message, error.to_string());
+        // In lax mode, it discards them.
+        left.merge(&right, MergeOptions::new(&[", "], false)).unwrap();
+        assert_eq!(left.get_variable("key"), Some("value"));
+        assert!(!left.has_append("key"));
+    }
+
+    #[test]
+    fn test_retain_variables() {
+        let mut s = make_dummy_scenario("name", &["_a", "_b", "c", "_d", "e"]);
+        s.retain_variables(|name, _| !name.starts_with('_'));
+        assert_eq!(s.variables_sorted(), [("c", ""), ("e", "")]);
+    }
+
+    #[test]
+    fn test_variables_sorted() {
+        let mut s = Scenario::new("name").unwrap();
+        s.add_variable("c", "3").unwrap();
+        s.add_variable("a", "1").unwrap();
+        s.add_variable("B", "2").unwrap();
+        assert_eq!(s.variables_sorted(), [("B", "2"), ("a", "1"), ("c", "3")]);
+    }
+
+    #[test]
+    fn test_merge_removal_error() {
+        let expected_message = "variable \"a\" defined both in scenario \"A\" and in scenario \
+                                \"C\"";
+        let mut removes_a = Scenario::new("C").unwrap();
+        removes_a.add_removal("a").unwrap();
+        let scenarios = [
+            make_dummy_scenario("A", &["a"]),
+            make_dummy_scenario("B", &["b"]),
+            removes_a,
+        ];
+        let error = Scenario::merge_all(&scenarios, MergeOptions::default()).unwrap_err();
+        assert_eq!(expected_message, error.to_string());
+    }
+
+    #[test]
+    fn test_lax_merge_removal() {
+        let mut merged = make_dummy_scenario("A", &["a", "b"]);
+        let mut added = Scenario::new("B").unwrap();
+        added.add_removal("a").unwrap();
+        merged
+            .merge(&added, MergeOptions::new(&[", "], false))
+            .unwrap();
+        assert!(!merged.has_variable("a"));
+        assert!(merged.has_removal("a"));
+        // A later definition overrides the removal again.
+        merged
+            .merge(&make_dummy_scenario("C", &["a"]), MergeOptions::new(&[", "], false))
+            .unwrap();
+        assert!(merged.has_variable("a"));
+        assert!(!merged.has_removal("a"));
+    }
+
     #[test]
     #[should_panic]
     fn test_merge_none_panics() {
@@ -422,8 +1343,8 @@ mod tests {
     #[test]
     fn test_merge_one() {
         let expected = make_dummy_scenario("A", &[]);
-        // TODO: Improve signature of merge_all to get rid of cloning here.
-        let merged = Scenario::merge_all(&[expected.clone()], MergeOptions::default()).unwrap();
+        let slice = ::std::slice::from_ref(&expected);
+        let merged = Scenario::merge_all(slice, MergeOptions::default()).unwrap();
         assert_eq!(expected, merged);
     }
 
@@ -433,7 +1354,7 @@ mod tests {
         let mut merged = make_dummy_scenario("A", &["a"]);
         let added = make_dummy_scenario("B", &["b"]);
         merged
-            .merge(&added, MergeOptions::new(" -- ", true))
+            .merge(&added, MergeOptions::new(&[" -- "], true))
             .unwrap();
         assert_eq!(expected, merged);
     }
@@ -467,11 +1388,80 @@ mod tests {
         let mut merged = make_dummy_scenario("A", &["a"]);
         let added = make_dummy_scenario("B", &["a"]);
         merged
-            .merge(&added, MergeOptions::new(", ", false))
+            .merge(&added, MergeOptions::new(&[", "], false))
             .unwrap();
         assert_eq!(expected, merged);
     }
 
+    #[test]
+    fn test_lax_merge_first_wins() {
+        let first = MergeOptions::builder()
+            .strict(false)
+            .precedence(Precedence::First)
+            .track_origins(true)
+            .build();
+        let mut a = Scenario::new("A").unwrap();
+        a.add_variable("a", "from A").unwrap();
+        a.add_removal("r").unwrap();
+        let mut b = Scenario::new("B").unwrap();
+        b.add_variable("a", "from B").unwrap();
+        b.add_variable("b", "from B").unwrap();
+        b.add_variable("r", "from B").unwrap();
+        let merged = Scenario::merge_all(&[a.clone(), b.clone()], first).unwrap();
+        assert_eq!(merged.get_variable("a"), Some("from A"));
+        assert_eq!(merged.get_variable("b"), Some("from B"));
+        assert!(merged.has_removal("r"));
+        assert_eq!(merged.origin_of("a"), Some("A"));
+        assert_eq!(merged.origin_of("b"), Some("B"));
+        // The default stays that the last definition wins.
+        let last = MergeOptions::builder().strict(false).build();
+        let merged = Scenario::merge_all(&[a, b], last).unwrap();
+        assert_eq!(merged.get_variable("a"), Some("from B"));
+        assert_eq!(merged.get_variable("r"), Some("from B"));
+    }
+
+    #[test]
+    fn test_escape_names() {
+        let escaping = MergeOptions::builder()
+            .delimiters(&[", ", "/"])
+            .escape_names(true)
+            .build();
+        let scenarios = [
+            make_dummy_scenario("a, b", &[]),
+            make_dummy_scenario("c/d", &[]),
+            make_dummy_scenario("e\\f", &[]),
+        ];
+        let merged = Scenario::merge_all(&scenarios, escaping).unwrap();
+        assert_eq!(merged.name(), "a\\, b, c\\/d/e\\\\f");
+        assert_eq!(merged.name_parts(&[", ", "/"]), ["a, b", "c/d", "e\\f"]);
+        // Merging more doesn't escape the existing name twice.
+        let mut more = merged.clone();
+        more.merge(&make_dummy_scenario("g", &[]), escaping).unwrap();
+        assert_eq!(more.name_parts(&[", ", "/"]), ["a, b", "c/d", "e\\f", "g"]);
+        // Without escaping, the parts are ambiguous.
+        let merged = Scenario::merge_all(&scenarios, MergeOptions::default()).unwrap();
+        assert_eq!(merged.name(), "a, b, c/d, e\\f");
+        assert_eq!(merged.name_parts(&[", "]), ["a", "b", "c/d", "e\\f"]);
+    }
+
+    #[test]
+    fn test_with_base() {
+        let base = make_dummy_scenario("Base", &["a", "b"]);
+        let scenario = make_dummy_scenario("A", &["c"]);
+        let expected = make_dummy_scenario("A", &["a", "b", "c"]);
+        let merged = scenario.with_base(&base, MergeOptions::default()).unwrap();
+        assert_eq!(expected, merged);
+        let scenario = make_dummy_scenario("A", &["a"]);
+        let error = scenario.with_base(&base, MergeOptions::default()).unwrap_err();
+        assert_eq!(
+            "variable \"a\" defined both in scenario \"Base\" and in scenario \"A\"",
+            error.to_string()
+        );
+        let lax = MergeOptions::builder().strict(false).build();
+        let expected = make_dummy_scenario("A", &["a", "b"]);
+        assert_eq!(expected, scenario.with_base(&base, lax).unwrap());
+    }
+
     #[test]
     fn test_multi_merge() {
         let expected = make_dummy_scenario("A/B/C", &["a", "aa", "b", "bb", "c", "cc"]);
@@ -480,7 +1470,105 @@ mod tests {
             make_dummy_scenario("B", &["b", "bb"]),
             make_dummy_scenario("C", &["c", "cc"]),
         ];
-        let actual = Scenario::merge_all(&all, MergeOptions::new("/", true)).unwrap();
+        let actual = Scenario::merge_all(&all, MergeOptions::new(&["/"], true)).unwrap();
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_merge_all_into() {
+        let expected = make_dummy_scenario("A/B/C", &["a", "b", "c"]);
+        let mut merged = make_dummy_scenario("A", &["a"]);
+        let others = [make_dummy_scenario("B", &["b"]), make_dummy_scenario("C", &["c"])];
+        merged
+            .merge_all_into(&others, MergeOptions::new(&["/"], true))
+            .unwrap();
+        assert_eq!(expected, merged);
+        // Merging nothing changes nothing.
+        merged.merge_all_into(&[], MergeOptions::default()).unwrap();
+        assert_eq!(expected, merged);
+    }
+
+    #[test]
+    fn test_merge_all_into_error() {
+        let others = [make_dummy_scenario("B", &["b"]), make_dummy_scenario("C", &["a"])];
+        let error = make_dummy_scenario("A", &["a"])
+            .merge_all_into(&others, MergeOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            "variable \"a\" defined both in scenario \"A\" and in scenario \"C\"",
+            error.to_string()
+        );
+        let others = [make_dummy_scenario("B", &["b"]), make_dummy_scenario("C", &["b"])];
+        let error = make_dummy_scenario("A", &["a"])
+            .merge_all_into(&others, MergeOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            "variable \"b\" defined both in scenario \"B\" and in scenario \"C\"",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_merge_options_builder() {
+        assert_eq!(MergeOptions::builder().build(), MergeOptions::default());
+        let opts = MergeOptions::builder()
+            .delimiters(&["/"])
+            .strict(false)
+            .track_origins(true)
+            .precedence(Precedence::First)
+            .escape_names(true)
+            .build();
+        let expected = MergeOptions {
+            delimiters: &["/"],
+            is_strict: false,
+            track_origins: true,
+            precedence: Precedence::First,
+            escape_names: true,
+        };
+        assert_eq!(opts, expected);
+    }
+
+    #[test]
+    fn test_track_origins() {
+        let all = [
+            make_dummy_scenario("A", &["a", "x"]),
+            make_dummy_scenario("B", &["b"]),
+            make_dummy_scenario("C", &["c", "x"]),
+        ];
+        let opts = MergeOptions::builder()
+            .strict(false)
+            .track_origins(true)
+            .build();
+        let merged = Scenario::merge_all(&all, opts).unwrap();
+        assert_eq!(merged.origin_of("a"), Some("A"));
+        assert_eq!(merged.origin_of("b"), Some("B"));
+        assert_eq!(merged.origin_of("x"), Some("C"));
+        assert_eq!(merged.origin_of("d"), None);
+        // Without tracking, all variables come from the merged scenario.
+        let merged = Scenario::merge_all(&all, MergeOptions::new(&[", "], false)).unwrap();
+        assert_eq!(merged.origin_of("a"), Some("A, B, C"));
+        assert_eq!(all[0].origin_of("a"), Some("A"));
+        // Variables added after a merge come from the merged scenario.
+        let mut ab = make_dummy_scenario("A", &["a"]);
+        ab.merge(&make_dummy_scenario("B", &["b"]), opts).unwrap();
+        ab.add_variable("y", "").unwrap();
+        let mut merged = make_dummy_scenario("C", &["c"]);
+        merged.merge(&ab, opts).unwrap();
+        assert_eq!(merged.origin_of("b"), Some("B"));
+        assert_eq!(merged.origin_of("y"), Some(ab.name()));
+    }
+
+    #[test]
+    fn test_multi_merge_several_delimiters() {
+        let all = [
+            make_dummy_scenario("A", &[]),
+            make_dummy_scenario("B", &[]),
+            make_dummy_scenario("C", &[]),
+            make_dummy_scenario("D", &[]),
+        ];
+        let actual = Scenario::merge_all(&all, MergeOptions::new(&[":", "/"], true)).unwrap();
+        assert_eq!("A:B/C/D", actual.name());
+        let actual = Scenario::merge_all(&all, MergeOptions::new(&[], true)).unwrap();
+        assert_eq!("ABCD", actual.name());
+    }
 }
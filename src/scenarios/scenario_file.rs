@@ -14,17 +14,159 @@
 
 
 use std::{
+    borrow::Cow,
     collections::hash_map::{Entry, HashMap},
     ffi::OsStr,
-    fs::File,
-    io::{self, BufRead},
+    fs::{self, File},
+    io::{self, BufRead, Read},
     iter::FusedIterator,
-    path::Path,
+    path::{Path, PathBuf},
+    str,
 };
 
 use failure::{Error, Fail, ResultExt};
+use glob::{MatchOptions, Pattern};
 
-use super::{inputline::InputLine, location::ErrorLocation, scenario::Scenario};
+use trytostr;
+
+use super::{
+    inputline::{self, InputLine},
+    location::ErrorLocation,
+    scenario::{self, Scenario, ScenarioError},
+};
+
+
+/// Customization flags for [`ScenarioFile`].
+///
+/// [`ScenarioFile`]: ./struct.ScenarioFile.html
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Options<'a> {
+    /// Check the file for duplicate scenario names.
+    ///
+    /// If `true`, it is an error for two header lines in the same
+    /// file to have the same content.
+    ///
+    /// The default is `true`.
+    pub is_strict: bool,
+    /// Compare scenario names case-insensitively.
+    ///
+    /// If `true`, the check for duplicate scenario names considers
+    /// e.g. `[Build]` and `[build]` to be the same name. This has no
+    /// effect unless `is_strict` is `true` as well.
+    ///
+    /// Note that this check only covers the scenarios of a single
+    /// file. Merged names, which combine scenarios from several files,
+    /// are never checked for duplicates.
+    ///
+    /// The default is `false`.
+    pub case_insensitive_names: bool,
+    /// Strip comments at the end of definition lines.
+    ///
+    /// If `true`, a hash sign `#` that follows whitespace in the value
+    /// of a variable definition starts a comment. A literal hash sign
+    /// can then be written as `\#`.
+    ///
+    /// The default is `false`.
+    pub allow_inline_comments: bool,
+    /// The format in which standard input is read.
+    ///
+    /// This only applies to [`ScenarioFile::from_cl_arg()`] if it is
+    /// passed `"-"`. Regular files are always read as
+    /// [`Format::Ini`].
+    ///
+    /// The default is [`Format::Ini`].
+    ///
+    /// [`ScenarioFile::from_cl_arg()`]: ./struct.ScenarioFile.html#method.from_cl_arg
+    /// [`Format::Ini`]: ./enum.Format.html#variant.Ini
+    pub stdin_format: Format,
+    /// A glob pattern that all scenario names must match.
+    ///
+    /// If set, it is an error for a file to contain a scenario whose
+    /// name doesn't match this pattern. The match is case-insensitive
+    /// if `case_insensitive_names` is `true`. The defaults section
+    /// `[*]` is exempt from this check.
+    ///
+    /// The default is `None`.
+    pub name_pattern: Option<&'a Pattern>,
+    /// Read values of the form `@PATH` from the file at `PATH`.
+    ///
+    /// If `true`, a variable definition like `cert = @certs/ca.pem`
+    /// gets the contents of the given file as its value, verbatim.
+    /// Relative paths are resolved relative to the directory of the
+    /// scenario file. This only applies to the INI format and not to
+    /// appends.
+    ///
+    /// The default is `false`.
+    pub allow_file_values: bool,
+    /// Keep bytes that are not valid UTF-8 in variable values.
+    ///
+    /// If `true`, such bytes are carried through as raw bytes, as
+    /// described in [`trytostr::from_utf8_with_raw_bytes()`]. Scenario
+    /// names, variable names, and heredocs must still be valid UTF-8.
+    /// This only applies to the INI format.
+    ///
+    /// The default is `false`.
+    ///
+    /// [`trytostr::from_utf8_with_raw_bytes()`]:
+    /// ../trytostr/fn.from_utf8_with_raw_bytes.html
+    pub allow_non_utf8_values: bool,
+}
+
+impl<'a> Default for Options<'a> {
+    /// Creates an `Options` value with defaults as specified above.
+    fn default() -> Self {
+        Options {
+            is_strict: true,
+            case_insensitive_names: false,
+            allow_inline_comments: false,
+            stdin_format: Format::Ini,
+            name_pattern: None,
+            allow_file_values: false,
+            allow_non_utf8_values: false,
+        }
+    }
+}
+
+
+/// The formats in which scenarios can be read.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Format {
+    /// The line-based, INI-like format of regular scenario files.
+    Ini,
+    /// A stream of NUL-separated records.
+    ///
+    /// Each record consists of NUL-terminated fields. The first field
+    /// is the scenario name, each following field a variable
+    /// definition of the form `NAME=VALUE`. An empty field ends the
+    /// record:
+    ///
+    /// ```text
+    /// NAME\0VAR=VAL\0VAR=VAL\0\0
+    /// ```
+    ///
+    /// Since no line-based parsing takes place, names and values may
+    /// contain any character except NUL, e.g. line breaks. As in the
+    /// INI format, whitespace around variable names and values is
+    /// ignored, and `VAR+=VAL` appends to a variable. In error
+    /// messages, fields are counted as if they were lines, with the
+    /// empty fields ending each record included.
+    Nul,
+    /// Tab-separated values, e.g. exported from a spreadsheet.
+    ///
+    /// The first line contains the column titles, which must be valid
+    /// variable names. Each following line is one scenario; its fields
+    /// are the values of the variables, in the order of the columns.
+    /// Empty lines are ignored.
+    ///
+    /// If a column is titled `SCENARIOS_NAME`, its fields are used as
+    /// scenario names instead of variable values. Otherwise, the
+    /// scenarios are numbered, starting at `1`.
+    ///
+    /// Within a field, `\t`, `\n`, `\r` and `\\` stand for a tab, a
+    /// line feed, a carriage return, and a backslash respectively.
+    /// All other backslashes are an error.
+    Tsv,
+}
 
 
 /// Type that represents a scenario file.
@@ -44,19 +186,37 @@ use super::{inputline::InputLine, location::ErrorLocation, scenario::Scenario};
 pub struct ScenarioFile<'a> {
     filename: &'a Path,
     lines: Vec<InputLine>,
+    /// The line number in the source of each element of `lines`.
+    ///
+    /// This is empty if `lines` correspond one-to-one to the lines of
+    /// the source.
+    linenos: Vec<usize>,
+    /// Flag that is set if the scenario names have been made up by
+    /// numbering the scenarios.
+    has_numbered_names: bool,
 }
 
 impl<'a> ScenarioFile<'a> {
+    /// Creates a file whose `lines` correspond to the source lines.
+    fn with_lines(filename: &'a Path, lines: Vec<InputLine>) -> Self {
+        ScenarioFile {
+            filename,
+            lines,
+            linenos: Vec::new(),
+            has_numbered_names: false,
+        }
+    }
+
     /// Takes a command-line argument and reads a file from it.
     ///
     /// If `path` equals `"-"`, this reads scenarios from standard
     /// input. Otherwise, it reads from the regular file located at
     /// `path`.
     ///
-    /// If `is_strict` is `true`, this function checks after reading
-    /// whether any two scenarios in it have the same name. If they do,
-    /// this function returns an error. If `is_strict` is `false`, the
-    /// check is not performed.
+    /// If `opts.is_strict` is `true`, this function checks after
+    /// reading whether any two scenarios in it have the same name. If
+    /// they do, this function returns an error. If `opts.is_strict` is
+    /// `false`, the check is not performed.
     ///
     /// Note that this call reads all lines in the file into memory,
     /// but does not create any [`Scenario`]s yet. This only happens
@@ -69,36 +229,200 @@ impl<'a> ScenarioFile<'a> {
     /// 2. Reading from the file fails at any point.
     /// 3. The file breaks the syntax of scenario files.
     /// 4. The file defines two scenarios with the same name. (only if
-    /// `is_strict` is `true`).
+    ///    `opts.is_strict` is `true`).
     ///
     /// [`Scenario`]: ./struct.Scenario.html
-    pub fn from_cl_arg(path: &OsStr, is_strict: bool) -> Result<ScenarioFile, Error> {
+    pub fn from_cl_arg(path: &'a OsStr, opts: Options) -> Result<Self, Error> {
+        let format = if path == Path::new("-") {
+            opts.stdin_format
+        } else {
+            Format::Ini
+        };
+        Self::from_cl_arg_as(path, format, opts)
+    }
+
+    /// Like [`from_cl_arg()`], but reads the file in the given format.
+    ///
+    /// This also applies to standard input; `opts.stdin_format` is
+    /// ignored.
+    ///
+    /// [`from_cl_arg()`]: #method.from_cl_arg
+    pub fn from_cl_arg_as(path: &'a OsStr, format: Format, opts: Options) -> Result<Self, Error> {
         let stdin = io::stdin();
         if path == Path::new("-") {
-            Self::new(stdin.lock(), "<stdin>".as_ref(), is_strict)
+            Self::from_reader_as(stdin.lock(), "<stdin>", format, opts)
         } else {
             let file = File::open(path).with_context(|_| ErrorLocation::new(path.to_owned()))?;
             let file = io::BufReader::new(file);
-            Self::new(file, path.as_ref(), is_strict)
+            Self::from_reader_as(file, path, format, opts)
+        }
+    }
+
+    /// Dispatches to the constructor that reads `format`.
+    fn from_reader_as<R, P>(
+        reader: R,
+        filename: &'a P,
+        format: Format,
+        opts: Options,
+    ) -> Result<Self, Error>
+    where
+        R: BufRead,
+        P: AsRef<Path> + ?Sized,
+    {
+        match format {
+            Format::Ini => Self::from_reader(reader, filename, opts),
+            Format::Nul => Self::from_nul_reader(reader, filename, opts),
+            Format::Tsv => Self::from_tsv_reader(reader, filename, opts),
         }
     }
 
     /// Reads scenarios from a given buffered reader.
-    fn new<F>(reader: F, filename: &Path, is_strict: bool) -> Result<ScenarioFile, Error>
+    ///
+    /// The `filename` is not opened; it is only used in error messages
+    /// and returned by [`filename()`]. Apart from that, this behaves
+    /// like [`from_cl_arg()`].
+    ///
+    /// # Errors
+    /// This function fails if reading from `reader` fails, if the
+    /// read lines break the syntax of scenario files, if two
+    /// scenarios have the same name (only if `opts.is_strict` is
+    /// `true`), if a scenario name doesn't match `opts.name_pattern`,
+    /// or if a value cannot be read from a file (only if
+    /// `opts.allow_file_values` is `true`).
+    ///
+    /// [`filename()`]: #method.filename
+    /// [`from_cl_arg()`]: #method.from_cl_arg
+    pub fn from_reader<R, P>(reader: R, filename: &'a P, opts: Options) -> Result<Self, Error>
     where
-        F: BufRead,
+        R: BufRead,
+        P: AsRef<Path> + ?Sized,
     {
-        let lines = Vec::new();
-        let mut file = ScenarioFile { filename, lines };
-        file.read_from(reader)?;
-        if is_strict {
-            file.check_for_duplicate_headers()?;
+        let filename = filename.as_ref();
+        let mut file = ScenarioFile::with_lines(filename, Vec::new());
+        file.read_from(reader, opts)?;
+        file.check_headers(opts)?;
+        if opts.allow_file_values {
+            file.read_file_values()?;
         }
         Ok(file)
     }
 
+    /// Reads NUL-separated records from a reader.
+    ///
+    /// See [`Format::Nul`] for a description of the format. Apart from
+    /// that, this behaves like [`from_reader()`].
+    ///
+    /// # Errors
+    /// This function fails if reading from `reader` fails, if the
+    /// records are malformed, or if two scenarios have the same name
+    /// (only if `opts.is_strict` is `true`). Malformed records are
+    /// reported with the byte offset at which the problem occurs.
+    ///
+    /// [`Format::Nul`]: ./enum.Format.html#variant.Nul
+    /// [`from_reader()`]: #method.from_reader
+    pub fn from_nul_reader<R, P>(
+        mut reader: R,
+        filename: &'a P,
+        opts: Options,
+    ) -> Result<Self, Error>
+    where
+        R: Read,
+        P: AsRef<Path> + ?Sized,
+    {
+        let filename = filename.as_ref();
+        let mut buffer = Vec::new();
+        reader
+            .read_to_end(&mut buffer)
+            .with_context(|_| ErrorLocation::new(filename.to_owned()))?;
+        let lines = parse_nul_records(&buffer)
+            .with_context(|_| ErrorLocation::new(filename.to_owned()))?;
+        let file = ScenarioFile::with_lines(filename, lines);
+        file.check_headers(opts)?;
+        Ok(file)
+    }
+
+    /// Reads tab-separated values from a reader.
+    ///
+    /// See [`Format::Tsv`] for a description of the format. Apart from
+    /// that, this behaves like [`from_reader()`].
+    ///
+    /// # Errors
+    /// This function fails if reading from `reader` fails, if the
+    /// header line or any row is malformed, or if two scenarios have
+    /// the same name (only if `opts.is_strict` is `true`). All of this
+    /// is checked up front, so iterating over the file never fails.
+    ///
+    /// [`Format::Tsv`]: ./enum.Format.html#variant.Tsv
+    /// [`from_reader()`]: #method.from_reader
+    pub fn from_tsv_reader<R, P>(
+        mut reader: R,
+        filename: &'a P,
+        opts: Options,
+    ) -> Result<Self, Error>
+    where
+        R: Read,
+        P: AsRef<Path> + ?Sized,
+    {
+        let filename = filename.as_ref();
+        let mut contents = String::new();
+        reader
+            .read_to_string(&mut contents)
+            .with_context(|_| ErrorLocation::new(filename.to_owned()))?;
+        parse_tsv(&contents, filename, opts)
+    }
+
+    /// Creates a file with a single scenario from an inline definition.
+    ///
+    /// The definition has the form `NAME:VAR=VAL,VAR=VAL`, e.g. as
+    /// passed on the command line. Everything up to the first colon is
+    /// the scenario name; the rest is a comma-separated list of
+    /// variable definitions. If there is no colon, the scenario has no
+    /// variables. As in the INI format, whitespace around variable
+    /// names and values is ignored, and `VAR+=VAL` appends to a
+    /// variable.
+    ///
+    /// A backslash makes the following backslash, comma, or colon
+    /// literal, e.g. `\,` is a comma inside a value. All other
+    /// backslashes are an error.
+    ///
+    /// The file is named `<command line>`. In error messages, the
+    /// name counts as line 1 and each variable definition as one
+    /// further line.
+    ///
+    /// # Errors
+    /// This function fails if the definition is malformed or if the
+    /// name doesn't match `opts.name_pattern`.
+    pub fn from_inline(spec: &str, opts: Options) -> Result<Self, Error> {
+        let filename = Path::new(INLINE_FILENAME);
+        let lines = parse_inline(spec)?;
+        let file = ScenarioFile::with_lines(filename, lines);
+        file.check_headers(opts)?;
+        Ok(file)
+    }
+
+    /// Reads scenarios from a string.
+    ///
+    /// This is a convenience wrapper around [`from_reader()`] for
+    /// scenario definitions that are already in memory.
+    ///
+    /// [`from_reader()`]: #method.from_reader
+    pub fn from_str<P>(contents: &str, filename: &'a P, opts: Options) -> Result<Self, Error>
+    where
+        P: AsRef<Path> + ?Sized,
+    {
+        Self::from_reader(contents.as_bytes(), filename, opts)
+    }
+
     /// Reads lines from `reader`, parses them, and keeps them.
-    fn read_from<F: BufRead>(&mut self, mut reader: F) -> Result<(), Error> {
+    ///
+    /// If `opts.allow_inline_comments` is `true`, comments at the end
+    /// of definition lines are stripped. If
+    /// `opts.allow_non_utf8_values` is `true`, invalid UTF-8 in values
+    /// is kept as raw bytes.
+    fn read_from<F>(&mut self, mut reader: F, opts: Options) -> Result<(), Error>
+    where
+        F: BufRead,
+    {
         let mut loc = ErrorLocation::new(self.filename);
         let mut buffer = String::new();
         loop {
@@ -106,23 +430,110 @@ impl<'a> ScenarioFile<'a> {
             // end of the loop, an error in the first line would be
             // reported as "error in line 0".
             loc.lineno += 1;
-            let num_bytes = reader
-                .read_line(&mut buffer)
-                .with_context(|_| loc.to_owned())?;
+            let num_bytes = if opts.allow_non_utf8_values {
+                read_line_with_raw_bytes(&mut reader, &mut buffer)
+            } else {
+                reader.read_line(&mut buffer).map_err(Error::from)
+            };
+            let num_bytes = num_bytes.with_context(|_| loc.to_owned())?;
             if num_bytes == 0 {
                 break;
             }
-            let line = buffer
-                .parse::<InputLine>()
+            // Files written on Windows may start with a BOM. Line
+            // endings need no such care: "\r" is trimmed like any
+            // other whitespace.
+            if loc.lineno == 1 && buffer.starts_with(BYTE_ORDER_MARK) {
+                buffer.remove(0);
+            }
+            if let Some((name, marker)) = inputline::parse_heredoc_start(&buffer) {
+                let (value, num_lines) =
+                    read_heredoc(&mut reader, marker).with_context(|_| loc.to_owned())?;
+                self.lines.push(InputLine::new_definition(name, &value));
+                // Keep one line per line in the file so that line
+                // numbers in later error messages stay correct.
+                for _ in 0..num_lines {
+                    self.lines.push(InputLine::new_comment());
+                }
+                loc.lineno += num_lines;
+                buffer.clear();
+                continue;
+            }
+            let line = InputLine::parse(&buffer, opts.allow_inline_comments)
                 .with_context(|_| loc.to_owned())?;
+            if !line.is_definition() && !line.is_append() {
+                trytostr::check_no_raw_bytes(&buffer).with_context(|_| loc.to_owned())?;
+            }
             self.lines.push(line);
             buffer.clear();
         }
         Ok(())
     }
 
+    /// Performs all checks on header lines that `opts` ask for.
+    fn check_headers(&self, opts: Options) -> Result<(), Error> {
+        if let Some(pattern) = opts.name_pattern {
+            self.check_header_pattern(pattern, opts.case_insensitive_names)?;
+        }
+        if opts.is_strict {
+            self.check_for_duplicate_headers(opts.case_insensitive_names)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces each value `@PATH` with the contents of `PATH`.
+    ///
+    /// Relative paths are resolved relative to the directory of this
+    /// file. Definitions outside of any section are left alone; they
+    /// are reported as errors when iterating.
+    fn read_file_values(&mut self) -> Result<(), Error> {
+        let dir = self.filename.parent().unwrap_or_else(|| Path::new(""));
+        let mut loc = ErrorLocation::new(self.filename);
+        let mut scenario = None;
+        for line in &mut self.lines {
+            loc.lineno += 1;
+            if let Some(header) = line.as_header() {
+                scenario = Some(header.to_owned());
+                continue;
+            }
+            let (variable, path) = match (&scenario, line.as_definition()) {
+                (Some(_), Some((variable, value))) if value.starts_with('@') => {
+                    (variable.to_owned(), dir.join(&value[1..]))
+                },
+                _ => continue,
+            };
+            let contents = fs::read_to_string(&path)
+                .with_context(|_| FileValueError {
+                    scenario: scenario.clone().unwrap_or_default(),
+                    variable: variable.clone(),
+                    path,
+                })
+                .with_context(|_| loc.to_owned())?;
+            *line = InputLine::new_definition(&variable, &contents);
+        }
+        Ok(())
+    }
+
+    /// Returns an error if a header line doesn't match `pattern`.
+    ///
+    /// The header of the defaults section is not checked.
+    fn check_header_pattern(&self, pattern: &Pattern, ignore_case: bool) -> Result<(), Error> {
+        let mut loc = ErrorLocation::new(self.filename);
+        for line in &self.lines {
+            loc.lineno += 1;
+            match line.as_header() {
+                Some(DEFAULTS_HEADER) | None => {},
+                Some(header) => check_name_pattern(header, pattern, ignore_case)
+                    .with_context(|_| loc.to_owned())?,
+            }
+        }
+        Ok(())
+    }
+
     /// Returns an error if two header lines have the same content.
-    fn check_for_duplicate_headers(&self) -> Result<(), Error> {
+    ///
+    /// If `ignore_case` is `true`, headers are lowercased before being
+    /// compared.
+    fn check_for_duplicate_headers(&self, ignore_case: bool) -> Result<(), Error> {
         let mut seen_headers = HashMap::new();
         let mut loc = ErrorLocation::new(self.filename);
         for line in &self.lines {
@@ -133,7 +544,12 @@ impl<'a> ScenarioFile<'a> {
             // from the header line's content, the current line number
             // and the line number of the previous occurrence.
             if let Some(header) = line.as_header() {
-                match seen_headers.entry(header) {
+                let key = if ignore_case {
+                    Cow::Owned(header.to_lowercase())
+                } else {
+                    Cow::Borrowed(header)
+                };
+                match seen_headers.entry(key) {
                     Entry::Vacant(entry) => {
                         entry.insert(loc.lineno);
                     },
@@ -162,14 +578,69 @@ impl<'a> ScenarioFile<'a> {
         self.filename
     }
 
+    /// Returns the line number in the source of `self.lines[index]`.
+    fn lineno(&self, index: usize) -> usize {
+        match self.linenos.get(index) {
+            Some(&lineno) => lineno,
+            None => index + 1,
+        }
+    }
+
     /// Returns an iterator that creates [`Scenario`]s from the file.
     ///
     /// [`Scenario`]: ./struct.Scenario.html
-    pub fn iter(&self) -> ScenariosIter {
+    pub fn iter(&self) -> ScenariosIter<'_> {
         ScenariosIter::new(self.filename, &self.lines)
     }
 }
 
+/// Returns an error if two files define a scenario of the same name.
+///
+/// This only compares scenarios of different files; duplicates within
+/// a single file are found when reading it in strict mode. The header
+/// of the defaults section is not checked, nor are the names of
+/// numbered TSV rows. If `ignore_case` is `true`, names are lowercased
+/// before being compared.
+///
+/// # Errors
+/// The error contains the locations of both headers, the earlier one
+/// first.
+pub fn check_for_shared_names(files: &[ScenarioFile], ignore_case: bool) -> Result<(), Error> {
+    let mut seen_headers = HashMap::new();
+    for (file_index, file) in files.iter().enumerate() {
+        if file.has_numbered_names {
+            continue;
+        }
+        for (line_index, line) in file.lines.iter().enumerate() {
+            let loc = ErrorLocation::with_lineno(file.filename, file.lineno(line_index));
+            let header = match line.as_header() {
+                Some(DEFAULTS_HEADER) | None => continue,
+                Some(header) => header,
+            };
+            let key = if ignore_case {
+                Cow::Owned(header.to_lowercase())
+            } else {
+                Cow::Borrowed(header)
+            };
+            match seen_headers.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert((file_index, loc));
+                },
+                Entry::Occupied(ref entry) if entry.get().0 == file_index => {},
+                Entry::Occupied(entry) => {
+                    let prev_loc = entry.get().1;
+                    let err = DuplicateScenarioName(header.to_owned())
+                        .context(loc.to_owned())
+                        .context(prev_loc.to_owned())
+                        .into();
+                    return Err(err);
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
 impl<'a, 'b: 'a> IntoIterator for &'a ScenarioFile<'b> {
     type IntoIter = ScenariosIter<'a>;
     type Item = <Self::IntoIter as Iterator>::Item;
@@ -180,21 +651,55 @@ impl<'a, 'b: 'a> IntoIterator for &'a ScenarioFile<'b> {
 }
 
 
+/// The header of the section that holds defaults for a whole file.
+const DEFAULTS_HEADER: &str = "*";
+
+/// The byte order mark that some editors put at the start of a file.
+const BYTE_ORDER_MARK: char = '\u{FEFF}';
+
+
 /// An iterator that reads [`Scenario`]s from a [`ScenarioFile`].
 ///
+/// If the first section of the file has the header `[*]`, it does not
+/// produce a scenario. Instead, its lines are added to every scenario
+/// in the file, unless the scenario itself defines, removes or appends
+/// to the same variable. See [`Scenario::add_defaults()`].
+///
 /// [`Scenario`]: ./struct.Scenario.html
 /// [`ScenarioFile`]: ./struct.ScenarioFile.html
+/// [`Scenario::add_defaults()`]: ./struct.Scenario.html#method.add_defaults
 #[derive(Debug, Clone)]
 pub struct ScenariosIter<'a> {
     location: ErrorLocation<&'a Path>,
     lines: &'a [InputLine],
+    defaults: Option<Scenario<'a>>,
 }
 
 impl<'a> ScenariosIter<'a> {
     /// Creates a new instance.
     fn new(filename: &'a Path, lines: &'a [InputLine]) -> Self {
         let location = ErrorLocation::new(filename);
-        ScenariosIter { location, lines }
+        ScenariosIter {
+            location,
+            lines,
+            defaults: None,
+        }
+    }
+
+    /// Returns the name of the next scenario without consuming it.
+    ///
+    /// This only looks for the next header line; the scenario itself
+    /// is not built. Hence, even if this returns a name, the next call
+    /// to [`next()`] may still fail. This returns `None` if there are
+    /// no more scenarios.
+    ///
+    /// [`next()`]: #method.next
+    pub fn peek_name(&self) -> Option<&'a str> {
+        self.lines
+            .iter()
+            .skip(self.location.lineno)
+            .filter_map(InputLine::as_header)
+            .find(|&header| header != DEFAULTS_HEADER)
     }
 
     /// Continue parsing the file until the next header line or EOF.
@@ -203,24 +708,56 @@ impl<'a> ScenariosIter<'a> {
     /// header line. It is private and merely a convenience helper for
     /// [`next()`].
     ///
+    /// If the header line is `[*]`, the section is read as the
+    /// defaults of this file and the next scenario is returned.
+    ///
     /// # Errors
-    /// This may fail either with a [`ScenarioError`] or an
-    /// [`UnexpectedVarDef`].
+    /// This may fail with a [`ScenarioError`], an
+    /// [`UnexpectedVarDef`], or a [`MisplacedDefaults`].
     ///
     /// [`next()`]: #method.next
     /// [`ScenarioError`]: ./enum.ScenarioError.html
     /// [`UnexpectedVarDef`]: ./struct.UnexpectedVarDef.html
+    /// [`MisplacedDefaults`]: ./struct.MisplacedDefaults.html
     fn next_scenario(&mut self) -> Result<Option<Scenario<'a>>, Error> {
-        let mut scenario = match self.next_header_line()? {
+        let mut header = self.next_header_line()?;
+        if header == Some(DEFAULTS_HEADER) {
+            let previous_lines = &self.lines[..self.location.lineno - 1];
+            if previous_lines.iter().any(InputLine::is_header) {
+                return Err(MisplacedDefaults.into());
+            }
+            let mut defaults = Scenario::new(DEFAULTS_HEADER)?;
+            self.read_section(&mut defaults)?;
+            self.defaults = Some(defaults);
+            header = self.next_header_line()?;
+        }
+        let mut scenario = match header {
             Some(line) => Scenario::new(line)?,
             None => return Ok(None),
         };
-        while let Some((name, value)) = self.next_definition_line() {
-            scenario.add_variable(name, value)?;
+        self.read_section(&mut scenario)?;
+        if let Some(ref defaults) = self.defaults {
+            scenario.add_defaults(defaults);
         }
         Ok(Some(scenario))
     }
 
+    /// Adds all lines up to the next header line to `scenario`.
+    fn read_section(&mut self, scenario: &mut Scenario<'a>) -> Result<(), ScenarioError> {
+        while let Some(line) = self.next_definition_line() {
+            if let Some((name, value)) = line.as_definition() {
+                scenario.add_variable(name, value)?;
+            } else if let Some((name, value)) = line.as_append() {
+                scenario.add_append(name, value)?;
+            } else if let Some(name) = line.as_removal() {
+                scenario.add_removal(name)?;
+            } else if let Some(tag) = line.as_tag() {
+                scenario.add_tag(tag)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Fetches the next header line, skipping over comments.
     ///
     /// # Errors
@@ -233,28 +770,31 @@ impl<'a> ScenariosIter<'a> {
             self.location.lineno += 1;
             if let Some(header) = line.as_header() {
                 return Ok(Some(header));
-            } else if let Some((name, _)) = line.as_definition() {
+            } else if let Some((name, _)) = line.as_definition().or_else(|| line.as_append()) {
+                return Err(UnexpectedVarDef(name.to_owned()));
+            } else if let Some(name) = line.as_removal() {
                 return Err(UnexpectedVarDef(name.to_owned()));
+            } else if line.is_tag() {
+                return Err(UnexpectedVarDef("@tag".to_owned()));
             }
         }
         Ok(None)
     }
 
-    /// Fetches the next definition line.
+    /// Fetches the next definition or removal line.
     ///
     /// Comment lines are skipped over. This returns `None` if the
     /// end-of-file is reached or a header line is found. (The header
-    /// line is *not* extracted!) Otherwise, the split variable
-    /// definition is returned.
-    fn next_definition_line(&mut self) -> Option<(&'a str, &'a str)> {
+    /// line is *not* extracted!) Otherwise, the line is returned.
+    fn next_definition_line(&mut self) -> Option<&'a InputLine> {
         while let Some(line) = self.lines.get(self.location.lineno) {
             if line.is_header() {
                 // Leave *without* moving to the next line.
                 break;
             } else {
                 self.location.lineno += 1;
-                if let Some(parts) = line.as_definition() {
-                    return Some(parts);
+                if !line.is_comment() {
+                    return Some(line);
                 }
             }
         }
@@ -295,7 +835,8 @@ impl<'a> ExactSizeIterator for ScenariosIter<'a> {
         self.lines
             .iter()
             .skip(self.location.lineno)
-            .filter(|line| line.is_header())
+            .filter_map(InputLine::as_header)
+            .filter(|&header| header != DEFAULTS_HEADER)
             .count()
     }
 }
@@ -303,6 +844,363 @@ impl<'a> ExactSizeIterator for ScenariosIter<'a> {
 impl<'a> FusedIterator for ScenariosIter<'a> {}
 
 
+/// Reads the body of a heredoc up to and including its terminator.
+///
+/// The terminator is the first line that equals `marker`, ignoring
+/// surrounding whitespace. All lines before it are taken verbatim,
+/// except for the line break directly before the terminator. This
+/// returns the value and the number of lines read.
+fn read_heredoc<F: BufRead>(reader: &mut F, marker: &str) -> Result<(String, usize), Error> {
+    let mut value = String::new();
+    let mut line = String::new();
+    let mut num_lines = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(UnterminatedHeredoc(marker.to_owned()).into());
+        }
+        num_lines += 1;
+        if line.trim() == marker {
+            break;
+        }
+        value.push_str(&line);
+    }
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok((value, num_lines))
+}
+
+
+/// Turns NUL-separated records into a list of input lines.
+///
+/// Each field becomes one input line, so that errors in later stages
+/// can report the number of the offending field.
+fn parse_nul_records(buffer: &[u8]) -> Result<Vec<InputLine>, NulFormatError> {
+    if !buffer.is_empty() && !buffer.ends_with(b"\0") {
+        return Err(NulFormatError::MissingTerminator(buffer.len()));
+    }
+    let mut lines = Vec::new();
+    let mut in_record = false;
+    let mut offset = 0;
+    // The slice after the final NUL byte is always empty, so skip it.
+    let num_fields = buffer.split(|&b| b == 0).count() - 1;
+    for field in buffer.split(|&b| b == 0).take(num_fields) {
+        let text = str::from_utf8(field)
+            .map_err(|err| NulFormatError::NotUtf8(offset + err.valid_up_to()))?;
+        let line = if !in_record {
+            if text.is_empty() {
+                return Err(NulFormatError::EmptyName(offset));
+            }
+            in_record = true;
+            InputLine::new_header(text)
+        } else if text.is_empty() {
+            in_record = false;
+            InputLine::new_comment()
+        } else {
+            let eq_pos = text
+                .find('=')
+                .ok_or_else(|| NulFormatError::NotAVarDef(offset, text.to_owned()))?;
+            let (name, value) = (text[..eq_pos].trim(), text[eq_pos + 1..].trim());
+            if name.is_empty() || name == "+" {
+                return Err(NulFormatError::MissingVariableName(offset, text.to_owned()));
+            }
+            InputLine::new_definition(name, value)
+        };
+        lines.push(line);
+        offset += field.len() + 1;
+    }
+    if in_record {
+        return Err(NulFormatError::UnterminatedRecord(buffer.len()));
+    }
+    Ok(lines)
+}
+
+
+/// The file name used for scenarios defined inline.
+const INLINE_FILENAME: &str = "<command line>";
+
+
+/// Turns an inline scenario definition into a list of input lines.
+///
+/// See [`ScenarioFile::from_inline()`] for the syntax.
+///
+/// [`ScenarioFile::from_inline()`]: ./struct.ScenarioFile.html#method.from_inline
+fn parse_inline(spec: &str) -> Result<Vec<InputLine>, InlineFormatError> {
+    // The first field is the name, all others are definitions.
+    let mut fields = vec![String::new()];
+    let mut in_name = true;
+    let mut chars = spec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ '\\') | Some(c @ ',') | Some(c @ ':') => {
+                    fields.last_mut().expect("at least one field").push(c)
+                },
+                _ => return Err(InlineFormatError::BadEscape(spec.to_owned())),
+            },
+            ':' if in_name => {
+                in_name = false;
+                fields.push(String::new());
+            },
+            ',' if !in_name => fields.push(String::new()),
+            c => fields.last_mut().expect("at least one field").push(c),
+        }
+    }
+    // Allow `NAME:` as a scenario without variables.
+    if fields.len() == 2 && fields[1].trim().is_empty() {
+        fields.pop();
+    }
+    let mut fields = fields.into_iter();
+    let name = fields.next().expect("at least one field");
+    if name.is_empty() {
+        return Err(InlineFormatError::EmptyName(spec.to_owned()));
+    }
+    let mut lines = vec![InputLine::new_header(&name)];
+    for field in fields {
+        let eq_pos = field
+            .find('=')
+            .ok_or_else(|| InlineFormatError::NotAVarDef(field.clone()))?;
+        let (var, value) = (field[..eq_pos].trim(), field[eq_pos + 1..].trim());
+        if var.is_empty() || var == "+" {
+            return Err(InlineFormatError::MissingVariableName(field.clone()));
+        }
+        lines.push(InputLine::new_definition(var, value));
+    }
+    Ok(lines)
+}
+
+
+/// Returns an error if `name` doesn't match `pattern`.
+fn check_name_pattern(
+    name: &str,
+    pattern: &Pattern,
+    ignore_case: bool,
+) -> Result<(), NameMismatch> {
+    let options = MatchOptions {
+        case_sensitive: !ignore_case,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+    if pattern.matches_with(name, &options) {
+        Ok(())
+    } else {
+        Err(NameMismatch(name.to_owned(), pattern.as_str().to_owned()))
+    }
+}
+
+
+/// The name of the TSV column that holds scenario names.
+const TSV_NAME_COLUMN: &str = "SCENARIOS_NAME";
+
+
+/// Turns tab-separated values into a scenario file.
+///
+/// Each row becomes a header line followed by one definition line per
+/// column. Because of this, the input lines don't correspond to the
+/// lines in the file; the file remembers the row of each input line
+/// instead. All errors are reported here, with the correct line
+/// number, so that iterating over the lines never fails.
+fn parse_tsv<'a>(
+    contents: &str,
+    filename: &'a Path,
+    opts: Options,
+) -> Result<ScenarioFile<'a>, Error> {
+    let contents = contents.trim_start_matches(BYTE_ORDER_MARK);
+    let mut rows = contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|&(_, line)| !line.is_empty());
+    let (header_lineno, header) = match rows.next() {
+        Some(header) => header,
+        None => return Ok(ScenarioFile::with_lines(filename, Vec::new())),
+    };
+    let columns = parse_tsv_header(header)
+        .with_context(|_| ErrorLocation::with_lineno(filename.to_owned(), header_lineno))?;
+    let name_column = columns.iter().position(|column| column == TSV_NAME_COLUMN);
+    let mut lines = Vec::new();
+    let mut linenos = Vec::new();
+    let mut seen_names = HashMap::new();
+    for (num_rows, (lineno, row)) in rows.enumerate() {
+        let loc = ErrorLocation::with_lineno(filename.to_owned(), lineno);
+        let fields = row
+            .split('\t')
+            .map(unescape_tsv_field)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|_| loc.clone())?;
+        if fields.len() != columns.len() {
+            let err = TsvFormatError::WrongNumberOfFields(columns.len(), fields.len());
+            return Err(err.context(loc).into());
+        }
+        let name = match name_column {
+            Some(index) => fields[index].clone(),
+            None => (num_rows + 1).to_string(),
+        };
+        if name.is_empty() {
+            return Err(TsvFormatError::EmptyName.context(loc).into());
+        }
+        if let Some(pattern) = opts.name_pattern {
+            check_name_pattern(&name, pattern, opts.case_insensitive_names)
+                .with_context(|_| loc.clone())?;
+        }
+        if opts.is_strict {
+            let key = if opts.case_insensitive_names {
+                name.to_lowercase()
+            } else {
+                name.clone()
+            };
+            if let Some(&prev_lineno) = seen_names.get(&key) {
+                let prev_loc = ErrorLocation::with_lineno(filename.to_owned(), prev_lineno);
+                let err = DuplicateScenarioName(name).context(loc).context(prev_loc);
+                return Err(err.into());
+            }
+            seen_names.insert(key, lineno);
+        }
+        lines.push(InputLine::new_header(&name));
+        linenos.push(lineno);
+        for (index, (column, value)) in columns.iter().zip(&fields).enumerate() {
+            if Some(index) != name_column {
+                lines.push(InputLine::new_definition(column, value));
+                linenos.push(lineno);
+            }
+        }
+    }
+    Ok(ScenarioFile {
+        filename,
+        lines,
+        linenos,
+        has_numbered_names: name_column.is_none(),
+    })
+}
+
+
+/// Splits the header line of a TSV file into checked column titles.
+fn parse_tsv_header(header: &str) -> Result<Vec<String>, TsvFormatError> {
+    let mut columns: Vec<String> = Vec::new();
+    for column in header.split('\t') {
+        let column = unescape_tsv_field(column)?;
+        if !scenario::is_c_identifier(&column) {
+            return Err(TsvFormatError::InvalidColumn(column));
+        } else if columns.contains(&column) {
+            return Err(TsvFormatError::DuplicateColumn(column));
+        }
+        columns.push(column);
+    }
+    Ok(columns)
+}
+
+
+/// Like `BufRead::read_line()`, but keeps invalid UTF-8 as raw bytes.
+///
+/// See [`trytostr::from_utf8_with_raw_bytes()`] for how raw bytes are
+/// represented.
+///
+/// [`trytostr::from_utf8_with_raw_bytes()`]:
+/// ../trytostr/fn.from_utf8_with_raw_bytes.html
+fn read_line_with_raw_bytes<R>(reader: &mut R, buffer: &mut String) -> Result<usize, Error>
+where
+    R: BufRead,
+{
+    let mut bytes = Vec::new();
+    let num_bytes = reader.read_until(b'\n', &mut bytes)?;
+    buffer.push_str(&trytostr::from_utf8_with_raw_bytes(&bytes)?);
+    Ok(num_bytes)
+}
+
+
+/// Replaces the escape sequences in a TSV field.
+///
+/// See [`Format::Tsv`] for the recognized escape sequences.
+///
+/// [`Format::Tsv`]: ./enum.Format.html#variant.Tsv
+fn unescape_tsv_field(field: &str) -> Result<String, TsvFormatError> {
+    let mut result = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            _ => return Err(TsvFormatError::BadEscape(field.to_owned())),
+        }
+    }
+    Ok(result)
+}
+
+
+/// The error returned if the value of a variable cannot be read from
+/// a file.
+#[derive(Debug, Fail)]
+#[fail(
+    display = "could not read the value of \"{}\" in scenario \"{}\" from {:?}",
+    variable, scenario, path
+)]
+pub struct FileValueError {
+    scenario: String,
+    variable: String,
+    path: PathBuf,
+}
+
+
+/// The error returned for malformed tab-separated values.
+#[derive(Debug, Fail)]
+pub enum TsvFormatError {
+    #[fail(display = "column title is not a valid variable name: {:?}", _0)]
+    InvalidColumn(String),
+    #[fail(display = "duplicate column title: {:?}", _0)]
+    DuplicateColumn(String),
+    #[fail(display = "expected {} fields, found {}", _0, _1)]
+    WrongNumberOfFields(usize, usize),
+    #[fail(display = "empty scenario name")]
+    EmptyName,
+    #[fail(display = "invalid escape sequence in field: {:?}", _0)]
+    BadEscape(String),
+}
+
+
+/// The error returned for malformed inline scenario definitions.
+#[derive(Debug, Fail)]
+pub enum InlineFormatError {
+    #[fail(display = "empty scenario name: {:?}", _0)]
+    EmptyName(String),
+    #[fail(display = "invalid escape sequence: {:?}", _0)]
+    BadEscape(String),
+    #[fail(display = "no variable name before \"=\": {:?}", _0)]
+    MissingVariableName(String),
+    #[fail(display = "no equals sign \"=\" in variable definition: {:?}", _0)]
+    NotAVarDef(String),
+}
+
+
+/// The error returned for malformed NUL-separated records.
+///
+/// Each variant contains the byte offset at which the problem occurs.
+#[derive(Debug, Fail)]
+pub enum NulFormatError {
+    #[fail(display = "at byte {}: input does not end with a NUL byte", _0)]
+    MissingTerminator(usize),
+    #[fail(display = "at byte {}: last record does not end with an empty field", _0)]
+    UnterminatedRecord(usize),
+    #[fail(display = "at byte {}: empty scenario name", _0)]
+    EmptyName(usize),
+    #[fail(display = "at byte {}: no variable name before \"=\": {:?}", _0, _1)]
+    MissingVariableName(usize, String),
+    #[fail(display = "at byte {}: no equals sign \"=\" in variable definition: {:?}", _0, _1)]
+    NotAVarDef(usize, String),
+    #[fail(display = "at byte {}: invalid UTF-8", _0)]
+    NotUtf8(usize),
+}
+
+
 /// The error returned for unexpected variable definitions.
 ///
 /// A variable definition is unexpected if it appears in the scenario
@@ -313,25 +1211,85 @@ impl<'a> FusedIterator for ScenariosIter<'a> {}
 pub struct UnexpectedVarDef(String);
 
 
+/// The error returned if the `[*]` section is not the first section.
+#[derive(Debug, Fail)]
+#[fail(display = "the section \"[*]\" must come before all scenarios")]
+pub struct MisplacedDefaults;
+
+
+/// The error returned if a heredoc has no terminator.
+#[derive(Debug, Fail)]
+#[fail(display = "heredoc is not terminated by a line \"{}\"", _0)]
+pub struct UnterminatedHeredoc(String);
+
+
 /// The error returned if two scenarios share the same name.
 #[derive(Debug, Fail)]
 #[fail(display = "duplicate scenario name: \"{}\"", _0)]
 pub struct DuplicateScenarioName(String);
 
 
+/// The error returned if a scenario name doesn't match the required
+/// pattern.
+#[derive(Debug, Fail)]
+#[fail(display = "scenario name \"{}\" does not match the pattern \"{}\"", _0, _1)]
+pub struct NameMismatch(String, String);
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use std::{collections::HashSet, io::Cursor};
 
+    #[test]
+    fn test_shared_names() {
+        let opts = Options::default();
+        let files = vec![
+            ScenarioFile::from_str("[a]\n[b]\n", "first.ini", opts).unwrap(),
+            ScenarioFile::from_str("[*]\n[c]\n[B]\n", "second.ini", opts).unwrap(),
+        ];
+        assert!(check_for_shared_names(&files, false).is_ok());
+        let err = check_for_shared_names(&files, true).unwrap_err();
+        let messages = err.causes().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            ["in first.ini:2", "in second.ini:3", "duplicate scenario name: \"B\""]
+        );
+        // Duplicates within one file are not checked here.
+        let lax = Options {
+            is_strict: false,
+            ..opts
+        };
+        let file = ScenarioFile::from_str("[c]\n[c]\n", "third.ini", lax).unwrap();
+        assert!(check_for_shared_names(&[file], false).is_ok());
+        // TSV files report the row of a name, not its input line.
+        let tsv = &b"SCENARIOS_NAME\tx\nb\t1\n\nc\t2\n"[..];
+        let tsv = ScenarioFile::from_tsv_reader(tsv, "fourth.tsv", opts).unwrap();
+        let files = vec![files.into_iter().nth(1).unwrap(), tsv];
+        let err = check_for_shared_names(&files, false).unwrap_err();
+        let messages = err.causes().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            ["in second.ini:2", "in fourth.tsv:4", "duplicate scenario name: \"c\""]
+        );
+        // Numbered rows never clash.
+        let numbered = || ScenarioFile::from_tsv_reader(&b"x\n1\n"[..], "fifth.tsv", opts);
+        let files = vec![numbered().unwrap(), numbered().unwrap()];
+        assert!(check_for_shared_names(&files, false).is_ok());
+    }
+
 
-    fn get_scenarios(contents: &str) -> Result<ScenarioFile, Error> {
-        ScenarioFile::new(Cursor::new(contents), Path::new("<memory>"), true)
+    fn get_scenarios(contents: &str) -> Result<ScenarioFile<'static>, Error> {
+        ScenarioFile::from_str(contents, "<memory>", Options::default())
     }
 
-    fn get_scenarios_lax(contents: &str) -> Result<ScenarioFile, Error> {
-        ScenarioFile::new(Cursor::new(contents), Path::new("<memory>"), false)
+    fn get_scenarios_lax(contents: &str) -> Result<ScenarioFile<'static>, Error> {
+        let opts = Options {
+            is_strict: false,
+            ..Options::default()
+        };
+        ScenarioFile::from_str(contents, "<memory>", opts)
     }
 
     fn assert_vars(s: &Scenario, variables: &[(&str, &str)]) {
@@ -372,20 +1330,242 @@ mod tests {
         let the_scenario = scenarios.next().unwrap();
         let the_variables = [("aaaa", "1"), ("bbbb", "8"), ("cdcd", "complicated value")];
         assert_eq!(the_scenario.name(), "First Scenario");
-        assert_vars(&the_scenario, &the_variables);
+        assert_vars(the_scenario, &the_variables);
 
         let the_scenario = scenarios.next().unwrap();
         let the_variables = [("aaaa", "8"), ("bbbb", "1"), ("cdcd", "lesscomplicated")];
         assert_eq!(the_scenario.name(), "Second Scenario");
-        assert_vars(&the_scenario, &the_variables);
+        assert_vars(the_scenario, &the_variables);
 
         let the_scenario = scenarios.next().unwrap();
         assert_eq!(the_scenario.name(), "Third Scenario");
-        assert_vars(&the_scenario, &[]);
+        assert_vars(the_scenario, &[]);
 
         assert!(scenarios.next().is_none());
     }
 
+    #[test]
+    fn test_file_values() {
+        let contents = "[Scenario]\nfile = @one_empty.ini\n[Other]\nmissing = @nope.txt\n";
+        let opts = Options {
+            allow_file_values: true,
+            ..Options::default()
+        };
+        let err = ScenarioFile::from_str(contents, "tests/virtual.ini", opts).unwrap_err();
+        let causes: Vec<String> = err.causes().map(ToString::to_string).collect();
+        assert_eq!(causes[0], "in tests/virtual.ini:4");
+        assert_eq!(
+            causes[1],
+            "could not read the value of \"missing\" in scenario \"Other\" \
+             from \"tests/nope.txt\""
+        );
+        let contents = "[Scenario]\nfile = @one_empty.ini\nplain = value\n";
+        let file = ScenarioFile::from_str(contents, "tests/virtual.ini", opts).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_vars(&scenarios[0], &[("file", "[Empty]\n"), ("plain", "value")]);
+        // Without the option, values are taken literally.
+        let opts = Options::default();
+        let file = ScenarioFile::from_str(contents, "tests/virtual.ini", opts).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_vars(&scenarios[0], &[("file", "@one_empty.ini"), ("plain", "value")]);
+    }
+
+    #[test]
+    fn test_non_utf8_values() {
+        let contents = &b"[Latin-1]\nword = caf\xe9\n"[..];
+        assert!(ScenarioFile::from_reader(contents, "<memory>", Options::default()).is_err());
+        let opts = Options {
+            allow_non_utf8_values: true,
+            ..Options::default()
+        };
+        let file = ScenarioFile::from_reader(contents, "<memory>", opts).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let word = scenarios[0].get_variable("word").unwrap();
+        assert!(word.starts_with("caf"));
+        assert!(trytostr::has_raw_bytes(word));
+        // Scenario names must still be valid UTF-8.
+        let contents = &b"[caf\xe9]\n"[..];
+        let err = ScenarioFile::from_reader(contents, "<memory>", opts).unwrap_err();
+        let causes: Vec<String> = err.causes().map(ToString::to_string).collect();
+        assert_eq!(
+            causes,
+            ["in <memory>:1", "contains invalid UTF-8 character: \"[caf\u{FFFD}]\n\""]
+        );
+    }
+
+    #[test]
+    fn test_nul_records() {
+        let contents = "First\0a=1\0b = two\nlines\0 c =  3 \0\0Second\0 a += x \0\0Empty\0\0";
+        let opts = Options::default();
+        let file = ScenarioFile::from_nul_reader(contents.as_bytes(), "<memory>", opts).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let names: Vec<&str> = scenarios.iter().map(Scenario::name).collect();
+        assert_eq!(names, ["First", "Second", "Empty"]);
+        assert_vars(&scenarios[0], &[("a", "1"), ("b", "two\nlines"), ("c", "3")]);
+        assert_eq!(scenarios[1].get_appends("a"), Some(&["x"][..]));
+        assert_vars(&scenarios[2], &[]);
+        // Empty input is allowed.
+        let file = ScenarioFile::from_nul_reader(&b""[..], "<memory>", Options::default()).unwrap();
+        assert_eq!(file.iter().len(), 0);
+    }
+
+    #[test]
+    fn test_inline() {
+        fn get_file(spec: &str) -> ScenarioFile<'static> {
+            let file = ScenarioFile::from_inline(spec, Options::default()).unwrap();
+            assert_eq!(file.iter().len(), 1);
+            file
+        }
+        let file = get_file("First:a=1, b = two=2 ,c+=x");
+        let scenario = file.iter().next().unwrap().unwrap();
+        assert_eq!(scenario.name(), "First");
+        assert_vars(&scenario, &[("a", "1"), ("b", "two=2")]);
+        assert_eq!(scenario.get_appends("c"), Some(&["x"][..]));
+        let file = get_file(r"a\:b\\c:a=x\,y:z");
+        let scenario = file.iter().next().unwrap().unwrap();
+        assert_eq!(scenario.name(), r"a:b\c");
+        assert_vars(&scenario, &[("a", "x,y:z")]);
+        for spec in &["Empty", "Empty:"] {
+            let file = get_file(spec);
+            assert_vars(&file.iter().next().unwrap().unwrap(), &[]);
+        }
+    }
+
+    #[test]
+    fn test_bad_inline() {
+        fn err_string(spec: &str) -> String {
+            let err = ScenarioFile::from_inline(spec, Options::default()).unwrap_err();
+            err.cause().to_string()
+        }
+        assert_eq!(err_string(":a=1"), "empty scenario name: \":a=1\"");
+        assert_eq!(err_string(r"A:a=\n"), r#"invalid escape sequence: "A:a=\\n""#);
+        assert_eq!(err_string("A:=1"), "no variable name before \"=\": \"=1\"");
+        assert_eq!(
+            err_string("A:a=1,"),
+            "no equals sign \"=\" in variable definition: \"\""
+        );
+    }
+
+    #[test]
+    fn test_name_pattern() {
+        let contents = "[*]\n[test_a]\n[Test_B]\n[other]\n";
+        let pattern = Pattern::new("test_*").unwrap();
+        let opts = Options {
+            name_pattern: Some(&pattern),
+            ..Options::default()
+        };
+        let err = ScenarioFile::from_str(contents, "<memory>", opts).unwrap_err();
+        let causes: Vec<String> = err.causes().map(ToString::to_string).collect();
+        assert_eq!(
+            causes,
+            ["in <memory>:3", "scenario name \"Test_B\" does not match the pattern \"test_*\""]
+        );
+        let opts = Options {
+            case_insensitive_names: true,
+            ..opts
+        };
+        let err = ScenarioFile::from_str(contents, "<memory>", opts).unwrap_err();
+        assert_eq!(err.cause().to_string(), "in <memory>:4");
+        let contents = "SCENARIOS_NAME\ntest_a\nother\n";
+        let err = ScenarioFile::from_tsv_reader(contents.as_bytes(), "<memory>", opts).unwrap_err();
+        assert_eq!(err.cause().to_string(), "in <memory>:3");
+    }
+
+    #[test]
+    fn test_crlf_and_bom() {
+        let file = "\u{FEFF}[first]\r\na = 1\r\n\r\n[second]\r\nb = 2\r\n";
+        let file = get_scenarios(file).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let names: Vec<&str> = scenarios.iter().map(Scenario::name).collect();
+        assert_eq!(names, ["first", "second"]);
+        assert_vars(&scenarios[0], &[("a", "1")]);
+        assert_vars(&scenarios[1], &[("b", "2")]);
+        // A BOM anywhere else is not special.
+        assert!(get_scenarios("[first]\n\u{FEFF}[second]\n").is_err());
+        let contents = "\u{FEFF}SCENARIOS_NAME\ta\r\nrow\t1\r\n";
+        let opts = Options::default();
+        let file = ScenarioFile::from_tsv_reader(contents.as_bytes(), "<memory>", opts).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(scenarios[0].name(), "row");
+        assert_vars(&scenarios[0], &[("a", "1")]);
+    }
+
+    #[test]
+    fn test_tsv() {
+        let contents = "a\tSCENARIOS_NAME\tb\n1\tFirst\ttab\\there\n\n2\tSecond\t\n";
+        let opts = Options::default();
+        let file = ScenarioFile::from_tsv_reader(contents.as_bytes(), "<memory>", opts).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let names: Vec<&str> = scenarios.iter().map(Scenario::name).collect();
+        assert_eq!(names, ["First", "Second"]);
+        assert_vars(&scenarios[0], &[("a", "1"), ("b", "tab\there")]);
+        assert_vars(&scenarios[1], &[("a", "2"), ("b", "")]);
+        // Without a name column, rows are numbered.
+        let contents = "a\nx\ny\n";
+        let file = ScenarioFile::from_tsv_reader(contents.as_bytes(), "<memory>", opts).unwrap();
+        let names: Vec<String> = file.iter().map(|s| s.unwrap().name().to_owned()).collect();
+        assert_eq!(names, ["1", "2"]);
+        // Empty input is allowed.
+        let file = ScenarioFile::from_tsv_reader(&b""[..], "<memory>", opts).unwrap();
+        assert_eq!(file.iter().len(), 0);
+    }
+
+    #[test]
+    fn test_bad_tsv() {
+        fn err_string(contents: &str) -> String {
+            let opts = Options::default();
+            let err = ScenarioFile::from_tsv_reader(contents.as_bytes(), "<memory>", opts)
+                .unwrap_err();
+            let causes: Vec<String> = err.causes().map(ToString::to_string).collect();
+            causes.join(": ")
+        }
+        assert_eq!(
+            err_string("a\tb-c\n"),
+            "in <memory>:1: column title is not a valid variable name: \"b-c\""
+        );
+        assert_eq!(err_string("a\ta\n"), "in <memory>:1: duplicate column title: \"a\"");
+        assert_eq!(err_string("a\tb\n1\n"), "in <memory>:2: expected 2 fields, found 1");
+        assert_eq!(
+            err_string("a\n\\x\n"),
+            "in <memory>:2: invalid escape sequence in field: \"\\\\x\""
+        );
+        assert_eq!(
+            err_string("SCENARIOS_NAME\nx\n\nx\n"),
+            "in <memory>:2: in <memory>:4: duplicate scenario name: \"x\""
+        );
+        assert_eq!(
+            err_string("SCENARIOS_NAME\n\n\t\n"),
+            "in <memory>:3: expected 1 fields, found 2"
+        );
+    }
+
+    #[test]
+    fn test_bad_nul_records() {
+        fn err_string(contents: &[u8]) -> String {
+            let err = ScenarioFile::from_nul_reader(contents, "<memory>", Options::default())
+                .unwrap_err();
+            err.cause().cause().unwrap().to_string()
+        }
+        assert_eq!(
+            err_string(b"A\0a=1"),
+            "at byte 5: input does not end with a NUL byte"
+        );
+        assert_eq!(
+            err_string(b"A\0a=1\0"),
+            "at byte 6: last record does not end with an empty field"
+        );
+        assert_eq!(err_string(b"A\0\0\0"), "at byte 3: empty scenario name");
+        assert_eq!(
+            err_string(b"A\0=1\0\0"),
+            "at byte 2: no variable name before \"=\": \"=1\""
+        );
+        assert_eq!(
+            err_string(b"A\0a\0\0"),
+            "at byte 2: no equals sign \"=\" in variable definition: \"a\""
+        );
+        assert_eq!(err_string(b"A\0a=\xFF\0\0"), "at byte 4: invalid UTF-8");
+    }
+
     #[test]
     fn test_non_unique_names() {
         let err = get_scenarios("[first]\n[second]\n\n[third]\n[second]").unwrap_err();
@@ -405,6 +1585,45 @@ mod tests {
         assert_eq!(names, ["first", "second", "third", "second"]);
     }
 
+    #[test]
+    fn test_non_unique_names_case_sensitive() {
+        let file = get_scenarios("[Build]\n[build]\n").unwrap();
+        assert_eq!(file.iter().len(), 2);
+    }
+
+    #[test]
+    fn test_non_unique_names_ignore_case() {
+        let opts = Options {
+            case_insensitive_names: true,
+            ..Options::default()
+        };
+        let contents = Cursor::new("[Build]\n[Test]\n[build]\n");
+        let err = ScenarioFile::from_reader(contents, "<memory>", opts).unwrap_err();
+        let mut err = err.cause();
+        assert_eq!(err.to_string(), "in <memory>:1");
+        err = err.cause().unwrap();
+        assert_eq!(err.to_string(), "in <memory>:3");
+        err = err.cause().unwrap();
+        assert_eq!(err.to_string(), "duplicate scenario name: \"build\"");
+    }
+
+    #[test]
+    fn test_inline_comments() {
+        let contents = "[scenario # not a comment]\na = b # comment\nc = \\#d\n";
+        let file = get_scenarios(contents).unwrap();
+        let s = file.iter().next().unwrap().unwrap();
+        assert_eq!(s.name(), "scenario # not a comment");
+        assert_vars(&s, &[("a", "b # comment"), ("c", "\\#d")]);
+        let opts = Options {
+            allow_inline_comments: true,
+            ..Options::default()
+        };
+        let file = ScenarioFile::from_str(contents, "<memory>", opts).unwrap();
+        let s = file.iter().next().unwrap().unwrap();
+        assert_eq!(s.name(), "scenario # not a comment");
+        assert_vars(&s, &[("a", "b"), ("c", "#d")]);
+    }
+
     #[test]
     fn test_invalid_variable_def() {
         let err = get_scenarios("[scenario]\nthe bad line").unwrap_err();
@@ -424,7 +1643,124 @@ mod tests {
         let mut err = err.cause();
         assert_eq!(err.to_string(), "in <memory>:3");
         err = err.cause().unwrap();
-        assert_eq!(err.to_string(), "variable already defined: \"a\"");
+        assert_eq!(
+            err.to_string(),
+            "variable already defined: \"a\": \"a = b\" conflicts with \"a = c\""
+        );
+    }
+
+    #[test]
+    fn test_removal() {
+        let file = get_scenarios("[scenario]\na = b\n!c\n").unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_vars(&scenarios[0], &[("a", "b")]);
+        assert!(scenarios[0].has_removal("c"));
+    }
+
+    #[test]
+    fn test_removal_of_defined_variable() {
+        let file = get_scenarios("[scenario]\na = b\n!a\n").unwrap();
+        let err = file.iter().collect::<Result<Vec<_>, _>>().unwrap_err();
+        let mut err = err.cause();
+        assert_eq!(err.to_string(), "in <memory>:3");
+        err = err.cause().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "variable already defined: \"a\": \"a = b\" conflicts with \"!a\""
+        );
+    }
+
+    #[test]
+    fn test_heredoc() {
+        let file = "[scenario]\nscript <<END\n  if true; then\n    echo \"a = b\"\n  fi\n  END\n\
+                    json<<EOF\n{}\n\nEOF\nempty <<X\nX\nafter = 1\n!after\n";
+        let file = get_scenarios(file).unwrap();
+        let err = file.iter().collect::<Result<Vec<_>, _>>().unwrap_err();
+        // The heredocs don't throw off the line count.
+        assert_eq!(err.cause().to_string(), "in <memory>:14");
+        let file = get_scenarios("[scenario]\nscript <<END\n  a\r\n b\r\nEND\r\n").unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_vars(&scenarios[0], &[("script", "  a\r\n b")]);
+    }
+
+    #[test]
+    fn test_heredoc_values() {
+        let file = "[scenario]\nscript <<END\n  echo \"a = b\"\n[not a header]\nEND\n\
+                    json<<EOF\n{}\n\nEOF\nempty <<X\nX\n";
+        let file = get_scenarios(file).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let the_variables = [
+            ("script", "  echo \"a = b\"\n[not a header]"),
+            ("json", "{}\n"),
+            ("empty", ""),
+        ];
+        assert_vars(&scenarios[0], &the_variables);
+    }
+
+    #[test]
+    fn test_unterminated_heredoc() {
+        let err = get_scenarios("[scenario]\n\nvar <<END\nvalue\nEND OF FILE\n").unwrap_err();
+        let mut err = err.cause();
+        assert_eq!(err.to_string(), "in <memory>:3");
+        err = err.cause().unwrap();
+        assert_eq!(err.to_string(), "heredoc is not terminated by a line \"END\"");
+    }
+
+    #[test]
+    fn test_tags() {
+        let file = "[first]\n@tag = slow\na = 1\n@tag = nightly\n[second]\na = 2\n";
+        let file = get_scenarios(file).unwrap();
+        let scenarios = file.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert!(scenarios[0].has_tag("slow"));
+        assert!(scenarios[0].has_tag("nightly"));
+        assert_vars(&scenarios[0], &[("a", "1")]);
+        assert_eq!(scenarios[1].tags().count(), 0);
+        let file = get_scenarios("[first]\n@tag = two words\n").unwrap();
+        let err = file.iter().collect::<Result<Vec<_>, _>>().unwrap_err();
+        let mut err = err.cause();
+        assert_eq!(err.to_string(), "in <memory>:2");
+        err = err.cause().unwrap();
+        assert_eq!(err.to_string(), "invalid tag: \"two words\"");
+    }
+
+    #[test]
+    fn test_defaults() {
+        let file = r"
+        [*]
+        a = default
+        b = default
+        c += default
+        @tag = all
+
+        [first]
+        a = 1
+        [second]
+        c = 2
+        !b
+        ";
+        let file = get_scenarios(file).unwrap();
+        let iter = file.iter();
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.peek_name(), Some("first"));
+        let scenarios = iter.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(scenarios.len(), 2);
+        assert_vars(&scenarios[0], &[("a", "1"), ("b", "default")]);
+        assert_eq!(scenarios[0].get_appends("c"), Some(&["default"][..]));
+        assert!(scenarios[0].has_tag("all"));
+        assert_vars(&scenarios[1], &[("a", "default"), ("c", "2")]);
+        assert!(scenarios[1].has_removal("b"));
+        assert!(!scenarios[1].has_append("c"));
+        assert!(scenarios[1].has_tag("all"));
+    }
+
+    #[test]
+    fn test_misplaced_defaults() {
+        let file = get_scenarios_lax("[first]\na = 1\n[*]\na = 2\n").unwrap();
+        let err = file.iter().collect::<Result<Vec<_>, _>>().unwrap_err();
+        let mut err = err.cause();
+        assert_eq!(err.to_string(), "in <memory>:3");
+        err = err.cause().unwrap();
+        assert_eq!(err.to_string(), "the section \"[*]\" must come before all scenarios");
     }
 
     #[test]
@@ -490,4 +1826,22 @@ mod tests {
         assert_eq!(scenarios.len(), 3);
     }
 
+    #[test]
+    fn test_peek_name() {
+        let file = "# comment\n[first]\na = 1\n\n[second]\n# b = 2\n[third]";
+        let file = get_scenarios(file).unwrap();
+        let mut scenarios = file.iter();
+        for &expected in &["first", "second", "third"] {
+            let lineno = scenarios.location.lineno;
+            assert_eq!(scenarios.peek_name(), Some(expected));
+            assert_eq!(scenarios.peek_name(), Some(expected));
+            assert_eq!(scenarios.location.lineno, lineno);
+            let len = scenarios.len();
+            assert_eq!(scenarios.next().unwrap().unwrap().name(), expected);
+            assert_eq!(scenarios.len(), len - 1);
+        }
+        assert_eq!(scenarios.peek_name(), None);
+        assert!(scenarios.next().is_none());
+    }
+
 }
@@ -0,0 +1,122 @@
+// Copyright 2017 Nico Madysa.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you
+// may not use this file except in compliance with the License. You may
+// obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Provides the function `shuffle::shuffle()`.
+//!
+//! This is a minimal, seedable pseudo-random number generator. It is
+//! good enough to randomize the order of scenarios, but not for
+//! anything that needs to be unpredictable.
+
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+
+/// Shuffles `items` in place.
+///
+/// The same `seed` always produces the same order.
+///
+/// # Examples
+///
+/// ```rust
+/// let mut items = vec![1, 2, 3, 4];
+/// shuffle(&mut items, 42);
+/// items.sort();
+/// assert_eq!(items, [1, 2, 3, 4]);
+/// ```
+pub fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut rng = SplitMix64(seed);
+    // Fisher-Yates shuffle.
+    for i in (1..items.len()).rev() {
+        let j = rng.next_below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+}
+
+
+/// Returns a seed that differs between calls.
+///
+/// This uses the random keys with which the standard library
+/// initializes its hash maps.
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+
+/// The SplitMix64 pseudo-random number generator.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    /// Returns the next pseudo-random number.
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a pseudo-random number in the range `0..bound`.
+    ///
+    /// The slight bias of this method does not matter for shuffling.
+    fn next_below(&mut self, bound: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(bound)) >> 64) as u64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_same_seed_same_order() {
+        let mut first = (0..100).collect::<Vec<_>>();
+        let mut second = first.clone();
+        shuffle(&mut first, 1234);
+        shuffle(&mut second, 1234);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_is_permutation() {
+        let original = (0..100).collect::<Vec<_>>();
+        let mut shuffled = original.clone();
+        shuffle(&mut shuffled, 1234);
+        assert_ne!(shuffled, original);
+        shuffled.sort();
+        assert_eq!(shuffled, original);
+    }
+
+    #[test]
+    fn test_different_seeds() {
+        let mut first = (0..100).collect::<Vec<_>>();
+        let mut second = first.clone();
+        shuffle(&mut first, 1);
+        shuffle(&mut second, 2);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_short_slices() {
+        let mut empty: [u8; 0] = [];
+        shuffle(&mut empty, 0);
+        let mut single = [1];
+        shuffle(&mut single, 0);
+        assert_eq!(single, [1]);
+    }
+}
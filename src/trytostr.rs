@@ -13,10 +13,30 @@
 // permissions and limitations under the License.
 
 
-//! Provides the method `OsStr::try_to_str()`.
+//! Provides the method `OsStr::try_to_str()` and the function
+//! `try_from_utf8()`.
+//!
+//! Additionally, this module provides a way to carry bytes that are
+//! not valid UTF-8 inside a `str`. See
+//! [`from_utf8_with_raw_bytes()`] and [`decode_raw_bytes()`].
+//!
+//! [`from_utf8_with_raw_bytes()`]: ./fn.from_utf8_with_raw_bytes.html
+//! [`decode_raw_bytes()`]: ./fn.decode_raw_bytes.html
 
 
-use std::ffi::OsStr;
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    str,
+};
+
+
+/// The character that stands for the raw byte `0x00`.
+///
+/// Raw bytes are mapped to the last 256 characters of Unicode, which
+/// lie in a private-use area. Since bytes below `0x80` are always valid
+/// UTF-8, only the last 128 of these characters ever occur.
+const RAW_BYTE_BASE: u32 = 0x10_FF00;
 
 
 /// Extension trait on `OsStr`.
@@ -36,9 +56,186 @@ impl OsStrExt for OsStr {
 }
 
 
+/// Tries to convert a byte buffer into a `String`.
+///
+/// This is like `String::from_utf8`, except it fails with the same
+/// error type as `OsStrExt::try_to_str`.
+pub fn try_from_utf8(bytes: Vec<u8>) -> Result<String, NotUtf8> {
+    String::from_utf8(bytes)
+        .map_err(|err| NotUtf8(String::from_utf8_lossy(err.as_bytes()).into_owned()))
+}
+
+
+/// Converts bytes into a string, keeping invalid UTF-8 as raw bytes.
+///
+/// Each byte that is not part of a valid UTF-8 sequence is replaced
+/// with a character from a private-use area of Unicode.
+/// [`decode_raw_bytes()`] turns these characters back into the
+/// original bytes.
+///
+/// # Errors
+/// This fails if the valid part of `bytes` contains one of these
+/// private-use characters already, since it could not be told apart
+/// from a raw byte later on.
+///
+/// [`decode_raw_bytes()`]: ./fn.decode_raw_bytes.html
+pub fn from_utf8_with_raw_bytes(bytes: &[u8]) -> Result<String, ReservedChar> {
+    let mut result = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    loop {
+        let err = match str::from_utf8(rest) {
+            Ok(valid) => return push_checked(&mut result, valid).map(|()| result),
+            Err(err) => err,
+        };
+        let (valid, invalid) = rest.split_at(err.valid_up_to());
+        let valid = str::from_utf8(valid).expect("valid_up_to() returned invalid UTF-8");
+        push_checked(&mut result, valid)?;
+        let invalid_len = err.error_len().unwrap_or(invalid.len());
+        for &byte in &invalid[..invalid_len] {
+            result.push(raw_byte_char(byte));
+        }
+        rest = &invalid[invalid_len..];
+    }
+}
+
+
+/// Returns `true` if `s` carries raw bytes.
+///
+/// See [`from_utf8_with_raw_bytes()`] for details.
+///
+/// [`from_utf8_with_raw_bytes()`]: ./fn.from_utf8_with_raw_bytes.html
+pub fn has_raw_bytes(s: &str) -> bool {
+    s.chars().any(|c| as_raw_byte(c).is_some())
+}
+
+
+/// Returns an error if `s` carries raw bytes.
+///
+/// The error is the same as for invalid UTF-8 in an `OsStr`, with
+/// each raw byte shown as a replacement character.
+pub fn check_no_raw_bytes(s: &str) -> Result<(), NotUtf8> {
+    if !has_raw_bytes(s) {
+        return Ok(());
+    }
+    let lossy = s
+        .chars()
+        .map(|c| if as_raw_byte(c).is_some() { '\u{FFFD}' } else { c })
+        .collect();
+    Err(NotUtf8(lossy))
+}
+
+
+/// Turns the raw bytes in `s` back into their original form.
+///
+/// This is the reverse of [`from_utf8_with_raw_bytes()`]. Only Unix
+/// allows arbitrary bytes in an `OsStr`; on other platforms, `s` is
+/// converted as-is.
+///
+/// [`from_utf8_with_raw_bytes()`]: ./fn.from_utf8_with_raw_bytes.html
+pub fn decode_raw_bytes(s: Cow<str>) -> Cow<OsStr> {
+    if cfg!(unix) && has_raw_bytes(&s) {
+        let mut bytes = Vec::with_capacity(s.len());
+        for c in s.chars() {
+            match as_raw_byte(c) {
+                Some(byte) => bytes.push(byte),
+                None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        return Cow::Owned(os_string_from_vec(bytes));
+    }
+    match s {
+        Cow::Borrowed(s) => Cow::Borrowed(OsStr::new(s)),
+        Cow::Owned(s) => Cow::Owned(OsString::from(s)),
+    }
+}
+
+
+#[cfg(unix)]
+fn os_string_from_vec(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+
+#[cfg(not(unix))]
+fn os_string_from_vec(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+
+/// Appends `valid` to `result` unless it contains reserved characters.
+fn push_checked(result: &mut String, valid: &str) -> Result<(), ReservedChar> {
+    if let Some(c) = valid.chars().find(|&c| as_raw_byte(c).is_some()) {
+        return Err(ReservedChar(c));
+    }
+    result.push_str(valid);
+    Ok(())
+}
+
+
+/// Returns the character that stands for `byte`.
+fn raw_byte_char(byte: u8) -> char {
+    ::std::char::from_u32(RAW_BYTE_BASE + u32::from(byte)).expect("not a valid character")
+}
+
+
+/// Returns the byte that `c` stands for, if any.
+fn as_raw_byte(c: char) -> Option<u8> {
+    let c = c as u32;
+    if c >= RAW_BYTE_BASE + 0x80 {
+        Some((c - RAW_BYTE_BASE) as u8)
+    } else {
+        None
+    }
+}
+
+
 /// The error type of [`OsStrExt`].
 ///
 /// [`OsStrExt`]: ./trait.OsStrExt.html
 #[derive(Debug, Fail)]
 #[fail(display = "contains invalid UTF-8 character: \"{}\"", _0)]
 pub struct NotUtf8(String);
+
+
+/// The error type of [`from_utf8_with_raw_bytes()`].
+///
+/// [`from_utf8_with_raw_bytes()`]: ./fn.from_utf8_with_raw_bytes.html
+#[derive(Debug, Fail)]
+#[fail(display = "contains reserved character: {:?}", _0)]
+pub struct ReservedChar(char);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_bytes_round_trip() {
+        let bytes = b"caf\xe9 \xc3\xa9 \xff";
+        let s = from_utf8_with_raw_bytes(bytes).unwrap();
+        assert!(s.starts_with("caf"));
+        assert!(s.contains(" \u{e9} "));
+        assert!(has_raw_bytes(&s));
+        assert!(check_no_raw_bytes(&s).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            assert_eq!(decode_raw_bytes(Cow::Borrowed(&s)).as_bytes(), &bytes[..]);
+        }
+    }
+
+    #[test]
+    fn test_valid_utf8_unchanged() {
+        let s = from_utf8_with_raw_bytes("plain välue".as_bytes()).unwrap();
+        assert_eq!(s, "plain välue");
+        assert!(!has_raw_bytes(&s));
+        assert_eq!(decode_raw_bytes(Cow::Borrowed(&s)), OsStr::new("plain välue"));
+    }
+
+    #[test]
+    fn test_reserved_char() {
+        let reserved = "\u{10FFE9}".as_bytes();
+        assert!(from_utf8_with_raw_bytes(reserved).is_err());
+    }
+}
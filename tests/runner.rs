@@ -18,6 +18,7 @@
 use std::{
     env,
     ffi::{OsStr, OsString},
+    fs::File,
     path::{Path, PathBuf},
     process::{Command, ExitStatus, Output},
 };
@@ -98,6 +99,18 @@ impl Runner {
         self.arg(path)
     }
 
+    /// Connects the program's stdin to the given example file.
+    ///
+    /// # Panics
+    /// This panics if `filename` is not a relative path, or if the
+    /// file cannot be opened.
+    pub fn stdin_file<S: AsRef<Path>>(&mut self, filename: S) -> &mut Self {
+        let path = self.get_scenario_file_path(filename);
+        let file = File::open(&path).expect("could not open file");
+        self.command.stdin(file);
+        self
+    }
+
     /// This is to `scenario_file` what `args` is to `arg`.
     pub fn scenario_files<I>(&mut self, filenames: I) -> &mut Self
     where
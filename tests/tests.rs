@@ -29,6 +29,181 @@ mod printing {
     }
 
 
+    #[test]
+    fn test_shuffle() {
+        let unshuffled = Runner::new()
+            .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+            .output();
+        let run_shuffled = || {
+            Runner::new()
+                .arg("--shuffle=1")
+                .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+                .output()
+        };
+        let shuffled = run_shuffled();
+        assert_eq!("", &shuffled.stderr);
+        assert!(shuffled.status.success());
+        assert_ne!(unshuffled.stdout, shuffled.stdout);
+        // The same seed gives the same order.
+        assert_eq!(shuffled.stdout, run_shuffled().stdout);
+        // Shuffling doesn't lose or duplicate any scenarios.
+        let mut unshuffled = unshuffled.stdout.lines().collect::<Vec<_>>();
+        let mut shuffled = shuffled.stdout.lines().collect::<Vec<_>>();
+        unshuffled.sort();
+        shuffled.sort();
+        assert_eq!(unshuffled, shuffled);
+    }
+
+
+    #[test]
+    fn test_axis_order() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--axis-order", "1,0"])
+            .output();
+        assert_eq!("A1, B1\nA2, B1\nA1, B2\nA2, B2\n", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--axis-order", "0,0"])
+            .output();
+        let expected_stderr = "scenarios: error: invalid value for --axis-order
+scenarios:   -> reason: not an ordering of the 2 input files: \"0,0\"
+";
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_group_by() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--group-by", "1"])
+            .output();
+        assert_eq!("A1, B1\nA2, B1\nA1, B2\nA2, B2\n", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--group-by", "2"])
+            .output();
+        let expected_stderr = "scenarios: error: invalid value for --group-by
+scenarios:   -> reason: no file at index 2, there are only 2 input files
+";
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_continue_from() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--continue-from", "A2, B1"])
+            .output();
+        assert_eq!("A2, B1\nA2, B2\n", &output.stdout);
+        assert!(output.status.success());
+        // Resuming a shuffled run keeps its order.
+        let shuffled = Runner::new()
+            .arg("--shuffle=1")
+            .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+            .output();
+        let lines: Vec<&str> = shuffled.stdout.lines().collect();
+        let output = Runner::new()
+            .arg("--shuffle=1")
+            .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+            .args(&["--continue-from", lines[3]])
+            .output();
+        assert_eq!(lines[3..], output.stdout.lines().collect::<Vec<_>>()[..]);
+        // Unknown names are an error.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--continue-from", "A3"])
+            .output();
+        let expected_stderr = "scenarios: error: invalid value for --continue-from
+scenarios:   -> reason: no scenario is named \"A3\"
+";
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+        // Merge errors are reported, even for the named scenario.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .args(&["--continue-from", "A1, C3"])
+            .output();
+        let expected_stderr = "scenarios: error: variable \"a_var1\" defined both in scenario \
+                               \"A1\" and in scenario \"C3\"\n";
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_only_first() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--only-first", "3", "--exclude", "A1, B1"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1, B2\nA2, B1\nA2, B2\n", &output.stdout);
+        assert!(output.status.success());
+        // With --shuffle, this takes a sample of the shuffled order.
+        let shuffled = Runner::new()
+            .arg("--shuffle=1")
+            .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+            .output();
+        let lines: Vec<&str> = shuffled.stdout.lines().collect();
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+            .args(&["--only-first", "4", "--shuffle=1"])
+            .output();
+        assert_eq!(lines[..4], output.stdout.lines().collect::<Vec<_>>()[..]);
+        // Bad numbers are an error.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini"])
+            .args(&["--only-first", "many"])
+            .output();
+        let expected_stderr = "scenarios: error: invalid value for --only-first
+scenarios:   -> reason: not a number: \"many\"
+";
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(Some(2), output.status.code());
+        // So is zero.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini"])
+            .args(&["--only-first", "0"])
+            .output();
+        let expected_stderr = "scenarios: error: invalid value for --only-first
+scenarios:   -> reason: must be greater than zero: \"0\"
+";
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_shuffle_random_seed() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+            .arg("--shuffle")
+            .output();
+        assert!(output.status.success());
+        let seed = output
+            .stderr
+            .trim_end()
+            .trim_start_matches("scenarios: shuffling with seed ");
+        assert!(seed.parse::<u64>().is_ok(), "bad stderr: {:?}", output.stderr);
+        // The logged seed reproduces the order.
+        let rerun = Runner::new()
+            .arg(format!("--shuffle={}", seed))
+            .scenario_files(&["good_a.ini", "many_scenarios.ini"])
+            .output();
+        assert_eq!(output.stdout, rerun.stdout);
+    }
+
+
     #[test]
     fn test_delimiter() {
         let expected = "A1 -- B1\nA1 -- B2\nA2 -- B1\nA2 -- B2\n";
@@ -43,11 +218,11 @@ mod printing {
 
 
     #[test]
-    fn test_print() {
-        let expected = "A1\nA2\n";
+    fn test_escape_names() {
+        let expected = "A1B\\B1\nA1B\\B2\nA2B\\B1\nA2B\\B2\n";
         let output = Runner::new()
-            .scenario_file("good_a.ini")
-            .arg("--print")
+            .args(&["-d", "B", "--escape-names"])
+            .scenario_files(&["good_a.ini", "good_b.ini"])
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
@@ -56,11 +231,11 @@ mod printing {
 
 
     #[test]
-    fn test_print0() {
-        let expected = "A1\0A2\0";
+    fn test_sort_names() {
+        let expected = "A1, B1\nA1, B2\nA2, B1\nA2, B2\n";
         let output = Runner::new()
-            .scenario_file("good_a.ini")
-            .arg("--print0")
+            .args(&["--sort-names", "--axis-order", "1,0"])
+            .scenario_files(&["good_a.ini", "good_b.ini"])
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
@@ -69,11 +244,11 @@ mod printing {
 
 
     #[test]
-    fn test_template() {
-        let expected = "Some(A1)\nSome(A2)\n";
+    fn test_several_delimiters() {
+        let expected = "A1:B1/1\nA1:B1/2\n";
         let output = Runner::new()
-            .scenario_file("good_a.ini")
-            .args(&["--print", "Some({})"])
+            .args(&["-d", ":", "-d", "/", "--choose", "A1:B1/[12]"])
+            .scenario_files(&["good_a.ini", "good_b.ini", "many_scenarios.ini"])
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
@@ -82,11 +257,11 @@ mod printing {
 
 
     #[test]
-    fn test_lax_mode() {
-        let expected = "A1, C1\nA1, C2\nA1, C3\nA2, C1\nA2, C2\nA2, C3\n";
+    fn test_name_format() {
+        let expected = "B1 (A1)\nB2 (A1)\nB1 (A2)\nB2 (A2)\n";
         let output = Runner::new()
-            .arg("--lax")
-            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .args(&["--name-format", "{1} ({0})"])
+            .scenario_files(&["good_a.ini", "good_b.ini"])
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
@@ -95,77 +270,120 @@ mod printing {
 
 
     #[test]
-    fn test_strict_mode() {
-        let expected_stdout = "A1, C1\nA1, C2\n";
-        let expected_stderr = "scenarios: error: variable \"a_var1\" defined both in scenario \
-                               \"A1\" and in scenario \"C3\"\n";
+    fn test_name_format_out_of_range() {
+        let expected = "scenarios: error: invalid value for --name-format
+scenarios:   -> reason: {2} is out of range, there are only 2 input files
+";
         let output = Runner::new()
-            .arg("--strict")
-            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .args(&["--name-format", "{0}-{2}"])
+            .scenario_files(&["good_a.ini", "good_b.ini"])
             .output();
-        assert_eq!(expected_stderr, &output.stderr);
-        assert_eq!(expected_stdout, &output.stdout);
-        assert!(!output.status.success());
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
     }
 
 
     #[test]
-    fn test_strict_mode_is_default() {
+    fn test_glob_inputs() {
+        let expected = "A1, B1\nA1, B2\nA2, B1\nA2, B2\n";
+        let mut runner = Runner::new();
+        let pattern = runner
+            .get_scenario_file_path("good_a.ini")
+            .with_file_name("good_*.ini");
+        let output = runner.arg("--glob-inputs").arg(pattern).output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_explain() {
+        let expected = r#"A1, B1
+    a_var1=first scenario (from "A1")
+    a_var2=one (from "A1")
+    b_var1=first scenario (from "B1")
+    b_var2=one (from "B1")
+"#;
         let output = Runner::new()
-            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .args(&["--explain", "--choose", "A1, B1"])
+            .scenario_files(&["good_a.ini", "good_b.ini"])
             .output();
-        assert!(!output.status.success());
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
     }
 
 
     #[test]
-    fn test_choose() {
-        let expected = "A1\n";
+    fn test_print_vars() {
+        let expected = "a_var1=first scenario\na_var2=one\n\n\
+                        a_var1=second scenario\na_var2=two\n\n";
         let output = Runner::new()
             .scenario_file("good_a.ini")
-            .args(&["--choose", "?1"])
+            .arg("--print-vars")
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
         assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--print-vars", "--terminator", "---\\n", "--choose", "A2"])
+            .output();
+        assert_eq!("a_var1=second scenario\na_var2=two\n---\n", &output.stdout);
     }
 
+
     #[test]
-    fn test_exclude() {
-        let expected = "1\n3\n5\n";
+    fn test_table() {
+        let expected = "\
+scenario      a_var1           a_var2  long_name  x
+A1 + full     first scenario   one     2          1
+A1 + partial  first scenario   one                three
+A2 + full     second scenario  two     2          1
+A2 + partial  second scenario  two                three
+";
         let output = Runner::new()
-            .scenario_file("many_scenarios.ini")
-            .args(&["--exclude", "[24]"])
+            .args(&["--table", "--delimiter", " + "])
+            .scenario_files(&["good_a.ini", "sparse.ini"])
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
         assert!(output.status.success());
     }
-}
 
-mod environment {
-    use runner::Runner;
 
     #[test]
-    fn test_insert_name() {
-        let expected = "-A1-\n-A2-\n";
+    fn test_count() {
         let output = Runner::new()
-            .scenario_file("good_a.ini")
-            .args(&["--exec", "echo", "-{}-"])
+            .args(&["--count", "--exclude", "A1, B2"])
+            .scenario_files(&["good_a.ini", "good_b.ini"])
             .output();
         assert_eq!("", &output.stderr);
-        assert_eq!(expected, &output.stdout);
+        assert_eq!("3\n", &output.stdout);
         assert!(output.status.success());
     }
 
 
     #[test]
-    fn test_no_insert_name() {
-        let expected = "-{}-\n-{}-\n";
+    fn test_check() {
+        let output = Runner::new()
+            .arg("--check")
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("4\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_print() {
+        let expected = "A1\nA2\n";
         let output = Runner::new()
             .scenario_file("good_a.ini")
-            .arg("--no-insert-name")
-            .args(&["--exec", "echo", "-{}-"])
+            .arg("--print")
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
@@ -174,12 +392,11 @@ mod environment {
 
 
     #[test]
-    fn test_no_export_name() {
-        let expected = "outer_variable=1\n";
+    fn test_print0() {
+        let expected = "A1\0A2\0";
         let output = Runner::new()
-            .scenario_file("one_empty.ini")
-            .arg("--no-export-name")
-            .args(&["--exec", "env"])
+            .scenario_file("good_a.ini")
+            .arg("--print0")
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
@@ -188,200 +405,1807 @@ mod environment {
 
 
     #[test]
-    fn test_ignore_env() {
-        let expected = "SCENARIOS_NAME=Empty\n";
+    fn test_print_variable_names() {
         let output = Runner::new()
-            .scenario_file("one_empty.ini")
-            .arg("--ignore-env")
-            .args(&["--exec", "env"])
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--choose", "A1*B1", "--print", "{}: {count} {names}"])
             .output();
         assert_eq!("", &output.stderr);
-        assert_eq!(expected, &output.stdout);
+        assert_eq!("A1, B1: 4 a_var1,a_var2,b_var1,b_var2\n", &output.stdout);
         assert!(output.status.success());
     }
 
 
     #[test]
-    fn test_empty_env() {
+    fn test_terminator() {
         let output = Runner::new()
-            .scenario_file("one_empty.ini")
-            .args(&["--ignore-env", "--no-export-name"])
-            .args(&["--exec", "env"])
+            .scenario_file("good_a.ini")
+            .args(&["--print", "<{}>", "--terminator", "\\t"])
             .output();
         assert_eq!("", &output.stderr);
+        assert_eq!("<A1>\t<A2>\t", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--terminator", "\\x"])
+            .output();
+        let expected = "scenarios: error: invalid value for --terminator\n\
+                        scenarios:   -> reason: invalid escape sequence: \"\\\\x\"\n";
+        assert_eq!(expected, &output.stderr);
         assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_print_file() {
+        let expected = "name: A1\nname: A2\n";
+        let mut runner = Runner::new();
+        let print_file = runner.get_scenario_file_path("print_file.txt");
+        let output = runner
+            .scenario_file("good_a.ini")
+            .arg("--print-file")
+            .arg(print_file)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
         assert!(output.status.success());
     }
 
 
     #[test]
-    fn test_non_empty_env() {
-        let expected = "a_var1=This conflicts with A1 and A2.\n";
+    fn test_template() {
+        let expected = "Some(A1)\nSome(A2)\n";
         let output = Runner::new()
-            .scenario_file("conflicts_with_a.ini")
-            .args(&["--ignore-env", "--no-export-name"])
-            .args(&["--exec", "env"])
+            .scenario_file("good_a.ini")
+            .args(&["--print", "Some({})"])
             .output();
         assert_eq!("", &output.stderr);
         assert_eq!(expected, &output.stdout);
         assert!(output.status.success());
     }
-}
 
-mod errors {
-    use runner::Runner;
 
-
-    /// Returns a runner that will fail in a specific scenario.
-    fn stop_at_scenario(name: &str, additional_args: &[&str]) -> Runner {
-        let script = format!("if [ {{}} = {} ]; then exit 1; else echo {{}}; fi", name);
-        let mut runner = Runner::new();
-        runner
-            .scenario_file("many_scenarios.ini")
-            .args(additional_args)
-            .args(&["--exec", "sh", "-c", &script]);
-        runner
+    #[test]
+    fn test_lax_mode() {
+        let expected = "A1, C1\nA1, C2\nA1, C3\nA2, C1\nA2, C2\nA2, C3\n";
+        let output = Runner::new()
+            .arg("--lax")
+            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
     }
 
+
     #[test]
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn test_conflict_print_exec() {
-        let mut runner = Runner::new();
-        runner.args(&["--print", "--exec", "echo", "aaa"]);
-        let expected = "error: The argument '--exec <COMMAND...>' cannot be used with '--print \
-                        <FORMAT>'
+    fn test_override_first() {
+        let run = |precedence| {
+            Runner::new()
+                .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+                .args(&["--lax", "--override", precedence, "--choose", "A1, C3"])
+                .args(&["--exec", "sh", "-c", "echo \"{}: $a_var1\""])
+                .output()
+        };
+        let output = run("first");
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1, C3: first scenario\n", &output.stdout);
+        assert!(output.status.success());
+        let output = run("last");
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1, C3: This conflicts with A1 and A2.\n", &output.stdout);
+        assert!(output.status.success());
+    }
 
-USAGE:
-    scenarios [FlAGS] [OPTIONS] <SCENARIO FILES>... [--exec <COMMAND...>]
 
-For more information try --help
-";
-        let output = runner.output();
-        assert_eq!(&expected, &output.stderr);
+    #[test]
+    fn test_strict_mode() {
+        let expected_stdout = "A1, C1\nA1, C2\n";
+        let expected_stderr = "scenarios: error: variable \"a_var1\" defined both in scenario \
+                               \"A1\" and in scenario \"C3\"\n";
+        let output = Runner::new()
+            .arg("--strict")
+            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(expected_stdout, &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_strict_mode_is_default() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .output();
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_choose() {
+        let expected = "A1\n";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--choose", "?1"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_list_scenarios() {
+        let mut runner = Runner::new();
+        runner
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .arg("--list-scenarios");
+        let expected = format!(
+            "{}:\n    A1\n    A2\n{}:\n    B1\n    B2\n",
+            runner.get_scenario_file_path("good_a.ini").display(),
+            runner.get_scenario_file_path("good_b.ini").display(),
+        );
+        let output = runner.output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_choose_case_insensitive() {
+        let expected = "A1, B1\nA2, B1\n";
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--choose", "a?, b1", "--case-insensitive-names"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_exclude() {
+        let expected = "1\n3\n5\n";
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--exclude", "[24]"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_exclude_matches_nothing() {
+        let expected_stderr =
+            "scenarios: warning: the pattern \"[67]\" of --exclude did not match any scenarios\n";
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--exclude", "[67]"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("1\n2\n3\n4\n5\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_tags() {
+        let output = Runner::new()
+            .scenario_file("tagged.ini")
+            .args(&["--with-tag", "slow", "--without-tag", "nightly"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("integration\n", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_files(&["tagged.ini", "good_a.ini"])
+            .args(&["--with-tag", "nightly"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("benchmark, A1\nbenchmark, A2\n", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("tagged.ini")
+            .args(&["--with-tag", "fast", "--with-tag", "slow"])
+            .output();
+        assert_eq!("scenarios: no scenarios matched the filter\n", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_choose_negated() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--choose", "!*1*"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A2, B2\n", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--choose", "!A?"])
+            .output();
+        let expected = "scenarios: warning: the pattern \"!A?\" of --choose did not match any \
+                        scenarios\n";
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_exclude_everything() {
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--exclude", "*"])
+            .output();
+        assert_eq!("scenarios: no scenarios matched the filter\n", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--quiet", "--exclude", "*"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_choose_braces() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--choose", "{A1,A2}, B{2}"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1, B2\nA2, B2\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_choose_matches_nothing() {
+        let expected_stderr =
+            "scenarios: warning: the pattern \"A3\" of --choose did not match any scenarios\n";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--choose", "A3"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_emit_envfiles() {
+        let dir = std::env::temp_dir().join(format!("scenarios-envfiles-{}", std::process::id()));
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .arg("--emit-envfiles")
+            .arg(&dir)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["A1__B1.env", "A1__B2.env", "A2__B1.env", "A2__B2.env"]);
+        let contents = std::fs::read_to_string(dir.join("A2__B1.env")).unwrap();
+        let expected = "a_var1=second scenario\na_var2=two\nb_var1=first scenario\nb_var2=one\n";
+        assert_eq!(expected, contents);
+        // Existing files are only overwritten with --force.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--choose", "A1*B1", "--emit-envfiles"])
+            .arg(&dir)
+            .output();
+        assert!(output.stderr.contains("already exists, pass --force"));
+        assert!(!output.status.success());
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--force", "--emit-envfiles"])
+            .arg(&dir)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_emit_envfiles_round_trip() {
+        let dir = std::env::temp_dir().join(format!("scenarios-quoted-{}", std::process::id()));
+        let output = Runner::new()
+            .scenario_file("emit_quoted.ini")
+            .arg("--emit-envfiles")
+            .arg(&dir)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+        let contents = std::fs::read_to_string(dir.join("Quoted.env")).unwrap();
+        assert_eq!("quoted=\"\"kept\"\"\n", contents);
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .arg("--env-file")
+            .arg(dir.join("Quoted.env"))
+            .args(&["--exec", "sh", "-c", "echo \"$quoted\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("\"kept\"\n", &output.stdout);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_emit_envfiles_unsupported() {
+        let dir = format!("scenarios-unsupported-{}", std::process::id());
+        let dir = std::env::temp_dir().join(dir);
+        let expected_stderr = r#"scenarios: error: cannot write an env file
+scenarios:   -> reason: scenario "Multiline": value of "text" has line breaks or invalid UTF-8
+"#;
+        let output = Runner::new()
+            .scenario_file("emit_multiline.ini")
+            .arg("--emit-envfiles")
+            .arg(&dir)
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert!(!output.status.success());
+        // Nothing is written, not even the valid scenario before.
+        assert!(!dir.exists());
+        let expected_stderr = r#"scenarios: error: cannot write an env file
+scenarios:   -> reason: scenario "Removed" removes variable "outer_variable"
+"#;
+        let output = Runner::new()
+            .scenario_file("removes_outer.ini")
+            .arg("--emit-envfiles")
+            .arg(&dir)
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert!(!output.status.success());
+        assert!(!dir.exists());
+    }
+}
+
+mod environment {
+    use runner::Runner;
+
+    #[test]
+    fn test_insert_name() {
+        let expected = "-A1-\n-A2-\n";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--exec", "echo", "-{}-"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_placeholder() {
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--placeholder", "%name%", "--exec", "echo", "{} %name%"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("{} A1\n{} A2\n", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--placeholder", "@", "--print", "{@}"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("{A1}\n{A2}\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_inline_comments() {
+        let expected = "some value, #1\n";
+        let output = Runner::new()
+            .scenario_file("inline_comments.ini")
+            .args(&["--allow-inline-comments", "--exec", "sh", "-c"])
+            .arg(r#"echo "$value, $escaped""#)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_append() {
+        let expected = "1:2, a:b\n";
+        let output = Runner::new()
+            .scenario_file("appends.ini")
+            .args(&["--exec", "sh", "-c"])
+            .arg(r#"echo "$outer_variable, $list""#)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_append_ignore_env() {
+        let expected = "2, a b\n";
+        let output = Runner::new()
+            .scenario_file("appends.ini")
+            .args(&["--ignore-env", "--append-separator", " "])
+            .args(&["--exec", "sh", "-c"])
+            .arg(r#"echo "$outer_variable, $list""#)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_stdin_format_nul() {
+        let expected = "First: 1, two\nlines\nSecond: 2, \n";
+        let output = Runner::new()
+            .stdin_file("nul_records.bin")
+            .args(&["--stdin-format", "nul", "-", "--exec", "sh", "-c"])
+            .arg(r#"echo "$SCENARIOS_NAME: $x, $y""#)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_exec_file() {
+        let expected = "-A1-\n-A2-\n";
+        let mut runner = Runner::new();
+        let exec_file = runner.get_scenario_file_path("exec_file.txt");
+        let output = runner
+            .scenario_file("good_a.ini")
+            .arg("--exec-file")
+            .arg(exec_file)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_exec_stdin() {
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .stdin_file("exec_file.txt")
+            .arg("--exec-stdin")
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("-A1-\n-A2-\n", &output.stdout);
+        assert!(output.status.success());
+        // Stdin cannot provide both scenarios and the command.
+        let output = Runner::new()
+            .stdin_file("good_a.ini")
+            .args(&["-", "--exec-stdin"])
+            .output();
+        let expected_stderr =
+            "scenarios: error: cannot read both scenarios and --exec-stdin from stdin\n";
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_stdin_used_twice() {
+        let expected =
+            "scenarios: error: stdin can only be read once, but \"-\" was given 2 times\n";
+        let output = Runner::new()
+            .stdin_file("good_a.ini")
+            .args(&["-", "-"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_out_dir() {
+        let dir = std::env::temp_dir().join(format!("scenarios-out-dir-{}", std::process::id()));
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .arg("--out-dir")
+            .arg(&dir)
+            .args(&["--exec", "sh", "-c", "echo $a_var2; echo $a_var1 >&2"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!("one\n", read("A1.out"));
+        assert_eq!("first scenario\n", read("A1.err"));
+        assert_eq!("two\n", read("A2.out"));
+        assert_eq!("second scenario\n", read("A2.err"));
+        // Files that cannot be created keep the job from starting.
+        std::fs::remove_file(dir.join("A1.out")).unwrap();
+        std::fs::create_dir(dir.join("A1.out")).unwrap();
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .arg("--out-dir")
+            .arg(&dir)
+            .args(&["--exec", "true"])
+            .output();
+        assert!(output
+            .stderr
+            .starts_with("scenarios: error: could not start scenario \"A1\"\n"));
+        assert!(!output.status.success());
+        // Names that map to the same files are an error.
+        let output = Runner::new()
+            .scenario_file("same_slug.ini")
+            .arg("--out-dir")
+            .arg(&dir)
+            .args(&["--exec", "echo", "{}"])
+            .output();
+        let expected = format!(
+            "scenarios: error: could not start scenario \"x-y\"
+scenarios:   -> reason: scenarios \"x y\" and \"x-y\" would both write to {:?}
+scenarios: not all scenarios terminated successfully
+",
+            dir.join("x_y.out")
+        );
+        assert_eq!(expected, output.stderr);
+        assert_eq!("x y\n", std::fs::read_to_string(dir.join("x_y.out")).unwrap());
+        assert!(!output.status.success());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+
+    #[test]
+    fn test_if_changed() {
+        let state = std::env::temp_dir().join(format!("scenarios-state-{}", std::process::id()));
+        let run = |script: &str| {
+            Runner::new()
+                .scenario_file("good_a.ini")
+                .arg("--if-changed")
+                .arg(&state)
+                .args(&["--keep-going", "--exec", "sh", "-c", script])
+                .output()
+        };
+        let script = "echo $a_var2; test $a_var2 = one";
+        let output = run(script);
+        assert_eq!("one\ntwo\n", &output.stdout);
+        assert!(!output.status.success());
+        // Only the failed scenario runs again.
+        let output = run(script);
+        assert_eq!("two\n", &output.stdout);
+        assert!(output
+            .stderr
+            .contains("scenarios: skipped 1 unchanged scenarios\n"));
+        // A different command makes every scenario run again.
+        let output = run("echo $a_var2");
+        assert_eq!("one\ntwo\n", &output.stdout);
+        assert!(output.status.success());
+        let output = run("echo $a_var2");
+        assert_eq!("", &output.stdout);
+        assert_eq!("scenarios: skipped 2 unchanged scenarios\n", &output.stderr);
+        assert!(output.status.success());
+        std::fs::remove_file(&state).unwrap();
+    }
+
+
+    #[test]
+    fn test_warn_env_shadow() {
+        let expected = "scenarios: warning: scenario \"upper\": variable \"OUTER_VARIABLE\" \
+                        differs only in case from inherited variable \"outer_variable\"\n";
+        let output = Runner::new()
+            .scenario_file("shadow.ini")
+            .args(&["--warn-env-shadow", "--exec", "sh", "-c", "echo $OUTER_VARIABLE"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("uppercase\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_max_name_length() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--name-format", "{0} combined with {1}", "--max-name-length=12"])
+            .args(&["--exec", "sh", "-c", "echo $SCENARIOS_NAME"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+        let mut names: Vec<&str> = output.stdout.lines().collect();
+        assert_eq!(names.len(), 4);
+        assert!(names.iter().all(|name| name.len() == 12 && name.starts_with("A")));
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 4);
+        // There must be room for the hash suffix.
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--max-name-length=9", "--exec", "true"])
+            .output();
+        let expected = "scenarios: error: invalid value for --max-name-length
+scenarios:   -> reason: must be greater than 9, the length of the hash suffix
+";
+        assert_eq!(expected, &output.stderr);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_crlf_and_bom() {
+        let output = Runner::new()
+            .scenario_file("crlf_bom.ini")
+            .args(&["--exec", "sh", "-c", "echo \"$SCENARIOS_NAME: $line_ending\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("Windows: crlf\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_env_file() {
+        let expected = "from env file|  spaced  |overridden\n";
+        let mut runner = Runner::new();
+        let env_file = runner.get_scenario_file_path("base.env");
+        let output = runner
+            .scenario_file("one_empty.ini")
+            .arg("--env-file")
+            .arg(env_file)
+            .args(&["--exec", "sh", "-c", "echo \"$SHARED|$QUOTED|$a_var1\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_env_file_overridden() {
+        let expected_stderr = "scenarios: error: could not start scenario \"A1\"
+scenarios:   -> reason: variable also defined in the environment file: \"a_var1\" \
+                               (strict mode is enabled)
+scenarios: not all scenarios terminated successfully
+";
+        let run = |extra_args: &[&str]| {
+            let mut runner = Runner::new();
+            let env_file = runner.get_scenario_file_path("base.env");
+            runner
+                .scenario_file("good_a.ini")
+                .args(extra_args)
+                .arg("--env-file")
+                .arg(env_file)
+                .args(&["--exec", "sh", "-c", "echo \"$SHARED|$a_var1\""])
+                .output()
+        };
+        let output = run(&[]);
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+        let output = run(&["--lax"]);
+        assert_eq!("", &output.stderr);
+        let expected_stdout = "from env file|first scenario\nfrom env file|second scenario\n";
+        assert_eq!(expected_stdout, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_no_insert_name() {
+        let expected = "-{}-\n-{}-\n";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .arg("--no-insert-name")
+            .args(&["--exec", "echo", "-{}-"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_name_transform() {
+        let expected = "-a1- a1\n-a2- a2\n";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--name-transform", "lower"])
+            .args(&["--exec", "sh", "-c", "echo -{}- $SCENARIOS_NAME"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_defaults() {
+        let output = Runner::new()
+            .scenario_file("defaults.ini")
+            .args(&["--exec", "sh", "-c", "echo $SCENARIOS_NAME: $greeting $target"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("plain: hello world\ncustom: hello you\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_inline_scenarios() {
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--scenario", "x:size=1,mode=a\\,b", "--scenario", "y:size+=2"])
+            .args(&["--exec", "sh", "-c", "echo \"{}: $a_var2 $size $mode\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1, x, y: one 1:2 a,b\nA2, x, y: two 1:2 a,b\n", &output.stdout);
+        assert!(output.status.success());
+        // No scenario files are needed.
+        let output = Runner::new().args(&["--scenario", "x:A=1"]).output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("x\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_tsv() {
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .arg("--tsv")
+            .scenario_file("grid.tsv")
+            .args(&["--exec", "sh", "-c", "echo \"{}: $a_var2 $size $mode\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        let expected = "\
+A1, small: one 10 fast
+A1, large: one 1000 slow\tcareful
+A2, small: two 10 fast
+A2, large: two 1000 slow\tcareful
+";
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_tsv_numbered() {
+        // Both files number their rows, but that is not a name clash.
+        let output = Runner::new()
+            .arg("--tsv")
+            .scenario_file("numbered_x.tsv")
+            .arg("--tsv")
+            .scenario_file("numbered_y.tsv")
+            .args(&["--exec", "sh", "-c", "echo \"{}: $x $y\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("1, 1: 1 10\n1, 2: 1 20\n2, 1: 2 10\n2, 2: 2 20\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_base() {
+        let output = Runner::new()
+            .scenario_file("good_b.ini")
+            .arg("--base")
+            .scenario_file("base.ini")
+            .args(&["--exec", "sh", "-c", "echo \"{}: $shared $a_var2 $b_var2\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("B1: yes default one\nB2: yes default two\n", &output.stdout);
+        assert!(output.status.success());
+        // The base may be overridden in lax mode only.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .arg("--base")
+            .scenario_file("base.ini")
+            .args(&["--lax", "--exec", "sh", "-c", "echo \"{}: $shared $a_var2\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        let expected = "A1, B1: yes one\nA1, B2: yes one\nA2, B1: yes two\nA2, B2: yes two\n";
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .arg("--base")
+            .scenario_file("base.ini")
+            .arg("--print")
+            .output();
+        let expected = "scenarios: error: variable \"a_var2\" defined both in scenario \"Base\" \
+                        and in scenario \"A1\"\n";
+        assert_eq!(expected, &output.stderr);
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn test_dedup_vars() {
+        let output = Runner::new()
+            .scenario_file("duplicate_vars.ini")
+            .args(&["--dedup-vars", "--print"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A\nC\nD\n", &output.stdout);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("duplicate_vars.ini")
+            .args(&["--dedup-vars", "--exclude", "A", "--count"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("3\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_export_index() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--exclude", "*B1", "--export-index"])
+            .args(&["--exec", "sh", "-c", "echo $SCENARIOS_INDEX/$SCENARIOS_TOTAL {}"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("0/2 A1, B2\n1/2 A2, B2\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_tags_not_exported() {
+        let expected = "KIND=unit\n";
+        let output = Runner::new()
+            .scenario_file("tagged.ini")
+            .args(&["--choose", "unit", "--no-export-name", "--ignore-env"])
+            .args(&["--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_no_export_name() {
+        let expected = "outer_variable=1\n";
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .arg("--no-export-name")
+            .args(&["--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_no_export_name_with_placeholder() {
+        let expected_stderr = "scenarios: warning: the scenario name is inserted into COMMAND, \
+                               but not exported because of --no-export-name (pass \
+                               --no-insert-name to turn off both)\n";
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .arg("--no-export-name")
+            .args(&["--exec", "echo", "{}"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("Empty\n", &output.stdout);
+        assert!(output.status.success());
+        // Both flags together are fine.
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .args(&["--no-export-name", "--no-insert-name"])
+            .args(&["--exec", "echo", "{}"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("{}\n", &output.stdout);
+        // An empty placeholder inserts nothing, so there is no warning.
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .args(&["--no-export-name", "--placeholder="])
+            .args(&["--exec", "echo", "{}"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("{}\n", &output.stdout);
+    }
+
+
+    #[test]
+    fn test_strict_unused_vars() {
+        let expected_stderr = "scenarios: warning: scenario \"A1\" defines variables, \
+                               but COMMAND \"true\" ignores them\n";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--strict-unused-vars", "--ignore-env", "--exec", "true"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert!(output.status.success());
+        // Without --ignore-env, there is no warning.
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--strict-unused-vars", "--exec", "true"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+        // Scenarios without variables have nothing to lose.
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .args(&["--strict-unused-vars", "--ignore-env", "--exec", "true"])
+            .output();
+        assert_eq!("", &output.stderr);
+        // Commands that may read the variables are fine.
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--strict-unused-vars", "--ignore-env", "--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_name_transform_without_name() {
+        let expected_stderr = "scenarios: warning: --name-transform has no effect because of \
+                               --no-export-name and --no-insert-name\n";
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .args(&["--no-export-name", "--no-insert-name", "--name-transform", "upper"])
+            .args(&["--exec", "echo", "{}"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("{}\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_removal() {
+        let output = Runner::new()
+            .scenario_file("removes_outer.ini")
+            .arg("--no-export-name")
+            .args(&["--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_timeout_override() {
+        let output = Runner::new()
+            .scenario_file("timeout_override.ini")
+            .args(&["--timeout=1", "--exec", "sh", "-c"])
+            .arg(r#"sleep 2; echo "done${SCENARIOS_TIMEOUT}""#)
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("done\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_ignore_env() {
+        let expected = "SCENARIOS_NAME=Empty\n";
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .arg("--ignore-env")
+            .args(&["--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_env_passthrough() {
+        let expected = "SCENARIOS_NAME=Empty\nouter_variable=1\n";
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .args(&["--ignore-env", "--env-passthrough", "outer_variable,NOT_SET"])
+            .args(&["--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_file_values() {
+        let expected = "Literal: {\"key\": \"value\"}\nFromFile: {\"key\": \"from file\"}\n\n";
+        let output = Runner::new()
+            .arg("--allow-file-values")
+            .scenario_file("file_values.ini")
+            .args(&["--exec", "sh", "-c", "echo \"{}: $payload\""])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_non_utf8_values() {
+        let output = Runner::new()
+            .arg("--allow-non-utf8-values")
+            .scenario_file("latin1.ini")
+            .args(&["--exec", "sh", "-c", "printf %s \"$word\" | od -An -tx1"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(" 63 61 66 e9\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_empty_env() {
+        let output = Runner::new()
+            .scenario_file("one_empty.ini")
+            .args(&["--ignore-env", "--no-export-name"])
+            .args(&["--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_non_empty_env() {
+        let expected = "a_var1=This conflicts with A1 and A2.\n";
+        let output = Runner::new()
+            .scenario_file("conflicts_with_a.ini")
+            .args(&["--ignore-env", "--no-export-name"])
+            .args(&["--exec", "env"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!(expected, &output.stdout);
+        assert!(output.status.success());
+    }
+}
+
+mod errors {
+    use std::time::{Duration, Instant};
+
+    use runner::Runner;
+
+
+    #[test]
+    fn test_check_conflicts() {
+        let expected_stderr = "scenarios: error: variable \"a_var1\" defined both in scenario \
+                               \"A1\" and in scenario \"C3\"
+scenarios: error: variable \"a_var1\" defined both in scenario \"A2\" and in scenario \"C3\"
+scenarios: error: --check found 2 error(s)
+";
+        let output = Runner::new()
+            .arg("--check")
+            .scenario_files(&["good_a.ini", "conflicts_with_a.ini"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("6\n", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_non_utf8_values() {
+        let mut runner = Runner::new();
+        runner.scenario_file("latin1.ini");
+        let expected = format!(
+            r#"scenarios: error: could not read file
+scenarios:   -> reason: in {}:2
+scenarios:   -> reason: stream did not contain valid UTF-8
+"#,
+            runner.get_scenario_file_path("latin1.ini").display()
+        );
+        let output = runner.output();
+        assert_eq!(&expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_check_all_files() {
+        // All files are read, even after the first one failed.
+        let output = Runner::new()
+            .arg("--check")
+            .scenario_file("broken.ini")
+            .arg("does_not_exist.ini")
+            .scenario_file("good_a.ini")
+            .output();
+        let errors: Vec<&str> = output
+            .stderr
+            .lines()
+            .filter(|line| line.starts_with("scenarios: error:"))
+            .collect();
+        let expected = [
+            "scenarios: error: could not read file",
+            "scenarios: error: could not read file",
+            "scenarios: error: --check found 2 error(s)",
+        ];
+        assert_eq!(expected, errors.as_slice());
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    /// Returns a runner that will fail in a specific scenario.
+    fn stop_at_scenario(name: &str, additional_args: &[&str]) -> Runner {
+        let script = format!("if [ {{}} = {} ]; then exit 1; else echo {{}}; fi", name);
+        let mut runner = Runner::new();
+        runner
+            .scenario_file("many_scenarios.ini")
+            .args(additional_args)
+            .args(&["--exec", "sh", "-c", &script]);
+        runner
+    }
+
+    /// Splits output into lines and sorts them.
+    ///
+    /// Parallel jobs may finish in any order, so their output can only
+    /// be compared after sorting.
+    fn sorted_lines(output: &str) -> Vec<&str> {
+        let mut lines = output.lines().collect::<Vec<_>>();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_conflict_print_exec() {
+        let mut runner = Runner::new();
+        runner.args(&["--print", "--exec", "echo", "aaa"]);
+        let expected = "error: The argument '--print <FORMAT>' cannot be used with '--exec \
+                        <COMMAND...>'
+
+USAGE:
+    scenarios [FlAGS] [OPTIONS] <SCENARIO FILES>... [--exec <COMMAND...>]
+
+For more information try --help
+";
+        let output = runner.output();
+        assert_eq!(&expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_conflict_print0_exec() {
+        let mut runner = Runner::new();
+        runner.args(&["--print0", "--exec", "echo", "aaa"]);
+        let expected = "error: The argument '--print0 <FORMAT>' cannot be used with '--exec \
+                        <COMMAND...>'
+
+USAGE:
+    scenarios [FlAGS] [OPTIONS] <SCENARIO FILES>... [--exec <COMMAND...>]
+
+For more information try --help
+";
+        let output = runner.output();
+        assert_eq!(&expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_conflict_print_print0() {
+        let mut runner = Runner::new();
+        runner.args(&["--print", "{}", "--print0", "{}"]);
+        let expected = "error: The argument '--print0 <FORMAT>' cannot be used with '--print \
+                        <FORMAT>'
+
+USAGE:
+    scenarios [FlAGS] [OPTIONS] <SCENARIO FILES>... [--exec <COMMAND...>]
+
+For more information try --help
+";
+        let output = runner.output();
+        assert_eq!(&expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_color_always() {
+        let expected = "scenarios: \x1b[1;31merror:\x1b[0m no scenarios provided\n";
+        let output = Runner::new().args(&["--color", "always"]).output();
+        assert_eq!(expected, &output.stderr);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_no_args() {
+        let expected = "scenarios: error: no scenarios provided\n";
+        let output = Runner::new().output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_fail_on_empty() {
+        let mut runner = Runner::new();
+        runner.scenario_files(&["good_a.ini", "no_scenarios.ini"]);
+        // By default, the empty file silently leaves no combinations.
+        let output = runner.output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+        let expected = format!(
+            "scenarios: error: file \"{}\" defines no scenarios\n",
+            runner.get_scenario_file_path("no_scenarios.ini").display()
+        );
+        let output = runner.arg("--fail-on-empty").output();
+        assert_eq!(expected, output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_log_format_json() {
+        let expected = "{\"level\":\"error\",\
+                        \"message\":\"scenario did not finish successfully: \\\"A1\\\"\",\
+                        \"causes\":[\"job exited with non-zero exit code: 1\"],\
+                        \"scenario\":\"A1\"}
+{\"level\":\"info\",\"message\":\"not all scenarios terminated successfully\"}
+";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--choose", "A1", "--log-format", "json", "--exec", "false"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert!(!output.status.success());
+        let expected = r#"{"level":"error","message":"no scenarios provided","causes":[]}
+"#;
+        let output = Runner::new().args(&["--log-format", "json"]).output();
+        assert_eq!(expected, &output.stderr);
+        let expected = "{\"level\":\"warning\",\
+                        \"message\":\"the pattern \\\"C*\\\" of --choose \
+                        did not match any scenarios\"}
+";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--choose", "C*", "--log-format", "json"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_exit_codes() {
+        fn exit_code(runner: &mut Runner) -> Option<i32> {
+            runner.output().status.code()
+        }
+        let mut runner = Runner::new();
+        assert_eq!(Some(0), exit_code(runner.scenario_file("good_a.ini")));
+        let mut runner = Runner::new();
+        assert_eq!(Some(2), exit_code(runner.arg("--no-such-flag")));
+        let mut runner = Runner::new();
+        runner.scenario_file("good_a.ini").args(&["--jobs", "x", "--exec", "true"]);
+        assert_eq!(Some(2), exit_code(&mut runner));
+        let mut runner = Runner::new();
+        assert_eq!(Some(2), exit_code(runner.scenario_file("broken.ini")));
+        assert_eq!(Some(3), exit_code(&mut Runner::new()));
+        let mut runner = Runner::new();
+        runner.scenario_file("good_a.ini").args(&["--exec", "false"]);
+        assert_eq!(Some(4), exit_code(&mut runner));
+        let mut runner = Runner::new();
+        runner.scenario_file("good_a.ini").args(&["--keep-going", "--exec", "false"]);
+        assert_eq!(Some(4), exit_code(&mut runner));
+        let mut runner = Runner::new();
+        runner.scenario_file("good_a.ini").args(&["--exec", "not a command"]);
+        assert_eq!(Some(5), exit_code(&mut runner));
+        // Errors found while building scenarios are bad input as well.
+        let mut runner = Runner::new();
+        assert_eq!(Some(2), exit_code(runner.scenario_file("def_before_header.ini")));
+        let mut runner = Runner::new();
+        assert_eq!(Some(2), exit_code(runner.scenario_file("misplaced_defaults.ini")));
+    }
+
+
+    #[test]
+    fn test_bad_nul_records() {
+        let expected = r#"scenarios: error: could not read file
+scenarios:   -> reason: file "<stdin>"
+scenarios:   -> reason: at byte 10: no equals sign "=" in variable definition: "y"
+"#;
+        let output = Runner::new()
+            .stdin_file("bad_nul_records.bin")
+            .args(&["--stdin-format", "nul", "-"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_missing_file() {
+        let output = Runner::new().arg("does not exist").output();
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_shared_scenario_name() {
+        let mut runner = Runner::new();
+        runner.scenario_files(&["good_a.ini", "good_b.ini", "good_a.ini"]);
+        let expected = format!(
+            r#"scenarios: error: two scenario files define the same scenario
+scenarios:   -> reason: in {0}:1
+scenarios:   -> reason: in {0}:1
+scenarios:   -> reason: duplicate scenario name: "A1"
+"#,
+            runner.get_scenario_file_path("good_a.ini").display()
+        );
+        let output = runner.output();
+        assert_eq!(&expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+        // In lax mode, this is allowed.
+        let output = Runner::new()
+            .arg("--lax")
+            .scenario_files(&["good_a.ini", "good_a.ini"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_broken_file() {
+        let mut runner = Runner::new();
+        runner.scenario_file("broken.ini");
+        let expected = format!(
+            r#"scenarios: error: could not read file
+scenarios:   -> reason: in {0}:1
+scenarios:   -> reason: in {0}:17
+scenarios:   -> reason: duplicate scenario name: "Scenario 1"
+"#,
+            runner.get_scenario_file_path("broken.ini").display()
+        );
+        let output = runner.output();
+        assert_eq!(&expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_name_pattern() {
+        let mut runner = Runner::new();
+        runner.scenario_files(&["good_a.ini", "good_b.ini"]);
+        runner.args(&["--name-pattern", "A*", "--print"]);
+        let expected = format!(
+            r#"scenarios: error: could not read file
+scenarios:   -> reason: in {}:1
+scenarios:   -> reason: scenario name "B1" does not match the pattern "A*"
+"#,
+            runner.get_scenario_file_path("good_b.ini").display()
+        );
+        let output = runner.output();
+        assert_eq!(&expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--name-pattern", "A?", "--print"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1\nA2\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_broken_command() {
+        let expected = r#"scenarios: error: could not start scenario "A1"
+scenarios:   -> reason: could not execute command "not a command"
+scenarios:   -> reason: No such file or directory (os error 2)
+scenarios: not all scenarios terminated successfully
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--exec", "not a command"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_broken_command_parallel() {
+        let expected = r#"scenarios: error: could not start scenario "A1"
+scenarios:   -> reason: could not execute command "not a command"
+scenarios:   -> reason: No such file or directory (os error 2)
+scenarios: waiting for unfinished jobs ...
+scenarios: not all scenarios terminated successfully
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--jobs=2", "--exec", "not a command"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_summary() {
+        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "A1"
+scenarios:   -> reason: job exited with non-zero exit code: 1
+scenarios: ran 2 scenarios: 1 ok, 1 failed
+scenarios: not all scenarios terminated successfully
+"#;
+        let script = "test $SCENARIOS_NAME = A2";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--summary", "--keep-going", "--exec", "sh", "-c", script])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert!(!output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--summary", "--exec", "true"])
+            .output();
+        assert_eq!("scenarios: ran 2 scenarios: 2 ok, 0 failed\n", &output.stderr);
+        assert!(output.status.success());
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--summary", "--quiet", "--exec", "true"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_ordered_output() {
+        let script = "if [ $SCENARIOS_NAME = A1 ]; then sleep 0.5; fi; \
+                      echo out $SCENARIOS_NAME; echo err $SCENARIOS_NAME >&2";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--jobs=2", "--ordered-output", "--exec", "sh", "-c", script])
+            .output();
+        assert_eq!("err A1\nerr A2\n", &output.stderr);
+        assert_eq!("out A1\nout A2\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_verbose() {
+        let expected_stderr = "scenarios: running scenario \"A1\": echo A1 'a b' 'it'\\''s'
+scenarios: running scenario \"A2\": echo A2 'a b' 'it'\\''s'
+";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--verbose", "--exec", "echo", "{}", "a b", "it's"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("A1 a b it's\nA2 a b it's\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_separator() {
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--separator", "== {} ==", "--exec", "echo", "out"])
+            .output();
+        assert_eq!("== A1 ==\nout\n== A2 ==\nout\n", &output.stdout);
+        assert!(output.status.success());
+        // With captured output, separators stay next to their output.
+        let script = "if [ $SCENARIOS_NAME = A1 ]; then sleep 0.5; fi; echo out $SCENARIOS_NAME";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--jobs=2", "--ordered-output", "--separator", "## {}"])
+            .args(&["--exec", "sh", "-c", script])
+            .output();
+        assert_eq!("## A1\nout A1\n## A2\nout A2\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_ordered_output_failure() {
+        let expected_stderr = r#"failing
+scenarios: error: scenario did not finish successfully: "A1"
+scenarios:   -> reason: job exited with non-zero exit code: 1
+waiting
+scenarios: not all scenarios terminated successfully
+"#;
+        let script = "if [ $SCENARIOS_NAME = A1 ]; then sleep 0.5; echo failing >&2; exit 1; fi; \
+                      echo waiting >&2";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--jobs=2", "--ordered-output", "--keep-going", "--exec", "sh", "-c", script])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_stop_at_first_error() {
+        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "3"
+scenarios:   -> reason: job exited with non-zero exit code: 1
+scenarios: not all scenarios terminated successfully
+"#;
+        let expected_stdout = "1\n2\n";
+        let output = stop_at_scenario("3", &[]).output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(expected_stdout, &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_stop_at_first_error_parallel() {
+        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "1"
+scenarios:   -> reason: job exited with non-zero exit code: 1
+scenarios: waiting for unfinished jobs ...
+scenarios: not all scenarios terminated successfully
+"#;
+        let expected_stdout = ["2", "3"];
+        let output = stop_at_scenario("1", &["--jobs=3"]).output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(expected_stdout, sorted_lines(&output.stdout).as_slice());
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_interrupt() {
+        // The first job interrupts us; all jobs must be killed then.
+        let expected_stderr_start = "scenarios: error: interrupted by SIGINT
+scenarios: waiting for unfinished jobs ...
+";
+        let start = Instant::now();
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--jobs=2", "--exec", "sh", "-c", "kill -INT $PPID; exec sleep 10"])
+            .output();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(output.stderr.starts_with(expected_stderr_start), "{}", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_unbounded_jobs() {
+        // Run serially, this would take at least five seconds.
+        let start = Instant::now();
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--jobs=0", "--exec", "sleep", "1"])
+            .output();
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert_eq!("", &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_timeout() {
+        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "A1"
+scenarios:   -> reason: job timed out after 1 seconds
+scenarios: not all scenarios terminated successfully
+"#;
+        let start = Instant::now();
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--timeout=1", "--exec", "sleep", "10"])
+            .output();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(expected_stderr, &output.stderr);
         assert_eq!("", &output.stdout);
         assert!(!output.status.success());
     }
 
 
     #[test]
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn test_conflict_print0_exec() {
-        let mut runner = Runner::new();
-        runner.args(&["--print0", "--exec", "echo", "aaa"]);
-        let expected = "error: The argument '--exec <COMMAND...>' cannot be used with '--print0 \
-                        <FORMAT>'
-
-USAGE:
-    scenarios [FlAGS] [OPTIONS] <SCENARIO FILES>... [--exec <COMMAND...>]
-
-For more information try --help
-";
-        let output = runner.output();
-        assert_eq!(&expected, &output.stderr);
+    fn test_abort_timeout() {
+        // A job that handles SIGTERM gets to clean up.
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--choose", "A1", "--timeout=1", "--abort-timeout=5"])
+            .args(&["--exec", "sh", "-c"])
+            .arg("trap 'kill $!; echo cleanup; exit 1' TERM; sleep 10 >/dev/null & wait")
+            .output();
+        assert_eq!("cleanup\n", &output.stdout);
+        assert!(!output.status.success());
+        // A job that ignores SIGTERM is killed after the grace period.
+        let start = Instant::now();
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--choose", "A1", "--timeout=1", "--abort-timeout=1"])
+            .args(&["--exec", "sh", "-c", "trap '' TERM; exec sleep 10"])
+            .output();
+        assert!(start.elapsed() < Duration::from_secs(5));
         assert_eq!("", &output.stdout);
         assert!(!output.status.success());
     }
 
 
     #[test]
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    fn test_conflict_print_print0() {
-        let mut runner = Runner::new();
-        runner.args(&["--print", "{}", "--print0", "{}"]);
-        let expected = "error: The argument '--print0 <FORMAT>' cannot be used with '--print \
-                        <FORMAT>'
-
-USAGE:
-    scenarios [FlAGS] [OPTIONS] <SCENARIO FILES>... [--exec <COMMAND...>]
-
-For more information try --help
+    fn test_enable_functions() {
+        let script = r#"echo "$stamp $id $shell""#;
+        // Without the flag, function calls are passed on verbatim.
+        let output = Runner::new()
+            .scenario_file("functions.ini")
+            .args(&["--choose", "stamped", "--exec", "sh", "-c", script])
+            .output();
+        assert_eq!("t$(now) $(uuid) $(echo hi)\n", &output.stdout);
+        assert!(output.status.success());
+        // With it, they are expanded; unknown functions are errors.
+        let output = Runner::new()
+            .scenario_file("functions.ini")
+            .args(&["--enable-functions", "--exec", "sh", "-c", script])
+            .output();
+        let words: Vec<&str> = output.stdout.split_whitespace().collect();
+        assert_eq!(words.len(), 4);
+        assert!(words[0].starts_with('t') && words[0][1..].parse::<u64>().is_ok());
+        assert_eq!(words[1].len(), 36);
+        assert_eq!(&words[2..], ["$(echo", "hi)"]);
+        let expected_stderr = "scenarios: error: could not start scenario \"broken\"
+scenarios:   -> reason: unknown function: \"$(pwd)\"
+scenarios: not all scenarios terminated successfully
 ";
-        let output = runner.output();
-        assert_eq!(&expected, &output.stderr);
-        assert_eq!("", &output.stdout);
+        assert_eq!(expected_stderr, &output.stderr);
         assert!(!output.status.success());
     }
 
 
     #[test]
-    fn test_no_args() {
-        let expected = "scenarios: error: no scenarios provided\n";
-        let output = Runner::new().output();
-        assert_eq!(expected, &output.stderr);
-        assert_eq!("", &output.stdout);
+    fn test_expand_command() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "sparse.ini"])
+            .args(&["--expand-command", "--exec", "echo", "${a_var2}:${long_name}"])
+            .output();
+        assert_eq!("one:2\n", &output.stdout);
+        let expected_stderr = "scenarios: error: could not start scenario \"A1, partial\"
+scenarios:   -> reason: variable is not set: \"long_name\"
+scenarios: not all scenarios terminated successfully
+";
+        assert_eq!(expected_stderr, &output.stderr);
         assert!(!output.status.success());
+        // With --allow-unset, unset variables expand to nothing.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "sparse.ini"])
+            .args(&["--expand-command", "--allow-unset"])
+            .args(&["--exec", "echo", "${a_var2}:${long_name}"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("one:2\none:\ntwo:2\ntwo:\n", &output.stdout);
+        assert!(output.status.success());
     }
 
 
     #[test]
-    fn test_missing_file() {
-        let output = Runner::new().arg("does not exist").output();
+    fn test_files_from() {
+        let mut runner = Runner::new();
+        let manifest = runner.get_scenario_file_path("manifest.txt");
+        let output = runner.arg("--files-from").arg(manifest).output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1, B1\nA1, B2\nA2, B1\nA2, B2\n", &output.stdout);
+        assert!(output.status.success());
+        // Missing files are reported with their line in the manifest.
+        let mut runner = Runner::new();
+        let manifest = runner.get_scenario_file_path("bad_manifest.txt");
+        let expected_stderr = format!(
+            "scenarios: error: invalid value for --files-from\n\
+             scenarios:   -> reason: in {}:2: no such file: {:?}\n",
+            manifest.display(),
+            manifest.with_file_name("missing.ini"),
+        );
+        let output = runner.arg("--files-from").arg(&manifest).output();
+        assert_eq!(expected_stderr, output.stderr);
         assert_eq!("", &output.stdout);
-        assert!(!output.status.success());
+        assert_eq!(Some(2), output.status.code());
     }
 
 
     #[test]
-    fn test_broken_file() {
+    fn test_glob_inputs_no_match() {
         let mut runner = Runner::new();
-        runner.scenario_file("broken.ini");
-        let expected = format!(
-            r#"scenarios: error: could not read file
-scenarios:   -> reason: in {0}:1
-scenarios:   -> reason: in {0}:17
-scenarios:   -> reason: duplicate scenario name: "Scenario 1"
-"#,
-            runner.get_scenario_file_path("broken.ini").display()
+        let pattern = runner
+            .get_scenario_file_path("good_a.ini")
+            .with_file_name("nonexistent_*.ini");
+        let expected_stderr = format!(
+            "scenarios: error: could not expand file name patterns\n\
+             scenarios:   -> reason: no files match the pattern {:?}\n",
+            pattern.display().to_string(),
         );
-        let output = runner.output();
-        assert_eq!(&expected, &output.stderr);
+        let output = runner.arg("--glob-inputs").arg(pattern).output();
+        assert_eq!(expected_stderr, output.stderr);
         assert_eq!("", &output.stdout);
         assert!(!output.status.success());
     }
 
 
     #[test]
-    fn test_broken_command() {
-        let expected = r#"scenarios: error: could not start scenario "A1"
-scenarios:   -> reason: could not execute command "not a command"
-scenarios:   -> reason: No such file or directory (os error 2)
+    fn test_invalid_timeout() {
+        let expected_stderr = r#"scenarios: error: could not start scenario "Impatient"
+scenarios:   -> reason: invalid value for SCENARIOS_TIMEOUT: "soon"
 scenarios: not all scenarios terminated successfully
 "#;
         let output = Runner::new()
-            .scenario_file("good_a.ini")
-            .args(&["--exec", "not a command"])
+            .scenario_file("bad_timeout.ini")
+            .args(&["--exec", "true"])
             .output();
-        assert_eq!(expected, &output.stderr);
+        assert_eq!(expected_stderr, &output.stderr);
         assert_eq!("", &output.stdout);
         assert!(!output.status.success());
     }
 
 
     #[test]
-    fn test_broken_command_parallel() {
-        let expected = r#"scenarios: error: could not start scenario "A1"
-scenarios:   -> reason: could not execute command "not a command"
-scenarios:   -> reason: No such file or directory (os error 2)
+    fn test_max_total_time() {
+        let expected_stderr = "scenarios: time limit exceeded, not starting any more jobs
+scenarios: skipped 5 scenarios because time ran out
+";
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--max-total-time=0", "--exec", "echo"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_finish_what_is_started() {
+        // Either of the two parallel jobs may fail first.
+        let expected_stderr = [("1", "2"), ("2", "1")]
+            .iter()
+            .map(|&(first, second)| {
+                format!(
+                    r#"scenarios: error: scenario did not finish successfully: "{}"
+scenarios:   -> reason: job exited with non-zero exit code: 1
 scenarios: waiting for unfinished jobs ...
+scenarios: error: scenario did not finish successfully: "{}"
+scenarios:   -> reason: job exited with non-zero exit code: 1
 scenarios: not all scenarios terminated successfully
-"#;
+"#,
+                    first, second
+                )
+            })
+            .collect::<Vec<_>>();
+        let expected_stdout = "";
         let output = Runner::new()
-            .scenario_file("good_a.ini")
-            .args(&["--jobs=2", "--exec", "not a command"])
+            .scenario_file("many_scenarios.ini")
+            .args(&["--jobs=2", "--exec", "sh", "-c", "exit 1"])
             .output();
-        assert_eq!(expected, &output.stderr);
+        assert!(expected_stderr.contains(&output.stderr), "{}", &output.stderr);
+        assert_eq!(expected_stdout, &output.stdout);
         assert!(!output.status.success());
     }
 
 
     #[test]
-    fn test_stop_at_first_error() {
-        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "3"
+    fn test_keep_going() {
+        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "1"
 scenarios:   -> reason: job exited with non-zero exit code: 1
 scenarios: not all scenarios terminated successfully
 "#;
-        let expected_stdout = "1\n2\n";
-        let output = stop_at_scenario("3", &[]).output();
+        let expected_stdout = "2\n3\n4\n5\n";
+        let output = stop_at_scenario("1", &["--keep-going"]).output();
         assert_eq!(expected_stderr, &output.stderr);
         assert_eq!(expected_stdout, &output.stdout);
         assert!(!output.status.success());
@@ -389,33 +2213,35 @@ scenarios: not all scenarios terminated successfully
 
 
     #[test]
-    fn test_stop_at_first_error_parallel() {
-        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "1"
-scenarios:   -> reason: job exited with non-zero exit code: 1
-scenarios: waiting for unfinished jobs ...
+    fn test_keep_going_spawn_failure() {
+        let expected_stderr = r#"scenarios: error: could not start scenario "1"
+scenarios:   -> reason: could not execute command "./does-not-exist"
+scenarios:   -> reason: No such file or directory (os error 2)
 scenarios: not all scenarios terminated successfully
 "#;
-        let expected_stdout = "2\n3\n";
-        let output = stop_at_scenario("1", &["--jobs=3"]).output();
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--keep-going", "--exec", "./does-not-exist"])
+            .output();
         assert_eq!(expected_stderr, &output.stderr);
-        assert_eq!(expected_stdout, &output.stdout);
+        assert_eq!("", &output.stdout);
         assert!(!output.status.success());
     }
 
 
     #[test]
-    fn test_finish_what_is_started() {
-        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "1"
+    fn test_fail_fast_after() {
+        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "2"
 scenarios:   -> reason: job exited with non-zero exit code: 1
-scenarios: waiting for unfinished jobs ...
-scenarios: error: scenario did not finish successfully: "2"
+scenarios: error: scenario did not finish successfully: "4"
 scenarios:   -> reason: job exited with non-zero exit code: 1
 scenarios: not all scenarios terminated successfully
 "#;
-        let expected_stdout = "";
+        let expected_stdout = "1\n3\n";
         let output = Runner::new()
             .scenario_file("many_scenarios.ini")
-            .args(&["--jobs=2", "--exec", "sh", "-c", "exit 1"])
+            .args(&["--fail-fast-after=2", "--exec", "sh", "-c"])
+            .arg("if [ $(({} % 2)) = 0 ]; then exit 1; else echo {}; fi")
             .output();
         assert_eq!(expected_stderr, &output.stderr);
         assert_eq!(expected_stdout, &output.stdout);
@@ -424,15 +2250,82 @@ scenarios: not all scenarios terminated successfully
 
 
     #[test]
-    fn test_keep_going() {
-        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "1"
+    fn test_on_success() {
+        let hook = "echo success: $SCENARIOS_SUCCEEDED $SCENARIOS_FAILED";
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--on-success", hook, "--on-failure", "echo failure"])
+            .args(&["--exec", "true"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("success: 5 0\n", &output.stdout);
+        assert!(output.status.success());
+    }
+
+
+    #[test]
+    fn test_on_failure() {
+        let expected_stderr = r#"scenarios: error: scenario did not finish successfully: "2"
+scenarios:   -> reason: job exited with non-zero exit code: 1
+scenarios: error: scenario did not finish successfully: "4"
 scenarios:   -> reason: job exited with non-zero exit code: 1
 scenarios: not all scenarios terminated successfully
 "#;
-        let expected_stdout = "2\n3\n4\n5\n";
-        let output = stop_at_scenario("1", &["--keep-going"]).output();
+        let hook = "echo failure: $SCENARIOS_SUCCEEDED $SCENARIOS_FAILED";
+        let output = Runner::new()
+            .scenario_file("many_scenarios.ini")
+            .args(&["--on-success", "echo success", "--on-failure", hook])
+            .args(&["--keep-going", "--exec", "sh", "-c", "exit $(({} % 2 == 0))"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!("failure: 3 2\n", &output.stdout);
+        assert_eq!(output.status.code(), Some(4));
+    }
+
+
+    #[test]
+    fn test_on_success_fails() {
+        let expected_stderr = "scenarios: error: --on-success command failed
+scenarios:   -> reason: exited with non-zero exit code: 3
+";
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--on-success", "exit 3", "--exec", "true"])
+            .output();
+        assert_eq!(expected_stderr, &output.stderr);
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+
+    #[test]
+    fn test_on_group_change() {
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--group-by", "1", "--jobs=2"])
+            .args(&["--on-group-change", "echo setup $SCENARIOS_GROUP"])
+            .args(&["--exec", "sh", "-c", "sleep 0.1; echo $SCENARIOS_NAME"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert!(output.status.success());
+        // Each group starts only after the previous one has finished.
+        let mut lines: Vec<&str> = output.stdout.lines().collect();
+        assert_eq!(lines[0], "setup B1");
+        assert_eq!(lines[3], "setup B2");
+        lines[1..3].sort();
+        lines[4..].sort();
+        assert_eq!(lines[1..3], ["A1, B1", "A2, B1"]);
+        assert_eq!(lines[4..], ["A1, B2", "A2, B2"]);
+        // A failing hook stops everything.
+        let output = Runner::new()
+            .scenario_files(&["good_a.ini", "good_b.ini"])
+            .args(&["--group-by", "0", "--on-group-change", "false"])
+            .args(&["--exec", "true"])
+            .output();
+        let expected_stderr = "scenarios: error: --on-group-change command failed
+scenarios:   -> reason: exited with non-zero exit code: 1
+scenarios: not all scenarios terminated successfully
+";
         assert_eq!(expected_stderr, &output.stderr);
-        assert_eq!(expected_stdout, &output.stdout);
         assert!(!output.status.success());
     }
 
@@ -443,14 +2336,10 @@ scenarios: not all scenarios terminated successfully
 scenarios:   -> reason: job exited with non-zero exit code: 1
 scenarios: not all scenarios terminated successfully
 "#;
-        let expected_stdout = ["2\n3\n4\n5\n", "3\n2\n4\n5\n"];
+        let expected_stdout = ["2", "3", "4", "5"];
         let output = stop_at_scenario("1", &["--keep-going", "--jobs=3"]).output();
         assert_eq!(expected_stderr, &output.stderr);
-        assert!(
-            expected_stdout.contains(&output.stdout.as_str()),
-            "{}",
-            &output.stdout,
-        );
+        assert_eq!(expected_stdout, sorted_lines(&output.stdout).as_slice());
         assert!(!output.status.success());
     }
 }
@@ -476,6 +2365,37 @@ scenarios:   -> reason: contains invalid UTF-8 character: "�"
     }
 
 
+    #[test]
+    fn test_env_passthrough() {
+        let expected = r#"scenarios: error: invalid value for --env-passthrough
+scenarios:   -> reason: not a valid variable name: ""
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--ignore-env", "--env-passthrough", "HOME,,PATH"])
+            .args(&["--exec", "true"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
+    #[test]
+    fn test_scenario() {
+        let expected = r#"scenarios: error: invalid value for --scenario
+scenarios:   -> reason: invalid escape sequence: "x:A=\\n"
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--scenario", r"x:A=\n"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(Some(2), output.status.code());
+    }
+
+
     #[test]
     fn test_input_file() {
         // Here we check that a non-UTF8 filename does not cause a panic.
@@ -555,6 +2475,24 @@ scenarios:   -> reason: contains invalid UTF-8 character: "n�f"
     }
 
 
+    #[test]
+    fn test_print_file() {
+        let expected = r#"scenarios: error: invalid value for --print-file
+scenarios:   -> reason: contains invalid UTF-8 character: "n�f"
+"#;
+        let mut runner = Runner::new();
+        let print_file = runner.get_scenario_file_path("bad_print_file.txt");
+        let output = runner
+            .scenario_file("good_a.ini")
+            .arg("--print-file")
+            .arg(print_file)
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert!(!output.status.success());
+    }
+
+
     #[test]
     fn test_command_line() {
         // Here we check that a non-UTF8 command does not cause a panic.
@@ -601,4 +2539,64 @@ scenarios:   -> reason: not a number: "three"
         assert_eq!("", &output.stdout);
         assert!(!output.status.success());
     }
+
+
+    #[test]
+    fn test_jobs_percentage() {
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--jobs", "150%", "--ordered-output", "--exec", "echo", "{}"])
+            .output();
+        assert_eq!("", &output.stderr);
+        assert_eq!("A1\nA2\n", &output.stdout);
+        assert!(output.status.success());
+        let expected = r#"scenarios: error: invalid value for --jobs
+scenarios:   -> reason: must be greater than zero: "0%"
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--jobs", "0%", "--exec", "echo"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert!(!output.status.success());
+        let expected = r#"scenarios: error: invalid value for --jobs
+scenarios:   -> reason: not a number: "half"
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--jobs", "half%", "--exec", "echo"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert!(!output.status.success());
+    }
+
+
+    #[test]
+    fn test_fail_fast_after_zero() {
+        let expected = r#"scenarios: error: invalid value for --fail-fast-after
+scenarios:   -> reason: must be greater than zero: "0"
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .args(&["--fail-fast-after", "0", "--exec", "echo"])
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+
+    #[test]
+    fn test_shuffle_not_a_number() {
+        let expected = r#"scenarios: error: invalid value for --shuffle
+scenarios:   -> reason: not a number: "-1"
+"#;
+        let output = Runner::new()
+            .scenario_file("good_a.ini")
+            .arg("--shuffle=-1")
+            .output();
+        assert_eq!(expected, &output.stderr);
+        assert_eq!("", &output.stdout);
+        assert_eq!(output.status.code(), Some(2));
+    }
 }